@@ -0,0 +1,133 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use imap::types::Flag;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::connection::{ImapOps, ImapSession};
+use crate::report::ActionReport;
+use crate::search;
+
+/// Parse `--date` for APPEND: `YYYY-MM-DD` (midnight UTC), RFC 3339, or an RFC 2822
+/// date as found in a Date header.
+pub fn parse_append_date(s: &str) -> Result<DateTime<FixedOffset>> {
+    let s = s.trim();
+    if let Ok(day) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+        return Ok(midnight.and_utc().fixed_offset());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .or_else(|_| DateTime::parse_from_rfc2822(s))
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid date '{s}': expected YYYY-MM-DD, RFC 3339 (2025-03-09T12:00:00+01:00) \
+                 or RFC 2822 (Sun, 9 Mar 2025 12:00:00 +0100)"
+            )
+        })
+}
+
+/// Initial flags for appended messages.
+pub fn append_flags(read: bool, flagged: bool) -> Vec<Flag<'static>> {
+    let mut flags = Vec::new();
+    if read {
+        flags.push(Flag::Seen);
+    }
+    if flagged {
+        flags.push(Flag::Flagged);
+    }
+    flags
+}
+
+/// Read every file up front so a missing or empty one aborts before anything is appended.
+fn read_files(files: &[PathBuf]) -> Result<Vec<(&Path, Vec<u8>)>> {
+    files
+        .iter()
+        .map(|path| {
+            let body = std::fs::read(path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            if body.is_empty() {
+                bail!("'{}' is empty", path.display());
+            }
+            Ok((path.as_path(), body))
+        })
+        .collect()
+}
+
+/// Append .eml files to `folder`, which must already exist. A message the server
+/// rejects is counted as failed and the rest are still appended.
+pub fn append_files(
+    session: &mut ImapSession,
+    folder: &str,
+    files: &[PathBuf],
+    flags: &[Flag<'_>],
+    date: Option<DateTime<FixedOffset>>,
+) -> Result<ActionReport> {
+    let bodies = read_files(files)?;
+    search::ensure_folder_exists(session, folder)?;
+
+    let mut report = ActionReport::new("append", &BTreeMap::from([(folder.to_string(), vec![])]));
+    report.matched = bodies.len();
+    report.folders[0].matched = bodies.len();
+
+    for (path, body) in &bodies {
+        match session.append(folder, flags, body, date) {
+            Ok(()) => report.add_acted(folder, 1),
+            Err(e) => {
+                eprintln!("Warning: failed to append '{}': {e}", path.display());
+                report.add_failed(folder, 1);
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_append_date_formats() {
+        assert_eq!(
+            parse_append_date("2025-03-09").unwrap().to_rfc3339(),
+            "2025-03-09T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_append_date("2025-03-09T12:30:00+01:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2025-03-09T12:30:00+01:00"
+        );
+        assert_eq!(
+            parse_append_date("Sun, 9 Mar 2025 12:30:00 -0500")
+                .unwrap()
+                .to_rfc3339(),
+            "2025-03-09T12:30:00-05:00"
+        );
+    }
+
+    #[test]
+    fn parse_append_date_rejects_garbage() {
+        let err = parse_append_date("yesterday").unwrap_err().to_string();
+        assert!(err.contains("YYYY-MM-DD"), "{err}");
+        assert!(parse_append_date("2025-02-30").is_err());
+    }
+
+    #[test]
+    fn append_flags_from_options() {
+        assert!(append_flags(false, false).is_empty());
+        assert_eq!(append_flags(true, true), vec![Flag::Seen, Flag::Flagged]);
+    }
+
+    #[test]
+    fn read_files_rejects_missing_and_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.eml");
+        std::fs::write(&empty, b"").unwrap();
+        let good = dir.path().join("good.eml");
+        std::fs::write(&good, b"Subject: hi\r\n\r\nbody\r\n").unwrap();
+
+        assert_eq!(read_files(std::slice::from_ref(&good)).unwrap().len(), 1);
+        assert!(read_files(&[good.clone(), empty]).is_err());
+        assert!(read_files(&[dir.path().join("missing.eml")]).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use anyhow::{bail, Context, Result};
+use mailparse::{DispositionType, ParsedMail};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::connection::{ImapOps, ImapSession};
+use crate::display::MessageRow;
+use crate::export;
+use crate::report::ActionReport;
+use crate::search;
+
+/// Messages per FETCH: whole bodies are pulled, so keep batches small.
+const FETCH_BATCH: usize = 20;
+
+/// Longest filename kept from a part, in bytes; longer names lose the end of their stem.
+const MAX_NAME_BYTES: usize = 120;
+
+/// One attachment part, with its transfer encoding already decoded.
+#[derive(Debug, PartialEq)]
+pub struct Attachment {
+    /// From the Content-Disposition `filename` or Content-Type `name` parameter.
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Counts for the summary line; the per-message outcome is in the `ActionReport`.
+#[derive(Debug, Default, PartialEq)]
+pub struct DownloadSummary {
+    /// Attachments written to disk.
+    pub saved: usize,
+    /// Attachments left alone because the file already existed (without --force).
+    pub existing: usize,
+    /// Messages with no attachment (matching --name-filter).
+    pub without: usize,
+}
+
+/// Turn a `--name-filter` glob (`*` and `?` wildcards) into a case-insensitive
+/// regex matching the whole filename.
+pub fn parse_name_filter(glob: &str) -> Result<Regex> {
+    if glob.is_empty() {
+        bail!("--name-filter must not be empty");
+    }
+    let mut pattern = String::from("(?i)^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).with_context(|| format!("Invalid --name-filter '{glob}'"))
+}
+
+/// The attachment parts of a raw message: leaf parts with a filename or an
+/// `attachment` disposition, in MIME order.
+pub fn attachments(raw: &[u8]) -> Result<Vec<Attachment>> {
+    let mail = mailparse::parse_mail(raw).context("Failed to parse message")?;
+    let mut found = Vec::new();
+    collect_attachments(&mail, &mut found)?;
+    Ok(found)
+}
+
+fn collect_attachments(part: &ParsedMail, found: &mut Vec<Attachment>) -> Result<()> {
+    if part.ctype.mimetype.to_lowercase().starts_with("multipart/") {
+        for sub in &part.subparts {
+            collect_attachments(sub, found)?;
+        }
+        return Ok(());
+    }
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .filter(|name| !name.trim().is_empty())
+        .cloned();
+    if filename.is_none() && disposition.disposition != DispositionType::Attachment {
+        return Ok(());
+    }
+    let data = part.get_body_raw().context("Failed to decode attachment")?;
+    found.push(Attachment { filename, data });
+    Ok(())
+}
+
+/// Make an attachment's filename safe to write: path separators and control
+/// characters become `_`, leading/trailing dots and spaces are dropped, and long
+/// names are shortened before the extension.
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let trimmed = cleaned.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if trimmed.is_empty() {
+        return "unnamed".to_string();
+    }
+    export::clamp_name(trimmed, MAX_NAME_BYTES)
+}
+
+/// `{uid}-{name}`, with the folder in front when the search spanned several folders,
+/// shortened to leave `unique_name` room under NAME_MAX.
+fn file_name(prefix: Option<&str>, uid: u32, attachment: &Attachment) -> String {
+    let name = sanitize_filename(attachment.filename.as_deref().unwrap_or(""));
+    let full = match prefix {
+        Some(folder) => format!("{}-{uid}-{name}", export::sanitize_folder_name(folder)),
+        None => format!("{uid}-{name}"),
+    };
+    export::clamp_name(&full, export::NAME_MAX - export::SUFFIX_ROOM)
+}
+
+/// Save the attachments of `messages` into `out_dir`. A message counts as acted on
+/// when at least one attachment was written, and as skipped when it has none (or
+/// every file already existed). A folder that fails to select or fetch is counted
+/// as failed.
+pub fn download(
+    session: &mut ImapSession,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_dir: &Path,
+    name_filter: Option<&Regex>,
+    force: bool,
+) -> Result<(ActionReport, DownloadSummary)> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create directory '{}'", out_dir.display()))?;
+
+    let by_folder = search::group_by_folder(messages, default_folder);
+    let prefix_folder = messages.iter().any(|m| m.folder.is_some());
+    let mut report = ActionReport::new("download-attachments", &by_folder);
+    let mut summary = DownloadSummary::default();
+    let mut used = HashSet::new();
+
+    for (folder, uids) in &by_folder {
+        let mut sorted = uids.clone();
+        sorted.sort_unstable();
+        let handled_before = report_handled(&report, folder);
+        let prefix = prefix_folder.then_some(folder.as_str());
+        let result = download_folder(
+            session,
+            folder,
+            &sorted,
+            prefix,
+            out_dir,
+            name_filter,
+            force,
+            &mut used,
+            &mut report,
+            &mut summary,
+        );
+        if let Err(e) = result {
+            eprintln!("Warning: {e:#}");
+            let handled = report_handled(&report, folder) - handled_before;
+            report.add_failed(folder, uids.len().saturating_sub(handled));
+        }
+    }
+    Ok((report, summary))
+}
+
+fn report_handled(report: &ActionReport, folder: &str) -> usize {
+    report
+        .folders
+        .iter()
+        .find(|f| f.folder == folder)
+        .map_or(0, |f| f.acted + f.skipped + f.failed)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_folder(
+    session: &mut ImapSession,
+    folder: &str,
+    uids: &[u32],
+    prefix: Option<&str>,
+    out_dir: &Path,
+    name_filter: Option<&Regex>,
+    force: bool,
+    used: &mut HashSet<String>,
+    report: &mut ActionReport,
+    summary: &mut DownloadSummary,
+) -> Result<()> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    for batch in uids.chunks(FETCH_BATCH) {
+        for set in &search::build_uid_set(batch) {
+            let fetches = session
+                .uid_fetch(set, "BODY.PEEK[]")
+                .with_context(|| format!("Failed to fetch messages from '{folder}'"))?;
+            let mut bodies: Vec<(u32, &[u8])> = fetches
+                .iter()
+                .filter_map(|f| Some((f.uid?, f.body()?)))
+                .collect();
+            bodies.sort_unstable_by_key(|(uid, _)| *uid);
+
+            for (uid, body) in bodies {
+                let parts = match attachments(body) {
+                    Ok(parts) => parts,
+                    Err(e) => {
+                        eprintln!("Warning: UID {uid} in '{folder}': {e:#}");
+                        report.add_failed(folder, 1);
+                        continue;
+                    }
+                };
+                let wanted: Vec<Attachment> = parts
+                    .into_iter()
+                    .filter(|a| {
+                        name_filter.is_none_or(|re| {
+                            a.filename.as_deref().is_some_and(|name| re.is_match(name))
+                        })
+                    })
+                    .collect();
+                if wanted.is_empty() {
+                    summary.without += 1;
+                    report.add_skipped(folder, 1);
+                    continue;
+                }
+
+                let (mut written, mut failed) = (0, false);
+                for attachment in &wanted {
+                    let name = export::unique_name(file_name(prefix, uid, attachment), used);
+                    let path = out_dir.join(&name);
+                    if path.exists() && !force {
+                        summary.existing += 1;
+                        continue;
+                    }
+                    match std::fs::write(&path, &attachment.data) {
+                        Ok(()) => written += 1,
+                        Err(e) => {
+                            eprintln!("Warning: failed to write '{}': {e}", path.display());
+                            failed = true;
+                        }
+                    }
+                }
+                summary.saved += written;
+                if failed {
+                    report.add_failed(folder, 1);
+                } else if written > 0 {
+                    report.add_acted(folder, 1);
+                } else {
+                    report.add_skipped(folder, 1);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIXED: &[u8] = b"From: a@example.com\r\n\
+Subject: Invoice\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\"\r\n\
+\r\n\
+--b1\r\n\
+Content-Type: multipart/alternative; boundary=\"b2\"\r\n\
+\r\n\
+--b2\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See attached.\r\n\
+--b2\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>See attached.</p>\r\n\
+--b2--\r\n\
+--b1\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=\"invoice.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQ=\r\n\
+--b1\r\n\
+Content-Type: image/png; name=\"logo.png\"\r\n\
+Content-Disposition: inline\r\n\
+\r\n\
+PNG\r\n\
+--b1\r\n\
+Content-Type: application/octet-stream\r\n\
+Content-Disposition: attachment\r\n\
+\r\n\
+blob\r\n\
+--b1--\r\n";
+
+    #[test]
+    fn attachments_finds_named_and_attachment_parts() {
+        let found = attachments(MIXED).unwrap();
+        assert_eq!(
+            found,
+            vec![
+                Attachment {
+                    filename: Some("invoice.pdf".to_string()),
+                    data: b"%PDF-1.4".to_vec(),
+                },
+                Attachment {
+                    filename: Some("logo.png".to_string()),
+                    data: b"PNG\r\n".to_vec(),
+                },
+                Attachment {
+                    filename: None,
+                    data: b"blob\r\n".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn attachments_none_in_plain_message() {
+        let raw = b"From: a@example.com\r\nSubject: Hi\r\n\r\nJust text.\r\n";
+        assert!(attachments(raw).unwrap().is_empty());
+    }
+
+    #[test]
+    fn name_filter_matches_whole_name_case_insensitively() {
+        let re = parse_name_filter("*.pdf").unwrap();
+        assert!(re.is_match("invoice.pdf"));
+        assert!(re.is_match("SCAN.PDF"));
+        assert!(!re.is_match("invoice.pdf.zip"));
+        assert!(!re.is_match("notes.txt"));
+
+        let re = parse_name_filter("report-??.csv").unwrap();
+        assert!(re.is_match("report-01.csv"));
+        assert!(!re.is_match("report-1.csv"));
+
+        // Regex metacharacters are literal
+        let re = parse_name_filter("a+b (1).txt").unwrap();
+        assert!(re.is_match("a+b (1).txt"));
+        assert!(!re.is_match("aab (1).txt"));
+    }
+
+    #[test]
+    fn name_filter_rejects_empty() {
+        assert!(parse_name_filter("").is_err());
+    }
+
+    #[test]
+    fn sanitize_filename_strips_paths_and_keeps_extension() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_filename("a\\b.txt"), "a_b.txt");
+        assert_eq!(sanitize_filename(" .. "), "unnamed");
+        let long = format!("{}.pdf", "x".repeat(300));
+        let short = sanitize_filename(&long);
+        assert_eq!(short.len(), MAX_NAME_BYTES);
+        assert!(short.ends_with("x.pdf"));
+        let wide = sanitize_filename(&format!("{}.pdf", "ü".repeat(100)));
+        assert!(wide.len() <= MAX_NAME_BYTES);
+        assert!(wide.ends_with("ü.pdf"));
+    }
+
+    #[test]
+    fn file_name_prefixes_uid_and_folder() {
+        let a = Attachment {
+            filename: Some("invoice.pdf".to_string()),
+            data: Vec::new(),
+        };
+        assert_eq!(file_name(None, 42, &a), "42-invoice.pdf");
+        assert_eq!(
+            file_name(Some("Work/Clients"), 42, &a),
+            "Work_Clients-42-invoice.pdf"
+        );
+        let unnamed = Attachment {
+            filename: None,
+            data: Vec::new(),
+        };
+        assert_eq!(file_name(None, 7, &unnamed), "7-unnamed");
+    }
+
+    #[test]
+    fn file_name_fits_name_max_with_a_long_folder() {
+        let a = Attachment {
+            filename: Some(format!("{}.pdf", "r".repeat(200))),
+            data: Vec::new(),
+        };
+        let folder = "Archive/".repeat(40);
+        let name = file_name(Some(&folder), 42, &a);
+        assert!(name.len() <= export::NAME_MAX - export::SUFFIX_ROOM);
+        assert!(name.starts_with("Archive_Archive_"));
+        assert!(name.ends_with(".pdf"));
+    }
+}
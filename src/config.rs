@@ -1,16 +1,42 @@
 use anyhow::{Context, Result};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+use crate::connection::Security;
+
 #[derive(Debug, Default, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     pub host: Option<String>,
     pub port: Option<u16>,
+    /// Port used without implicit TLS when `port` is unset [default: 1143 plain, 143 STARTTLS]
+    pub default_port_plain: Option<u16>,
+    /// Port used with TLS when `port` is unset [default: 993]
+    pub default_port_tls: Option<u16>,
     pub tls: Option<bool>,
+    /// Upgrade a plaintext connection with STARTTLS before login
+    pub starttls: Option<bool>,
+    /// PEM file with extra root certificates to trust for TLS
+    pub ca_cert: Option<String>,
+    /// Seconds each IMAP read/write may take before giving up (0 = wait forever) [default: 30]
+    pub timeout: Option<u64>,
     pub user: Option<String>,
+    /// Shell command whose stdout is the password (not `${VAR}`-expanded; the shell does that)
+    pub pass_command: Option<String>,
     pub trash_folder: Option<String>,
+    /// Mark messages read before `delete` moves them to the trash folder
+    pub mark_read_on_delete: Option<bool>,
     pub default_folder: Option<String>,
+    /// Extra folders (full names) that `--all-folders` leaves out, besides Trash, Spam and All Mail
+    pub skip_folders: Option<Vec<String>>,
+    /// Record moves and trash deletes so `undo` can reverse the last one [default: false]
+    pub undo_log: Option<bool>,
+    /// `[accounts.<name>]` section used when `--account` is not given
+    pub default_account: Option<String>,
+    /// Named profiles; each may set any key above, overriding the top-level value
+    #[serde(default)]
+    pub accounts: BTreeMap<String, Config>,
 }
 
 impl Config {
@@ -35,11 +61,172 @@ impl Config {
             .with_context(|| format!("Failed to parse config file: {}", path.display()))
     }
 
+    /// Load several config files in order, later files overriding earlier ones.
+    /// An empty list falls back to the default path, like `load(None)`.
+    /// With `expand_env`, `${VAR}` references in string values are resolved.
+    pub fn load_all(paths: &[PathBuf], expand_env: bool) -> Result<Self> {
+        let config = if paths.is_empty() {
+            Self::load(None)?
+        } else {
+            paths.iter().try_fold(Self::default(), |merged, p| {
+                Ok::<_, anyhow::Error>(merged.merge(Self::load(Some(p))?))
+            })?
+        };
+        if expand_env {
+            config.expand_env(|name| std::env::var(name).ok())
+        } else {
+            Ok(config)
+        }
+    }
+
+    /// Resolve `${VAR}` references in every string field using `lookup`.
+    pub fn expand_env(self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        self.expand_with(&lookup)
+    }
+
+    fn expand_with(mut self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        let expand = |field: &str, value: Option<String>| -> Result<Option<String>> {
+            value
+                .map(|v| {
+                    expand_vars(&v, lookup)
+                        .with_context(|| format!("Failed to expand config value '{field}'"))
+                })
+                .transpose()
+        };
+        let accounts = std::mem::take(&mut self.accounts)
+            .into_iter()
+            .map(|(name, account)| {
+                let account = account
+                    .expand_with(lookup)
+                    .with_context(|| format!("In [accounts.{name}]"))?;
+                Ok((name, account))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            accounts,
+            host: expand("host", self.host)?,
+            user: expand("user", self.user)?,
+            trash_folder: expand("trash_folder", self.trash_folder)?,
+            default_folder: expand("default_folder", self.default_folder)?,
+            ca_cert: expand("ca_cert", self.ca_cert)?,
+            skip_folders: self
+                .skip_folders
+                .map(|list| {
+                    list.into_iter()
+                        .map(|v| Ok(expand("skip_folders", Some(v))?.unwrap_or_default()))
+                        .collect::<Result<_>>()
+                })
+                .transpose()?,
+            ..self
+        })
+    }
+
+    /// Overlay `other` on top of `self`: every field set in `other` wins.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+            default_port_plain: other.default_port_plain.or(self.default_port_plain),
+            default_port_tls: other.default_port_tls.or(self.default_port_tls),
+            tls: other.tls.or(self.tls),
+            starttls: other.starttls.or(self.starttls),
+            timeout: other.timeout.or(self.timeout),
+            ca_cert: other.ca_cert.or(self.ca_cert),
+            user: other.user.or(self.user),
+            pass_command: other.pass_command.or(self.pass_command),
+            trash_folder: other.trash_folder.or(self.trash_folder),
+            mark_read_on_delete: other.mark_read_on_delete.or(self.mark_read_on_delete),
+            default_folder: other.default_folder.or(self.default_folder),
+            skip_folders: other.skip_folders.or(self.skip_folders),
+            undo_log: other.undo_log.or(self.undo_log),
+            default_account: other.default_account.or(self.default_account),
+            accounts: merge_accounts(self.accounts, other.accounts),
+        }
+    }
+
+    /// Apply the `[accounts.<name>]` section for `name` (or `default_account`) on top
+    /// of the top-level keys. Without either, the top-level keys are used as they are.
+    pub fn select_account(mut self, name: Option<&str>) -> Result<Self> {
+        let mut accounts = std::mem::take(&mut self.accounts);
+        let Some(name) = name.map(str::to_string).or(self.default_account.take()) else {
+            return Ok(self);
+        };
+        let Some(account) = accounts.remove(&name) else {
+            if accounts.is_empty() {
+                anyhow::bail!(
+                    "Account '{name}' not found: the config has no [accounts.<name>] sections"
+                );
+            }
+            let names: Vec<&str> = accounts.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "Account '{name}' not found in config (available: {})",
+                names.join(", ")
+            );
+        };
+        if account.default_account.is_some() || !account.accounts.is_empty() {
+            anyhow::bail!("[accounts.{name}] cannot set default_account or nested accounts");
+        }
+        Ok(self.merge(account))
+    }
+
+    /// The port to use when neither `--port` nor `port` is set.
+    pub fn default_port(&self, security: Security) -> u16 {
+        match security {
+            Security::Tls => self.default_port_tls.unwrap_or(993),
+            Security::StartTls => self.default_port_plain.unwrap_or(143),
+            Security::Plain => self.default_port_plain.unwrap_or(1143),
+        }
+    }
+
     pub fn default_path() -> Option<PathBuf> {
         dirs::config_dir().map(|d| d.join("slashmail").join("config.toml"))
     }
 }
 
+/// Merge account sections by name; for a name in both, `other`'s keys win.
+fn merge_accounts(
+    mut base: BTreeMap<String, Config>,
+    other: BTreeMap<String, Config>,
+) -> BTreeMap<String, Config> {
+    for (name, account) in other {
+        let merged = base.remove(&name).unwrap_or_default().merge(account);
+        base.insert(name, merged);
+    }
+    base
+}
+
+/// Expand `${VAR}` references in `value`. `$$` yields a literal `$`; any other
+/// `$` is kept as-is. Undefined variables are an error.
+fn expand_vars(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated '${{' in '{value}'"))?;
+            let name = &after[..end];
+            if name.is_empty() {
+                anyhow::bail!("Empty variable name in '{value}'");
+            }
+            let resolved = lookup(name)
+                .ok_or_else(|| anyhow::anyhow!("Environment variable '{name}' is not set"))?;
+            out.push_str(&resolved);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,4 +283,290 @@ mod tests {
         let result = Config::load(Some(Path::new("/nonexistent/config.toml")));
         assert!(result.is_err());
     }
+
+    #[test]
+    fn merge_partial_override() {
+        let base: Config = toml::from_str(
+            r#"
+            host = "imap.example.com"
+            port = 993
+            tls = true
+            trash_folder = "Trash"
+        "#,
+        )
+        .unwrap();
+        let local: Config = toml::from_str(
+            r#"
+            user = "alice@example.com"
+            trash_folder = "[Gmail]/Trash"
+        "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(local);
+        assert_eq!(merged.host.as_deref(), Some("imap.example.com"));
+        assert_eq!(merged.port, Some(993));
+        assert_eq!(merged.tls, Some(true));
+        assert_eq!(merged.user.as_deref(), Some("alice@example.com"));
+        assert_eq!(merged.trash_folder.as_deref(), Some("[Gmail]/Trash"));
+        assert_eq!(merged.default_folder, None);
+    }
+
+    #[test]
+    fn merge_empty_overlay_keeps_base() {
+        let base: Config = toml::from_str(r#"host = "a.example.com""#).unwrap();
+        let merged = base.merge(Config::default());
+        assert_eq!(merged.host.as_deref(), Some("a.example.com"));
+    }
+
+    #[test]
+    fn merge_later_wins() {
+        let a: Config = toml::from_str("tls = true\nport = 993").unwrap();
+        let b: Config = toml::from_str("tls = false").unwrap();
+        let merged = a.merge(b);
+        assert_eq!(merged.tls, Some(false));
+        assert_eq!(merged.port, Some(993));
+    }
+
+    #[test]
+    fn parse_skip_folders_and_undo_log() {
+        let a: Config =
+            toml::from_str("skip_folders = [\"Newsletters\", \"Archive/2019\"]\nundo_log = false")
+                .unwrap();
+        let merged = a.merge(Config::default());
+        assert_eq!(
+            merged.skip_folders,
+            Some(vec!["Newsletters".to_string(), "Archive/2019".to_string()])
+        );
+        assert_eq!(merged.undo_log, Some(false));
+    }
+
+    #[test]
+    fn load_all_merges_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("slashmail-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.toml");
+        let local = dir.join("local.toml");
+        std::fs::write(&base, "host = \"imap.example.com\"\nuser = \"team\"\n").unwrap();
+        std::fs::write(&local, "user = \"alice\"\n").unwrap();
+
+        let merged = Config::load_all(&[base, local], true).unwrap();
+        assert_eq!(merged.host.as_deref(), Some("imap.example.com"));
+        assert_eq!(merged.user.as_deref(), Some("alice"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_all_missing_file_errors() {
+        let result = Config::load_all(&[PathBuf::from("/nonexistent/config.toml")], true);
+        assert!(result.is_err());
+    }
+
+    fn test_env(name: &str) -> Option<String> {
+        match name {
+            "MAIL_USER" => Some("alice@example.com".to_string()),
+            "MAIL_HOST" => Some("imap.example.com".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_vars_defined() {
+        assert_eq!(
+            expand_vars("${MAIL_USER}", test_env).unwrap(),
+            "alice@example.com"
+        );
+        assert_eq!(
+            expand_vars("imap-${MAIL_HOST}-x", test_env).unwrap(),
+            "imap-imap.example.com-x"
+        );
+    }
+
+    #[test]
+    fn expand_vars_undefined_errors() {
+        let err = expand_vars("${NOPE}", test_env).unwrap_err();
+        assert!(err.to_string().contains("NOPE"));
+    }
+
+    #[test]
+    fn expand_vars_escaped_dollar() {
+        assert_eq!(
+            expand_vars("$${MAIL_USER}", test_env).unwrap(),
+            "${MAIL_USER}"
+        );
+        assert_eq!(expand_vars("cost $$5", test_env).unwrap(), "cost $5");
+    }
+
+    #[test]
+    fn expand_vars_plain_dollar_kept() {
+        assert_eq!(expand_vars("a$b", test_env).unwrap(), "a$b");
+        assert_eq!(expand_vars("trailing$", test_env).unwrap(), "trailing$");
+    }
+
+    #[test]
+    fn expand_vars_malformed_errors() {
+        assert!(expand_vars("${MAIL_USER", test_env).is_err());
+        assert!(expand_vars("${}", test_env).is_err());
+    }
+
+    #[test]
+    fn expand_env_walks_string_fields() {
+        let config: Config = toml::from_str(
+            r#"
+            host = "${MAIL_HOST}"
+            user = "${MAIL_USER}"
+            port = 993
+            skip_folders = ["Lists", "${MAIL_USER}/Spam"]
+        "#,
+        )
+        .unwrap();
+        let config = config.expand_env(test_env).unwrap();
+        assert_eq!(config.host.as_deref(), Some("imap.example.com"));
+        assert_eq!(config.user.as_deref(), Some("alice@example.com"));
+        assert_eq!(config.port, Some(993));
+        assert_eq!(
+            config.skip_folders.unwrap(),
+            ["Lists", "alice@example.com/Spam"]
+        );
+    }
+
+    #[test]
+    fn expand_env_undefined_names_field() {
+        let config: Config = toml::from_str(r#"trash_folder = "${NOPE}""#).unwrap();
+        let err = config.expand_env(test_env).unwrap_err();
+        assert!(format!("{err:#}").contains("trash_folder"));
+    }
+
+    #[test]
+    fn default_port_builtin() {
+        let config = Config::default();
+        assert_eq!(config.default_port(Security::Plain), 1143);
+        assert_eq!(config.default_port(Security::Tls), 993);
+        assert_eq!(config.default_port(Security::StartTls), 143);
+    }
+
+    #[test]
+    fn default_port_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+            default_port_plain = 143
+            default_port_tls = 9993
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.default_port(Security::Plain), 143);
+        assert_eq!(config.default_port(Security::Tls), 9993);
+        assert_eq!(config.default_port(Security::StartTls), 143);
+    }
+
+    #[test]
+    fn merge_default_ports() {
+        let base: Config = toml::from_str("default_port_plain = 143").unwrap();
+        let local: Config = toml::from_str("default_port_tls = 9993").unwrap();
+        let merged = base.merge(local);
+        assert_eq!(merged.default_port_plain, Some(143));
+        assert_eq!(merged.default_port_tls, Some(9993));
+    }
+
+    const ACCOUNTS_TOML: &str = r#"
+        user = "shared@example.com"
+        tls = true
+        default_account = "work"
+
+        [accounts.work]
+        host = "imap.work.example"
+        default_folder = "Projects"
+
+        [accounts.personal]
+        host = "imap.home.example"
+        user = "me@home.example"
+    "#;
+
+    #[test]
+    fn select_account_overlays_named_section() {
+        let config: Config = toml::from_str(ACCOUNTS_TOML).unwrap();
+        let config = config.select_account(Some("personal")).unwrap();
+        assert_eq!(config.host.as_deref(), Some("imap.home.example"));
+        assert_eq!(config.user.as_deref(), Some("me@home.example"));
+        assert_eq!(config.tls, Some(true));
+        assert!(config.accounts.is_empty());
+    }
+
+    #[test]
+    fn select_account_falls_back_to_default_account() {
+        let config: Config = toml::from_str(ACCOUNTS_TOML).unwrap();
+        let config = config.select_account(None).unwrap();
+        assert_eq!(config.host.as_deref(), Some("imap.work.example"));
+        assert_eq!(config.user.as_deref(), Some("shared@example.com"));
+        assert_eq!(config.default_folder.as_deref(), Some("Projects"));
+    }
+
+    #[test]
+    fn select_account_without_accounts_keeps_flat_keys() {
+        let config: Config = toml::from_str(r#"host = "flat.example""#).unwrap();
+        let config = config.select_account(None).unwrap();
+        assert_eq!(config.host.as_deref(), Some("flat.example"));
+    }
+
+    #[test]
+    fn select_account_unknown_name_errors() {
+        let config: Config = toml::from_str(ACCOUNTS_TOML).unwrap();
+        let err = config.select_account(Some("nope")).unwrap_err().to_string();
+        assert!(err.contains("'nope'"), "{err}");
+        assert!(err.contains("personal, work"), "{err}");
+
+        let err = Config::default()
+            .select_account(Some("work"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no [accounts.<name>]"), "{err}");
+    }
+
+    #[test]
+    fn select_account_rejects_nested_accounts() {
+        let toml = r#"
+            [accounts.work]
+            default_account = "other"
+        "#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.select_account(Some("work")).is_err());
+    }
+
+    #[test]
+    fn merge_combines_accounts_by_name() {
+        let base: Config = toml::from_str(
+            r#"
+            [accounts.work]
+            host = "old.example"
+            user = "bob"
+        "#,
+        )
+        .unwrap();
+        let overlay: Config = toml::from_str(
+            r#"
+            [accounts.work]
+            host = "new.example"
+        "#,
+        )
+        .unwrap();
+        let work = &base.merge(overlay).accounts["work"];
+        assert_eq!(work.host.as_deref(), Some("new.example"));
+        assert_eq!(work.user.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn expand_env_expands_account_values() {
+        let config: Config = toml::from_str(
+            r#"
+            [accounts.work]
+            user = "${WORK_USER}"
+        "#,
+        )
+        .unwrap();
+        let config = config
+            .expand_env(|name| (name == "WORK_USER").then(|| "carol".to_string()))
+            .unwrap();
+        assert_eq!(config.accounts["work"].user.as_deref(), Some("carol"));
+    }
 }
@@ -1,7 +1,77 @@
 use anyhow::{Context, Result};
 use imap::Session;
+use imap_proto::types::Capability;
 use std::collections::HashSet;
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::error::{self, SlashmailError};
+use crate::search;
+use crate::timings::{self, Phase};
+
+/// Set by `--verbose`: log each IMAP command and the size of its response to stderr.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Log IMAP traffic to stderr from now on. LOGIN arguments and message contents are
+/// never logged, only commands and response sizes.
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Log a command about to be sent; `command` is only built with `--verbose`.
+fn trace_command(command: impl FnOnce() -> String) {
+    if verbose() {
+        eprintln!("C: {}", command());
+    }
+}
+
+/// Log how a command ended, summarizing a successful response with `summary`.
+fn trace_result<T, E: std::fmt::Display>(
+    result: &std::result::Result<T, E>,
+    summary: impl FnOnce(&T) -> String,
+) {
+    if verbose() {
+        match result {
+            Ok(value) => eprintln!("S: {}", summary(value)),
+            Err(e) => eprintln!("S: error: {e}"),
+        }
+    }
+}
+
+/// How the connection is protected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /// No encryption; only sensible for a local bridge.
+    #[default]
+    Plain,
+    /// Implicit TLS from the first byte (usually port 993).
+    Tls,
+    /// Plain connect, then upgraded with STARTTLS before login (usually port 143).
+    StartTls,
+}
+
+/// How `connect` reaches and secures the server.
+#[derive(Debug, Default, Clone)]
+pub struct ConnectOptions {
+    pub security: Security,
+    /// Bounds connect, TLS handshake, greeting and login.
+    pub connect_timeout: Option<Duration>,
+    /// Bounds each later socket read/write (`None` waits forever).
+    pub timeout: Option<Duration>,
+    /// PEM file with extra root certificates to trust, e.g. a private CA.
+    pub ca_cert: Option<PathBuf>,
+    /// Accept any certificate and hostname. Only for throwaway test servers.
+    pub insecure: bool,
+    /// Leave out the `--insecure` and plaintext warnings, for extra connections after
+    /// the first has already printed them.
+    pub quiet: bool,
+}
 
 pub type PlainSession = Session<TcpStream>;
 pub type TlsSession = Session<native_tls::TlsStream<TcpStream>>;
@@ -14,141 +84,571 @@ enum Inner {
 pub struct ImapSession {
     inner: Inner,
     capabilities: HashSet<String>,
+    /// Handle on the underlying socket, used to adjust read/write timeouts.
+    socket: TcpStream,
+    /// Read/write timeout restored after `idle`, which changes it while waiting.
+    timeout: Option<Duration>,
 }
 
-impl ImapSession {
-    pub fn list(
+/// The IMAP commands the search, delete and export code issues, so that code can run
+/// against a test double as well as a live `ImapSession`.
+pub trait ImapOps {
+    fn list(
+        &mut self,
+        reference: Option<&str>,
+        pattern: Option<&str>,
+    ) -> imap::error::Result<imap::types::ZeroCopy<Vec<imap::types::Name>>>;
+
+    fn create(&mut self, mailbox: &str) -> imap::error::Result<()>;
+
+    fn delete_mailbox(&mut self, mailbox: &str) -> imap::error::Result<()>;
+
+    fn rename(&mut self, from: &str, to: &str) -> imap::error::Result<()>;
+
+    fn select(&mut self, mailbox: &str) -> imap::error::Result<imap::types::Mailbox>;
+
+    fn uid_search(&mut self, query: &str) -> imap::error::Result<std::collections::HashSet<u32>>;
+
+    fn uid_fetch(
+        &mut self,
+        uid_set: &str,
+        query: &str,
+    ) -> imap::error::Result<imap::types::ZeroCopy<Vec<imap::types::Fetch>>>;
+
+    /// APPEND a message to `mailbox` with initial `flags` and, if given, internal date.
+    fn append(
+        &mut self,
+        mailbox: &str,
+        flags: &[imap::types::Flag<'_>],
+        body: &[u8],
+        date: Option<chrono::DateTime<chrono::FixedOffset>>,
+    ) -> imap::error::Result<()>;
+
+    /// UID MOVE; returns the untagged response data, which carries `[COPYUID ...]`
+    /// on servers with UIDPLUS.
+    fn uid_mv(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<Vec<u8>>;
+
+    fn uid_copy(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<()>;
+
+    fn uid_store(&mut self, uid_set: &str, query: &str) -> imap::error::Result<()>;
+
+    fn expunge(&mut self) -> imap::error::Result<()>;
+
+    fn uid_expunge(&mut self, uid_set: &str) -> imap::error::Result<()>;
+
+    fn logout(&mut self) -> imap::error::Result<()>;
+
+    /// Whether the server advertised `cap`. `ImapSession` checks the capabilities
+    /// cached at connect time; no round trip.
+    fn has_capability(&self, cap: &str) -> bool;
+
+    /// Send a raw command and return the response data. Commands containing CR or LF
+    /// are refused before anything is written, so an unsanitized value can never
+    /// smuggle in a second tagged command.
+    fn run_command_and_read_response(&mut self, command: &str) -> imap::error::Result<Vec<u8>>;
+
+    /// Move UIDs to dest, falling back to COPY+DELETE+EXPUNGE if MOVE is unsupported.
+    /// Returns (source, destination) UID pairs when a MOVE reply carries COPYUID;
+    /// the fallback's COPYUID comes in the tagged reply, which the imap crate drops.
+    fn uid_move_or_fallback(
+        &mut self,
+        uid_set: &str,
+        dest: &str,
+    ) -> error::Result<Vec<(u32, u32)>> {
+        if self.has_capability("MOVE") {
+            let data = self.uid_mv(uid_set, dest).context("UID MOVE failed")?;
+            return Ok(copyuid_pairs(&data));
+        }
+        self.uid_copy(uid_set, dest).context("UID COPY failed")?;
+        self.uid_store(uid_set, "+FLAGS (\\Deleted)")
+            .context("UID STORE +FLAGS failed")?;
+        self.expunge().context("EXPUNGE failed")?;
+        Ok(Vec::new())
+    }
+}
+
+impl ImapOps for ImapSession {
+    fn list(
         &mut self,
         reference: Option<&str>,
         pattern: Option<&str>,
     ) -> imap::error::Result<imap::types::ZeroCopy<Vec<imap::types::Name>>> {
-        match &mut self.inner {
+        let _t = timings::start(Phase::List);
+        trace_command(|| {
+            format!(
+                "LIST {} {}",
+                search::imap_quote(reference.unwrap_or("")),
+                search::imap_quote(pattern.unwrap_or(""))
+            )
+        });
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.list(reference, pattern),
             Inner::Tls(s) => s.list(reference, pattern),
-        }
+        };
+        trace_result(&result, |names| format!("{} folder(s)", names.len()));
+        result
     }
 
-    pub fn create(&mut self, mailbox: &str) -> imap::error::Result<()> {
-        match &mut self.inner {
+    fn create(&mut self, mailbox: &str) -> imap::error::Result<()> {
+        trace_command(|| format!("CREATE {}", search::imap_quote(mailbox)));
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.create(mailbox),
             Inner::Tls(s) => s.create(mailbox),
-        }
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
     }
 
-    pub fn select(&mut self, mailbox: &str) -> imap::error::Result<imap::types::Mailbox> {
-        match &mut self.inner {
+    fn delete_mailbox(&mut self, mailbox: &str) -> imap::error::Result<()> {
+        trace_command(|| format!("DELETE {}", search::imap_quote(mailbox)));
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.delete(mailbox),
+            Inner::Tls(s) => s.delete(mailbox),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> imap::error::Result<()> {
+        trace_command(|| {
+            format!(
+                "RENAME {} {}",
+                search::imap_quote(from),
+                search::imap_quote(to)
+            )
+        });
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.rename(from, to),
+            Inner::Tls(s) => s.rename(from, to),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
+    }
+
+    fn select(&mut self, mailbox: &str) -> imap::error::Result<imap::types::Mailbox> {
+        let _t = timings::start(Phase::Select);
+        trace_command(|| format!("SELECT {}", search::imap_quote(mailbox)));
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.select(mailbox),
             Inner::Tls(s) => s.select(mailbox),
-        }
+        };
+        trace_result(&result, |mb| format!("{} message(s)", mb.exists));
+        result
     }
 
-    pub fn uid_search(
-        &mut self,
-        query: &str,
-    ) -> imap::error::Result<std::collections::HashSet<u32>> {
-        match &mut self.inner {
+    fn uid_search(&mut self, query: &str) -> imap::error::Result<std::collections::HashSet<u32>> {
+        let _t = timings::start(Phase::Search);
+        trace_command(|| format!("UID SEARCH {query}"));
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.uid_search(query),
             Inner::Tls(s) => s.uid_search(query),
-        }
+        };
+        trace_result(&result, |uids| format!("{} UID(s)", uids.len()));
+        result
     }
 
-    pub fn uid_fetch(
+    fn uid_fetch(
         &mut self,
         uid_set: &str,
         query: &str,
     ) -> imap::error::Result<imap::types::ZeroCopy<Vec<imap::types::Fetch>>> {
-        match &mut self.inner {
+        let _t = timings::start(Phase::Fetch);
+        trace_command(|| format!("UID FETCH {uid_set} {query}"));
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.uid_fetch(uid_set, query),
             Inner::Tls(s) => s.uid_fetch(uid_set, query),
-        }
+        };
+        trace_result(&result, |fetches| {
+            let bytes: usize = fetches
+                .iter()
+                .map(|f| {
+                    f.body().map_or(0, <[u8]>::len)
+                        + f.header().map_or(0, <[u8]>::len)
+                        + f.text().map_or(0, <[u8]>::len)
+                })
+                .sum();
+            format!("{} message(s), {bytes} bytes of content", fetches.len())
+        });
+        result
     }
 
-    pub fn uid_mv(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<()> {
-        match &mut self.inner {
-            Inner::Plain(s) => s.uid_mv(uid_set, dest),
-            Inner::Tls(s) => s.uid_mv(uid_set, dest),
-        }
+    fn append(
+        &mut self,
+        mailbox: &str,
+        flags: &[imap::types::Flag<'_>],
+        body: &[u8],
+        date: Option<chrono::DateTime<chrono::FixedOffset>>,
+    ) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Act);
+        trace_command(|| {
+            format!(
+                "APPEND {} ({} bytes)",
+                search::imap_quote(mailbox),
+                body.len()
+            )
+        });
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.append_with_flags_and_date(mailbox, body, flags, date),
+            Inner::Tls(s) => s.append_with_flags_and_date(mailbox, body, flags, date),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
     }
 
-    pub fn uid_copy(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<()> {
-        match &mut self.inner {
-            Inner::Plain(s) => {
-                s.uid_copy(uid_set, dest)?;
-                Ok(())
-            }
-            Inner::Tls(s) => {
-                s.uid_copy(uid_set, dest)?;
-                Ok(())
-            }
-        }
+    fn uid_mv(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<Vec<u8>> {
+        let _t = timings::start(Phase::Act);
+        let command = format!("UID MOVE {uid_set} {}", search::imap_quote(dest));
+        trace_command(|| command.clone());
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.run_command_and_read_response(&command),
+            Inner::Tls(s) => s.run_command_and_read_response(&command),
+        };
+        trace_result(&result, |_| "OK".to_string());
+        result
     }
 
-    pub fn uid_store(&mut self, uid_set: &str, query: &str) -> imap::error::Result<()> {
-        match &mut self.inner {
-            Inner::Plain(s) => {
-                s.uid_store(uid_set, query)?;
-                Ok(())
-            }
-            Inner::Tls(s) => {
-                s.uid_store(uid_set, query)?;
-                Ok(())
-            }
-        }
+    fn uid_copy(&mut self, uid_set: &str, dest: &str) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Act);
+        trace_command(|| format!("UID COPY {uid_set} {}", search::imap_quote(dest)));
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.uid_copy(uid_set, dest),
+            Inner::Tls(s) => s.uid_copy(uid_set, dest),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
     }
 
-    pub fn expunge(&mut self) -> imap::error::Result<()> {
-        match &mut self.inner {
-            Inner::Plain(s) => {
-                s.expunge()?;
-                Ok(())
-            }
-            Inner::Tls(s) => {
-                s.expunge()?;
-                Ok(())
-            }
-        }
+    fn uid_store(&mut self, uid_set: &str, query: &str) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Act);
+        trace_command(|| format!("UID STORE {uid_set} {query}"));
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.uid_store(uid_set, query).map(drop),
+            Inner::Tls(s) => s.uid_store(uid_set, query).map(drop),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
+    }
+
+    fn expunge(&mut self) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Act);
+        trace_command(|| "EXPUNGE".to_string());
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.expunge().map(drop),
+            Inner::Tls(s) => s.expunge().map(drop),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
     }
 
-    pub fn logout(&mut self) -> imap::error::Result<()> {
+    fn uid_expunge(&mut self, uid_set: &str) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Act);
+        trace_command(|| format!("UID EXPUNGE {uid_set}"));
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.uid_expunge(uid_set).map(drop),
+            Inner::Tls(s) => s.uid_expunge(uid_set).map(drop),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
+    }
+
+    fn logout(&mut self) -> imap::error::Result<()> {
+        let _t = timings::start(Phase::Logout);
+        trace_command(|| "LOGOUT".to_string());
         match &mut self.inner {
             Inner::Plain(s) => s.logout(),
             Inner::Tls(s) => s.logout(),
         }
     }
 
-    pub fn has_capability(&self, cap: &str) -> bool {
+    fn has_capability(&self, cap: &str) -> bool {
         self.capabilities.contains(&cap.to_uppercase())
     }
 
-    pub fn run_command_and_read_response(&mut self, command: &str) -> imap::error::Result<Vec<u8>> {
-        match &mut self.inner {
+    fn run_command_and_read_response(&mut self, command: &str) -> imap::error::Result<Vec<u8>> {
+        if let Some(c) = find_line_break(command) {
+            return Err(imap::error::Error::Validate(imap::error::ValidateError(c)));
+        }
+        trace_command(|| command.to_string());
+        let result = match &mut self.inner {
             Inner::Plain(s) => s.run_command_and_read_response(command),
             Inner::Tls(s) => s.run_command_and_read_response(command),
-        }
+        };
+        trace_result(&result, |data| format!("{} bytes", data.len()));
+        result
     }
+}
 
-    /// Move UIDs to dest, falling back to COPY+DELETE+EXPUNGE if MOVE is unsupported.
-    pub fn uid_move_or_fallback(&mut self, uid_set: &str, dest: &str) -> anyhow::Result<()> {
-        if self.has_capability("MOVE") {
-            self.uid_mv(uid_set, dest).context("UID MOVE failed")?;
-        } else {
-            self.uid_copy(uid_set, dest).context("UID COPY failed")?;
-            self.uid_store(uid_set, "+FLAGS (\\Deleted)")
-                .context("UID STORE +FLAGS failed")?;
-            self.expunge().context("EXPUNGE failed")?;
-        }
+impl ImapSession {
+    /// Send NOOP, which does nothing but needs a live, logged-in connection.
+    pub fn noop(&mut self) -> imap::error::Result<()> {
+        trace_command(|| "NOOP".to_string());
+        let result = match &mut self.inner {
+            Inner::Plain(s) => s.noop(),
+            Inner::Tls(s) => s.noop(),
+        };
+        trace_result(&result, |()| "OK".to_string());
+        result
+    }
+
+    /// Send IDLE and block until the server reports a change to the selected mailbox or
+    /// `wait` passes, then end it with DONE. The socket timeout is restored afterwards.
+    pub fn idle(
+        &mut self,
+        wait: Duration,
+    ) -> imap::error::Result<imap::extensions::idle::WaitOutcome> {
+        trace_command(|| format!("IDLE (up to {}s)", wait.as_secs()));
+        let outcome = match &mut self.inner {
+            Inner::Plain(s) => s.idle()?.wait_with_timeout(wait),
+            Inner::Tls(s) => s.idle()?.wait_with_timeout(wait),
+        };
+        trace_result(&outcome, |o| format!("{o:?}"));
+        self.set_timeout(self.timeout)
+            .map_err(imap::error::Error::Io)?;
+        outcome
+    }
+
+    /// A second handle on the underlying socket, e.g. for a Ctrl-C handler to shut
+    /// down reads and wake a thread blocked in `idle`.
+    pub fn socket_handle(&self) -> std::io::Result<TcpStream> {
+        self.socket.try_clone()
+    }
+
+    /// Set the read/write timeout on the underlying socket (`None` blocks indefinitely).
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        set_socket_timeout(&self.socket, timeout)
+    }
+
+    /// Capabilities the server advertised after login, uppercased and sorted.
+    pub fn capabilities(&self) -> Vec<&str> {
+        let mut caps: Vec<&str> = self.capabilities.iter().map(String::as_str).collect();
+        caps.sort_unstable();
+        caps
+    }
+
+    /// Re-read CAPABILITY into the cache, for when the server may have changed what it
+    /// offers (e.g. after enabling an extension). `connect` already reads it after login.
+    pub fn refresh_capabilities(&mut self) -> error::Result<()> {
+        self.capabilities = fetch_capabilities(&mut self.inner)?;
         Ok(())
     }
 }
 
+/// (source, destination) UID pairs from the `[COPYUID validity src dst]` response
+/// codes (RFC 4315) in `data`. A code whose two sets differ in length is ignored.
+pub(crate) fn copyuid_pairs(data: &[u8]) -> Vec<(u32, u32)> {
+    let text = String::from_utf8_lossy(data);
+    let mut pairs = Vec::new();
+    for (i, _) in text.match_indices("[COPYUID ") {
+        let code = &text[i + 1..];
+        let code = &code[..code.find(']').unwrap_or(code.len())];
+        let mut parts = code.split_whitespace().skip(2);
+        let (Some(src), Some(dst)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if let (Some(src), Some(dst)) = (expand_uid_set(src), expand_uid_set(dst)) {
+            if src.len() == dst.len() {
+                pairs.extend(src.into_iter().zip(dst));
+            }
+        }
+    }
+    pairs
+}
+
+/// Every UID in a set like `3,5:7`, in the order given.
+fn expand_uid_set(set: &str) -> Option<Vec<u32>> {
+    let mut uids = Vec::new();
+    for part in set.split(',') {
+        let (lo, hi) = part.split_once(':').unwrap_or((part, part));
+        let (lo, hi): (u32, u32) = (lo.parse().ok()?, hi.parse().ok()?);
+        uids.extend(lo.min(hi)..=lo.max(hi));
+    }
+    Some(uids)
+}
+
+fn find_line_break(command: &str) -> Option<char> {
+    command.chars().find(|c| *c == '\r' || *c == '\n')
+}
+
 fn is_loopback(host: &str) -> bool {
     host == "127.0.0.1" || host == "::1" || host == "localhost"
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn copyuid_pairs_reads_the_response_code() {
+        let data = b"* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n* 2 EXPUNGE\r\n";
+        assert_eq!(copyuid_pairs(data), [(142, 41), (399, 42)]);
+        assert!(copyuid_pairs(b"* 2 EXPUNGE\r\n").is_empty());
+        assert!(copyuid_pairs(b"* OK [COPYUID 1 1:3 7]\r\n").is_empty());
+    }
+
+    #[test]
+    fn find_line_break_clean_command() {
+        assert_eq!(find_line_break("STATUS \"INBOX\" (MESSAGES)"), None);
+    }
+
+    #[test]
+    fn find_line_break_rejects_crlf() {
+        assert_eq!(
+            find_line_break("STATUS \"INBOX\r\nA1 DELETE x\" (MESSAGES)"),
+            Some('\r')
+        );
+        assert_eq!(find_line_break("GETQUOTAROOT a\nb"), Some('\n'));
+    }
+
+    #[test]
+    fn greeting_offers_starttls_from_capability_code() {
+        assert_eq!(
+            greeting_offers_starttls(
+                b"* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] Dovecot ready.\r\n"
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            greeting_offers_starttls(b"* OK [capability imap4rev1 starttls] hi\r\n"),
+            Some(true)
+        );
+        assert_eq!(
+            greeting_offers_starttls(b"* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] no STARTTLS\r\n"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn greeting_offers_starttls_unknown_without_code() {
+        assert_eq!(greeting_offers_starttls(b"* OK IMAP4rev1 ready\r\n"), None);
+    }
+
+    #[test]
+    fn is_timeout_matches_io_and_imap_errors() {
+        let io = std::io::Error::new(std::io::ErrorKind::WouldBlock, "read");
+        let err = anyhow::Error::new(imap::error::Error::Io(io)).context("Failed to fetch");
+        assert!(is_timeout(&err));
+
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "connect");
+        assert!(is_timeout(
+            &anyhow::Error::new(io).context("Failed to connect")
+        ));
+
+        let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "no");
+        assert!(!is_timeout(&anyhow::Error::new(refused)));
+        assert!(!is_timeout(&anyhow::anyhow!("IMAP login failed")));
+    }
+
+    #[test]
+    fn connect_to_closed_port_is_a_connection_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let opts = ConnectOptions {
+            connect_timeout: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let err = connect("127.0.0.1", port, "u", "p", &opts).err().unwrap();
+        assert!(matches!(err, SlashmailError::Connection(_)), "{err:?}");
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to connect to 127.0.0.1:{port}")
+        );
+        assert!(err.io_error().is_some());
+    }
+
+    #[test]
+    fn connect_times_out_on_silent_server() {
+        // Accepts the TCP connection (backlog) but never sends a greeting
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let opts = ConnectOptions {
+            connect_timeout: Some(Duration::from_millis(200)),
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let err = anyhow::Error::from(connect("127.0.0.1", port, "u", "p", &opts).err().unwrap());
+        assert!(is_timeout(&err), "{err:#}");
+        drop(listener);
+    }
+
+    /// Self-signed test CA (CN=slashmail-test-ca).
+    const TEST_CA_PEM: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBjTCCATOgAwIBAgIUR/dfQ2odNEDYcJcfqnJ6ju8lhKEwCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRc2xhc2htYWlsLXRlc3QtY2EwHhcNMjYxMDE2MTAxMjI3WhcN
+MzYxMDEzMTAxMjI3WjAcMRowGAYDVQQDDBFzbGFzaG1haWwtdGVzdC1jYTBZMBMG
+ByqGSM49AgEGCCqGSM49AwEHA0IABNMj5nu2PIZXCRQho2jhFPKQ2YL3l49ChhcD
+wUNsJ8GfXtmiHmCB+MEa8PPBa4Tho2kJOZj46ktCeaJSe0m7D16jUzBRMB0GA1Ud
+DgQWBBQ7KRjO1/q9AgEyg5RJyQh8/DX1ZjAfBgNVHSMEGDAWgBQ7KRjO1/q9AgEy
+g5RJyQh8/DX1ZjAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIAOZ
+H6im44Pu/anu7xsQjDu71WTJlTPAMrXVRrenZlCMAiEAikmtAcgYiPoucJIBTPmD
+Y1ua3J8WHk6QyKfgw4hQFwQ=
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn pem_certificates_reads_bundle() {
+        assert_eq!(pem_certificates(TEST_CA_PEM.as_bytes()).unwrap().len(), 1);
+        let bundle = format!("# comment\n{TEST_CA_PEM}\n{TEST_CA_PEM}");
+        assert_eq!(pem_certificates(bundle.as_bytes()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn tls_connector_accepts_ca_file_and_reports_bad_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("ca.pem");
+        std::fs::write(&good, TEST_CA_PEM).unwrap();
+        assert!(tls_connector(Some(&good), false).is_ok());
+
+        let bad = dir.path().join("bad.pem");
+        std::fs::write(&bad, "garbage").unwrap();
+        let err = tls_connector(Some(&bad), false).err().unwrap();
+        assert!(
+            format!("{err:#}").contains("Failed to parse CA certificate"),
+            "{err:#}"
+        );
+        let err = tls_connector(Some(&dir.path().join("missing.pem")), false)
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Failed to read CA certificate"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn pem_certificates_rejects_non_pem() {
+        let err = pem_certificates(b"not a certificate").err().unwrap();
+        assert!(err.to_string().contains("no PEM certificate"), "{err}");
+        let err = pem_certificates(b"-----BEGIN CERTIFICATE-----\nMIIB\n")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("unterminated"), "{err}");
+        let garbage = b"-----BEGIN CERTIFICATE-----\nnot base64!\n-----END CERTIFICATE-----\n";
+        assert!(pem_certificates(garbage).is_err());
+    }
+
+    #[test]
+    fn ca_cert_requires_tls() {
+        let opts = ConnectOptions {
+            ca_cert: Some(PathBuf::from("ca.pem")),
+            ..Default::default()
+        };
+        let err = connect("127.0.0.1", 1, "u", "p", &opts).err().unwrap();
+        assert!(err.to_string().contains("--tls or --starttls"), "{err}");
+    }
+
+    #[test]
+    fn insecure_requires_tls() {
+        let opts = ConnectOptions {
+            insecure: true,
+            ..Default::default()
+        };
+        let err = connect("127.0.0.1", 1, "u", "p", &opts).err().unwrap();
+        assert!(err.to_string().contains("--insecure only applies"), "{err}");
+    }
+
     #[test]
     fn is_loopback_ipv4() {
         assert!(is_loopback("127.0.0.1"));
@@ -175,54 +675,246 @@ mod tests {
     }
 }
 
-pub fn connect(host: &str, port: u16, tls: bool, user: &str, pass: &str) -> Result<ImapSession> {
-    if !tls && !is_loopback(host) {
+/// Whether the greeting's `[CAPABILITY ...]` response code lists STARTTLS, or `None`
+/// when the greeting carries no capability list.
+fn greeting_offers_starttls(greeting: &[u8]) -> Option<bool> {
+    let text = String::from_utf8_lossy(greeting).to_ascii_uppercase();
+    let start = text.find("[CAPABILITY ")? + "[CAPABILITY ".len();
+    let end = start + text[start..].find(']')?;
+    Some(text[start..end].split_whitespace().any(|c| c == "STARTTLS"))
+}
+
+/// Split a PEM file into its certificates; a bundle may hold several.
+fn pem_certificates(pem: &[u8]) -> Result<Vec<native_tls::Certificate>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let text = String::from_utf8_lossy(pem);
+    let mut certs = Vec::new();
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+        let Some(end) = rest[start..].find(END).map(|i| start + i + END.len()) else {
+            anyhow::bail!("unterminated certificate block");
+        };
+        certs.push(native_tls::Certificate::from_pem(
+            &rest.as_bytes()[start..end],
+        )?);
+        rest = &rest[end..];
+    }
+    if certs.is_empty() {
+        anyhow::bail!("no PEM certificate found");
+    }
+    Ok(certs)
+}
+
+fn tls_connector(ca_cert: Option<&Path>, insecure: bool) -> Result<native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    builder
+        .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
+        .danger_accept_invalid_certs(insecure)
+        .danger_accept_invalid_hostnames(insecure);
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate '{}'", path.display()))?;
+        let certs = pem_certificates(&pem)
+            .with_context(|| format!("Failed to parse CA certificate '{}'", path.display()))?;
+        for cert in certs {
+            builder.add_root_certificate(cert);
+        }
+    }
+    builder.build().context("Failed to create TLS connector")
+}
+
+/// Whether `err` comes from a socket read/write that hit its timeout. imap's error type
+/// only exposes its cause through the deprecated `cause()`, so it is matched directly,
+/// and so is the error each `SlashmailError` variant wraps.
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    let timed_out = |e: &std::io::Error| {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )
+    };
+    err.chain().any(|cause| {
+        if let Some(imap::error::Error::Io(e)) = cause.downcast_ref::<imap::error::Error>() {
+            return timed_out(e);
+        }
+        if let Some(
+            SlashmailError::Connection(inner)
+            | SlashmailError::Protocol(inner)
+            | SlashmailError::Other(inner),
+        ) = cause.downcast_ref::<SlashmailError>()
+        {
+            return is_timeout(inner);
+        }
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(timed_out)
+    })
+}
+
+/// Connect and log in as described by `opts`.
+pub fn connect(
+    host: &str,
+    port: u16,
+    user: &str,
+    pass: &str,
+    opts: &ConnectOptions,
+) -> error::Result<ImapSession> {
+    let security = opts.security;
+    if security == Security::Plain && opts.ca_cert.is_some() {
+        return Err(anyhow::anyhow!("--ca-cert only applies with --tls or --starttls").into());
+    }
+    if security == Security::Plain && opts.insecure {
+        return Err(anyhow::anyhow!("--insecure only applies with --tls or --starttls").into());
+    }
+    if opts.insecure && !opts.quiet {
+        eprintln!("WARNING: --insecure: TLS certificate and hostname checks are DISABLED.");
+        eprintln!("         Anyone on the network path can impersonate {host} and read your");
+        eprintln!("         password. Only use this against a test server you control.");
+    }
+    if security == Security::Plain && !opts.quiet && !is_loopback(host) {
         eprintln!(
             "Warning: connecting to {} without TLS. Credentials will be sent in plaintext.",
             host
         );
-        eprintln!("         Use --tls for remote servers.");
-    }
-
-    let mut session = if tls {
-        let tls_connector = native_tls::TlsConnector::builder()
-            .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
-            .danger_accept_invalid_certs(false)
-            .danger_accept_invalid_hostnames(false)
-            .build()
-            .context("Failed to create TLS connector")?;
-        let client = imap::connect((host, port), host, &tls_connector)
-            .context(format!("Failed to TLS-connect to {host}:{port}"))?;
-        let s = client
-            .login(user, pass)
-            .map_err(|e| e.0)
-            .context("IMAP login failed")?;
-        Inner::Tls(s)
-    } else {
-        let tcp = TcpStream::connect(format!("{host}:{port}"))
-            .context(format!("Failed to connect to {host}:{port}"))?;
-        let client = imap::Client::new(tcp);
-        let s = client
-            .login(user, pass)
-            .map_err(|e| e.0)
-            .context("IMAP login failed")?;
-        Inner::Plain(s)
+        eprintln!("         Use --tls (or --starttls) for remote servers.");
+    }
+
+    let tcp = open_socket(host, port, opts.connect_timeout)?;
+    let socket = tcp.try_clone().context("Failed to clone socket handle")?;
+    // Only the command name: the arguments are the username and password
+    trace_command(|| format!("LOGIN (to {host}:{port}, arguments not shown)"));
+
+    let mut session = match security {
+        Security::Tls => {
+            let stream = tls_connector(opts.ca_cert.as_deref(), opts.insecure)?
+                .connect(host, tcp)
+                .context(format!("Failed to TLS-connect to {host}:{port}"))?;
+            let mut client = imap::Client::new(stream);
+            client
+                .read_greeting()
+                .context(format!("Failed to read greeting from {host}:{port}"))?;
+            let s = client
+                .login(user, pass)
+                .map_err(|e| error::login_error(e.0))?;
+            Inner::Tls(s)
+        }
+        Security::StartTls => {
+            let mut client = imap::Client::new(tcp);
+            let greeting = client
+                .read_greeting()
+                .context(format!("Failed to read greeting from {host}:{port}"))?;
+            let unsupported = || {
+                anyhow::anyhow!(
+                    "{host}:{port} does not offer STARTTLS; use --tls (usually port 993) instead"
+                )
+            };
+            if greeting_offers_starttls(&greeting) == Some(false) {
+                return Err(unsupported().into());
+            }
+            // No second greeting after the upgrade: the session continues where it was
+            let client = match client.secure(
+                host,
+                &tls_connector(opts.ca_cert.as_deref(), opts.insecure)?,
+            ) {
+                Ok(c) => c,
+                Err(imap::error::Error::No(_) | imap::error::Error::Bad(_)) => {
+                    return Err(unsupported().into())
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("STARTTLS with {host}:{port} failed"))
+                        .into())
+                }
+            };
+            let s = client
+                .login(user, pass)
+                .map_err(|e| error::login_error(e.0))?;
+            Inner::Tls(s)
+        }
+        Security::Plain => {
+            let mut client = imap::Client::new(tcp);
+            client
+                .read_greeting()
+                .context(format!("Failed to read greeting from {host}:{port}"))?;
+            let s = client
+                .login(user, pass)
+                .map_err(|e| error::login_error(e.0))?;
+            Inner::Plain(s)
+        }
     };
 
-    let caps = match &mut session {
+    let capabilities = fetch_capabilities(&mut session)?;
+
+    // The connect timeout only covers connect/greeting/login; later operations use `timeout`
+    set_socket_timeout(&socket, opts.timeout).context("Failed to set socket timeout")?;
+
+    Ok(ImapSession {
+        inner: session,
+        capabilities,
+        socket,
+        timeout: opts.timeout,
+    })
+}
+
+/// Ask the server for CAPABILITY, uppercased (`AUTH=` mechanisms included).
+fn fetch_capabilities(session: &mut Inner) -> Result<HashSet<String>> {
+    trace_command(|| "CAPABILITY".to_string());
+    let caps = match session {
         Inner::Plain(s) => s.capabilities(),
         Inner::Tls(s) => s.capabilities(),
     }
     .context("Failed to fetch capabilities")?;
-    let capabilities = ["SORT", "MOVE", "QUOTA"]
+    let capabilities: HashSet<String> = caps
         .iter()
-        .filter(|c| caps.has_str(**c))
-        .map(|c| c.to_string())
+        .map(|c| match c {
+            Capability::Imap4rev1 => "IMAP4REV1".to_string(),
+            Capability::Auth(mech) => format!("AUTH={}", mech.to_uppercase()),
+            Capability::Atom(atom) => atom.to_uppercase(),
+        })
         .collect();
     drop(caps);
 
-    Ok(ImapSession {
-        inner: session,
-        capabilities,
-    })
+    if verbose() {
+        eprintln!("S: {} capabilities", capabilities.len());
+    }
+    Ok(capabilities)
+}
+
+/// Open a TCP connection, bounding both the connect and the following reads/writes
+/// by `timeout` when given.
+fn open_socket(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    let tcp = match timeout {
+        None => TcpStream::connect((host, port))
+            .context(format!("Failed to connect to {host}:{port}"))?,
+        Some(t) => {
+            let addrs = (host, port)
+                .to_socket_addrs()
+                .context(format!("Failed to resolve {host}:{port}"))?;
+            let mut last_err = None;
+            let mut connected = None;
+            for addr in addrs {
+                match TcpStream::connect_timeout(&addr, t) {
+                    Ok(s) => {
+                        connected = Some(s);
+                        break;
+                    }
+                    Err(e) => last_err = Some(e),
+                }
+            }
+            match (connected, last_err) {
+                (Some(s), _) => s,
+                (None, Some(e)) => {
+                    return Err(e).context(format!("Failed to connect to {host}:{port}"))
+                }
+                (None, None) => anyhow::bail!("No addresses found for {host}:{port}"),
+            }
+        }
+    };
+    set_socket_timeout(&tcp, timeout).context("Failed to set socket timeout")?;
+    Ok(tcp)
+}
+
+fn set_socket_timeout(socket: &TcpStream, timeout: Option<Duration>) -> std::io::Result<()> {
+    socket.set_read_timeout(timeout)?;
+    socket.set_write_timeout(timeout)
 }
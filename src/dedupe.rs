@@ -0,0 +1,219 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+
+use crate::connection::ImapSession;
+use crate::delete::{self, ActOptions};
+use crate::display::{MessageRow, Ui};
+use crate::report::ActionReport;
+use crate::search::{self, SearchCriteria};
+
+/// Messages in one folder sharing a Message-ID. The lowest UID (the first copy the
+/// folder received) is kept; the rest are duplicates.
+pub struct DuplicateSet<'a> {
+    pub folder: String,
+    pub message_id: String,
+    pub keep: &'a MessageRow,
+    pub extras: Vec<&'a MessageRow>,
+}
+
+/// Message-ID without surrounding whitespace and angle brackets; `None` when empty.
+pub fn normalize_message_id(raw: &str) -> Option<String> {
+    let id = raw.trim();
+    let id = id
+        .strip_prefix('<')
+        .and_then(|s| s.strip_suffix('>'))
+        .unwrap_or(id)
+        .trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// Group `messages` by folder and normalized Message-ID, returning the groups with
+/// more than one message, ordered by folder and kept UID. Messages without a
+/// Message-ID are never treated as duplicates.
+pub fn duplicate_sets<'a>(
+    messages: &'a [MessageRow],
+    default_folder: &str,
+) -> Vec<DuplicateSet<'a>> {
+    let mut groups: BTreeMap<(String, String), Vec<&MessageRow>> = BTreeMap::new();
+    for m in messages {
+        let Some(id) = m.message_id.as_deref().and_then(normalize_message_id) else {
+            continue;
+        };
+        let folder = m.folder.as_deref().unwrap_or(default_folder).to_string();
+        groups.entry((folder, id)).or_default().push(m);
+    }
+
+    let mut sets: Vec<DuplicateSet> = groups
+        .into_iter()
+        .filter(|(_, rows)| rows.len() > 1)
+        .map(|((folder, message_id), mut rows)| {
+            rows.sort_by_key(|m| m.uid);
+            let keep = rows.remove(0);
+            DuplicateSet {
+                folder,
+                message_id,
+                keep,
+                extras: rows,
+            }
+        })
+        .collect();
+    sets.sort_by(|a, b| (&a.folder, a.keep.uid).cmp(&(&b.folder, b.keep.uid)));
+    sets
+}
+
+/// Find messages that share a Message-ID within a folder and move all but the first
+/// copy of each to `trash_folder`. Messages already in `trash_folder` are left alone.
+pub fn dedupe(
+    session: &mut ImapSession,
+    criteria: &SearchCriteria,
+    trash_folder: &str,
+    opts: &ActOptions,
+    ui: Ui,
+) -> Result<ActionReport> {
+    if !criteria.all_folders && criteria.folder == trash_folder {
+        bail!("'{trash_folder}' is the trash folder, where duplicates would be moved");
+    }
+
+    let sp = ui.spinner("Searching...");
+    let messages = search::search(session, criteria)?;
+    sp.finish_and_clear();
+    let sets: Vec<DuplicateSet> = duplicate_sets(&messages, &criteria.folder)
+        .into_iter()
+        .filter(|set| set.folder != trash_folder)
+        .collect();
+
+    let mut by_folder: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for set in &sets {
+        by_folder
+            .entry(set.folder.clone())
+            .or_default()
+            .extend(set.extras.iter().map(|m| m.uid));
+    }
+    let mut report = ActionReport::new("dedupe", &by_folder);
+
+    if sets.is_empty() {
+        ui.info("No duplicate messages found.");
+        return Ok(report);
+    }
+
+    for set in &sets {
+        let extras: Vec<String> = set.extras.iter().map(|m| m.uid.to_string()).collect();
+        println!(
+            "{}: <{}> \"{}\" keeps UID {}, duplicates: {}",
+            set.folder,
+            set.message_id,
+            set.keep.subject,
+            set.keep.uid,
+            extras.join(", ")
+        );
+    }
+    let total = report.matched;
+
+    if opts.dry_run {
+        report.dry_run = true;
+        println!(
+            "Dry run: {total} duplicate(s) in {} set(s) would be moved to {trash_folder}.",
+            sets.len()
+        );
+        return Ok(report);
+    }
+
+    search::ensure_folder_exists(session, trash_folder)?;
+
+    if !opts.yes {
+        let confirm =
+            inquire::Confirm::new(&format!("Move {total} duplicate(s) to {trash_folder}?"))
+                .with_default(false)
+                .prompt()
+                .context("Prompt failed")?;
+
+        if !confirm {
+            report.aborted = true;
+            println!("Aborted.");
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner(&format!("Moving to {trash_folder}..."));
+    for (folder, uids) in &by_folder {
+        let mut moved = 0usize;
+        let result = delete::move_folder(
+            session,
+            folder,
+            uids,
+            trash_folder,
+            false,
+            &mut moved,
+            &mut Vec::new(),
+        );
+        report.add_acted(folder, moved);
+        if let Err(e) = result {
+            sp.suspend(|| eprintln!("Warning: {e:#}"));
+            report.add_failed(folder, uids.len() - moved);
+        }
+    }
+    sp.finish_and_clear();
+
+    ui.info(format_args!(
+        "Moved {} duplicate(s) to {trash_folder}.",
+        report.acted
+    ));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(uid: u32, folder: Option<&str>, message_id: Option<&str>) -> MessageRow {
+        MessageRow {
+            uid,
+            folder: folder.map(str::to_string),
+            subject: format!("msg {uid}"),
+            message_id: message_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn normalize_message_id_strips_brackets_and_space() {
+        assert_eq!(
+            normalize_message_id("  <abc@example.com> ").as_deref(),
+            Some("abc@example.com")
+        );
+        assert_eq!(
+            normalize_message_id("abc@example.com").as_deref(),
+            Some("abc@example.com")
+        );
+        assert_eq!(normalize_message_id(" <> "), None);
+        assert_eq!(normalize_message_id(""), None);
+    }
+
+    #[test]
+    fn duplicate_sets_keep_lowest_uid() {
+        let messages = vec![
+            row(9, None, Some("<a@x>")),
+            row(3, None, Some("a@x")),
+            row(5, None, Some(" <a@x>")),
+            row(4, None, Some("<b@x>")),
+        ];
+        let sets = duplicate_sets(&messages, "INBOX");
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].folder, "INBOX");
+        assert_eq!(sets[0].message_id, "a@x");
+        assert_eq!(sets[0].keep.uid, 3);
+        let extras: Vec<u32> = sets[0].extras.iter().map(|m| m.uid).collect();
+        assert_eq!(extras, [5, 9]);
+    }
+
+    #[test]
+    fn duplicate_sets_skip_missing_ids_and_other_folders() {
+        let messages = vec![
+            row(1, Some("INBOX"), None),
+            row(2, Some("INBOX"), None),
+            row(3, Some("INBOX"), Some("<a@x>")),
+            row(3, Some("Archive"), Some("<a@x>")),
+        ];
+        assert!(duplicate_sets(&messages, "INBOX").is_empty());
+    }
+}
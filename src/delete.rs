@@ -1,52 +1,204 @@
-use anyhow::{Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use indicatif::ProgressBar;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 
-use crate::connection::ImapSession;
-use crate::display::display_messages;
+use crate::connection::ImapOps;
+use crate::display::{display_messages, format_size, MessageRow, Ui};
+use crate::error;
+use crate::report::ActionReport;
 use crate::search::{self, SearchCriteria};
+use crate::undo;
 
-fn spinner(msg: &str) -> ProgressBar {
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.cyan} {msg}")
-            .unwrap(),
+/// How a destructive action runs.
+#[derive(Debug, Default, Clone)]
+pub struct ActOptions {
+    /// Skip the confirmation prompt.
+    pub yes: bool,
+    /// Show what would happen without acting.
+    pub dry_run: bool,
+    /// With `dry_run`, also print the exact IMAP commands.
+    pub show_commands: bool,
+    /// Set \Seen on the source messages before moving them.
+    pub mark_read: bool,
+    /// `delete` only: expunge instead of moving to the trash folder.
+    pub expunge: bool,
+    /// Pick the messages to act on from a checklist instead of confirming them all.
+    pub interactive: bool,
+    /// Create the destination folder if it doesn't exist instead of failing.
+    pub create: bool,
+    /// `move` and trash `delete`: record the run here so `undo` can reverse it.
+    pub undo_log: Option<PathBuf>,
+}
+
+/// `--interactive`: show `messages` as a checklist and keep only the ticked ones.
+/// `by_folder` is narrowed to match and the messages left out count as skipped.
+pub fn pick_messages(
+    messages: &mut Vec<MessageRow>,
+    by_folder: &mut BTreeMap<String, Vec<u32>>,
+    default_folder: &str,
+    report: &mut ActionReport,
+) -> error::Result<()> {
+    let labels: Vec<String> = messages.iter().map(pick_label).collect();
+    let picked: HashSet<usize> = inquire::MultiSelect::new("Select messages to act on:", labels)
+        .with_page_size(15)
+        .raw_prompt()
+        .context("Prompt failed")?
+        .into_iter()
+        .map(|option| option.index)
+        .collect();
+    let mut index = 0;
+    messages.retain(|_| {
+        index += 1;
+        picked.contains(&(index - 1))
+    });
+    keep_picked(
+        by_folder,
+        search::group_by_folder(messages, default_folder),
+        report,
     );
-    pb.set_message(msg.to_string());
-    pb.enable_steady_tick(Duration::from_millis(80));
-    pb
+    Ok(())
+}
+
+/// `uid | from | subject`, prefixed with the folder when the search spanned several.
+fn pick_label(msg: &MessageRow) -> String {
+    let label = format!("{} | {} | {}", msg.uid, msg.from, msg.subject);
+    match &msg.folder {
+        Some(folder) => format!("{folder} | {label}"),
+        None => label,
+    }
+}
+
+/// Replace `by_folder` with `picked`, counting the UIDs that were left out as skipped.
+fn keep_picked(
+    by_folder: &mut BTreeMap<String, Vec<u32>>,
+    picked: BTreeMap<String, Vec<u32>>,
+    report: &mut ActionReport,
+) {
+    for (folder, uids) in by_folder.iter() {
+        let kept = picked.get(folder).map_or(0, Vec::len);
+        if uids.len() > kept {
+            report.add_skipped(folder, uids.len() - kept);
+        }
+    }
+    *by_folder = picked;
+}
+
+/// The exact IMAP commands `search_and_move` issues for `by_folder`, for `--show-commands`.
+/// Mirrors `uid_move_or_fallback`: UID MOVE when supported, else COPY + STORE + EXPUNGE.
+pub fn planned_move_commands(
+    by_folder: &BTreeMap<String, Vec<u32>>,
+    dest: &str,
+    has_move: bool,
+    mark_read: bool,
+) -> Vec<String> {
+    let dest = search::imap_quote(dest);
+    let mut commands = Vec::new();
+    for (folder, uids) in by_folder {
+        commands.push(format!("SELECT {}", search::imap_quote(folder)));
+        for chunk in search::build_uid_set(uids) {
+            if mark_read {
+                commands.push(format!("UID STORE {chunk} +FLAGS (\\Seen)"));
+            }
+            if has_move {
+                commands.push(format!("UID MOVE {chunk} {dest}"));
+            } else {
+                commands.push(format!("UID COPY {chunk} {dest}"));
+                commands.push(format!("UID STORE {chunk} +FLAGS (\\Deleted)"));
+                commands.push("EXPUNGE".to_string());
+            }
+        }
+    }
+    commands
+}
+
+/// The exact IMAP commands `search_and_expunge` issues for `by_folder`, for `--show-commands`.
+/// With UIDPLUS only the matched UIDs are expunged; otherwise a plain EXPUNGE is used.
+pub fn planned_expunge_commands(
+    by_folder: &BTreeMap<String, Vec<u32>>,
+    has_uidplus: bool,
+) -> Vec<String> {
+    let mut commands = Vec::new();
+    for (folder, uids) in by_folder {
+        commands.push(format!("SELECT {}", search::imap_quote(folder)));
+        for chunk in search::build_uid_set(uids) {
+            commands.push(format!("UID STORE {chunk} +FLAGS (\\Deleted)"));
+            if has_uidplus {
+                commands.push(format!("UID EXPUNGE {chunk}"));
+            }
+        }
+        if !has_uidplus {
+            commands.push("EXPUNGE".to_string());
+        }
+    }
+    commands
 }
 
 pub fn search_and_move(
-    session: &mut ImapSession,
+    session: &mut impl ImapOps,
     criteria: &SearchCriteria,
     dest: &str,
-    yes: bool,
-    dry_run: bool,
-) -> Result<()> {
-    let sp = spinner("Searching...");
-    let messages = search::search(session, criteria)?;
+    opts: &ActOptions,
+    ui: Ui,
+) -> error::Result<ActionReport> {
+    if !criteria.all_folders && criteria.folder == dest {
+        return Err(anyhow!("Source and destination are both '{dest}'; nothing to move").into());
+    }
+
+    let sp = ui.spinner("Searching...");
+    let mut messages = search::search(session, criteria)?;
     sp.finish_and_clear();
 
+    let mut by_folder = search::group_by_folder(&messages, &criteria.folder);
+    let mut report = ActionReport::new("move", &by_folder);
+
+    // With --all-folders --include-skipped the destination itself may be searched
+    if let Some(already) = by_folder.remove(dest) {
+        ui.info(format_args!(
+            "Skipping {} message(s) already in {dest}.",
+            already.len()
+        ));
+        report.add_skipped(dest, already.len());
+        messages.retain(|m| m.folder.as_deref() != Some(dest));
+    }
+
     if messages.is_empty() {
-        println!("No messages match the criteria.");
-        return Ok(());
+        ui.info("No messages match the criteria.");
+        return Ok(report);
     }
 
-    display_messages(&messages);
+    if opts.interactive {
+        pick_messages(&mut messages, &mut by_folder, &criteria.folder, &mut report)?;
+        if messages.is_empty() {
+            report.aborted = true;
+            ui.info("No messages selected.");
+            return Ok(report);
+        }
+    } else {
+        display_messages(&messages, ui);
+    }
 
-    if dry_run {
+    if opts.dry_run {
+        report.dry_run = true;
         println!(
             "Dry run: {} message(s) would be moved to {dest}.",
             messages.len()
         );
-        return Ok(());
+        if opts.show_commands {
+            println!("Commands:");
+            let has_move = session.has_capability("MOVE");
+            for cmd in planned_move_commands(&by_folder, dest, has_move, opts.mark_read) {
+                println!("  {cmd}");
+            }
+        }
+        return Ok(report);
     }
 
-    search::ensure_folder_exists(session, dest)?;
+    if !opts.create {
+        search::ensure_folder_exists(session, dest)?;
+    }
 
-    if !yes {
+    if !opts.yes && !opts.interactive {
         let confirm =
             inquire::Confirm::new(&format!("Move {} message(s) to {dest}?", messages.len()))
                 .with_default(false)
@@ -54,50 +206,530 @@ pub fn search_and_move(
                 .context("Prompt failed")?;
 
         if !confirm {
+            report.aborted = true;
             println!("Aborted.");
-            return Ok(());
+            return Ok(report);
         }
     }
 
-    let sp = spinner(&format!("Moving to {dest}..."));
-
-    // Group by folder for multi-folder moves
-    let mut by_folder: std::collections::HashMap<String, Vec<u32>> =
-        std::collections::HashMap::new();
-    for msg in &messages {
-        let folder = msg
-            .folder
-            .clone()
-            .unwrap_or_else(|| criteria.folder.clone());
-        by_folder.entry(folder).or_default().push(msg.uid);
+    if opts.create && search::ensure_or_create_folder(session, dest)? {
+        ui.info(format_args!("Created folder {dest}."));
     }
 
-    let mut total = 0usize;
-    for (folder, uids) in &by_folder {
-        session
-            .select(folder)
-            .with_context(|| format!("Failed to select '{folder}'"))?;
+    let sp = ui.spinner(&format!("Moving to {dest}..."));
 
-        for chunk in &search::build_uid_set(uids) {
-            session
-                .uid_move_or_fallback(chunk, dest)
-                .with_context(|| format!("Failed to move messages from '{folder}' to {dest}"))?;
+    let mut touched = HashSet::new();
+    let mut new_uids = HashMap::new();
+    for (folder, uids) in &by_folder {
+        let mut moved = 0usize;
+        let mut pairs = Vec::new();
+        let result = move_folder(
+            session,
+            folder,
+            uids,
+            dest,
+            opts.mark_read,
+            &mut moved,
+            &mut pairs,
+        );
+        report.add_acted(folder, moved);
+        if moved > 0 {
+            touched.insert(folder.as_str());
+        }
+        new_uids.extend(
+            pairs
+                .into_iter()
+                .map(|(old, new)| ((folder.as_str(), old), new)),
+        );
+        if let Err(e) = result {
+            sp.suspend(|| eprintln!("Warning: {e:#}"));
+            report.add_failed(folder, uids.len() - moved);
         }
-
-        total += uids.len();
     }
 
     sp.finish_and_clear();
-    println!("Moved {total} message(s) to {dest}.");
+    // A partly failed folder is logged whole; undo only finds what actually arrived
+    messages.retain(|m| touched.contains(m.folder.as_deref().unwrap_or(&criteria.folder)));
+    if let Some(log) = &opts.undo_log {
+        undo::record(log, dest, &messages, &criteria.folder, &new_uids);
+    }
+    ui.info(format_args!("Moved {} message(s) to {dest}.", report.acted));
+    Ok(report)
+}
+
+/// Move one folder's UIDs to `dest`, counting completed chunks in `moved` so a
+/// failure part-way through still reports what was done. (old, new) UID pairs the
+/// server reports with COPYUID are added to `new_uids`.
+pub(crate) fn move_folder(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    dest: &str,
+    mark_read: bool,
+    moved: &mut usize,
+    new_uids: &mut Vec<(u32, u32)>,
+) -> Result<()> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    for chunk in &search::build_uid_set(uids) {
+        if mark_read {
+            session
+                .uid_store(chunk, "+FLAGS (\\Seen)")
+                .with_context(|| format!("Failed to mark messages read in '{folder}'"))?;
+        }
+        let pairs = session
+            .uid_move_or_fallback(chunk, dest)
+            .with_context(|| format!("Failed to move messages from '{folder}' to {dest}"))?;
+        new_uids.extend(pairs);
+        *moved += search::uid_set_len(chunk);
+    }
     Ok(())
 }
 
 pub fn delete(
-    session: &mut ImapSession,
+    session: &mut impl ImapOps,
     criteria: &SearchCriteria,
     trash_folder: &str,
-    yes: bool,
-    dry_run: bool,
+    opts: &ActOptions,
+    ui: Ui,
+) -> error::Result<ActionReport> {
+    if opts.expunge {
+        return search_and_expunge(session, criteria, opts, ui);
+    }
+    if !criteria.all_folders && criteria.folder == trash_folder {
+        return Err(anyhow!(
+            "Messages in '{trash_folder}' are already in the trash folder; use --expunge to delete them permanently"
+        ).into());
+    }
+    let mut report = search_and_move(session, criteria, trash_folder, opts, ui)?;
+    report.command = "delete".to_string();
+    Ok(report)
+}
+
+/// Permanently delete matching messages: flag them \Deleted and expunge.
+pub fn search_and_expunge(
+    session: &mut impl ImapOps,
+    criteria: &SearchCriteria,
+    opts: &ActOptions,
+    ui: Ui,
+) -> error::Result<ActionReport> {
+    let sp = ui.spinner("Searching...");
+    let mut messages = search::search(session, criteria)?;
+    sp.finish_and_clear();
+
+    let mut by_folder = search::group_by_folder(&messages, &criteria.folder);
+    let mut report = ActionReport::new("delete", &by_folder);
+
+    if messages.is_empty() {
+        ui.info("No messages match the criteria.");
+        return Ok(report);
+    }
+
+    if opts.interactive {
+        pick_messages(&mut messages, &mut by_folder, &criteria.folder, &mut report)?;
+        if messages.is_empty() {
+            report.aborted = true;
+            ui.info("No messages selected.");
+            return Ok(report);
+        }
+    } else {
+        display_messages(&messages, ui);
+    }
+
+    let has_uidplus = session.has_capability("UIDPLUS");
+
+    let total_size = format_size(messages.iter().map(|m| m.size as u64).sum());
+
+    if opts.dry_run {
+        report.dry_run = true;
+        println!(
+            "Dry run: {} message(s) ({total_size}) would be permanently deleted.",
+            messages.len()
+        );
+        if opts.show_commands {
+            println!("Commands:");
+            for cmd in planned_expunge_commands(&by_folder, has_uidplus) {
+                println!("  {cmd}");
+            }
+        }
+        return Ok(report);
+    }
+
+    // Picking is confirmation enough for a move, but not for a permanent delete
+    if !opts.yes {
+        let confirm = inquire::Confirm::new(&format!(
+            "Permanently delete {} message(s) ({total_size})? This cannot be undone.",
+            messages.len()
+        ))
+        .with_default(false)
+        .prompt()
+        .context("Prompt failed")?;
+
+        if !confirm {
+            report.aborted = true;
+            println!("Aborted.");
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner("Expunging...");
+    expunge_uids(session, &by_folder, has_uidplus, &mut report, &sp);
+    sp.finish_and_clear();
+    let reclaimed = reclaimed_bytes(&messages, &criteria.folder, &report);
+    ui.info(format_args!(
+        "Permanently deleted {} message(s), reclaiming {}.",
+        report.acted,
+        format_size(reclaimed)
+    ));
+    Ok(report)
+}
+
+/// Permanently delete messages in `trash_folder`. `older_than` / `newer_than` take the
+/// same values as `--before` / `--since` (e.g. `30d`, `2025-01-01`); with neither, the
+/// whole folder is emptied.
+pub fn purge(
+    session: &mut impl ImapOps,
+    trash_folder: &str,
+    older_than: Option<&str>,
+    newer_than: Option<&str>,
+    opts: &ActOptions,
+    ui: Ui,
+) -> error::Result<ActionReport> {
+    let criteria = SearchCriteria {
+        folder: trash_folder.to_string(),
+        before: older_than.map(str::to_string),
+        since: newer_than.map(str::to_string),
+        ..Default::default()
+    };
+    let mut report = search_and_expunge(session, &criteria, opts, ui)?;
+    report.command = "purge".to_string();
+    Ok(report)
+}
+
+/// Size of `messages` in folders where nothing failed; a partly failed folder is left
+/// out since it is unknown which of its messages are gone.
+fn reclaimed_bytes(messages: &[MessageRow], default_folder: &str, report: &ActionReport) -> u64 {
+    messages
+        .iter()
+        .filter(|m| {
+            let folder = m.folder.as_deref().unwrap_or(default_folder);
+            report
+                .folders
+                .iter()
+                .any(|f| f.folder == folder && f.failed == 0)
+        })
+        .map(|m| m.size as u64)
+        .sum()
+}
+
+/// Flag `by_folder` UIDs \Deleted and expunge them, recording the outcome per folder
+/// in `report`. Without UIDPLUS a plain EXPUNGE also removes any other messages
+/// already flagged \Deleted in the folder. Warnings print above the spinner `sp`.
+fn expunge_uids(
+    session: &mut impl ImapOps,
+    by_folder: &BTreeMap<String, Vec<u32>>,
+    has_uidplus: bool,
+    report: &mut ActionReport,
+    sp: &ProgressBar,
+) {
+    for (folder, uids) in by_folder {
+        let mut expunged = 0usize;
+        let result = expunge_folder(session, folder, uids, has_uidplus, &mut expunged);
+        report.add_acted(folder, expunged);
+        if let Err(e) = result {
+            sp.suspend(|| eprintln!("Warning: {e:#}"));
+            report.add_failed(folder, uids.len() - expunged);
+        }
+    }
+}
+
+fn expunge_folder(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    has_uidplus: bool,
+    expunged: &mut usize,
 ) -> Result<()> {
-    search_and_move(session, criteria, trash_folder, yes, dry_run)
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    for chunk in &search::build_uid_set(uids) {
+        session
+            .uid_store(chunk, "+FLAGS (\\Deleted)")
+            .with_context(|| format!("Failed to flag messages deleted in '{folder}'"))?;
+        if has_uidplus {
+            session
+                .uid_expunge(chunk)
+                .with_context(|| format!("UID EXPUNGE failed in '{folder}'"))?;
+            *expunged += search::uid_set_len(chunk);
+        }
+    }
+    if !has_uidplus {
+        session
+            .expunge()
+            .with_context(|| format!("EXPUNGE failed in '{folder}'"))?;
+        *expunged = uids.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeImap};
+
+    fn one_folder(folder: &str, uids: &[u32]) -> BTreeMap<String, Vec<u32>> {
+        BTreeMap::from([(folder.to_string(), uids.to_vec())])
+    }
+
+    fn row(uid: u32, folder: Option<&str>, size: u32) -> MessageRow {
+        MessageRow {
+            uid,
+            folder: folder.map(str::to_string),
+            size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pick_label_shows_folder_only_when_set() {
+        let mut msg = row(7, None, 0);
+        msg.from = "alice@example.com".into();
+        msg.subject = "Hello".into();
+        assert_eq!(pick_label(&msg), "7 | alice@example.com | Hello");
+        msg.folder = Some("Archive".into());
+        assert_eq!(pick_label(&msg), "Archive | 7 | alice@example.com | Hello");
+    }
+
+    #[test]
+    fn keep_picked_counts_left_out_uids_as_skipped() {
+        let mut by_folder = BTreeMap::from([
+            ("INBOX".to_string(), vec![1, 2, 3]),
+            ("Lists".to_string(), vec![9]),
+        ]);
+        let mut report = ActionReport::new("move", &by_folder);
+        keep_picked(&mut by_folder, one_folder("INBOX", &[2]), &mut report);
+        assert_eq!(by_folder, one_folder("INBOX", &[2]));
+        assert_eq!(report.matched, 4);
+        assert_eq!(report.skipped, 3);
+        assert_eq!(report.folders[0].skipped, 2);
+        assert_eq!(report.folders[1].skipped, 1);
+    }
+
+    #[test]
+    fn reclaimed_bytes_skips_failed_folders() {
+        let messages = [
+            row(1, Some("Trash"), 100),
+            row(2, Some("Trash"), 50),
+            row(3, Some("Junk"), 1000),
+        ];
+        let by_folder = BTreeMap::from([
+            ("Junk".to_string(), vec![3]),
+            ("Trash".to_string(), vec![1, 2]),
+        ]);
+        let mut report = ActionReport::new("purge", &by_folder);
+        report.add_acted("Trash", 2);
+        report.add_failed("Junk", 1);
+        assert_eq!(reclaimed_bytes(&messages, "INBOX", &report), 150);
+    }
+
+    #[test]
+    fn reclaimed_bytes_uses_default_folder() {
+        let messages = [row(1, None, 10), row(2, None, 20)];
+        let mut report = ActionReport::new("purge", &one_folder("Trash", &[1, 2]));
+        report.add_acted("Trash", 2);
+        assert_eq!(reclaimed_bytes(&messages, "Trash", &report), 30);
+    }
+
+    #[test]
+    fn planned_move_commands_with_move() {
+        let commands =
+            planned_move_commands(&one_folder("INBOX", &[1, 2, 3, 7]), "Trash", true, false);
+        assert_eq!(
+            commands,
+            vec!["SELECT \"INBOX\"", "UID MOVE 1:3,7 \"Trash\""]
+        );
+    }
+
+    #[test]
+    fn planned_move_commands_fallback() {
+        let commands = planned_move_commands(&one_folder("INBOX", &[5]), "Archive", false, false);
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT \"INBOX\"",
+                "UID COPY 5 \"Archive\"",
+                "UID STORE 5 +FLAGS (\\Deleted)",
+                "EXPUNGE",
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_move_commands_mark_read_first() {
+        let commands = planned_move_commands(&one_folder("INBOX", &[4]), "Trash", true, true);
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT \"INBOX\"",
+                "UID STORE 4 +FLAGS (\\Seen)",
+                "UID MOVE 4 \"Trash\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_expunge_commands_with_uidplus() {
+        let commands = planned_expunge_commands(&one_folder("Trash", &[2, 3]), true);
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT \"Trash\"",
+                "UID STORE 2:3 +FLAGS (\\Deleted)",
+                "UID EXPUNGE 2:3",
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_expunge_commands_without_uidplus() {
+        let commands = planned_expunge_commands(&one_folder("Trash", &[2, 3]), false);
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT \"Trash\"",
+                "UID STORE 2:3 +FLAGS (\\Deleted)",
+                "EXPUNGE",
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_move_commands_selects_each_folder() {
+        let mut by_folder = one_folder("INBOX", &[1]);
+        by_folder.insert("Archive".to_string(), vec![9]);
+        let commands = planned_move_commands(&by_folder, "Trash", true, false);
+        assert_eq!(
+            commands,
+            vec![
+                "SELECT \"Archive\"",
+                "UID MOVE 9 \"Trash\"",
+                "SELECT \"INBOX\"",
+                "UID MOVE 1 \"Trash\"",
+            ]
+        );
+    }
+
+    fn mailboxes(capabilities: &[&str]) -> FakeImap {
+        let msg = |uid| fake::message(uid, "Mon, 01 Jan 2024 10:00:00 +0000", "news@x", "News");
+        FakeImap::new(capabilities)
+            .folder("INBOX", vec![msg(1), msg(2), msg(5)])
+            .folder("Work", vec![msg(3), fake::message(4, "", "boss@x", "Plan")])
+            .folder("Archive", Vec::new())
+    }
+
+    /// Search all folders for `news`, then move what matched the way `search_and_move`
+    /// does, returning the commands the move sent.
+    fn move_news(
+        session: &mut FakeImap,
+        mark_read: bool,
+    ) -> (BTreeMap<String, Vec<u32>>, Vec<String>) {
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            from: vec!["news".into()],
+            all_folders: true,
+            ..Default::default()
+        };
+        let rows = search::search(session, &criteria).unwrap();
+        let by_folder = search::group_by_folder(&rows, &criteria.folder);
+        session.log.clear();
+        for (folder, uids) in &by_folder {
+            let mut moved = 0;
+            let mut new_uids = Vec::new();
+            move_folder(
+                session,
+                folder,
+                uids,
+                "Archive",
+                mark_read,
+                &mut moved,
+                &mut new_uids,
+            )
+            .unwrap();
+            assert_eq!(moved, uids.len());
+        }
+        (by_folder, std::mem::take(&mut session.log))
+    }
+
+    #[test]
+    fn move_groups_matches_by_folder() {
+        let mut session = mailboxes(&["MOVE"]);
+        let (by_folder, sent) = move_news(&mut session, false);
+        assert_eq!(
+            sent,
+            [
+                "SELECT \"INBOX\"",
+                "UID MOVE 1:2,5 \"Archive\"",
+                "SELECT \"Work\"",
+                "UID MOVE 3 \"Archive\"",
+            ]
+        );
+        assert_eq!(
+            sent,
+            planned_move_commands(&by_folder, "Archive", true, false)
+        );
+        assert!(session.uids("INBOX").is_empty());
+        assert_eq!(session.uids("Work"), [4]);
+        assert_eq!(session.uids("Archive").len(), 4);
+    }
+
+    #[test]
+    fn move_folder_collects_copyuid_pairs() {
+        let mut session = mailboxes(&["MOVE", "UIDPLUS"]);
+        let (mut moved, mut new_uids) = (0, Vec::new());
+        move_folder(
+            &mut session,
+            "INBOX",
+            &[2, 5],
+            "Archive",
+            false,
+            &mut moved,
+            &mut new_uids,
+        )
+        .unwrap();
+        assert_eq!(new_uids, [(2, 1), (5, 2)]);
+
+        // Without UIDPLUS the server names no new UIDs
+        let mut session = mailboxes(&["MOVE"]);
+        new_uids.clear();
+        move_folder(
+            &mut session,
+            "INBOX",
+            &[2],
+            "Archive",
+            false,
+            &mut moved,
+            &mut new_uids,
+        )
+        .unwrap();
+        assert!(new_uids.is_empty());
+    }
+
+    #[test]
+    fn move_without_move_capability_matches_plan() {
+        let mut session = mailboxes(&[]);
+        let (by_folder, sent) = move_news(&mut session, true);
+        assert_eq!(
+            sent,
+            planned_move_commands(&by_folder, "Archive", false, true)
+        );
+        assert_eq!(session.uids("Work"), [4]);
+        let archived = &session.folders["Archive"];
+        assert_eq!(archived.len(), 4);
+        // Copied with \Seen set; \Deleted was only set on the originals
+        assert!(archived.iter().all(|m| m.flags == ["\\Seen"]));
+    }
 }
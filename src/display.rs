@@ -1,6 +1,20 @@
-use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, ContentArrangement, Table};
+use comfy_table::{
+    presets::UTF8_FULL_CONDENSED, Attribute, Cell, Color, ContentArrangement, Table,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+
+use crate::thread::ThreadNode;
 
 #[derive(serde::Serialize)]
+#[cfg_attr(test, derive(Default))]
 pub struct MessageRow {
     pub uid: u32,
     pub folder: Option<String>,
@@ -9,6 +23,250 @@ pub struct MessageRow {
     pub date: String,
     pub timestamp: i64,
     pub size: u32,
+    /// Raw Message-ID header, if the message has one; not part of the JSON output.
+    #[serde(skip)]
+    pub message_id: Option<String>,
+    /// Lowercased address from the untruncated From header (or the header itself if it
+    /// doesn't parse); not part of the JSON output.
+    #[serde(skip)]
+    pub from_address: String,
+    /// IMAP flags as sent by the server (`\Seen`, `\Flagged`, keywords), without `\Recent`.
+    pub flags: Vec<String>,
+    /// `mailto:` / `http(s):` targets from the List-Unsubscribe header; empty for mail
+    /// that isn't from a mailing list.
+    pub unsubscribe: Vec<String>,
+    /// The sender supports one-click unsubscribe (RFC 8058 List-Unsubscribe-Post).
+    pub unsubscribe_one_click: bool,
+}
+
+impl MessageRow {
+    pub fn is_unseen(&self) -> bool {
+        !self.flags.iter().any(|f| f == "\\Seen")
+    }
+
+    /// Compact state for the table: `N` unread, `R` replied, `!` flagged, `D` marked
+    /// for deletion, `L` from a mailing list; empty for a read message with none of those.
+    pub fn state(&self) -> String {
+        let has = |flag: &str| self.flags.iter().any(|f| f == flag);
+        let mut state = String::new();
+        for (on, c) in [
+            (self.is_unseen(), 'N'),
+            (has("\\Answered"), 'R'),
+            (has("\\Flagged"), '!'),
+            (has("\\Deleted"), 'D'),
+            (!self.unsubscribe.is_empty(), 'L'),
+        ] {
+            if on {
+                state.push(c);
+            }
+        }
+        state
+    }
+}
+
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Turn ANSI color in table cells and spinners on or off for the rest of the run.
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether to color output: off with `--no-color` or a non-empty `NO_COLOR`
+/// (see no-color.org).
+pub fn use_color(no_color_flag: bool, no_color_env: Option<&OsStr>) -> bool {
+    !no_color_flag && no_color_env.is_none_or(|v| v.is_empty())
+}
+
+/// The zone message dates are shown in, from `--tz`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DateZone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+static DATE_ZONE: Mutex<DateZone> = Mutex::new(DateZone::Local);
+static RELATIVE_DATES: AtomicBool = AtomicBool::new(false);
+
+/// Show message dates in `zone` for the rest of the run.
+pub fn set_date_zone(zone: DateZone) {
+    *DATE_ZONE.lock().unwrap() = zone;
+}
+
+/// Show message dates relative to now ("3 hours ago") for the rest of the run.
+pub fn set_relative_dates(enabled: bool) {
+    RELATIVE_DATES.store(enabled, Ordering::Relaxed);
+}
+
+/// `seconds` (now minus the message time) as "2 days ago", or "in 5 minutes" for dates
+/// in the future. Months are 30 days, and anything older shows in months.
+pub fn time_ago(seconds: i64) -> String {
+    const UNITS: [(u64, &str); 5] = [
+        (30 * 86400, "month"),
+        (7 * 86400, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+    let abs = seconds.unsigned_abs();
+    let (count, unit) = UNITS
+        .iter()
+        .find(|(size, _)| abs >= *size)
+        .map_or((abs, "second"), |&(size, unit)| (abs / size, unit));
+    let plural = if count == 1 { "" } else { "s" };
+    if seconds < 0 {
+        format!("in {count} {unit}{plural}")
+    } else {
+        format!("{count} {unit}{plural} ago")
+    }
+}
+
+/// Parse `--tz`: `local`, `utc` (or `Z`), or a fixed offset such as `+02:00`, `-0500`
+/// or `+1`.
+pub fn parse_date_zone(s: &str) -> Result<DateZone> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("local") {
+        return Ok(DateZone::Local);
+    }
+    if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+        return Ok(DateZone::Utc);
+    }
+    let invalid =
+        || anyhow::anyhow!("Invalid time zone '{s}': use local, utc or an offset like +02:00");
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let digits = |v: &str| !v.is_empty() && v.len() <= 2 && v.bytes().all(|b| b.is_ascii_digit());
+    if !digits(hours) || !digits(minutes) {
+        return Err(invalid());
+    }
+    let (hours, minutes): (i32, i32) = (hours.parse()?, minutes.parse()?);
+    if hours > 14 || minutes > 59 {
+        bail!("Time zone offset '{s}' is out of range");
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+        .map(DateZone::Fixed)
+        .ok_or_else(invalid)
+}
+
+/// `timestamp` (seconds since the epoch) as `YYYY-MM-DD HH:MM` in `zone`.
+pub fn format_timestamp(timestamp: i64, zone: DateZone) -> Option<String> {
+    let utc = DateTime::<Utc>::from_timestamp(timestamp, 0)?;
+    const FORMAT: &str = "%Y-%m-%d %H:%M";
+    Some(match zone {
+        DateZone::Local => utc.with_timezone(&Local).format(FORMAT).to_string(),
+        DateZone::Utc => utc.format(FORMAT).to_string(),
+        DateZone::Fixed(offset) => utc.with_timezone(&offset).format(FORMAT).to_string(),
+    })
+}
+
+/// The date of `msg` as shown in tables: its timestamp in the `--tz` zone (or relative
+/// to now with `--relative-dates`), or the Date header as sent when it couldn't be parsed.
+fn shown_date(msg: &MessageRow) -> String {
+    if msg.timestamp == 0 {
+        return msg.date.clone();
+    }
+    if RELATIVE_DATES.load(Ordering::Relaxed) {
+        return time_ago(Utc::now().timestamp().saturating_sub(msg.timestamp));
+    }
+    let zone = *DATE_ZONE.lock().unwrap();
+    format_timestamp(msg.timestamp, zone).unwrap_or_else(|| msg.date.clone())
+}
+
+/// `cell` in `color`, or left plain when color is off.
+pub fn colored(cell: Cell, color: Color) -> Cell {
+    if COLOR.load(Ordering::Relaxed) {
+        cell.fg(color)
+    } else {
+        cell
+    }
+}
+
+/// indicatif template shared by all spinners.
+fn spinner_template() -> &'static str {
+    if COLOR.load(Ordering::Relaxed) {
+        "{spinner:.cyan} {msg}"
+    } else {
+        "{spinner} {msg}"
+    }
+}
+
+/// indicatif template for a spinner that has turned into a bar with `show_count`.
+fn bar_template() -> &'static str {
+    if COLOR.load(Ordering::Relaxed) {
+        "{spinner:.cyan} {msg} [{bar:30.cyan/blue}] {pos}/{len}"
+    } else {
+        "{spinner} {msg} [{bar:30}] {pos}/{len}"
+    }
+}
+
+/// Show `done` of `total` on a spinner from `Ui::spinner`, turning it into a bar the
+/// first time the count is known.
+pub fn show_count(pb: &ProgressBar, done: usize, total: usize) {
+    if pb.length() != Some(total as u64) {
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(bar_template())
+                .unwrap(),
+        );
+        pb.set_length(total as u64);
+    }
+    pb.set_position(done as u64);
+}
+
+/// Turn a bar from `show_count` back into a spinner, for a step of unknown length.
+pub fn show_spinner(pb: &ProgressBar) {
+    if pb.length().is_some() {
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template(spinner_template())
+                .unwrap(),
+        );
+        pb.unset_length();
+    }
+}
+
+/// Human-oriented output shared by the command handlers: spinners and the
+/// informational lines around results.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ui {
+    /// `--quiet`: no spinners or chatter, only results, prompts and errors.
+    pub quiet: bool,
+    /// `--verbose`: the IMAP log goes to stderr, so spinners are hidden to keep it readable.
+    pub verbose: bool,
+}
+
+impl Ui {
+    /// A spinner on stderr, or a hidden progress bar with `--quiet` or `--verbose`.
+    pub fn spinner(self, msg: &str) -> ProgressBar {
+        if self.quiet || self.verbose {
+            return ProgressBar::hidden();
+        }
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template(spinner_template())
+                .unwrap(),
+        );
+        pb.set_message(msg.to_string());
+        pb.enable_steady_tick(Duration::from_millis(80));
+        pb
+    }
+
+    /// Print an informational line (summaries, "No messages found.") unless `--quiet`.
+    pub fn info(self, msg: impl std::fmt::Display) {
+        if !self.quiet {
+            println!("{msg}");
+        }
+    }
 }
 
 pub fn format_size(bytes: u64) -> String {
@@ -25,6 +283,206 @@ pub fn format_size(bytes: u64) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn quiet_ui_hides_spinner() {
+        let quiet = Ui {
+            quiet: true,
+            ..Default::default()
+        };
+        assert!(quiet.spinner("Searching...").is_hidden());
+        let verbose = Ui {
+            verbose: true,
+            ..Default::default()
+        };
+        assert!(verbose.spinner("Searching...").is_hidden());
+    }
+
+    #[test]
+    fn parse_date_zone_names_and_offsets() {
+        assert_eq!(parse_date_zone("local").unwrap(), DateZone::Local);
+        assert_eq!(parse_date_zone("UTC").unwrap(), DateZone::Utc);
+        assert_eq!(parse_date_zone("Z").unwrap(), DateZone::Utc);
+        let east = |secs| DateZone::Fixed(FixedOffset::east_opt(secs).unwrap());
+        assert_eq!(parse_date_zone("+02:00").unwrap(), east(7200));
+        assert_eq!(parse_date_zone("-0530").unwrap(), east(-19800));
+        assert_eq!(parse_date_zone("+1").unwrap(), east(3600));
+        for bad in ["", "Europe/Paris", "+2:xx", "+123", "+15:00", "02:00"] {
+            assert!(parse_date_zone(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn format_timestamp_in_zone() {
+        // 2025-03-09 12:00:00 UTC
+        let ts = 1_741_521_600;
+        assert_eq!(
+            format_timestamp(ts, DateZone::Utc).as_deref(),
+            Some("2025-03-09 12:00")
+        );
+        let zone = parse_date_zone("-05:30").unwrap();
+        assert_eq!(
+            format_timestamp(ts, zone).as_deref(),
+            Some("2025-03-09 06:30")
+        );
+        assert!(format_timestamp(i64::MAX, DateZone::Utc).is_none());
+    }
+
+    #[test]
+    fn time_ago_picks_the_largest_unit() {
+        assert_eq!(time_ago(0), "0 seconds ago");
+        assert_eq!(time_ago(1), "1 second ago");
+        assert_eq!(time_ago(59), "59 seconds ago");
+        assert_eq!(time_ago(60), "1 minute ago");
+        assert_eq!(time_ago(3 * 3600 + 59), "3 hours ago");
+        assert_eq!(time_ago(2 * 86400), "2 days ago");
+        assert_eq!(time_ago(13 * 86400), "1 week ago");
+        assert_eq!(time_ago(400 * 86400), "13 months ago");
+        assert_eq!(time_ago(-300), "in 5 minutes");
+    }
+
+    #[test]
+    fn time_ago_handles_extreme_offsets() {
+        assert_eq!(time_ago(i64::MAX), "3558399705576 months ago");
+        assert_eq!(time_ago(i64::MIN), "in 3558399705576 months");
+    }
+
+    #[test]
+    fn to_json_bare_array_by_default() {
+        let rows: Vec<u32> = vec![1, 2];
+        assert_eq!(to_json("search", &rows, false), "[1,2]");
+    }
+
+    #[test]
+    fn to_json_envelope_wraps_results() {
+        let rows: Vec<u32> = vec![1, 2];
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json("search", &rows, true)).unwrap();
+        assert_eq!(json["slashmail_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["command"], "search");
+        assert_eq!(json["results"], serde_json::json!([1, 2]));
+    }
+
+    fn row(uid: u32, folder: Option<&str>, from: &str, subject: &str) -> MessageRow {
+        MessageRow {
+            uid,
+            folder: folder.map(str::to_string),
+            from: from.to_string(),
+            subject: subject.to_string(),
+            date: "2025-03-09 12:00".to_string(),
+            size: 2048,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn state_letters_follow_flags() {
+        let mut msg = row(1, None, "a", "b");
+        assert!(msg.is_unseen());
+        assert_eq!(msg.state(), "N");
+        msg.flags = vec!["\\Seen".into(), "\\Answered".into(), "$Work".into()];
+        assert_eq!(msg.state(), "R");
+        msg.flags = vec!["\\Flagged".into(), "\\Deleted".into(), "\\Answered".into()];
+        assert_eq!(msg.state(), "NR!D");
+        msg.flags = vec!["\\Seen".into()];
+        assert!(!msg.is_unseen());
+        assert_eq!(msg.state(), "");
+        msg.unsubscribe = vec!["https://list.example/u".into()];
+        assert_eq!(msg.state(), "L");
+    }
+
+    #[test]
+    fn json_includes_flags() {
+        let mut msg = row(1, None, "a", "b");
+        msg.flags = vec!["\\Seen".into()];
+        let json: serde_json::Value = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["flags"], serde_json::json!(["\\Seen"]));
+    }
+
+    #[test]
+    fn csv_header_and_raw_size() {
+        let out = to_delimited(&[row(7, None, "alice", "hello")], Delimited::Csv);
+        assert_eq!(
+            out,
+            "uid,from,subject,date,size_bytes\r\n7,alice,hello,2025-03-09 12:00,2048\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_commas_quotes_and_line_breaks() {
+        let rows = [row(
+            1,
+            None,
+            "\"Doe, Jane\" <jane@example.com>",
+            "Re: a\r\nb",
+        )];
+        let out = to_delimited(&rows, Delimited::Csv);
+        let record = out.split_once("\r\n").unwrap().1;
+        assert_eq!(
+            record,
+            "1,\"\"\"Doe, Jane\"\" <jane@example.com>\",\"Re: a\r\nb\",2025-03-09 12:00,2048\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_folder_column_only_when_present() {
+        let rows = [row(1, Some("INBOX"), "a", "x"), row(2, None, "b", "y")];
+        let out = to_delimited(&rows, Delimited::Csv);
+        assert!(out.starts_with("uid,folder,from,subject,date,size_bytes\r\n"));
+        assert!(out.contains("1,INBOX,a,x,"));
+        assert!(out.contains("2,,b,y,"));
+    }
+
+    #[test]
+    fn csv_empty_is_header_only() {
+        assert_eq!(
+            to_delimited(&[], Delimited::Csv),
+            "uid,from,subject,date,size_bytes\r\n"
+        );
+    }
+
+    #[test]
+    fn tsv_replaces_tabs_and_line_breaks() {
+        let out = to_delimited(&[row(3, None, "a\tb", "x,\"y\"\nz")], Delimited::Tsv);
+        assert_eq!(
+            out,
+            "uid\tfrom\tsubject\tdate\tsize_bytes\r\n3\ta b\tx,\"y\" z\t2025-03-09 12:00\t2048\r\n"
+        );
+    }
+
+    #[test]
+    fn resolve_columns_keeps_requested_order() {
+        let cols = [Column::Subject, Column::Date];
+        assert_eq!(resolve_columns(&cols, false), cols);
+        assert_eq!(
+            resolve_columns(&Column::DEFAULT, false),
+            Column::DEFAULT.to_vec()
+        );
+    }
+
+    #[test]
+    fn resolve_columns_inserts_folder_when_rows_have_one() {
+        assert_eq!(
+            resolve_columns(&Column::DEFAULT, true)[..2],
+            [Column::Uid, Column::Folder]
+        );
+        assert_eq!(
+            resolve_columns(&[Column::Subject, Column::Date], true),
+            [Column::Folder, Column::Subject, Column::Date]
+        );
+        // An explicit position wins
+        let cols = [Column::Subject, Column::Folder];
+        assert_eq!(resolve_columns(&cols, true), cols);
+    }
+
+    #[test]
+    fn use_color_honors_flag_and_no_color() {
+        assert!(use_color(false, None));
+        assert!(!use_color(true, None));
+        assert!(!use_color(false, Some(OsStr::new("1"))));
+        // An empty NO_COLOR does not disable color
+        assert!(use_color(false, Some(OsStr::new(""))));
+    }
+
     #[test]
     fn format_size_zero() {
         assert_eq!(format_size(0), "0B");
@@ -66,12 +524,12 @@ mod tests {
     fn json_single_message() {
         let messages = vec![MessageRow {
             uid: 42,
-            folder: None,
             from: "alice@example.com".into(),
             subject: "Test".into(),
             date: "Mon, 1 Apr 2026".into(),
             timestamp: 1774000000,
             size: 1024,
+            ..Default::default()
         }];
         let json = serde_json::to_string(&messages).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
@@ -92,6 +550,7 @@ mod tests {
             date: "Tue, 2 Apr 2026".into(),
             timestamp: 1774100000,
             size: 512,
+            ..Default::default()
         }];
         let json = serde_json::to_string(&messages).unwrap();
         let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
@@ -99,39 +558,228 @@ mod tests {
     }
 }
 
-pub fn display_messages_json(messages: &[MessageRow]) {
-    println!("{}", serde_json::to_string(messages).unwrap());
+/// Versioned wrapper for `--json-envelope`, so consumers can detect schema changes.
+#[derive(serde::Serialize)]
+struct JsonEnvelope<'a, T: serde::Serialize> {
+    slashmail_version: &'static str,
+    command: &'a str,
+    results: &'a T,
+}
+
+/// Print `results` as JSON, wrapped in a `JsonEnvelope` when `envelope` is set.
+pub fn print_json<T: serde::Serialize>(command: &str, results: &T, envelope: bool) {
+    println!("{}", to_json(command, results, envelope));
+}
+
+fn to_json<T: serde::Serialize>(command: &str, results: &T, envelope: bool) -> String {
+    if envelope {
+        serde_json::to_string(&JsonEnvelope {
+            slashmail_version: env!("CARGO_PKG_VERSION"),
+            command,
+            results,
+        })
+        .unwrap()
+    } else {
+        serde_json::to_string(results).unwrap()
+    }
+}
+
+pub fn display_messages_json(messages: &[MessageRow], envelope: bool) {
+    print_json("search", &messages, envelope);
+}
+
+/// Print messages as RFC 4180 CSV with a header row. Size is the raw byte count.
+pub fn display_messages_csv(messages: &[MessageRow]) {
+    print!("{}", to_delimited(messages, Delimited::Csv));
+}
+
+/// Print messages as tab-separated values with a header row.
+pub fn display_messages_tsv(messages: &[MessageRow]) {
+    print!("{}", to_delimited(messages, Delimited::Tsv));
+}
+
+#[derive(Clone, Copy)]
+enum Delimited {
+    Csv,
+    Tsv,
+}
+
+impl Delimited {
+    fn separator(self) -> &'static str {
+        match self {
+            Delimited::Csv => ",",
+            Delimited::Tsv => "\t",
+        }
+    }
+
+    /// CSV quotes fields containing a separator, quote or line break and doubles
+    /// embedded quotes. TSV has no quoting, so tabs and line breaks become spaces.
+    fn field(self, value: &str) -> String {
+        match self {
+            Delimited::Csv if value.contains([',', '"', '\r', '\n']) => {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            }
+            Delimited::Csv => value.to_string(),
+            Delimited::Tsv => value.replace(['\t', '\r', '\n'], " "),
+        }
+    }
+}
+
+/// One header row plus one row per message, CRLF-terminated. The folder column is
+/// only present when some row has a folder, as in `display_messages`.
+fn to_delimited(messages: &[MessageRow], kind: Delimited) -> String {
+    let has_folder = messages.iter().any(|m| m.folder.is_some());
+    let mut header = vec!["uid", "from", "subject", "date", "size_bytes"];
+    if has_folder {
+        header.insert(1, "folder");
+    }
+
+    let sep = kind.separator();
+    let mut out = header.join(sep);
+    out.push_str("\r\n");
+    for msg in messages {
+        let mut row = vec![msg.uid.to_string()];
+        if has_folder {
+            row.push(kind.field(msg.folder.as_deref().unwrap_or("")));
+        }
+        row.push(kind.field(&msg.from));
+        row.push(kind.field(&msg.subject));
+        row.push(kind.field(&msg.date));
+        row.push(msg.size.to_string());
+        out.push_str(&row.join(sep));
+        out.push_str("\r\n");
+    }
+    out
 }
 
-pub fn display_messages(messages: &[MessageRow]) {
+/// A column of the message table, for `search --columns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Uid,
+    State,
+    Folder,
+    From,
+    Subject,
+    Date,
+    Size,
+}
+
+impl Column {
+    /// The standard layout; a folder column is added when rows come from several folders.
+    pub const DEFAULT: [Column; 6] = [
+        Column::Uid,
+        Column::State,
+        Column::From,
+        Column::Subject,
+        Column::Date,
+        Column::Size,
+    ];
+
+    fn header(self) -> &'static str {
+        match self {
+            Column::Uid => "UID",
+            Column::State => "State",
+            Column::Folder => "Folder",
+            Column::From => "From",
+            Column::Subject => "Subject",
+            Column::Date => "Date",
+            Column::Size => "Size",
+        }
+    }
+
+    fn cell(self, msg: &MessageRow) -> Cell {
+        match self {
+            Column::Uid => Cell::new(msg.uid),
+            Column::State => Cell::new(msg.state()),
+            Column::Folder => Cell::new(msg.folder.as_deref().unwrap_or("")),
+            Column::From => Cell::new(&msg.from),
+            Column::Subject => Cell::new(&msg.subject),
+            Column::Date => Cell::new(shown_date(msg)),
+            Column::Size => Cell::new(format_size(msg.size as u64)),
+        }
+    }
+}
+
+/// The requested columns, plus a folder column right after UID (or first) when rows
+/// carry a folder and it wasn't requested explicitly.
+fn resolve_columns(requested: &[Column], has_folder: bool) -> Vec<Column> {
+    let mut columns = requested.to_vec();
+    if has_folder && !columns.contains(&Column::Folder) {
+        let at = columns
+            .iter()
+            .position(|&c| c == Column::Uid)
+            .map_or(0, |i| i + 1);
+        columns.insert(at, Column::Folder);
+    }
+    columns
+}
+
+pub fn display_messages(messages: &[MessageRow], ui: Ui) {
+    display_messages_columns(messages, &Column::DEFAULT, ui);
+}
+
+/// Like `display_messages`, with the given columns in the given order.
+pub fn display_messages_columns(messages: &[MessageRow], columns: &[Column], ui: Ui) {
     if messages.is_empty() {
-        println!("No messages found.");
+        ui.info("No messages found.");
         return;
     }
 
     let has_folder = messages.iter().any(|m| m.folder.is_some());
+    let columns = resolve_columns(columns, has_folder);
     let mut table = Table::new();
     table.load_preset(UTF8_FULL_CONDENSED);
     table.set_content_arrangement(ContentArrangement::Dynamic);
+    table.set_header(columns.iter().map(|c| c.header()).collect::<Vec<_>>());
 
-    let mut header = vec!["UID", "From", "Subject", "Date", "Size"];
-    if has_folder {
-        header.insert(1, "Folder");
-    }
-    table.set_header(header);
-
+    let bold_unseen = COLOR.load(Ordering::Relaxed);
     for msg in messages {
-        let mut row: Vec<Cell> = vec![Cell::new(msg.uid)];
-        if has_folder {
-            row.push(Cell::new(msg.folder.as_deref().unwrap_or("")));
+        let cells = columns.iter().map(|c| c.cell(msg));
+        if bold_unseen && msg.is_unseen() {
+            table.add_row(cells.map(|cell| cell.add_attribute(Attribute::Bold)));
+        } else {
+            table.add_row(cells);
         }
-        row.push(Cell::new(&msg.from));
-        row.push(Cell::new(&msg.subject));
-        row.push(Cell::new(&msg.date));
-        row.push(Cell::new(format_size(msg.size as u64)));
-        table.add_row(row);
     }
 
     println!("{table}");
-    println!("{} message(s)", messages.len());
+    ui.info(format_args!("{} message(s)", messages.len()));
+}
+
+fn print_thread_node(node: &ThreadNode, depth: usize, by_uid: &HashMap<u32, &MessageRow>) {
+    let indent = "  ".repeat(depth);
+    match node.uid.and_then(|u| by_uid.get(&u)) {
+        Some(msg) => println!(
+            "{indent}{} {}  ({}, {})",
+            msg.uid,
+            msg.subject,
+            msg.from,
+            shown_date(msg)
+        ),
+        None => println!("{indent}(missing message)"),
+    }
+    for child in &node.children {
+        print_thread_node(child, depth + 1, by_uid);
+    }
+}
+
+/// Print conversation trees, indenting replies under the message they answer.
+pub fn display_threads(threads: &[ThreadNode], messages: &[MessageRow], ui: Ui) {
+    if threads.is_empty() {
+        ui.info("No messages found.");
+        return;
+    }
+
+    let by_uid: HashMap<u32, &MessageRow> = messages.iter().map(|m| (m.uid, m)).collect();
+    for (i, thread) in threads.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_thread_node(thread, 0, &by_uid);
+    }
+    ui.info(format_args!(
+        "\n{} conversation(s), {} message(s)",
+        threads.len(),
+        messages.len()
+    ));
 }
@@ -0,0 +1,206 @@
+use std::fmt;
+
+/// `Result` of the public library functions in `search`, `connection` and `delete`.
+pub type Result<T, E = SlashmailError> = std::result::Result<T, E>;
+
+/// The failures a library caller may want to tell apart. Everything else ends up in
+/// `Other`, with its context chain intact.
+#[derive(Debug, thiserror::Error)]
+pub enum SlashmailError {
+    /// The folder is not on the server; `suggestion` is the closest existing name.
+    #[error("{}", folder_not_found(.folder, .suggestion.as_deref()))]
+    FolderNotFound {
+        folder: String,
+        suggestion: Option<String>,
+    },
+    /// The server rejected the username or password (a NO or BAD reply to LOGIN).
+    #[error("IMAP login failed")]
+    AuthFailed(#[source] imap::error::Error),
+    /// A failure rooted in a socket error, e.g. a refused connection or a timeout. Keeps
+    /// the context it was raised with; `io_error` gives the socket error itself.
+    #[error(transparent)]
+    Connection(anyhow::Error),
+    /// A failure rooted in an IMAP error (a NO/BAD reply, a dropped connection). Keeps
+    /// the context it was raised with; `imap_error` gives the IMAP error itself.
+    #[error(transparent)]
+    Protocol(anyhow::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl SlashmailError {
+    /// The socket error behind a `Connection` failure.
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        match self {
+            SlashmailError::Connection(err) => io_cause(err),
+            _ => None,
+        }
+    }
+
+    /// The IMAP error behind a `Protocol` failure.
+    pub fn imap_error(&self) -> Option<&imap::error::Error> {
+        match self {
+            SlashmailError::Protocol(err) => err.chain().find_map(|e| e.downcast_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The first socket error in `err`'s chain. imap's error type doesn't expose its
+/// cause through `source()`, so its `Io` variant is looked into directly.
+fn io_cause(err: &anyhow::Error) -> Option<&std::io::Error> {
+    err.chain().find_map(|cause| match cause.downcast_ref() {
+        Some(imap::error::Error::Io(e)) => Some(e),
+        _ => cause.downcast_ref(),
+    })
+}
+
+fn folder_not_found(folder: &str, suggestion: Option<&str>) -> impl fmt::Display {
+    match suggestion {
+        Some(name) => format!("Folder '{folder}' does not exist; did you mean '{name}'?"),
+        None => format!(
+            "Folder '{folder}' does not exist. Use `slashmail status` to list available folders."
+        ),
+    }
+}
+
+/// Lets `?` lift anyhow errors from internal helpers. A `SlashmailError` that was
+/// passed through anyhow without added context comes back as itself; otherwise the
+/// chain is searched for a socket error, then an IMAP error, and the whole error,
+/// context included, goes in the matching variant.
+impl From<anyhow::Error> for SlashmailError {
+    fn from(err: anyhow::Error) -> Self {
+        // `downcast` also looks beneath context, which would drop it, so only take
+        // apart an error that has none
+        let top = err.chain().next().expect("an error chain is never empty");
+        if top.is::<SlashmailError>() {
+            err.downcast().unwrap_or_else(SlashmailError::Other)
+        } else if io_cause(&err).is_some() {
+            SlashmailError::Connection(err)
+        } else if err.chain().any(|e| e.is::<imap::error::Error>()) {
+            SlashmailError::Protocol(err)
+        } else {
+            SlashmailError::Other(err)
+        }
+    }
+}
+
+impl From<std::io::Error> for SlashmailError {
+    fn from(err: std::io::Error) -> Self {
+        anyhow::Error::new(err).into()
+    }
+}
+
+impl From<imap::error::Error> for SlashmailError {
+    fn from(err: imap::error::Error) -> Self {
+        anyhow::Error::new(err).into()
+    }
+}
+
+/// The error for a rejected LOGIN: `AuthFailed` for the server's NO/BAD, the usual
+/// "IMAP login failed" context for anything else (a dropped connection, a timeout).
+pub(crate) fn login_error(err: imap::error::Error) -> anyhow::Error {
+    match err {
+        imap::error::Error::No(_) | imap::error::Error::Bad(_) => {
+            SlashmailError::AuthFailed(err).into()
+        }
+        err => anyhow::Error::new(err).context("IMAP login failed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_not_found_keeps_the_cli_messages() {
+        let err = SlashmailError::FolderNotFound {
+            folder: "Archiv".into(),
+            suggestion: Some("Archive".into()),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Folder 'Archiv' does not exist; did you mean 'Archive'?"
+        );
+        let err = SlashmailError::FolderNotFound {
+            folder: "Nope".into(),
+            suggestion: None,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Folder 'Nope' does not exist. Use `slashmail status` to list available folders."
+        );
+    }
+
+    #[test]
+    fn from_anyhow_recovers_typed_errors() {
+        let folder: anyhow::Error = SlashmailError::FolderNotFound {
+            folder: "X".into(),
+            suggestion: None,
+        }
+        .into();
+        assert!(matches!(
+            SlashmailError::from(folder),
+            SlashmailError::FolderNotFound { .. }
+        ));
+
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(matches!(
+            SlashmailError::from(anyhow::Error::new(refused)),
+            SlashmailError::Connection(_)
+        ));
+
+        let lost: anyhow::Error = imap::error::Error::ConnectionLost.into();
+        let err = SlashmailError::from(lost);
+        assert!(matches!(
+            err.imap_error(),
+            Some(imap::error::Error::ConnectionLost)
+        ));
+
+        let other = SlashmailError::from(anyhow::anyhow!("Invalid date"));
+        assert!(matches!(other, SlashmailError::Other(_)));
+    }
+
+    #[test]
+    fn from_anyhow_classifies_by_root_cause_and_keeps_context() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let err = SlashmailError::from(anyhow::Error::new(refused).context("Failed to connect"));
+        assert!(matches!(err, SlashmailError::Connection(_)));
+        assert_eq!(
+            err.io_error().map(std::io::Error::kind),
+            Some(std::io::ErrorKind::ConnectionRefused)
+        );
+        assert_eq!(err.to_string(), "Failed to connect");
+        assert!(format!("{:#}", anyhow::Error::new(err)).starts_with("Failed to connect: "));
+
+        // A socket error inside an IMAP error is still a connection failure
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let err = anyhow::Error::new(imap::error::Error::Io(reset)).context("Failed to fetch");
+        assert!(matches!(
+            SlashmailError::from(err),
+            SlashmailError::Connection(_)
+        ));
+
+        let no = imap::error::Error::No("[NONEXISTENT] no such mailbox".into());
+        let err = SlashmailError::from(anyhow::Error::new(no).context("Failed to select 'X'"));
+        assert!(matches!(err, SlashmailError::Protocol(_)));
+        assert_eq!(err.to_string(), "Failed to select 'X'");
+    }
+
+    #[test]
+    fn login_error_separates_rejections() {
+        let err = login_error(imap::error::Error::No("bad credentials".into()));
+        assert_eq!(err.to_string(), "IMAP login failed");
+        assert!(matches!(
+            SlashmailError::from(err),
+            SlashmailError::AuthFailed(_)
+        ));
+
+        // Not a rejection: classified by its cause, with the login context kept
+        let err = login_error(imap::error::Error::ConnectionLost);
+        assert_eq!(err.to_string(), "IMAP login failed");
+        let err = SlashmailError::from(err);
+        assert!(matches!(err, SlashmailError::Protocol(_)));
+        assert_eq!(err.to_string(), "IMAP login failed");
+    }
+}
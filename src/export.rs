@@ -1,8 +1,12 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
 use std::path::Path;
 
-use crate::connection::ImapSession;
+use crate::connection::ImapOps;
 use crate::display::MessageRow;
+use crate::report::ActionReport;
 use crate::search;
 
 /// Sanitize folder name for use in filenames: keep alphanumerics and hyphens, replace rest with `_`.
@@ -19,69 +23,818 @@ pub fn sanitize_folder_name(folder: &str) -> String {
         .collect()
 }
 
-/// Export messages to .eml files. Returns (exported, skipped) counts.
+/// `--filename-template` default; matches the names used before templates existed.
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{folder}_{uid}.eml";
+
+const TEMPLATE_FIELDS: [&str; 5] = ["uid", "folder", "subject", "from", "date"];
+
+/// Longest text a `{subject}` or `{from}` placeholder expands to, in characters.
+const MAX_FIELD_CHARS: usize = 60;
+
+/// Longest filename most filesystems accept (NAME_MAX), in bytes.
+pub(crate) const NAME_MAX: usize = 255;
+
+/// Room left after a templated name for the `-N` suffix `unique_name` may add.
+pub(crate) const SUFFIX_ROOM: usize = 8;
+
+/// Check an export filename template (clap value parser): only known `{...}`
+/// placeholders, balanced braces, and no path separators.
+pub fn parse_filename_template(template: &str) -> Result<String> {
+    if template.trim().is_empty() {
+        bail!("--filename-template must not be empty");
+    }
+    if template.contains(['/', '\\']) {
+        bail!("Invalid --filename-template '{template}': path separators are not allowed");
+    }
+    if matches!(template.trim(), "." | "..") {
+        bail!("Invalid --filename-template '{template}': not a usable file name");
+    }
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest.as_bytes()[open] == b'}' {
+            bail!("Invalid --filename-template '{template}': unmatched '}}'");
+        }
+        let Some(close) = rest[open..].find('}') else {
+            bail!("Invalid --filename-template '{template}': unmatched '{{'");
+        };
+        let name = &rest[open + 1..open + close];
+        if !TEMPLATE_FIELDS.contains(&name) {
+            bail!(
+                "Invalid --filename-template '{template}': unknown placeholder '{{{name}}}' \
+                 (expected {{uid}}, {{folder}}, {{subject}}, {{from}} or {{date}})"
+            );
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(template.to_string())
+}
+
+/// Expand a template checked by `parse_filename_template` for one message. `{date}` is
+/// the Date header as `YYYY-MM-DD` (UTC), or `undated` when it could not be parsed.
+/// The name is shortened to fit NAME_MAX; `None` if it comes out empty, `.` or `..`.
+fn expand_template(template: &str, row: &MessageRow, folder: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = open + rest[open..].find('}').unwrap_or(rest.len() - open);
+        match &rest[open + 1..close] {
+            "uid" => out.push_str(&row.uid.to_string()),
+            "folder" => out.push_str(&sanitize_folder_name(folder)),
+            "subject" => out.push_str(&sanitize_field(&row.subject)),
+            "from" => out.push_str(&sanitize_field(&row.from)),
+            "date" if row.timestamp != 0 => {
+                let (y, m, d) = search::epoch_to_date(row.timestamp);
+                out.push_str(&format!("{y:04}-{m:02}-{d:02}"));
+            }
+            _ => out.push_str("undated"),
+        }
+        rest = rest.get(close + 1..).unwrap_or("");
+    }
+    out.push_str(rest);
+    let name = clamp_name(&out, NAME_MAX - SUFFIX_ROOM);
+    (!matches!(name.trim(), "" | "." | "..")).then_some(name)
+}
+
+/// Shorten `name` to at most `max_bytes` bytes, cutting on a char boundary and
+/// keeping a short extension (up to 10 bytes with the dot) intact.
+pub(crate) fn clamp_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 && name.len() - i <= 10 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut end = max_bytes.saturating_sub(ext.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ext}", &stem[..end])
+}
+
+/// Make header text safe as part of a filename: path separators and control characters
+/// become `_`, leading/trailing dots and spaces are dropped, and the result is clamped.
+fn sanitize_field(text: &str) -> String {
+    let cleaned: String = text
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .take(MAX_FIELD_CHARS)
+        .collect();
+    let trimmed = cleaned.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Return `name`, or `name-1`, `name-2`, ... (before the extension) if an earlier
+/// message of this export already took it. Compared case-insensitively, since two
+/// names differing only in case are the same file on some filesystems.
+pub(crate) fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    if used.insert(name.to_lowercase()) {
+        return name;
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name.as_str(), ""),
+    };
+    (1..)
+        .map(|n| format!("{stem}-{n}{ext}"))
+        .find(|candidate| used.insert(candidate.to_lowercase()))
+        .unwrap()
+}
+
+/// Written next to the .eml files so an import can restore message state.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Per-message metadata in the export manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub folder: String,
+    pub uid: u32,
+    /// Flags as sent in APPEND, e.g. `\Seen`; `\Recent` is left out since it cannot be set.
+    pub flags: Vec<String>,
+    /// INTERNALDATE (delivery time) in RFC 3339.
+    pub internal_date: Option<String>,
+}
+
+/// Merge `entries` into the manifest in `out_dir`, replacing entries for the same
+/// file, so repeated exports into one directory keep a single complete manifest.
+fn write_manifest(out_dir: &Path, entries: Vec<ManifestEntry>) -> Result<()> {
+    let path = out_dir.join(MANIFEST_FILE);
+    let mut by_file: BTreeMap<String, ManifestEntry> = BTreeMap::new();
+    if path.exists() {
+        let existing = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        let existing: Vec<ManifestEntry> = serde_json::from_str(&existing)
+            .with_context(|| format!("Failed to parse '{}'", path.display()))?;
+        by_file.extend(existing.into_iter().map(|e| (e.file.clone(), e)));
+    }
+    by_file.extend(entries.into_iter().map(|e| (e.file.clone(), e)));
+
+    let merged: Vec<&ManifestEntry> = by_file.values().collect();
+    let json = serde_json::to_string_pretty(&merged)?;
+    std::fs::write(&path, json + "\n")
+        .with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+/// Messages an export has finished with so far, whatever the outcome.
+fn handled(report: &ActionReport) -> usize {
+    report.acted + report.skipped + report.failed
+}
+
+/// Export messages to .eml files named by `template` (see `parse_filename_template`).
+/// A folder that fails to select or fetch is counted as failed and the export carries
+/// on with the next one.
 pub fn export_messages(
-    session: &mut ImapSession,
+    session: &mut impl ImapOps,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_dir: &Path,
+    template: &str,
+    force: bool,
+) -> Result<ActionReport> {
+    export_messages_with_progress(
+        session,
+        messages,
+        default_folder,
+        out_dir,
+        template,
+        force,
+        |_| {},
+    )
+}
+
+/// Like `export_messages`, calling `progress(done)` as each message is written,
+/// skipped or given up on (`done` counts up to `messages.len()`).
+pub fn export_messages_with_progress(
+    session: &mut impl ImapOps,
     messages: &[MessageRow],
     default_folder: &str,
     out_dir: &Path,
+    template: &str,
     force: bool,
-) -> Result<(usize, usize)> {
+    mut progress: impl FnMut(usize),
+) -> Result<ActionReport> {
     std::fs::create_dir_all(out_dir)
         .with_context(|| format!("Failed to create directory '{}'", out_dir.display()))?;
 
-    // Group by folder
-    let mut by_folder: std::collections::HashMap<String, Vec<u32>> =
-        std::collections::HashMap::new();
-    for msg in messages {
-        let folder = msg
-            .folder
-            .clone()
-            .unwrap_or_else(|| default_folder.to_string());
-        by_folder.entry(folder).or_default().push(msg.uid);
-    }
+    let by_folder = search::group_by_folder(messages, default_folder);
+    let mut report = ActionReport::new("export", &by_folder);
+    let mut manifest = Vec::new();
 
-    let mut exported = 0usize;
-    let mut skipped = 0usize;
+    // Names are assigned up front, folder by folder in UID order, so collision
+    // suffixes come out the same on every run and --force/skip stay predictable
+    let rows: HashMap<(&str, u32), &MessageRow> = messages
+        .iter()
+        .map(|m| ((m.folder.as_deref().unwrap_or(default_folder), m.uid), m))
+        .collect();
+    let mut used = HashSet::new();
 
     for (folder, uids) in &by_folder {
-        session
-            .select(folder)
-            .with_context(|| format!("Failed to select '{folder}'"))?;
+        let mut sorted = uids.clone();
+        sorted.sort_unstable();
+        let names: HashMap<u32, String> = sorted
+            .iter()
+            .filter_map(|&uid| {
+                let row = rows.get(&(folder.as_str(), uid))?;
+                let Some(name) = expand_template(template, row, folder) else {
+                    eprintln!(
+                        "Warning: --filename-template gives no usable name for UID {uid} in '{folder}'"
+                    );
+                    report.add_failed(folder, 1);
+                    return None;
+                };
+                Some((uid, unique_name(name, &mut used)))
+            })
+            .collect();
+        let result = export_folder(
+            session,
+            folder,
+            uids,
+            &names,
+            out_dir,
+            force,
+            &mut report,
+            &mut manifest,
+            &mut progress,
+        );
+        if let Err(e) = result {
+            eprintln!("Warning: {e:#}");
+            let handled = report
+                .folders
+                .iter()
+                .find(|f| f.folder == *folder)
+                .map_or(0, |f| f.acted + f.skipped + f.failed);
+            report.add_failed(folder, uids.len().saturating_sub(handled));
+            progress(self::handled(&report));
+        }
+    }
 
-        let safe_folder = sanitize_folder_name(folder);
+    write_manifest(out_dir, manifest)?;
+    Ok(report)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn export_folder(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    names: &HashMap<u32, String>,
+    out_dir: &Path,
+    force: bool,
+    report: &mut ActionReport,
+    manifest: &mut Vec<ManifestEntry>,
+    progress: &mut dyn FnMut(usize),
+) -> Result<()> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
 
-        for chunk in &search::build_uid_set(uids) {
-            let fetches = session
-                .uid_fetch(chunk, "BODY.PEEK[]")
-                .with_context(|| format!("Failed to fetch messages from '{folder}'"))?;
+    for chunk in &search::build_uid_set(uids) {
+        let fetches = session
+            .uid_fetch(chunk, "(FLAGS INTERNALDATE BODY.PEEK[])")
+            .with_context(|| format!("Failed to fetch messages from '{folder}'"))?;
 
-            for fetch in fetches.iter() {
-                let uid = match fetch.uid {
-                    Some(u) => u,
-                    None => continue,
+        for fetch in fetches.iter() {
+            let uid = match fetch.uid {
+                Some(u) => u,
+                None => continue,
+            };
+            let Some(file) = names.get(&uid).cloned() else {
+                continue;
+            };
+            if let Some(body) = fetch.body() {
+                let path = out_dir.join(&file);
+                let entry = ManifestEntry {
+                    file,
+                    folder: folder.to_string(),
+                    uid,
+                    flags: fetch
+                        .flags()
+                        .iter()
+                        .filter(|f| **f != imap::types::Flag::Recent)
+                        .map(|f| f.to_string())
+                        .collect(),
+                    internal_date: fetch.internal_date().map(|d| d.to_rfc3339()),
                 };
-                if let Some(body) = fetch.body() {
-                    let path = out_dir.join(format!("{safe_folder}_{uid}.eml"));
-                    if path.exists() && !force {
-                        skipped += 1;
-                        continue;
+                if path.exists() && !force {
+                    // Still listed: the file on disk is this message
+                    manifest.push(entry);
+                    report.add_skipped(folder, 1);
+                    progress(handled(report));
+                    continue;
+                }
+                match std::fs::write(&path, body) {
+                    Ok(()) => {
+                        manifest.push(entry);
+                        report.add_acted(folder, 1);
                     }
-                    std::fs::write(&path, body)
-                        .with_context(|| format!("Failed to write '{}'", path.display()))?;
-                    exported += 1;
+                    Err(e) => {
+                        eprintln!("Warning: failed to write '{}': {e}", path.display());
+                        report.add_failed(folder, 1);
+                    }
+                }
+                progress(handled(report));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Export messages into one mbox file at `out_path`, folder by folder, each in the
+/// order of `messages` (newest first from `search`). Bodies are written as they are
+/// fetched, `MBOX_BATCH` at a time. Uses mboxrd quoting, so lines starting with `From `
+/// (after any `>`s) get one more `>` and can be restored exactly. A folder that fails
+/// to select or fetch is counted as failed; what it wrote before failing stays.
+pub fn export_mbox(
+    session: &mut impl ImapOps,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_path: &Path,
+    force: bool,
+) -> Result<ActionReport> {
+    export_mbox_with_progress(session, messages, default_folder, out_path, force, |_| {})
+}
+
+/// Like `export_mbox`, calling `progress(done)` as each message is written or given up
+/// on (`done` counts up to `messages.len()`).
+pub fn export_mbox_with_progress(
+    session: &mut impl ImapOps,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_path: &Path,
+    force: bool,
+    mut progress: impl FnMut(usize),
+) -> Result<ActionReport> {
+    if out_path.exists() && !force {
+        bail!(
+            "'{}' already exists (use --force to overwrite)",
+            out_path.display()
+        );
+    }
+
+    let file = std::fs::File::create(out_path)
+        .with_context(|| format!("Failed to create '{}'", out_path.display()))?;
+    let mut out = std::io::BufWriter::new(file);
+    let by_folder = search::group_by_folder(messages, default_folder);
+    let mut report = ActionReport::new("export", &by_folder);
+    for (folder, uids) in &by_folder {
+        let result = mbox_folder(session, folder, uids, &mut out, &mut report, &mut progress)
+            .with_context(|| format!("Failed to write '{}'", out_path.display()))?;
+        if let Err(e) = result {
+            eprintln!("Warning: {e:#}");
+            let handled = report
+                .folders
+                .iter()
+                .find(|f| f.folder == *folder)
+                .map_or(0, |f| f.acted + f.failed);
+            report.add_failed(folder, uids.len().saturating_sub(handled));
+            progress(self::handled(&report));
+        }
+    }
+    out.flush()
+        .with_context(|| format!("Failed to write '{}'", out_path.display()))?;
+    Ok(report)
+}
+
+/// Messages fetched and held in memory at once by `export_mbox`.
+const MBOX_BATCH: usize = 200;
+
+/// Append `uids` (in that order) from `folder` to an mbox, fetching full bodies and
+/// INTERNALDATE (as an mbox `asctime` string, UTC) `MBOX_BATCH` at a time. The outer
+/// error is a failed write, which ends the export; the inner one a failed folder.
+fn mbox_folder(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    out: &mut impl Write,
+    report: &mut ActionReport,
+    progress: &mut dyn FnMut(usize),
+) -> std::io::Result<Result<()>> {
+    if let Err(e) = session.select(folder) {
+        return Ok(Err(e).with_context(|| format!("Failed to select '{folder}'")));
+    }
+
+    for batch in uids.chunks(MBOX_BATCH) {
+        let mut fetched = HashMap::new();
+        for chunk in &search::build_uid_set(batch) {
+            let fetches = match session.uid_fetch(chunk, "(INTERNALDATE BODY.PEEK[])") {
+                Ok(fetches) => fetches,
+                Err(e) => {
+                    return Ok(
+                        Err(e).with_context(|| format!("Failed to fetch messages from '{folder}'"))
+                    )
+                }
+            };
+            for fetch in fetches.iter() {
+                if let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) {
+                    let date = fetch
+                        .internal_date()
+                        .map(|d| d.naive_utc().format("%a %b %e %H:%M:%S %Y").to_string());
+                    fetched.insert(uid, (body.to_vec(), date));
+                }
+            }
+        }
+        for uid in batch {
+            match fetched.remove(uid) {
+                Some((body, date)) => {
+                    out.write_all(&mbox_entry(&body, date.as_deref()))?;
+                    report.add_acted(folder, 1);
+                }
+                // Gone since the search
+                None => report.add_failed(folder, 1),
+            }
+            progress(handled(report));
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// Export messages into a Maildir at `out_dir` (creating `cur/`, `new/`, `tmp/`).
+/// Each message is written to `tmp/` and renamed into place: unflagged unread mail
+/// goes to `new/`, everything else to `cur/` with its flags in the `:2,` suffix.
+/// Filenames are unique per run, so exporting twice stores the messages twice.
+pub fn export_maildir(
+    session: &mut impl ImapOps,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_dir: &Path,
+) -> Result<ActionReport> {
+    export_maildir_with_progress(session, messages, default_folder, out_dir, |_| {})
+}
+
+/// Like `export_maildir`, calling `progress(done)` as each message is written or given
+/// up on (`done` counts up to `messages.len()`).
+pub fn export_maildir_with_progress(
+    session: &mut impl ImapOps,
+    messages: &[MessageRow],
+    default_folder: &str,
+    out_dir: &Path,
+    mut progress: impl FnMut(usize),
+) -> Result<ActionReport> {
+    for sub in ["cur", "new", "tmp"] {
+        let dir = out_dir.join(sub);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory '{}'", dir.display()))?;
+    }
+
+    let by_folder = search::group_by_folder(messages, default_folder);
+    let mut report = ActionReport::new("export", &by_folder);
+    let mut counter = 0;
+
+    for (folder, uids) in &by_folder {
+        let result = maildir_folder(
+            session,
+            folder,
+            uids,
+            out_dir,
+            &mut counter,
+            &mut report,
+            &mut progress,
+        );
+        if let Err(e) = result {
+            eprintln!("Warning: {e:#}");
+            let handled = report
+                .folders
+                .iter()
+                .find(|f| f.folder == *folder)
+                .map_or(0, |f| f.acted + f.failed);
+            report.add_failed(folder, uids.len().saturating_sub(handled));
+            progress(self::handled(&report));
+        }
+    }
+    Ok(report)
+}
+
+fn maildir_folder(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    out_dir: &Path,
+    counter: &mut u32,
+    report: &mut ActionReport,
+    progress: &mut dyn FnMut(usize),
+) -> Result<()> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let pid = std::process::id();
+
+    for chunk in &search::build_uid_set(uids) {
+        let fetches = session
+            .uid_fetch(chunk, "(FLAGS BODY.PEEK[])")
+            .with_context(|| format!("Failed to fetch messages from '{folder}'"))?;
+        for fetch in fetches.iter() {
+            let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) else {
+                continue;
+            };
+            *counter += 1;
+            let name = format!("{secs}.P{pid}Q{counter}U{uid}.slashmail");
+            let (sub, file) = maildir_target(&name, fetch.flags());
+            let tmp = out_dir.join("tmp").join(&name);
+            let dest = out_dir.join(sub).join(file);
+            let written = std::fs::write(&tmp, lf_line_endings(body))
+                .and_then(|()| std::fs::rename(&tmp, &dest));
+            match written {
+                Ok(()) => report.add_acted(folder, 1),
+                Err(e) => {
+                    eprintln!("Warning: failed to write '{}': {e}", dest.display());
+                    let _ = std::fs::remove_file(&tmp);
+                    report.add_failed(folder, 1);
                 }
             }
+            progress(handled(report));
+        }
+    }
+    Ok(())
+}
+
+/// Subdirectory and filename for a message with `flags`: `new/<name>` for mail that
+/// is unread and has no other flags, else `cur/<name>:2,<letters>` with the Maildir
+/// letters in ASCII order (D draft, F flagged, R replied, S seen, T trashed).
+fn maildir_target(name: &str, flags: &[imap::types::Flag]) -> (&'static str, String) {
+    use imap::types::Flag;
+    let mut letters: Vec<char> = flags
+        .iter()
+        .filter_map(|f| match f {
+            Flag::Draft => Some('D'),
+            Flag::Flagged => Some('F'),
+            Flag::Answered => Some('R'),
+            Flag::Seen => Some('S'),
+            Flag::Deleted => Some('T'),
+            _ => None,
+        })
+        .collect();
+    letters.sort_unstable();
+    letters.dedup();
+    if letters.is_empty() {
+        ("new", name.to_string())
+    } else {
+        let letters: String = letters.into_iter().collect();
+        ("cur", format!("{name}:2,{letters}"))
+    }
+}
+
+/// Convert CRLF line endings (as IMAP sends them) to the LF that local mail stores use.
+fn lf_line_endings(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    for (i, &b) in raw.iter().enumerate() {
+        if !(b == b'\r' && raw.get(i + 1) == Some(&b'\n')) {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// One mbox record: the `From sender date` separator, the message with LF line endings
+/// and mboxrd `>From ` quoting, and a blank line.
+fn mbox_entry(raw: &[u8], date: Option<&str>) -> Vec<u8> {
+    let mut out = format!(
+        "From {} {}\n",
+        envelope_sender(raw),
+        date.unwrap_or("Thu Jan  1 00:00:00 1970")
+    )
+    .into_bytes();
+
+    let text = raw
+        .strip_suffix(b"\r\n")
+        .or(raw.strip_suffix(b"\n"))
+        .unwrap_or(raw);
+    for line in text.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let quotes = line.iter().take_while(|&&b| b == b'>').count();
+        if line[quotes..].starts_with(b"From ") {
+            out.push(b'>');
         }
+        out.extend_from_slice(line);
+        out.push(b'\n');
     }
+    out.push(b'\n');
+    out
+}
 
-    Ok((exported, skipped))
+/// Address for the mbox separator line: the first From address, else MAILER-DAEMON.
+fn envelope_sender(raw: &[u8]) -> String {
+    let from = mailparse::parse_headers(raw).ok().and_then(|(headers, _)| {
+        headers
+            .iter()
+            .find(|h| h.get_key().eq_ignore_ascii_case("from"))
+            .map(|h| h.get_value())
+    });
+    let addr = from
+        .and_then(|v| mailparse::addrparse(&v).ok())
+        .and_then(|list| {
+            list.iter().find_map(|a| match a {
+                mailparse::MailAddr::Single(s) => Some(s.addr.clone()),
+                mailparse::MailAddr::Group(g) => g.addrs.first().map(|s| s.addr.clone()),
+            })
+        });
+    match addr {
+        Some(a) if !a.is_empty() && !a.contains(char::is_whitespace) => a,
+        _ => "MAILER-DAEMON".to_string(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn row(uid: u32, from: &str, subject: &str, timestamp: i64) -> MessageRow {
+        MessageRow {
+            uid,
+            from: from.to_string(),
+            subject: subject.to_string(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_filename_template_accepts_known_placeholders() {
+        assert!(parse_filename_template(DEFAULT_FILENAME_TEMPLATE).is_ok());
+        assert!(parse_filename_template("{date} {from} - {subject} ({uid}).eml").is_ok());
+        assert!(parse_filename_template("static.eml").is_ok());
+    }
+
+    #[test]
+    fn parse_filename_template_rejects_bad_templates() {
+        let err = parse_filename_template("{size}.eml")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("{size}"), "{err}");
+        assert!(parse_filename_template("{uid.eml").is_err());
+        assert!(parse_filename_template("uid}.eml").is_err());
+        assert!(parse_filename_template("{folder}/{uid}.eml").is_err());
+        assert!(parse_filename_template("  ").is_err());
+        assert!(parse_filename_template(".").is_err());
+        assert!(parse_filename_template("..").is_err());
+    }
+
+    #[test]
+    fn expand_template_fills_fields() {
+        // 2025-03-09 12:00:00 UTC
+        let msg = row(42, "Jane Doe", "Re: Q1/Q2 plan", 1741521600);
+        assert_eq!(
+            expand_template(DEFAULT_FILENAME_TEMPLATE, &msg, "Work/Projects").unwrap(),
+            "Work_Projects_42.eml"
+        );
+        assert_eq!(
+            expand_template("{date}_{from}_{subject}.eml", &msg, "INBOX").unwrap(),
+            "2025-03-09_Jane Doe_Re: Q1_Q2 plan.eml"
+        );
+        let undated = row(1, "a", "b", 0);
+        assert_eq!(
+            expand_template("{date}.eml", &undated, "INBOX").unwrap(),
+            "undated.eml"
+        );
+    }
+
+    #[test]
+    fn expand_template_limits_name_bytes() {
+        let wide = row(1, &"é".repeat(60), &"日".repeat(60), 0);
+        let template = "{subject} {from} {subject} {from}.eml";
+        let name = expand_template(template, &wide, "INBOX").unwrap();
+        assert!(name.len() <= NAME_MAX - SUFFIX_ROOM, "{}", name.len());
+        assert!(name.ends_with(".eml"));
+        let mut used = HashSet::from([name.to_lowercase()]);
+        assert!(unique_name(name, &mut used).len() <= NAME_MAX);
+    }
+
+    #[test]
+    fn expand_template_rejects_dot_names() {
+        let msg = row(1, "a", "b", 0);
+        assert_eq!(expand_template("{folder}", &msg, ""), None);
+        assert_eq!(expand_template("{folder}.", &msg, "."), Some("_.".into()));
+    }
+
+    #[test]
+    fn clamp_name_cuts_on_char_boundary() {
+        assert_eq!(clamp_name("short.eml", 20), "short.eml");
+        assert_eq!(clamp_name("ééé.eml", 7), "é.eml");
+        assert_eq!(clamp_name("abcdef", 3), "abc");
+    }
+
+    #[test]
+    fn sanitize_field_strips_unsafe_characters() {
+        assert_eq!(sanitize_field("a/b\\c\td"), "a_b_c_d");
+        assert_eq!(sanitize_field(".. hidden ."), "hidden");
+        assert_eq!(sanitize_field(""), "_");
+        assert_eq!(sanitize_field(&"x".repeat(200)).len(), MAX_FIELD_CHARS);
+    }
+
+    #[test]
+    fn unique_name_suffixes_collisions() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_name("a.eml".into(), &mut used), "a.eml");
+        assert_eq!(unique_name("a.eml".into(), &mut used), "a-1.eml");
+        assert_eq!(unique_name("A.eml".into(), &mut used), "A-2.eml");
+        assert_eq!(unique_name("noext".into(), &mut used), "noext");
+        assert_eq!(unique_name("noext".into(), &mut used), "noext-1");
+    }
+
+    #[test]
+    fn mbox_entry_separator_and_line_endings() {
+        let raw = b"From: Jane <jane@example.com>\r\nSubject: hi\r\n\r\nline one\r\nline two\r\n";
+        let entry = mbox_entry(raw, Some("Sun Mar  9 12:00:00 2025"));
+        assert_eq!(
+            String::from_utf8(entry).unwrap(),
+            "From jane@example.com Sun Mar  9 12:00:00 2025\n\
+             From: Jane <jane@example.com>\nSubject: hi\n\nline one\nline two\n\n"
+        );
+    }
+
+    #[test]
+    fn mbox_entry_quotes_from_lines_mboxrd() {
+        let raw = b"Subject: x\r\n\r\nFrom here on\r\n>From quoted\r\n>>From deeper\r\nFromage\r\n";
+        let entry = String::from_utf8(mbox_entry(raw, None)).unwrap();
+        let body: Vec<&str> = entry.lines().skip(3).collect();
+        assert_eq!(
+            body,
+            [
+                ">From here on",
+                ">>From quoted",
+                ">>>From deeper",
+                "Fromage",
+                ""
+            ]
+        );
+        assert!(entry.starts_with("From MAILER-DAEMON Thu Jan  1 00:00:00 1970\n"));
+    }
+
+    #[test]
+    fn mbox_entry_without_trailing_newline() {
+        let entry = mbox_entry(b"Subject: x\r\n\r\nno newline", Some("D"));
+        assert!(String::from_utf8(entry)
+            .unwrap()
+            .ends_with("\n\nno newline\n\n"));
+    }
+
+    #[test]
+    fn maildir_target_new_without_flags() {
+        use imap::types::Flag;
+        assert_eq!(maildir_target("m1", &[]), ("new", "m1".to_string()));
+        // \Recent and keywords have no Maildir letter
+        assert_eq!(
+            maildir_target("m1", &[Flag::Recent, Flag::Custom("$Junk".into())]),
+            ("new", "m1".to_string())
+        );
+    }
+
+    #[test]
+    fn maildir_target_letters_sorted_in_cur() {
+        use imap::types::Flag;
+        assert_eq!(
+            maildir_target("m2", &[Flag::Seen]),
+            ("cur", "m2:2,S".to_string())
+        );
+        assert_eq!(
+            maildir_target(
+                "m3",
+                &[
+                    Flag::Seen,
+                    Flag::Flagged,
+                    Flag::Answered,
+                    Flag::Draft,
+                    Flag::Deleted
+                ]
+            ),
+            ("cur", "m3:2,DFRST".to_string())
+        );
+        // Flagged but unread still needs the info suffix, so it goes to cur/
+        assert_eq!(
+            maildir_target("m4", &[Flag::Flagged]),
+            ("cur", "m4:2,F".to_string())
+        );
+    }
+
+    #[test]
+    fn lf_line_endings_only_strips_crlf() {
+        assert_eq!(lf_line_endings(b"a\r\nb\rc\n"), b"a\nb\rc\n");
+    }
+
+    #[test]
+    fn envelope_sender_falls_back() {
+        assert_eq!(envelope_sender(b"Subject: x\r\n\r\n"), "MAILER-DAEMON");
+        assert_eq!(
+            envelope_sender(b"From: \"A, B\" <ab@example.com>\r\n\r\n"),
+            "ab@example.com"
+        );
+    }
+
     #[test]
     fn sanitize_folder_name_simple() {
         assert_eq!(sanitize_folder_name("INBOX"), "INBOX");
@@ -113,6 +866,55 @@ mod tests {
         assert_eq!(sanitize_folder_name("Work/Projects"), "Work_Projects");
     }
 
+    fn entry(file: &str, flags: &[&str]) -> ManifestEntry {
+        ManifestEntry {
+            file: file.to_string(),
+            folder: "INBOX".to_string(),
+            uid: 1,
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+            internal_date: Some("2025-01-06T10:00:00+00:00".to_string()),
+        }
+    }
+
+    fn read_manifest(dir: &Path) -> Vec<ManifestEntry> {
+        serde_json::from_str(&std::fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn write_manifest_creates_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), vec![entry("INBOX_1.eml", &["\\Seen"])]).unwrap();
+        assert_eq!(
+            read_manifest(dir.path()),
+            vec![entry("INBOX_1.eml", &["\\Seen"])]
+        );
+    }
+
+    #[test]
+    fn write_manifest_merges_by_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(
+            dir.path(),
+            vec![entry("INBOX_2.eml", &[]), entry("INBOX_1.eml", &[])],
+        )
+        .unwrap();
+        write_manifest(dir.path(), vec![entry("INBOX_1.eml", &["\\Flagged"])]).unwrap();
+        assert_eq!(
+            read_manifest(dir.path()),
+            vec![
+                entry("INBOX_1.eml", &["\\Flagged"]),
+                entry("INBOX_2.eml", &[])
+            ]
+        );
+    }
+
+    #[test]
+    fn write_manifest_rejects_corrupt_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(MANIFEST_FILE), "not json").unwrap();
+        assert!(write_manifest(dir.path(), vec![]).is_err());
+    }
+
     #[test]
     fn eml_filename_format() {
         let safe = sanitize_folder_name("[Gmail]/All Mail");
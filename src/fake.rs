@@ -0,0 +1,499 @@
+//! An in-memory `ImapOps` for unit tests: folders of messages, the commands it was
+//! sent, and just enough SEARCH/SORT/FETCH to drive the search and move code.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Cursor, Read, Write};
+
+use imap::error::{Error, Result};
+use imap::types::{Fetch, Flag, Mailbox, Name, ZeroCopy};
+use mailparse::MailHeaderMap;
+
+use crate::connection::ImapOps;
+use crate::search::imap_quote;
+
+#[derive(Clone)]
+pub(crate) struct FakeMessage {
+    pub uid: u32,
+    pub date: String,
+    pub from: String,
+    pub subject: String,
+    pub size: u32,
+    pub flags: Vec<String>,
+}
+
+/// A message from `from` (an address) with `subject`, sent `date` (RFC 2822).
+pub(crate) fn message(uid: u32, date: &str, from: &str, subject: &str) -> FakeMessage {
+    FakeMessage {
+        uid,
+        date: date.to_string(),
+        from: from.to_string(),
+        subject: subject.to_string(),
+        size: 100,
+        flags: Vec::new(),
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FakeImap {
+    pub folders: BTreeMap<String, Vec<FakeMessage>>,
+    capabilities: HashSet<String>,
+    selected: Option<String>,
+    /// Folders that are listed but refuse SELECT, like a shared folder without rights.
+    unselectable: HashSet<String>,
+    /// Every command received, without its tag, quoted as on the wire.
+    pub log: Vec<String>,
+}
+
+impl FakeImap {
+    pub fn new(capabilities: &[&str]) -> Self {
+        FakeImap {
+            capabilities: capabilities.iter().map(|c| c.to_uppercase()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn folder(mut self, name: &str, messages: Vec<FakeMessage>) -> Self {
+        self.folders.insert(name.to_string(), messages);
+        self
+    }
+
+    /// A listed folder whose SELECT fails with NO.
+    pub fn unselectable(mut self, name: &str) -> Self {
+        self.folders.insert(name.to_string(), Vec::new());
+        self.unselectable.insert(name.to_string());
+        self
+    }
+
+    /// Commands in the log starting with `prefix`.
+    pub fn sent(&self, prefix: &str) -> Vec<&str> {
+        self.log
+            .iter()
+            .filter(|c| c.starts_with(prefix))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// UIDs in `folder`, in storage order.
+    pub fn uids(&self, folder: &str) -> Vec<u32> {
+        self.folders[folder].iter().map(|m| m.uid).collect()
+    }
+
+    fn selected(&mut self) -> Result<&mut Vec<FakeMessage>> {
+        let folder = self
+            .selected
+            .clone()
+            .ok_or_else(|| Error::Bad("no folder selected".into()))?;
+        Ok(self
+            .folders
+            .get_mut(&folder)
+            .expect("selected folder exists"))
+    }
+
+    fn matching(&mut self, uid_set: &str) -> Result<Vec<&mut FakeMessage>> {
+        let uids = parse_uid_set(uid_set);
+        Ok(self
+            .selected()?
+            .iter_mut()
+            .filter(|m| uids.contains(&m.uid))
+            .collect())
+    }
+
+    /// Copy or move the selected folder's `uid_set` to `dest`, returning (old, new) UIDs.
+    fn transfer(&mut self, uid_set: &str, dest: &str, remove: bool) -> Result<Vec<(u32, u32)>> {
+        if !self.folders.contains_key(dest) {
+            return Err(Error::No(format!("[TRYCREATE] no folder {dest}")));
+        }
+        let uids = parse_uid_set(uid_set);
+        let source = self.selected()?;
+        let picked: Vec<FakeMessage> = if remove {
+            let (picked, kept) = std::mem::take(source)
+                .into_iter()
+                .partition(|m| uids.contains(&m.uid));
+            *source = kept;
+            picked
+        } else {
+            source
+                .iter()
+                .filter(|m| uids.contains(&m.uid))
+                .cloned()
+                .collect()
+        };
+        let target = self.folders.get_mut(dest).expect("checked above");
+        let mut next = target.iter().map(|m| m.uid).max().unwrap_or(0);
+        let mut pairs = Vec::new();
+        for mut m in picked {
+            next += 1;
+            pairs.push((m.uid, next));
+            m.uid = next;
+            target.push(m);
+        }
+        Ok(pairs)
+    }
+
+    /// UIDs of the selected folder matching `query`: `ALL`, `UID <set>`, `FROM` and
+    /// `SUBJECT` (substring, case-insensitive), ANDed.
+    fn search(&mut self, query: &str) -> Result<Vec<u32>> {
+        let words = split_query(query);
+        let messages = self.selected()?;
+        let mut keep: Vec<&FakeMessage> = messages.iter().collect();
+        let mut words = words.iter();
+        while let Some(word) = words.next() {
+            let mut arg = || {
+                words
+                    .next()
+                    .map(|w| w.to_lowercase())
+                    .ok_or_else(|| Error::Bad(format!("{word} needs an argument")))
+            };
+            match word.to_uppercase().as_str() {
+                "ALL" => {}
+                "UID" => {
+                    let uids = parse_uid_set(&arg()?);
+                    keep.retain(|m| uids.contains(&m.uid));
+                }
+                "FROM" => {
+                    let from = arg()?;
+                    keep.retain(|m| m.from.to_lowercase().contains(&from));
+                }
+                "SUBJECT" => {
+                    let subject = arg()?;
+                    keep.retain(|m| m.subject.to_lowercase().contains(&subject));
+                }
+                other => return Err(Error::Bad(format!("fake SEARCH can't do {other}"))),
+            }
+        }
+        let mut uids: Vec<u32> = keep.iter().map(|m| m.uid).collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+}
+
+impl ImapOps for FakeImap {
+    fn list(
+        &mut self,
+        _reference: Option<&str>,
+        pattern: Option<&str>,
+    ) -> Result<ZeroCopy<Vec<Name>>> {
+        let pattern = pattern.unwrap_or("");
+        self.log.push(format!("LIST \"\" {}", imap_quote(pattern)));
+        let lines: String = self
+            .folders
+            .keys()
+            .filter(|name| pattern == "*" || pattern == name.as_str())
+            .map(|name| format!("* LIST () \"/\" \"{name}\"\r\n"))
+            .collect();
+        respond(&lines, |s| s.list(None, Some("*")))
+    }
+
+    fn create(&mut self, mailbox: &str) -> Result<()> {
+        self.log.push(format!("CREATE {}", imap_quote(mailbox)));
+        if self.folders.contains_key(mailbox) {
+            return Err(Error::No("[ALREADYEXISTS] folder exists".into()));
+        }
+        self.folders.insert(mailbox.to_string(), Vec::new());
+        Ok(())
+    }
+
+    fn delete_mailbox(&mut self, mailbox: &str) -> Result<()> {
+        self.log.push(format!("DELETE {}", imap_quote(mailbox)));
+        self.folders
+            .remove(mailbox)
+            .map(drop)
+            .ok_or_else(|| Error::No("[NONEXISTENT] no such folder".into()))
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.log
+            .push(format!("RENAME {} {}", imap_quote(from), imap_quote(to)));
+        let messages = self
+            .folders
+            .remove(from)
+            .ok_or_else(|| Error::No("[NONEXISTENT] no such folder".into()))?;
+        self.folders.insert(to.to_string(), messages);
+        Ok(())
+    }
+
+    fn select(&mut self, mailbox: &str) -> Result<Mailbox> {
+        self.log.push(format!("SELECT {}", imap_quote(mailbox)));
+        if self.unselectable.contains(mailbox) {
+            return Err(Error::No("permission denied".into()));
+        }
+        let messages = self
+            .folders
+            .get(mailbox)
+            .ok_or_else(|| Error::No("[NONEXISTENT] no such folder".into()))?;
+        let exists = messages.len() as u32;
+        self.selected = Some(mailbox.to_string());
+        Ok(Mailbox {
+            exists,
+            ..Default::default()
+        })
+    }
+
+    fn uid_search(&mut self, query: &str) -> Result<HashSet<u32>> {
+        self.log.push(format!("UID SEARCH {query}"));
+        Ok(self.search(query)?.into_iter().collect())
+    }
+
+    fn uid_fetch(&mut self, uid_set: &str, query: &str) -> Result<ZeroCopy<Vec<Fetch>>> {
+        self.log.push(format!("UID FETCH {uid_set} {query}"));
+        let uids = parse_uid_set(uid_set);
+        let lines: String = self
+            .selected()?
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| uids.contains(&m.uid))
+            .map(|(i, m)| {
+                let (mailbox, host) = m.from.split_once('@').unwrap_or((&m.from, ""));
+                format!(
+                    "* {} FETCH (UID {} FLAGS ({}) RFC822.SIZE {} ENVELOPE (\"{}\" \"{}\" \
+                     ((NIL NIL \"{mailbox}\" \"{host}\")) NIL NIL NIL NIL NIL NIL NIL))\r\n",
+                    i + 1,
+                    m.uid,
+                    m.flags.join(" "),
+                    m.size,
+                    m.date,
+                    m.subject
+                )
+            })
+            .collect();
+        respond(&lines, |s| s.uid_fetch("1", "ALL"))
+    }
+
+    fn append(
+        &mut self,
+        mailbox: &str,
+        flags: &[Flag<'_>],
+        body: &[u8],
+        _date: Option<chrono::DateTime<chrono::FixedOffset>>,
+    ) -> Result<()> {
+        self.log.push(format!("APPEND {}", imap_quote(mailbox)));
+        let (headers, _) = mailparse::parse_headers(body).map_err(|e| Error::Bad(e.to_string()))?;
+        let header = |name: &str| headers.get_first_value(name).unwrap_or_default();
+        let target = self
+            .folders
+            .get_mut(mailbox)
+            .ok_or_else(|| Error::No("[TRYCREATE] no such folder".into()))?;
+        let uid = target.iter().map(|m| m.uid).max().unwrap_or(0) + 1;
+        target.push(FakeMessage {
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+            size: body.len() as u32,
+            ..message(uid, &header("Date"), &header("From"), &header("Subject"))
+        });
+        Ok(())
+    }
+
+    fn uid_mv(&mut self, uid_set: &str, dest: &str) -> Result<Vec<u8>> {
+        self.log
+            .push(format!("UID MOVE {uid_set} {}", imap_quote(dest)));
+        let pairs = self.transfer(uid_set, dest, true)?;
+        if pairs.is_empty() || !self.capabilities.contains("UIDPLUS") {
+            return Ok(Vec::new());
+        }
+        let list = |uids: Vec<String>| uids.join(",");
+        let (src, dst): (Vec<_>, Vec<_>) = pairs
+            .iter()
+            .map(|(s, d)| (s.to_string(), d.to_string()))
+            .unzip();
+        Ok(format!("* OK [COPYUID 1 {} {}] Moved\r\n", list(src), list(dst)).into_bytes())
+    }
+
+    fn uid_copy(&mut self, uid_set: &str, dest: &str) -> Result<()> {
+        self.log
+            .push(format!("UID COPY {uid_set} {}", imap_quote(dest)));
+        self.transfer(uid_set, dest, false).map(drop)
+    }
+
+    fn uid_store(&mut self, uid_set: &str, query: &str) -> Result<()> {
+        self.log.push(format!("UID STORE {uid_set} {query}"));
+        let (op, list) = query
+            .split_once(' ')
+            .ok_or_else(|| Error::Bad(format!("bad STORE {query}")))?;
+        let flags: Vec<String> = list
+            .trim_matches(|c| c == '(' || c == ')')
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        for m in self.matching(uid_set)? {
+            match op.to_uppercase().as_str() {
+                "+FLAGS" => {
+                    for f in &flags {
+                        if !m.flags.contains(f) {
+                            m.flags.push(f.clone());
+                        }
+                    }
+                }
+                "-FLAGS" => m.flags.retain(|f| !flags.contains(f)),
+                "FLAGS" => m.flags.clone_from(&flags),
+                _ => return Err(Error::Bad(format!("bad STORE {query}"))),
+            }
+        }
+        Ok(())
+    }
+
+    fn expunge(&mut self) -> Result<()> {
+        self.log.push("EXPUNGE".to_string());
+        self.selected()?
+            .retain(|m| !m.flags.iter().any(|f| f == "\\Deleted"));
+        Ok(())
+    }
+
+    fn uid_expunge(&mut self, uid_set: &str) -> Result<()> {
+        self.log.push(format!("UID EXPUNGE {uid_set}"));
+        let uids = parse_uid_set(uid_set);
+        self.selected()?
+            .retain(|m| !(uids.contains(&m.uid) && m.flags.iter().any(|f| f == "\\Deleted")));
+        Ok(())
+    }
+
+    fn logout(&mut self) -> Result<()> {
+        self.log.push("LOGOUT".to_string());
+        Ok(())
+    }
+
+    fn has_capability(&self, cap: &str) -> bool {
+        self.capabilities.contains(&cap.to_uppercase())
+    }
+
+    /// `UID SEARCH CHARSET <charset> <query>` and, with the SORT capability,
+    /// `UID SORT ([REVERSE] DATE|SUBJECT) <charset> <query>`. SUBJECT compares the
+    /// base subject, without "Re:" / "Fwd:", as RFC 5256 servers do.
+    fn run_command_and_read_response(&mut self, command: &str) -> Result<Vec<u8>> {
+        self.log.push(command.to_string());
+        if let Some(rest) = command.strip_prefix("UID SEARCH CHARSET ") {
+            let (_, query) = rest.split_once(' ').unwrap_or((rest, "ALL"));
+            let uids = self.search(query)?;
+            return Ok(format!("* SEARCH{}\r\n", numbers(&uids)).into_bytes());
+        }
+        if let Some(rest) = command.strip_prefix("UID SORT ") {
+            if !self.has_capability("SORT") {
+                return Err(Error::Bad("SORT not supported".into()));
+            }
+            let (key, rest) = rest
+                .split_once(") ")
+                .ok_or_else(|| Error::Bad(format!("bad SORT {rest}")))?;
+            let (_, query) = rest.split_once(' ').unwrap_or((rest, "ALL"));
+            let name = key.trim_start_matches('(');
+            let (reverse, name) = match name.strip_prefix("REVERSE ") {
+                Some(name) => (true, name),
+                None => (false, name),
+            };
+            if name != "DATE" && name != "SUBJECT" {
+                return Err(Error::Bad(format!("fake SORT can't do {key})")));
+            }
+            let uids = self.search(query)?;
+            let messages = self.selected()?;
+            let mut keyed: Vec<((i64, String), u32)> = messages
+                .iter()
+                .filter(|m| uids.contains(&m.uid))
+                .map(|m| {
+                    let key = if name == "DATE" {
+                        (mailparse::dateparse(&m.date).unwrap_or(0), String::new())
+                    } else {
+                        (0, base_subject(&m.subject))
+                    };
+                    (key, m.uid)
+                })
+                .collect();
+            keyed.sort_unstable();
+            if reverse {
+                keyed.reverse();
+            }
+            let uids: Vec<u32> = keyed.into_iter().map(|(_, uid)| uid).collect();
+            return Ok(format!("* SORT{}\r\n", numbers(&uids)).into_bytes());
+        }
+        Err(Error::Bad(format!("fake server can't do {command}")))
+    }
+}
+
+/// Lowercased subject without leading "Re:" / "Fwd:" markers.
+fn base_subject(subject: &str) -> String {
+    let mut rest = subject.trim().to_lowercase();
+    while let Some(stripped) = ["re:", "fwd:"].iter().find_map(|p| rest.strip_prefix(p)) {
+        rest = stripped.trim_start().to_string();
+    }
+    rest
+}
+
+fn numbers(uids: &[u32]) -> String {
+    uids.iter().map(|u| format!(" {u}")).collect()
+}
+
+/// UIDs of a set like `1:3,7`.
+fn parse_uid_set(set: &str) -> HashSet<u32> {
+    set.split(',')
+        .flat_map(|part| {
+            let (lo, hi) = part.split_once(':').unwrap_or((part, part));
+            let (lo, hi): (u32, u32) = (lo.parse().unwrap_or(0), hi.parse().unwrap_or(0));
+            lo.min(hi)..=lo.max(hi)
+        })
+        .collect()
+}
+
+/// Split a SEARCH query into atoms and unquoted strings; parentheses are ignored.
+fn split_query(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '(' | ')' => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut word = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => word.extend(chars.next()),
+                        '"' => break,
+                        c => word.push(c),
+                    }
+                }
+                words.push(word);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == ' ' || c == '(' || c == ')' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+    words
+}
+
+/// A stream that plays back a canned server response and ignores what is written.
+struct Replay(Cursor<Vec<u8>>);
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `command` on a throwaway session whose server answers with the untagged `lines`.
+/// imap's response types can only be built by its parser, so this is how the fake
+/// hands out `Name`s and `Fetch`es.
+fn respond<T>(
+    lines: &str,
+    command: impl FnOnce(&mut imap::Session<Replay>) -> Result<T>,
+) -> Result<T> {
+    let script = format!("a1 OK LOGIN done\r\n{lines}a2 OK done\r\n");
+    let client = imap::Client::new(Replay(Cursor::new(script.into_bytes())));
+    let mut session = client.login("fake", "fake").map_err(|e| e.0)?;
+    command(&mut session)
+}
@@ -0,0 +1,78 @@
+use anyhow::{bail, Context, Result};
+
+use crate::connection::{ImapOps, ImapSession};
+use crate::search;
+
+/// INBOX is case-insensitive in IMAP, so `inbox` names the same folder.
+pub fn is_inbox(name: &str) -> bool {
+    name.eq_ignore_ascii_case("INBOX")
+}
+
+/// Folder names go into the command line as quoted strings, which can't carry
+/// line breaks or other control characters.
+fn check_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!("Folder name must not be empty");
+    }
+    if name.chars().any(char::is_control) {
+        bail!(
+            "Folder name '{}' contains control characters",
+            name.escape_debug()
+        );
+    }
+    Ok(())
+}
+
+/// CREATE `name`; fails if it already exists.
+pub fn create_folder(session: &mut ImapSession, name: &str) -> Result<()> {
+    check_name(name)?;
+    if search::folder_exists(session, name)? {
+        bail!("Folder '{name}' already exists");
+    }
+    session
+        .create(name)
+        .with_context(|| format!("Failed to create folder '{name}'"))
+}
+
+/// DELETE `name` and every message in it. INBOX can't be deleted.
+pub fn delete_folder(session: &mut ImapSession, name: &str) -> Result<()> {
+    if is_inbox(name) {
+        bail!("Refusing to delete INBOX");
+    }
+    search::ensure_folder_exists(session, name)?;
+    session
+        .delete_mailbox(name)
+        .with_context(|| format!("Failed to delete folder '{name}'"))
+}
+
+/// RENAME `from` to `to`, along with any subfolders. `to` must not exist yet.
+pub fn rename_folder(session: &mut ImapSession, from: &str, to: &str) -> Result<()> {
+    check_name(to)?;
+    search::ensure_folder_exists(session, from)?;
+    if search::folder_exists(session, to)? {
+        bail!("Folder '{to}' already exists");
+    }
+    session
+        .rename(from, to)
+        .with_context(|| format!("Failed to rename folder '{from}' to '{to}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_inbox_ignores_case() {
+        assert!(is_inbox("INBOX"));
+        assert!(is_inbox("Inbox"));
+        assert!(!is_inbox("INBOX/Sub"));
+        assert!(!is_inbox("Archive"));
+    }
+
+    #[test]
+    fn check_name_rejects_empty_and_control_chars() {
+        assert!(check_name("Work/Clients").is_ok());
+        assert!(check_name("  ").is_err());
+        assert!(check_name("a\r\nb").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use keyring::Entry;
+use zeroize::Zeroizing;
+
+/// Keychain entries live under `slashmail:<host>`, one per IMAP user.
+fn entry(host: &str, user: &str) -> Result<Entry> {
+    Entry::new(&service_name(host), user)
+        .with_context(|| format!("Invalid keychain entry for {user} on {host}"))
+}
+
+fn service_name(host: &str) -> String {
+    format!("slashmail:{}", host.to_ascii_lowercase())
+}
+
+/// Look up a saved password. Any keychain failure (no entry, no secret service
+/// running, access denied) is treated as "not saved" so callers can fall back.
+pub fn lookup(host: &str, user: &str) -> Option<Zeroizing<String>> {
+    entry(host, user)
+        .ok()?
+        .get_password()
+        .ok()
+        .map(Zeroizing::new)
+}
+
+/// Save `pass` for `user` on `host`, replacing any existing entry.
+pub fn store(host: &str, user: &str, pass: &str) -> Result<()> {
+    entry(host, user)?
+        .set_password(pass)
+        .context("Failed to save password to the system keychain")
+}
+
+/// Delete the saved password. Returns false if there was none.
+pub fn forget(host: &str, user: &str) -> Result<bool> {
+    match entry(host, user)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("Failed to delete password from the system keychain"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_name_is_per_host() {
+        assert_eq!(
+            service_name("imap.example.com"),
+            "slashmail:imap.example.com"
+        );
+        assert_eq!(
+            service_name("IMAP.Example.com"),
+            "slashmail:imap.example.com"
+        );
+    }
+}
@@ -1,7 +1,23 @@
+pub mod append;
+pub mod attachments;
 pub mod config;
 pub mod connection;
+pub mod dedupe;
 pub mod delete;
 pub mod display;
+pub mod error;
 pub mod export;
+#[cfg(test)]
+mod fake;
+pub mod folder;
+pub mod keychain;
 pub mod read;
+pub mod report;
+pub mod response;
 pub mod search;
+pub mod stats;
+pub mod thread;
+pub mod timings;
+pub mod undo;
+pub mod unsubscribe;
+pub mod watch;
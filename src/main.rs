@@ -1,41 +1,23 @@
-use slashmail::{config, connection, delete, display, export, read, search};
+use slashmail::connection::{ImapOps, Security};
+use slashmail::report::ActionReport;
+use slashmail::response::{self, Token};
+use slashmail::timings::{self, Phase};
+use slashmail::{
+    append, attachments, config, connection, dedupe, delete, display, export, folder, keychain,
+    read, search, stats, thread, undo, unsubscribe, watch,
+};
 
 use anyhow::{bail, Context, Result};
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, Color, Table};
-use indicatif::{ProgressBar, ProgressStyle};
-use regex::Regex;
-use std::path::PathBuf;
-use std::sync::OnceLock;
-use std::time::Duration;
-use zeroize::Zeroize;
-
-fn spinner(msg: &str) -> ProgressBar {
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.cyan} {msg}")
-            .unwrap(),
-    );
-    pb.set_message(msg.to_string());
-    pb.enable_steady_tick(Duration::from_millis(80));
-    pb
-}
-
-fn quota_regex() -> &'static Regex {
-    static RE: OnceLock<Regex> = OnceLock::new();
-    RE.get_or_init(|| Regex::new(r"(?i)\*\s+QUOTA\s+.*?\(([^)]+)\)").unwrap())
-}
-
-fn quota_resource_regex() -> &'static Regex {
-    static RE: OnceLock<Regex> = OnceLock::new();
-    RE.get_or_init(|| Regex::new(r"(\w+)\s+(\d+)\s+(\d+)").unwrap())
-}
-
-fn status_regex() -> &'static Regex {
-    static RE: OnceLock<Regex> = OnceLock::new();
-    RE.get_or_init(|| Regex::new(r"(?i)\*\s+STATUS\s+.*?\(([^)]*)\)").unwrap())
-}
+use indicatif::ProgressBar;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Parser)]
 #[command(
@@ -43,11 +25,11 @@ fn status_regex() -> &'static Regex {
     about = "IMAP CLI for searching, managing, and inspecting email"
 )]
 struct Cli {
-    /// IMAP host [default: 127.0.0.1]
+    /// IMAP host or URL (imap://host[:port], imaps://host[:port]) [default: 127.0.0.1]
     #[arg(long, global = true)]
     host: Option<String>,
 
-    /// IMAP port [default: 1143 plain, 993 TLS]
+    /// IMAP port [default: 1143 plain (local bridge), 993 TLS; see default_port_* in config]
     #[arg(long, global = true)]
     port: Option<u16>,
 
@@ -55,13 +37,65 @@ struct Cli {
     #[arg(long, global = true)]
     tls: bool,
 
+    /// Connect in plaintext and upgrade with STARTTLS before login (usually port 143)
+    #[arg(long, global = true, conflicts_with = "tls")]
+    starttls: bool,
+
     /// IMAP username
     #[arg(short, long, env = "SLASHMAIL_USER", global = true)]
     user: Option<String>,
 
-    /// Path to config file
+    /// Path to config file (repeatable; later files override earlier ones)
+    #[arg(long, global = true)]
+    config: Vec<PathBuf>,
+
+    /// Leave `${VAR}` references in config values unexpanded
+    #[arg(long, global = true)]
+    no_env_expand: bool,
+
+    /// Use the [accounts.<NAME>] section of the config [default: default_account]
+    #[arg(long, env = "SLASHMAIL_ACCOUNT", global = true, value_name = "NAME")]
+    account: Option<String>,
+
+    /// Seconds to wait for connect, TLS handshake and login before giving up
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout: Option<u64>,
+
+    /// PEM file with extra root certificates to trust (e.g. a private CA), for --tls / --starttls
+    #[arg(long, global = true, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+
+    /// DANGEROUS: skip TLS certificate and hostname verification (self-signed test servers only)
     #[arg(long, global = true)]
-    config: Option<PathBuf>,
+    insecure: bool,
+
+    /// Seconds any IMAP read/write may stall before the command fails (0 = wait forever) [default: 30]
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Print time spent per phase (connect, list, select, search, fetch, act, logout) to stderr
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Disable colored output (also via NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Time zone for dates in message tables: local, utc or an offset like +02:00
+    #[arg(long, global = true, value_name = "ZONE", default_value = "local", value_parser = display::parse_date_zone)]
+    tz: display::DateZone,
+
+    /// Show dates in message tables relative to now ("3 hours ago")
+    #[arg(long, global = true)]
+    relative_dates: bool,
+
+    /// Hide spinners and informational messages; print only results, prompts and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Log each IMAP command and the size of its response to stderr (passwords are never shown)
+    #[arg(short, long, global = true)]
+    verbose: bool,
 
     /// IMAP password (or SLASHMAIL_PASS env; prompts if missing)
     #[arg(skip)]
@@ -77,20 +111,58 @@ enum Commands {
     Search(SearchArgs),
     /// Display the content of matching messages
     Read(ReadArgs),
+    /// Group matching messages into conversations (same as search --thread)
+    Thread(ThreadArgs),
+    /// Display one message by UID (headers and decoded body, or --raw source)
+    Show(ShowArgs),
     /// Search + delete matching messages (move to Trash)
     Delete(DeleteArgs),
     /// Search + move matching messages to a folder
     Move(MoveArgs),
     /// Search + export matching messages as .eml files
     Export(ExportArgs),
+    /// Search + save the attachments of matching messages to a directory
+    #[command(visible_alias = "download")]
+    DownloadAttachments(DownloadArgs),
     /// Search + set/unset flags on matching messages
     Mark(MarkArgs),
+    /// Upload .eml files into a folder
+    Append(AppendArgs),
+    /// Permanently delete messages from Trash, optionally only those older than an age
+    #[command(visible_alias = "empty-trash")]
+    Purge(PurgeArgs),
+    /// Move duplicate messages (same Message-ID in a folder) to Trash, keeping the first
+    Dedupe(DedupeArgs),
+    /// Move the messages of the last `move` or `delete` back where they came from
+    Undo(UndoArgs),
     /// Count matching messages (no FETCH)
     Count(CountArgs),
+    /// Write the raw RFC 822 source of a single message to stdout
+    Cat(CatArgs),
+    /// Dump the raw bytes of one BODY section of a message
+    Fetch(FetchArgs),
+    /// Create, delete or rename folders
+    #[command(subcommand)]
+    Folder(FolderCommand),
     /// Show mailbox quota usage
-    Quota,
+    Quota(QuotaArgs),
     /// Show per-folder message statistics
     Status,
+    /// List the capabilities the server advertises, highlighting the ones slashmail uses
+    Capabilities,
+    /// Check that the server is reachable and the login works (NOOP round trip)
+    #[command(alias = "noop")]
+    Ping,
+    /// Show the top senders by message count and by total size
+    Stats(StatsArgs),
+    /// List the unsubscribe links (List-Unsubscribe) of matching mailing-list messages
+    Unsubscribe(UnsubscribeArgs),
+    /// Wait for new mail with IDLE and print each message as it arrives
+    Watch(WatchArgs),
+    /// Prompt for the password, check it with the server and save it in the system keychain
+    Login,
+    /// Delete the password saved by `login` from the system keychain
+    Forget,
     /// Generate shell completions
     Completions {
         /// Shell to generate for (bash, zsh, fish, powershell, elvish)
@@ -108,20 +180,44 @@ struct FilterArgs {
     #[arg(short, long)]
     folder: Option<String>,
 
-    /// Search across all folders (excludes Trash, Spam)
+    /// Search across all folders (excludes Trash, Spam, All Mail and config skip_folders)
     #[arg(long)]
     all_folders: bool,
 
-    /// Subject contains
+    /// With --all-folders, also search Trash, Spam, All Mail and config skip_folders
+    #[arg(long, visible_aliases = ["no-skip", "include-trash"], requires = "all_folders")]
+    include_skipped: bool,
+
+    /// Charset announced with SEARCH/SORT/THREAD [default: UTF-8]
     #[arg(long)]
-    subject: Option<String>,
+    charset: Option<String>,
 
-    /// From address contains
+    /// Summarize inaccessible folders in one line instead of warning per folder
     #[arg(long)]
-    from: Option<String>,
+    quiet_warnings: bool,
 
-    /// To address contains
+    /// Config `skip_folders`, filled in after parsing
+    #[arg(skip)]
+    skip_folders: Vec<String>,
+
+    /// Subject contains (repeatable; any may match)
+    #[arg(long)]
+    subject: Vec<String>,
+
+    /// From address contains (repeatable; any may match)
+    #[arg(long)]
+    from: Vec<String>,
+
+    /// Exclude messages whose subject contains this
+    #[arg(long)]
+    not_subject: Option<String>,
+
+    /// Exclude messages whose From address contains this
     #[arg(long)]
+    not_from: Option<String>,
+
+    /// To address contains
+    #[arg(long, id = "filter_to")]
     to: Option<String>,
 
     /// CC address contains
@@ -136,6 +232,10 @@ struct FilterArgs {
     #[arg(long)]
     text: Option<String>,
 
+    /// Header field contains, e.g. "List-Id:rust-users" (repeatable; empty value matches any)
+    #[arg(long, value_name = "NAME:VALUE", value_parser = parse_header_filter)]
+    header: Vec<(String, String)>,
+
     /// Only read messages
     #[arg(long, conflicts_with = "unseen")]
     seen: bool,
@@ -144,37 +244,61 @@ struct FilterArgs {
     #[arg(long, conflicts_with = "seen")]
     unseen: bool,
 
-    /// Messages since date (YYYY-MM-DD or 7d, 2w, 3m, 1y)
+    /// Messages since date (YYYY-MM-DD, 7d, 2w, 3m, 1y, today, yesterday, last-week, ...)
     #[arg(long)]
     since: Option<String>,
 
-    /// Messages before date (YYYY-MM-DD or 7d, 2w, 3m, 1y)
+    /// Messages before date (YYYY-MM-DD, 7d, 2w, 3m, 1y, today, yesterday, last-week, ...)
     #[arg(long)]
     before: Option<String>,
 
-    /// Messages larger than N bytes (supports K/M suffix)
+    /// Messages on exactly this day (same formats as --since); not with --since/--before
+    #[arg(long, conflicts_with_all = ["since", "before"])]
+    on: Option<String>,
+
+    /// Apply --since/--before/--on to the Date: header instead of the received date
+    #[arg(long)]
+    sent: bool,
+
+    /// Messages larger than N bytes (K/M/G/T or KiB/MiB/GiB suffix)
     #[arg(long)]
     larger: Option<String>,
 
-    /// Messages smaller than N bytes (supports K/M suffix)
+    /// Messages smaller than N bytes (K/M/G/T or KiB/MiB/GiB suffix)
     #[arg(long)]
     smaller: Option<String>,
 
     /// Only flagged/starred messages
-    #[arg(long, conflicts_with = "unflagged")]
+    #[arg(long, id = "filter_flagged", conflicts_with = "filter_unflagged")]
     flagged: bool,
 
     /// Only unflagged messages
-    #[arg(long, conflicts_with = "flagged")]
+    #[arg(long, id = "filter_unflagged", conflicts_with = "filter_flagged")]
     unflagged: bool,
 
     /// Only replied-to messages
-    #[arg(long)]
+    #[arg(long, id = "filter_answered", conflicts_with = "filter_unanswered")]
     answered: bool,
 
+    /// Only messages not replied to
+    #[arg(long, id = "filter_unanswered", conflicts_with = "filter_answered")]
+    unanswered: bool,
+
     /// Only draft messages
-    #[arg(long)]
+    #[arg(long, id = "filter_draft")]
     draft: bool,
+
+    /// Extra IMAP search keys, sent as-is and ANDed with the other filters
+    #[arg(long, value_name = "KEYS")]
+    raw_query: Option<String>,
+
+    /// Gmail search syntax, e.g. 'has:attachment older_than:1y' (Gmail only, X-GM-RAW)
+    #[arg(long, value_name = "QUERY")]
+    gmail_raw: Option<String>,
+
+    /// Only messages with this Gmail label (Gmail only, X-GM-LABELS)
+    #[arg(long, value_name = "NAME")]
+    label: Option<String>,
 }
 
 #[derive(Parser)]
@@ -183,12 +307,71 @@ struct SearchArgs {
     filter: FilterArgs,
 
     /// Limit number of results
-    #[arg(short = 'n', long)]
+    #[arg(short = 'n', long, conflicts_with_all = ["first", "last"])]
     limit: Option<usize>,
 
+    /// Skip the first N results after sorting, for paging with -n
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with = "thread")]
+    offset: usize,
+
+    /// Show the N oldest matches, oldest first
+    #[arg(long, value_name = "N", conflicts_with = "last")]
+    first: Option<usize>,
+
+    /// Show the N newest matches, newest first (same as -n N)
+    #[arg(long, value_name = "N")]
+    last: Option<usize>,
+
+    /// Order results by date (newest first), size (largest first), from or subject (A-Z)
+    #[arg(long, value_enum, default_value_t = SortBy::Date, conflicts_with_all = ["first", "last"])]
+    sort: SortBy,
+
+    /// Reverse the --sort order
+    #[arg(long, conflicts_with_all = ["first", "last"])]
+    reverse: bool,
+
+    /// Table columns to show, in order (comma-separated)
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "COLS")]
+    columns: Vec<ColumnArg>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
     /// Output as JSON
     #[arg(long)]
     json: bool,
+
+    /// With JSON output, wrap it as {"slashmail_version", "command", "results"}
+    #[arg(long)]
+    json_envelope: bool,
+
+    /// Group results into conversations (server THREAD, else by subject)
+    #[arg(long, conflicts_with_all = ["json", "all_folders"])]
+    thread: bool,
+
+    /// With --all-folders, search folders over N parallel IMAP connections
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "all_folders",
+        value_parser = clap::value_parser!(u16).range(1..=MAX_JOBS)
+    )]
+    jobs: u16,
+}
+
+/// Upper bound for `search --jobs`; many servers cap connections per account (Gmail: 15).
+const MAX_JOBS: i64 = 8;
+
+#[derive(Parser)]
+struct ThreadArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Limit number of messages grouped (newest first)
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
 }
 
 #[derive(Parser)]
@@ -201,6 +384,76 @@ struct ReadArgs {
     limit: Option<usize>,
 }
 
+/// Format for `search --format` / `count --format`; `--json` is short for `--format json`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+    Tsv,
+}
+
+/// Format for `export --format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Eml,
+    Mbox,
+    Maildir,
+}
+
+/// Values for `search --sort`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortBy {
+    Date,
+    Size,
+    From,
+    Subject,
+}
+
+impl From<SortBy> for search::SortKey {
+    fn from(sort: SortBy) -> Self {
+        match sort {
+            SortBy::Date => search::SortKey::Date,
+            SortBy::Size => search::SortKey::Size,
+            SortBy::From => search::SortKey::From,
+            SortBy::Subject => search::SortKey::Subject,
+        }
+    }
+}
+
+/// Values for `search --columns`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColumnArg {
+    Uid,
+    /// Read/replied/flagged state: N unread, R replied, ! flagged, D deleted
+    State,
+    Folder,
+    From,
+    Subject,
+    Date,
+    Size,
+}
+
+impl From<ColumnArg> for display::Column {
+    fn from(column: ColumnArg) -> Self {
+        match column {
+            ColumnArg::Uid => display::Column::Uid,
+            ColumnArg::State => display::Column::State,
+            ColumnArg::Folder => display::Column::Folder,
+            ColumnArg::From => display::Column::From,
+            ColumnArg::Subject => display::Column::Subject,
+            ColumnArg::Date => display::Column::Date,
+            ColumnArg::Size => display::Column::Size,
+        }
+    }
+}
+
+/// Format for `--report`, printed as the last line of stdout.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    Json,
+}
+
 #[derive(Parser)]
 struct DeleteArgs {
     #[command(flatten)]
@@ -214,16 +467,46 @@ struct DeleteArgs {
     #[arg(short = 'n', long)]
     limit: Option<usize>,
 
+    /// Act on the UIDs in FILE ('-' for stdin) instead of searching: numbers, or `search --format json` output
+    #[arg(long, value_name = "FILE", conflicts_with = "all_folders")]
+    uids_from: Option<PathBuf>,
+
     /// Skip confirmation (batch mode)
     #[arg(long)]
     yes: bool,
 
+    /// Tick the messages to act on from a checklist instead of confirming them all
+    #[arg(long, conflicts_with = "yes")]
+    interactive: bool,
+
     /// Show what would be deleted without acting
     #[arg(long)]
     dry_run: bool,
+
+    /// Permanently delete (flag \Deleted and expunge) instead of moving to Trash
+    #[arg(long, visible_alias = "purge", conflicts_with = "trash_folder")]
+    expunge: bool,
+
+    /// Mark messages read before moving them to Trash
+    #[arg(long, conflicts_with = "expunge")]
+    mark_read_on_delete: bool,
+
+    /// Create the trash folder if it doesn't exist
+    #[arg(long, conflicts_with = "expunge")]
+    create: bool,
+
+    /// With --dry-run, also print the exact IMAP commands that would be sent
+    #[arg(long, requires = "dry_run")]
+    show_commands: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
 }
 
+// `--to` is the destination here, so the recipient filter is `--recipient`
 #[derive(Parser)]
+#[command(mut_arg("filter_to", |a| a.long("recipient")))]
 struct MoveArgs {
     #[command(flatten)]
     filter: FilterArgs,
@@ -232,17 +515,37 @@ struct MoveArgs {
     #[arg(long)]
     to: String,
 
+    /// Create the destination folder if it doesn't exist
+    #[arg(long)]
+    create: bool,
+
     /// Limit number of messages to act on
     #[arg(short = 'n', long)]
     limit: Option<usize>,
 
+    /// Act on the UIDs in FILE ('-' for stdin) instead of searching: numbers, or `search --format json` output
+    #[arg(long, value_name = "FILE", conflicts_with = "all_folders")]
+    uids_from: Option<PathBuf>,
+
     /// Skip confirmation
     #[arg(long)]
     yes: bool,
 
+    /// Tick the messages to act on from a checklist instead of confirming them all
+    #[arg(long, conflicts_with = "yes")]
+    interactive: bool,
+
     /// Show what would be moved without acting
     #[arg(long)]
     dry_run: bool,
+
+    /// With --dry-run, also print the exact IMAP commands that would be sent
+    #[arg(long, requires = "dry_run")]
+    show_commands: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
 }
 
 #[derive(Parser)]
@@ -258,20 +561,114 @@ struct ExportArgs {
     #[arg(short, long)]
     output_dir: Option<PathBuf>,
 
+    /// One .eml file per message, a single mbox file (with --output-file) or a Maildir
+    #[arg(long, value_enum, default_value_t = ExportFormat::Eml)]
+    format: ExportFormat,
+
+    /// Skip confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// With --format mbox: write all messages to this one mbox file
+    #[arg(long, value_name = "FILE", conflicts_with = "output_dir")]
+    output_file: Option<PathBuf>,
+
+    /// Name .eml files from {uid}, {folder}, {subject}, {from} and {date}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = export::DEFAULT_FILENAME_TEMPLATE,
+        value_parser = export::parse_filename_template
+    )]
+    filename_template: String,
+
+    /// Overwrite existing .eml files (or the --output-file)
+    #[arg(long)]
+    force: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+#[derive(Parser)]
+struct DownloadArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Limit number of results
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    /// Directory to save attachments in (default: current directory)
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Only save attachments whose filename matches this glob (e.g. '*.pdf')
+    #[arg(long, value_name = "GLOB", value_parser = attachments::parse_name_filter)]
+    name_filter: Option<regex::Regex>,
+
     /// Skip confirmation
     #[arg(long)]
     yes: bool,
 
-    /// Overwrite existing .eml files
+    /// Overwrite existing files
     #[arg(long)]
     force: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
 }
 
+// The flag options set flags here, so the matching filters are spelled `--is-*`
 #[derive(Parser)]
+#[command(
+    mut_arg("filter_flagged", |a| a.long("is-flagged")),
+    mut_arg("filter_unflagged", |a| a.long("is-unflagged")),
+    mut_arg("filter_answered", |a| a.long("is-answered")),
+    mut_arg("filter_unanswered", |a| a.long("is-unanswered")),
+    mut_arg("filter_draft", |a| a.long("is-draft"))
+)]
 struct MarkArgs {
     #[command(flatten)]
     filter: FilterArgs,
 
+    #[command(flatten)]
+    flags: MarkFlags,
+
+    /// Limit number of messages to act on
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    /// Act on the UIDs in FILE ('-' for stdin) instead of searching: numbers, or `search --format json` output
+    #[arg(long, value_name = "FILE", conflicts_with = "all_folders")]
+    uids_from: Option<PathBuf>,
+
+    /// Skip confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// Tick the messages to act on from a checklist instead of confirming them all
+    #[arg(long, conflicts_with = "yes")]
+    interactive: bool,
+
+    /// Show what would be changed without acting
+    #[arg(long)]
+    dry_run: bool,
+
+    /// With --dry-run, also print the exact IMAP commands that would be sent
+    #[arg(long, requires = "dry_run")]
+    show_commands: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+/// The flag changes `mark` applies.
+#[derive(Parser, Default)]
+struct MarkFlags {
     /// Mark as read (\Seen)
     #[arg(long)]
     read: bool,
@@ -288,616 +685,3113 @@ struct MarkArgs {
     #[arg(long)]
     unflagged: bool,
 
-    /// Limit number of messages to act on
-    #[arg(short = 'n', long)]
-    limit: Option<usize>,
+    /// Set \Answered
+    #[arg(long)]
+    answered: bool,
 
-    /// Skip confirmation
+    /// Remove \Answered
     #[arg(long)]
-    yes: bool,
+    unanswered: bool,
 
-    /// Show what would be changed without acting
+    /// Set \Draft
     #[arg(long)]
-    dry_run: bool,
-}
+    draft: bool,
 
-#[derive(Parser)]
-struct CountArgs {
-    #[command(flatten)]
-    filter: FilterArgs,
+    /// Remove \Draft
+    #[arg(long)]
+    undraft: bool,
 
-    /// Output as JSON
+    /// Set \Deleted (the messages stay until expunged, e.g. by `delete --expunge`)
     #[arg(long)]
-    json: bool,
+    deleted: bool,
+
+    /// Remove \Deleted
+    #[arg(long)]
+    undeleted: bool,
+
+    /// Set a custom keyword such as $Important or Work (repeatable)
+    #[arg(long, value_name = "KEYWORD", value_parser = parse_keyword)]
+    add_keyword: Vec<String>,
+
+    /// Remove a custom keyword (repeatable)
+    #[arg(long, value_name = "KEYWORD", value_parser = parse_keyword)]
+    remove_keyword: Vec<String>,
 }
 
-impl FilterArgs {
-    fn to_criteria(&self, limit: Option<usize>, default_folder: &str) -> search::SearchCriteria {
-        search::SearchCriteria {
-            folder: self
-                .folder
-                .clone()
-                .unwrap_or_else(|| default_folder.to_string()),
-            all_folders: self.all_folders,
-            subject: self.subject.clone(),
-            from: self.from.clone(),
-            to: self.to.clone(),
-            cc: self.cc.clone(),
-            body: self.body.clone(),
-            text: self.text.clone(),
-            seen: self.seen,
-            unseen: self.unseen,
-            since: self.since.clone(),
-            before: self.before.clone(),
-            larger: self.larger.clone(),
-            smaller: self.smaller.clone(),
-            flagged: self.flagged,
-            unflagged: self.unflagged,
-            answered: self.answered,
-            draft: self.draft,
-            limit,
-        }
-    }
+/// A system flag `mark` can set or remove.
+struct SystemFlag {
+    flag: &'static str,
+    set_option: &'static str,
+    unset_option: &'static str,
+    set_desc: &'static str,
+    unset_desc: &'static str,
 }
 
-fn get_password() -> Result<String> {
-    if let Ok(p) = std::env::var("SLASHMAIL_PASS") {
-        if !p.is_empty() {
-            return Ok(p);
-        }
+const SYSTEM_FLAGS: [SystemFlag; 5] = [
+    SystemFlag {
+        flag: "\\Seen",
+        set_option: "--read",
+        unset_option: "--unread",
+        set_desc: "mark read",
+        unset_desc: "mark unread",
+    },
+    SystemFlag {
+        flag: "\\Flagged",
+        set_option: "--flagged",
+        unset_option: "--unflagged",
+        set_desc: "flag",
+        unset_desc: "unflag",
+    },
+    SystemFlag {
+        flag: "\\Answered",
+        set_option: "--answered",
+        unset_option: "--unanswered",
+        set_desc: "mark answered",
+        unset_desc: "mark unanswered",
+    },
+    SystemFlag {
+        flag: "\\Draft",
+        set_option: "--draft",
+        unset_option: "--undraft",
+        set_desc: "mark draft",
+        unset_desc: "mark not draft",
+    },
+    SystemFlag {
+        flag: "\\Deleted",
+        set_option: "--deleted",
+        unset_option: "--undeleted",
+        set_desc: "mark deleted",
+        unset_desc: "undelete",
+    },
+];
+
+impl MarkFlags {
+    /// `(set, unset)` for each of `SYSTEM_FLAGS`, in the same order.
+    fn system(&self) -> [(bool, bool); 5] {
+        [
+            (self.read, self.unread),
+            (self.flagged, self.unflagged),
+            (self.answered, self.unanswered),
+            (self.draft, self.undraft),
+            (self.deleted, self.undeleted),
+        ]
     }
-    inquire::Password::new("IMAP password:")
-        .without_confirmation()
-        .prompt()
-        .context("Password prompt failed")
 }
 
-fn cmd_quota(session: &mut connection::ImapSession) -> Result<()> {
-    if !session.has_capability("QUOTA") {
-        bail!("Server does not support QUOTA extension (RFC 2087)");
-    }
+#[derive(Parser)]
+struct QuotaArgs {
+    /// Folder whose quota roots to show [default: INBOX]
+    #[arg(short, long)]
+    folder: Option<String>,
+}
 
-    let sp = spinner("Fetching quota...");
-    let response = session
-        .run_command_and_read_response("GETQUOTAROOT INBOX")
-        .context("GETQUOTAROOT failed")?;
-    sp.finish_and_clear();
+#[derive(Parser)]
+struct StatsArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
 
-    let text = String::from_utf8_lossy(&response);
+    /// Number of senders to show in each table
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    top: u32,
+}
 
-    // Parse: * QUOTA "root" (STORAGE used limit) (MESSAGE used limit) ...
-    let mut rows: Vec<(String, u64, u64)> = Vec::new();
-    for cap in quota_regex().captures_iter(&text) {
-        let inner = &cap[1];
-        if let Some(m) = quota_resource_regex().captures(inner) {
-            let name = m[1].to_string();
-            let used: u64 = m[2].parse().unwrap_or(0);
-            let limit: u64 = m[3].parse().unwrap_or(0);
-            rows.push((name, used, limit));
-        }
-    }
+#[derive(Subcommand)]
+enum FolderCommand {
+    /// Create a folder (with any missing parent folders, on most servers)
+    Create {
+        /// Folder name, using the server's hierarchy separator (e.g. Work/Clients)
+        name: String,
+    },
+    /// Permanently delete a folder and every message in it
+    Delete {
+        name: String,
 
-    if rows.is_empty() {
-        println!("No quota information available.");
-        return Ok(());
-    }
+        /// Skip confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Rename a folder, along with its subfolders
+    Rename { old: String, new: String },
+}
 
-    let mut table = Table::new();
-    table.load_preset(UTF8_FULL_CONDENSED);
-    table.set_header(vec!["Resource", "Used", "Limit", "Usage"]);
+#[derive(Parser)]
+struct UnsubscribeArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
 
-    for (name, used, limit) in &rows {
-        let (used_str, limit_str) = if name.eq_ignore_ascii_case("STORAGE") {
-            // STORAGE values are in KB
-            (
-                display::format_size(used * 1024),
-                display::format_size(limit * 1024),
-            )
-        } else {
-            (used.to_string(), limit.to_string())
-        };
+    /// Limit number of results
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
 
-        let pct = if *limit > 0 {
-            *used as f64 / *limit as f64 * 100.0
-        } else {
-            0.0
-        };
-        let pct_str = format!("{pct:.1}%");
+    /// Open one link per list in the browser or mail client (web links first)
+    #[arg(long)]
+    open: bool,
 
-        let mut row = vec![Cell::new(name), Cell::new(&used_str), Cell::new(&limit_str)];
-        let pct_cell = if pct >= 90.0 {
-            Cell::new(&pct_str).fg(Color::Red)
-        } else if pct >= 75.0 {
-            Cell::new(&pct_str).fg(Color::Yellow)
-        } else {
-            Cell::new(&pct_str)
-        };
-        row.push(pct_cell);
-        table.add_row(row);
+    /// With --open: skip confirmation
+    #[arg(long, requires = "open")]
+    yes: bool,
+}
+
+#[derive(Parser)]
+struct DedupeArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Destination trash folder [default: Trash]
+    #[arg(long)]
+    trash_folder: Option<String>,
+
+    /// Skip confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// List the duplicate sets without moving anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+#[derive(Parser)]
+struct UndoArgs {
+    /// Skip confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// Show what would be moved back without acting
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+#[derive(Parser)]
+struct PurgeArgs {
+    /// Only purge messages older than AGE (e.g. 30d, 2w, 6m, or YYYY-MM-DD)
+    #[arg(long, value_name = "AGE")]
+    older_than: Option<String>,
+
+    /// Only purge messages newer than AGE
+    #[arg(long, value_name = "AGE")]
+    newer_than: Option<String>,
+
+    /// Trash folder to purge [default: Trash]
+    #[arg(long)]
+    trash_folder: Option<String>,
+
+    /// Skip confirmation
+    #[arg(long)]
+    yes: bool,
+
+    /// Show what would be purged without acting
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+#[derive(Parser)]
+struct CountArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Output format (table or json)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
+    /// Output as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// With JSON output, wrap it as {"slashmail_version", "command", "results"}
+    #[arg(long)]
+    json_envelope: bool,
+
+    /// Print nothing; exit 0 if any message matches, 1 if none
+    #[arg(long, conflicts_with = "json")]
+    silent: bool,
+}
+
+#[derive(Parser)]
+struct CatArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// UID of the message in --folder (instead of search filters)
+    #[arg(long, conflicts_with = "all_folders")]
+    uid: Option<u32>,
+}
+
+#[derive(Parser)]
+struct AppendArgs {
+    /// .eml files to upload
+    #[arg(required = true, value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Destination folder [default: INBOX]
+    #[arg(short, long)]
+    folder: Option<String>,
+
+    /// Store the messages as read (\Seen)
+    #[arg(long)]
+    read: bool,
+
+    /// Store the messages as flagged (\Flagged)
+    #[arg(long)]
+    flagged: bool,
+
+    /// Internal (delivery) date: YYYY-MM-DD, RFC 3339 or RFC 2822 [default: now]
+    #[arg(long, value_parser = append::parse_append_date)]
+    date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Print a machine-readable summary of the outcome (matched/acted/skipped/failed per folder)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+}
+
+#[derive(Parser)]
+struct ShowArgs {
+    /// UID of the message
+    uid: u32,
+
+    /// Folder containing the message [default: INBOX]
+    #[arg(short, long)]
+    folder: Option<String>,
+
+    /// Print the full raw source instead of the decoded body
+    #[arg(long)]
+    raw: bool,
+}
+
+#[derive(Parser)]
+struct WatchArgs {
+    /// Folder to watch [default: INBOX]
+    #[arg(short, long)]
+    folder: Option<String>,
+
+    /// Seconds before IDLE is ended and re-issued, so the server doesn't drop the connection
+    #[arg(long, value_name = "SECS", default_value_t = watch::DEFAULT_IDLE_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    idle_timeout: u64,
+}
+
+#[derive(Parser)]
+struct FetchArgs {
+    /// Folder containing the message [default: INBOX]
+    #[arg(short, long)]
+    folder: Option<String>,
+
+    /// UID of the message
+    #[arg(long)]
+    uid: u32,
+
+    /// Section to fetch (e.g. 1.2, 2.MIME, HEADER, TEXT)
+    #[arg(long)]
+    section: String,
+
+    /// Write the section to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Resolve a command's `--format` against its `--json` / `--json-envelope`.
+fn resolve_format(format: OutputFormat, json: bool, json_envelope: bool) -> Result<OutputFormat> {
+    let format = if json { OutputFormat::Json } else { format };
+    if json_envelope && format != OutputFormat::Json {
+        bail!("--json-envelope requires --json or --format json");
     }
+    Ok(format)
+}
 
-    println!("{table}");
-    Ok(())
+/// The output format for `command` (a table for everything but `search` and `count`),
+/// rejecting option combinations that have no such output.
+fn command_format(command: &Commands) -> Result<OutputFormat> {
+    match command {
+        Commands::Search(args) => {
+            let format = resolve_format(args.format, args.json, args.json_envelope)?;
+            if args.thread && format != OutputFormat::Table {
+                bail!("--thread only supports table output");
+            }
+            if !args.columns.is_empty() && (args.thread || format != OutputFormat::Table) {
+                bail!("--columns only applies to the message table");
+            }
+            Ok(format)
+        }
+        Commands::Count(args) => {
+            let format = resolve_format(args.format, args.json, args.json_envelope)?;
+            if args.silent && format != OutputFormat::Table {
+                bail!("--silent prints nothing; it cannot be combined with --format");
+            }
+            if !matches!(format, OutputFormat::Table | OutputFormat::Json) {
+                bail!("count supports --format table or json");
+            }
+            Ok(format)
+        }
+        Commands::Export(args) => {
+            if args.format != ExportFormat::Eml
+                && args.filename_template != export::DEFAULT_FILENAME_TEMPLATE
+            {
+                bail!("--filename-template only applies to .eml export");
+            }
+            match (args.format, &args.output_file) {
+                (ExportFormat::Mbox, None) => bail!("--format mbox needs --output-file <FILE>"),
+                (ExportFormat::Eml | ExportFormat::Maildir, Some(_)) => {
+                    bail!("--output-file needs --format mbox")
+                }
+                _ => Ok(OutputFormat::Table),
+            }
+        }
+        _ => Ok(OutputFormat::Table),
+    }
 }
 
-fn cmd_status(session: &mut connection::ImapSession) -> Result<()> {
-    let sp = spinner("Fetching folder status...");
-    let folders = session
-        .list(Some(""), Some("*"))
-        .context("Failed to list folders")?;
-    let folder_names: Vec<String> = folders.iter().map(|f| f.name().to_string()).collect();
+impl SearchArgs {
+    /// Resolve -n/--first/--last/--reverse into (limit, invert_order).
+    fn limit_and_order(&self) -> (Option<usize>, bool) {
+        match (self.first, self.last) {
+            (Some(n), _) => (Some(n), true),
+            (None, Some(n)) => (Some(n), false),
+            (None, None) => (self.limit, self.reverse),
+        }
+    }
+}
+
+impl Commands {
+    /// The search filters of commands that take them.
+    fn filter_mut(&mut self) -> Option<&mut FilterArgs> {
+        match self {
+            Commands::Search(args) => Some(&mut args.filter),
+            Commands::Read(args) => Some(&mut args.filter),
+            Commands::Thread(args) => Some(&mut args.filter),
+            Commands::Delete(args) => Some(&mut args.filter),
+            Commands::Move(args) => Some(&mut args.filter),
+            Commands::Export(args) => Some(&mut args.filter),
+            Commands::DownloadAttachments(args) => Some(&mut args.filter),
+            Commands::Mark(args) => Some(&mut args.filter),
+            Commands::Dedupe(args) => Some(&mut args.filter),
+            Commands::Count(args) => Some(&mut args.filter),
+            Commands::Cat(args) => Some(&mut args.filter),
+            Commands::Stats(args) => Some(&mut args.filter),
+            Commands::Unsubscribe(args) => Some(&mut args.filter),
+            _ => None,
+        }
+    }
+}
+
+impl FilterArgs {
+    fn to_criteria(&self, limit: Option<usize>, default_folder: &str) -> search::SearchCriteria {
+        search::SearchCriteria {
+            folder: self
+                .folder
+                .clone()
+                .unwrap_or_else(|| default_folder.to_string()),
+            all_folders: self.all_folders,
+            include_skipped: self.include_skipped,
+            skip_folders: self.skip_folders.clone(),
+            subject: self.subject.clone(),
+            from: self.from.clone(),
+            not_subject: self.not_subject.clone(),
+            not_from: self.not_from.clone(),
+            to: self.to.clone(),
+            cc: self.cc.clone(),
+            body: self.body.clone(),
+            text: self.text.clone(),
+            headers: self.header.clone(),
+            seen: self.seen,
+            unseen: self.unseen,
+            since: self.since.clone(),
+            before: self.before.clone(),
+            on: self.on.clone(),
+            sent: self.sent,
+            larger: self.larger.clone(),
+            smaller: self.smaller.clone(),
+            flagged: self.flagged,
+            unflagged: self.unflagged,
+            answered: self.answered,
+            unanswered: self.unanswered,
+            draft: self.draft,
+            raw: self.raw_query.clone(),
+            gmail_raw: self.gmail_raw.clone(),
+            label: self.label.clone(),
+            sort: search::SortKey::Date,
+            invert_order: false,
+            charset: self.charset.clone(),
+            quiet_warnings: self.quiet_warnings,
+            offset: 0,
+            uids: None,
+            limit,
+        }
+    }
+}
+
+/// Connection target from `--host` / config `host`: a bare hostname, or an
+/// `imap://` / `imaps://` URL that also sets the port and TLS.
+#[derive(Debug, PartialEq)]
+struct HostTarget {
+    host: String,
+    port: Option<u16>,
+    tls: Option<bool>,
+}
+
+fn parse_host(value: &str) -> Result<HostTarget> {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return Ok(HostTarget {
+            host: value.to_string(),
+            port: None,
+            tls: None,
+        });
+    };
+    let tls = match scheme.to_ascii_lowercase().as_str() {
+        "imap" => false,
+        "imaps" => true,
+        _ => bail!("Unsupported scheme '{scheme}' in host URL; use imap:// or imaps://"),
+    };
+    let authority = rest.strip_suffix('/').unwrap_or(rest);
+    if authority.contains('/') {
+        bail!("Host URL '{value}' must not contain a path");
+    }
+    if authority.contains('@') {
+        bail!("Credentials in host URL '{value}' are not supported; use --user");
+    }
+
+    // [v6addr]:port, host:port or host
+    let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+        match v6.split_once(']') {
+            Some((addr, "")) => (addr, None),
+            Some((addr, tail)) => match tail.strip_prefix(':') {
+                Some(port) => (addr, Some(port)),
+                None => bail!("Invalid host URL '{value}'"),
+            },
+            None => bail!("Invalid host URL '{value}'"),
+        }
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() {
+        bail!("Host URL '{value}' has no host");
+    }
+    let port = port
+        .map(|p| {
+            p.parse::<u16>()
+                .with_context(|| format!("Invalid port '{p}' in host URL '{value}'"))
+        })
+        .transpose()?;
+
+    Ok(HostTarget {
+        host: host.to_string(),
+        port,
+        tls: Some(tls),
+    })
+}
+
+/// Report search progress on `sp`: the folder being searched, then a bar of its
+/// messages as they are fetched.
+fn show_search_progress(sp: &ProgressBar, progress: search::SearchProgress<'_>) {
+    match progress {
+        search::SearchProgress::Folder {
+            folder,
+            index,
+            total,
+        } => {
+            display::show_spinner(sp);
+            sp.set_message(format!("Searching {folder} ({index}/{total})..."));
+        }
+        search::SearchProgress::Fetched { done, total } if total > 0 => {
+            display::show_count(sp, done, total);
+        }
+        search::SearchProgress::Fetched { .. } => {}
+    }
+}
+
+/// `--timeout` when neither the flag nor the config sets one.
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Put a plain "timed out" message in front of a socket timeout buried in `err`.
+fn timeout_error(err: anyhow::Error, secs: u64) -> anyhow::Error {
+    if connection::is_timeout(&err) {
+        err.context(format!(
+            "Operation timed out after {secs}s (raise it with --timeout, or 0 to wait forever)"
+        ))
+    } else {
+        err
+    }
+}
 
+/// Pick the connection security: `--tls` / `--starttls` > host URL scheme > config.
+/// `imaps://` already means implicit TLS, so it cannot be combined with STARTTLS.
+fn resolve_security(
+    cli_tls: bool,
+    cli_starttls: bool,
+    url_tls: Option<bool>,
+    cfg: &config::Config,
+) -> Result<Security> {
+    if cli_tls {
+        return Ok(Security::Tls);
+    }
+    if cli_starttls {
+        if url_tls == Some(true) {
+            bail!("--starttls cannot be used with an imaps:// host (it already uses TLS)");
+        }
+        return Ok(Security::StartTls);
+    }
+    Ok(match url_tls.or(cfg.tls) {
+        Some(true) => Security::Tls,
+        _ if cfg.starttls.unwrap_or(false) => Security::StartTls,
+        _ => Security::Plain,
+    })
+}
+
+/// Split a `--header` value at its first colon into (field name, substring).
+fn parse_header_filter(value: &str) -> Result<(String, String)> {
+    let Some((name, text)) = value.split_once(':') else {
+        bail!("Invalid --header '{value}': expected NAME:VALUE, e.g. List-Id:rust-users");
+    };
+    let name = name.trim();
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        bail!("Invalid --header '{value}': '{name}' is not a header field name");
+    }
+    Ok((name.to_string(), text.trim_start().to_string()))
+}
+
+fn get_password(pass_command: Option<&str>, host: &str, user: &str) -> Result<String> {
+    if let Ok(p) = std::env::var("SLASHMAIL_PASS") {
+        if !p.is_empty() {
+            return Ok(p);
+        }
+    }
+    if let Some(cmd) = pass_command {
+        return run_pass_command(cmd);
+    }
+    if let Some(p) = keychain::lookup(host, user) {
+        return Ok(p.to_string());
+    }
+    prompt_password()
+}
+
+fn prompt_password() -> Result<String> {
+    inquire::Password::new("IMAP password:")
+        .without_confirmation()
+        .prompt()
+        .context("Password prompt failed")
+}
+
+/// Run `cmd` through the shell and return its stdout without the trailing newline.
+/// Stdin and stderr stay attached, so tools like `gpg` can still prompt.
+fn run_pass_command(cmd: &str) -> Result<String> {
+    #[cfg(windows)]
+    let mut command = std::process::Command::new("cmd");
+    #[cfg(windows)]
+    command.args(["/C", cmd]);
+    #[cfg(not(windows))]
+    let mut command = std::process::Command::new("sh");
+    #[cfg(not(windows))]
+    command.args(["-c", cmd]);
+
+    let output = command
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run pass_command '{cmd}'"))?;
+    let mut stdout = output.stdout;
+    if !output.status.success() {
+        stdout.zeroize();
+        bail!("pass_command '{cmd}' failed ({})", output.status);
+    }
+    let mut pass = match String::from_utf8(stdout) {
+        Ok(pass) => pass,
+        Err(err) => {
+            err.into_bytes().zeroize();
+            bail!("pass_command '{cmd}' printed a non-UTF-8 password");
+        }
+    };
+    let len = pass.trim_end_matches(['\r', '\n']).len();
+    pass.truncate(len);
+    if pass.is_empty() {
+        bail!("pass_command '{cmd}' printed an empty password");
+    }
+    Ok(pass)
+}
+
+fn cmd_watch(
+    session: &mut connection::ImapSession,
+    args: &WatchArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    let folder = args.folder.as_deref().unwrap_or(default_folder);
+    let stop = Arc::new(AtomicBool::new(false));
+    let socket = session
+        .socket_handle()
+        .context("Failed to set up Ctrl-C handling")?;
+    let flag = Arc::clone(&stop);
+    ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+        // Wakes the blocked IDLE read; DONE and LOGOUT are still sent afterwards
+        let _ = socket.shutdown(std::net::Shutdown::Read);
+    })
+    .context("Failed to set up Ctrl-C handling")?;
+
+    if !ui.quiet {
+        eprintln!("Watching {folder} for new mail (Ctrl-C to stop)...");
+    }
+    watch::watch(
+        session,
+        folder,
+        Duration::from_secs(args.idle_timeout),
+        &stop,
+        |rows| display::display_messages(rows, ui),
+    )
+}
+
+/// What a notable capability changes, for `capabilities`. Matched on the full name,
+/// or on the part before `=` for parameterized ones like `THREAD=REFERENCES`.
+fn capability_note(cap: &str) -> Option<&'static str> {
+    let name = cap.split_once('=').map_or(cap, |(name, _)| name);
+    Some(match name {
+        "IDLE" => "watch",
+        "MOVE" => "move/delete use UID MOVE (otherwise COPY + STORE + EXPUNGE)",
+        "SORT" => "server-side sort; --limit applied before FETCH",
+        "THREAD" => "thread / search --thread on the server",
+        "QUOTA" => "quota",
+        "UIDPLUS" => "expunge only the matched messages (UID EXPUNGE)",
+        "LIST-STATUS" => "status counts every folder in one LIST command",
+        "X-GM-EXT-1" => "Gmail search: --gmail-raw and --label",
+        "CONDSTORE" => "not used by slashmail (change tracking)",
+        "COMPRESS" => "not used by slashmail (compression)",
+        _ => return None,
+    })
+}
+
+fn cmd_capabilities(session: &connection::ImapSession) -> Result<()> {
     let mut table = Table::new();
     table.load_preset(UTF8_FULL_CONDENSED);
-    table.set_header(vec!["Folder", "Messages", "Unseen", "Recent"]);
+    table.set_header(vec!["Capability", "Notes"]);
+    for cap in session.capabilities() {
+        match capability_note(cap) {
+            Some(note) => table.add_row(vec![
+                display::colored(Cell::new(cap), Color::Green),
+                Cell::new(note),
+            ]),
+            None => table.add_row(vec![Cell::new(cap), Cell::new("")]),
+        };
+    }
+    println!("{table}");
+    Ok(())
+}
+
+/// Time a NOOP. Connecting and logging in already happened, so any failure there
+/// ends the run with the error exit status before this is reached.
+fn cmd_ping(session: &mut connection::ImapSession, host: &str) -> Result<()> {
+    let start = Instant::now();
+    session.noop().context("NOOP failed")?;
+    let elapsed = start.elapsed();
+    println!(
+        "{host}: NOOP round trip {:.1} ms ({} capabilities)",
+        elapsed.as_secs_f64() * 1000.0,
+        session.capabilities().len()
+    );
+    Ok(())
+}
+
+/// Folder names come from the user, so quote via imap_quote() like `status_command`.
+fn quota_command(folder: &str) -> String {
+    format!("GETQUOTAROOT {}", search::imap_quote(folder))
+}
+
+fn cmd_quota(
+    session: &mut connection::ImapSession,
+    args: &QuotaArgs,
+    ui: display::Ui,
+) -> Result<()> {
+    if !session.has_capability("QUOTA") {
+        bail!("Server does not support QUOTA extension (RFC 2087)");
+    }
+
+    let folder = args.folder.as_deref().unwrap_or("INBOX");
+    let sp = ui.spinner("Fetching quota...");
+    let response = session
+        .run_command_and_read_response(&quota_command(folder))
+        .context("GETQUOTAROOT failed")?;
+    sp.finish_and_clear();
+
+    let roots = parse_quota_response(&response)?;
+
+    if roots.is_empty() {
+        println!("No quota information available.");
+        return Ok(());
+    }
+
+    // A single root keeps the plain table; several get a heading each
+    let headings = roots.len() > 1;
+    for (i, (root, resources)) in roots.iter().enumerate() {
+        if headings {
+            if i > 0 {
+                println!();
+            }
+            println!("Quota root '{root}'");
+        }
+        println!("{}", quota_table(resources));
+    }
+    Ok(())
+}
+
+/// Resource name, usage and limit from a `* QUOTA` line.
+type QuotaResource = (String, u64, u64);
+
+fn quota_table(resources: &[QuotaResource]) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(vec!["Resource", "Used", "Limit", "Usage"]);
+
+    for (name, used, limit) in resources {
+        let (used_str, limit_str) = if name.eq_ignore_ascii_case("STORAGE") {
+            // STORAGE values are in KB
+            (
+                display::format_size(used * 1024),
+                display::format_size(limit * 1024),
+            )
+        } else {
+            (used.to_string(), limit.to_string())
+        };
+
+        let pct = if *limit > 0 {
+            *used as f64 / *limit as f64 * 100.0
+        } else {
+            0.0
+        };
+        let pct_str = format!("{pct:.1}%");
+
+        let mut row = vec![Cell::new(name), Cell::new(&used_str), Cell::new(&limit_str)];
+        let pct_cell = if pct >= 90.0 {
+            display::colored(Cell::new(&pct_str), Color::Red)
+        } else if pct >= 75.0 {
+            display::colored(Cell::new(&pct_str), Color::Yellow)
+        } else {
+            Cell::new(&pct_str)
+        };
+        row.push(pct_cell);
+        table.add_row(row);
+    }
+    table
+}
+
+/// Each quota root's resources from the `* QUOTA root (NAME used limit ...)` lines of
+/// a GETQUOTAROOT response, in the server's order. The root and resource names may be
+/// quoted strings or literals.
+fn parse_quota_response(data: &[u8]) -> Result<Vec<(String, Vec<QuotaResource>)>> {
+    let lines = response::tokenize(data).context("Malformed GETQUOTAROOT response")?;
+    let mut roots: Vec<(String, Vec<QuotaResource>)> = Vec::new();
+    for line in &lines {
+        if let [star, kw, root, Token::List(resources)] = line.as_slice() {
+            if !star.is_atom("*") || !kw.is_atom("QUOTA") {
+                continue;
+            }
+            let Some(root) = root.as_string() else {
+                continue;
+            };
+            let index = match roots.iter().position(|(name, _)| *name == root) {
+                Some(index) => index,
+                None => {
+                    roots.push((root, Vec::new()));
+                    roots.len() - 1
+                }
+            };
+            let rows = &mut roots[index].1;
+            for triple in resources.chunks(3) {
+                if let [name, used, limit] = triple {
+                    if let (Some(name), Some(used), Some(limit)) =
+                        (name.as_string(), used.as_number(), limit.as_number())
+                    {
+                        rows.push((name, used, limit));
+                    }
+                }
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// (MESSAGES, UNSEEN, RECENT) from the `* STATUS mailbox (...)` line of a STATUS
+/// response; attributes the server leaves out count as 0. The mailbox and attribute
+/// names may be quoted strings or literals.
+fn parse_status_response(data: &[u8]) -> Result<(u32, u32, u32)> {
+    let lines = response::tokenize(data).context("Malformed STATUS response")?;
+    let (mut messages, mut unseen, mut recent) = (0, 0, 0);
+    for line in &lines {
+        if let [star, kw, _mailbox, Token::List(attrs)] = line.as_slice() {
+            if !star.is_atom("*") || !kw.is_atom("STATUS") {
+                continue;
+            }
+            (messages, unseen, recent) = status_counts(attrs);
+        }
+    }
+    Ok((messages, unseen, recent))
+}
+
+/// (MESSAGES, UNSEEN, RECENT) from a STATUS attribute list such as
+/// `MESSAGES 142 UNSEEN 12 RECENT 3`; missing attributes count as 0.
+fn status_counts(attrs: &[Token]) -> (u32, u32, u32) {
+    let (mut messages, mut unseen, mut recent) = (0, 0, 0);
+    for pair in attrs.chunks(2) {
+        if let [key, val] = pair {
+            let val = val.as_number().unwrap_or(0);
+            match key.as_string().map(|k| k.to_ascii_uppercase()).as_deref() {
+                Some("MESSAGES") => messages = val,
+                Some("UNSEEN") => unseen = val,
+                Some("RECENT") => recent = val,
+                _ => {}
+            }
+        }
+    }
+    (messages, unseen, recent)
+}
+
+/// Folders in LIST order with their counts from a `LIST ... RETURN (STATUS ...)`
+/// response (RFC 5819). Folders the server sent no STATUS for (e.g. `\Noselect`) get `None`.
+fn parse_list_status_response(data: &[u8]) -> Result<Vec<FolderStatus>> {
+    let lines = response::tokenize(data).context("Malformed LIST-STATUS response")?;
+    let mut folders: Vec<FolderStatus> = Vec::new();
+    for line in &lines {
+        match line.as_slice() {
+            [star, kw, Token::List(_), _delim, name, ..]
+                if star.is_atom("*") && kw.is_atom("LIST") =>
+            {
+                if let Some(name) = name.as_string() {
+                    folders.push((name, None));
+                }
+            }
+            [star, kw, name, Token::List(attrs)] if star.is_atom("*") && kw.is_atom("STATUS") => {
+                let Some(name) = name.as_string() else {
+                    continue;
+                };
+                let counts = status_counts(attrs);
+                match folders.iter_mut().find(|(n, _)| *n == name) {
+                    Some(folder) => folder.1 = Some(counts),
+                    None => folders.push((name, Some(counts))),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(folders)
+}
+
+/// A folder name and its (MESSAGES, UNSEEN, RECENT), or `None` if STATUS failed.
+type FolderStatus = (String, Option<(u32, u32, u32)>);
+
+/// Counts for every folder: one LIST-STATUS round trip when the server supports it,
+/// otherwise LIST followed by a STATUS per folder.
+fn folder_statuses(session: &mut connection::ImapSession) -> Result<Vec<FolderStatus>> {
+    if session.has_capability("LIST-STATUS") {
+        let listed = session
+            .run_command_and_read_response(LIST_STATUS_COMMAND)
+            .map_err(anyhow::Error::from)
+            .and_then(|r| parse_list_status_response(&r));
+        match listed {
+            Ok(folders) if !folders.is_empty() => return Ok(folders),
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: LIST-STATUS failed, asking each folder instead: {e:#}"),
+        }
+    }
+    let folders = session
+        .list(Some(""), Some("*"))
+        .context("Failed to list folders")?;
+    let folder_names: Vec<String> = folders.iter().map(|f| f.name().to_string()).collect();
+    Ok(folder_names
+        .into_iter()
+        .map(|name| {
+            let counts = session
+                .run_command_and_read_response(&status_command(&name))
+                .map_err(anyhow::Error::from)
+                .and_then(|r| parse_status_response(&r))
+                .ok();
+            (name, counts)
+        })
+        .collect())
+}
+
+const LIST_STATUS_COMMAND: &str = "LIST \"\" \"*\" RETURN (STATUS (MESSAGES UNSEEN RECENT))";
+
+/// Folder names are server-controlled, so always quote via imap_quote()
+/// which strips control chars and escapes IMAP-special characters.
+fn status_command(folder: &str) -> String {
+    format!(
+        "STATUS {} (MESSAGES UNSEEN RECENT)",
+        search::imap_quote(folder)
+    )
+}
+
+fn cmd_status(session: &mut connection::ImapSession, ui: display::Ui) -> Result<()> {
+    let sp = ui.spinner("Fetching folder status...");
+    let statuses = folder_statuses(session)?;
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(vec!["Folder", "Messages", "Unseen", "Recent"]);
+
+    let mut total_messages: u32 = 0;
+    let mut total_unseen: u32 = 0;
+    let mut total_recent: u32 = 0;
+
+    for (name, counts) in &statuses {
+        let Some((messages, unseen, recent)) = *counts else {
+            table.add_row(vec![name.as_str(), "?", "?", "?"]);
+            continue;
+        };
+
+        total_messages += messages;
+        total_unseen += unseen;
+        total_recent += recent;
+
+        table.add_row(vec![
+            name.as_str(),
+            &messages.to_string(),
+            &unseen.to_string(),
+            &recent.to_string(),
+        ]);
+    }
+
+    sp.finish_and_clear();
+
+    // Total row
+    table.add_row(vec![
+        display::colored(Cell::new("Total"), Color::Cyan),
+        display::colored(Cell::new(total_messages), Color::Cyan),
+        display::colored(Cell::new(total_unseen), Color::Cyan),
+        display::colored(Cell::new(total_recent), Color::Cyan),
+    ]);
+
+    println!("{table}");
+    Ok(())
+}
+
+fn cmd_stats(
+    session: &mut connection::ImapSession,
+    args: &StatsArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    let criteria = args.filter.to_criteria(None, default_folder);
+    let sp = ui.spinner("Searching...");
+    let messages =
+        search::search_with_progress(session, &criteria, |p| show_search_progress(&sp, p))?;
+    sp.finish_and_clear();
+
+    if messages.is_empty() {
+        ui.info("No messages match the criteria.");
+        return Ok(());
+    }
+
+    let senders = stats::sender_stats(&messages);
+    let total: u64 = messages.iter().map(|m| u64::from(m.size)).sum();
+    let n = args.top as usize;
+    println!("Top senders by message count:");
+    println!("{}", stats::sender_table(&stats::top_by_count(&senders, n)));
+    println!("Top senders by total size:");
+    println!("{}", stats::sender_table(&stats::top_by_size(&senders, n)));
+    ui.info(format_args!(
+        "{} message(s), {}, from {} sender(s)",
+        messages.len(),
+        display::format_size(total),
+        senders.len()
+    ));
+    Ok(())
+}
+
+fn cmd_folder(session: &mut connection::ImapSession, cmd: &FolderCommand) -> Result<()> {
+    match cmd {
+        FolderCommand::Create { name } => {
+            folder::create_folder(session, name)?;
+            println!("Created folder {name}.");
+        }
+        FolderCommand::Delete { name, yes } => {
+            if folder::is_inbox(name) {
+                bail!("Refusing to delete INBOX");
+            }
+            search::ensure_folder_exists(session, name)?;
+            if !yes {
+                let confirm = inquire::Confirm::new(&format!(
+                    "Permanently delete folder '{name}' and all messages in it?"
+                ))
+                .with_default(false)
+                .prompt()
+                .context("Prompt failed")?;
+                if !confirm {
+                    println!("Aborted.");
+                    return Ok(());
+                }
+            }
+            folder::delete_folder(session, name)?;
+            println!("Deleted folder {name}.");
+        }
+        FolderCommand::Rename { old, new } => {
+            folder::rename_folder(session, old, new)?;
+            println!("Renamed folder {old} to {new}.");
+        }
+    }
+    Ok(())
+}
+
+fn cmd_unsubscribe(
+    session: &mut connection::ImapSession,
+    args: &UnsubscribeArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    let criteria = args.filter.to_criteria(args.limit, default_folder);
+    let sp = ui.spinner("Searching...");
+    let messages =
+        search::search_with_progress(session, &criteria, |p| show_search_progress(&sp, p))?;
+    sp.finish_and_clear();
+
+    if messages.is_empty() {
+        ui.info("No messages match the criteria.");
+        return Ok(());
+    }
+
+    let lists = unsubscribe::mailing_lists(&messages);
+    for list in &lists {
+        let one_click = if list.one_click { ", one-click" } else { "" };
+        println!("{} ({} message(s){one_click})", list.from, list.messages);
+        for target in &list.targets {
+            println!("  {target}");
+        }
+    }
+    let listed: usize = lists.iter().map(|l| l.messages).sum();
+    ui.info(format_args!(
+        "{} list(s) in {listed} message(s); {} message(s) without List-Unsubscribe",
+        lists.len(),
+        messages.len() - listed
+    ));
+
+    if !args.open || lists.is_empty() {
+        return Ok(());
+    }
+    if !args.yes {
+        let confirm = inquire::Confirm::new(&format!(
+            "Open the unsubscribe link of {} list(s)?",
+            lists.len()
+        ))
+        .with_default(false)
+        .prompt()
+        .context("Prompt failed")?;
+        if !confirm {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+    for target in lists.iter().filter_map(|l| l.preferred_target()) {
+        if let Err(e) = unsubscribe::open(target) {
+            eprintln!("Warning: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+fn cmd_export(
+    session: &mut connection::ImapSession,
+    args: &ExportArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<ActionReport> {
+    let criteria = args.filter.to_criteria(args.limit, default_folder);
+    let sp = ui.spinner("Searching...");
+    let messages =
+        search::search_with_progress(session, &criteria, |p| show_search_progress(&sp, p))?;
+    sp.finish_and_clear();
+
+    if messages.is_empty() {
+        ui.info("No messages found.");
+        return Ok(ActionReport::new("export", &BTreeMap::new()));
+    }
+
+    display::display_messages(&messages, ui);
+
+    // `--output-file` is only accepted with --format mbox (see `command_format`)
+    let out_path = args
+        .output_file
+        .clone()
+        .or_else(|| args.output_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if !args.yes {
+        let confirm = inquire::Confirm::new(&format!(
+            "Export {} message(s) to {}?",
+            messages.len(),
+            out_path.display()
+        ))
+        .with_default(false)
+        .prompt()
+        .context("Prompt failed")?;
+
+        if !confirm {
+            println!("Aborted.");
+            let by_folder = search::group_by_folder(&messages, &criteria.folder);
+            let mut report = ActionReport::new("export", &by_folder);
+            report.aborted = true;
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner("Exporting...");
+    let progress = |done| display::show_count(&sp, done, messages.len());
+    let report = match args.format {
+        ExportFormat::Mbox => export::export_mbox_with_progress(
+            session,
+            &messages,
+            &criteria.folder,
+            &out_path,
+            args.force,
+            progress,
+        ),
+        ExportFormat::Maildir => export::export_maildir_with_progress(
+            session,
+            &messages,
+            &criteria.folder,
+            &out_path,
+            progress,
+        ),
+        ExportFormat::Eml => export::export_messages_with_progress(
+            session,
+            &messages,
+            &criteria.folder,
+            &out_path,
+            &args.filename_template,
+            args.force,
+            progress,
+        ),
+    };
+    sp.finish_and_clear();
+    let report = report?;
+
+    let mut summary = format!(
+        "Exported {} message(s) to {}",
+        report.acted,
+        out_path.display()
+    );
+    if report.skipped > 0 {
+        summary.push_str(&format!(" ({} skipped, already exist)", report.skipped));
+    }
+    ui.info(summary);
+    Ok(report)
+}
+
+fn cmd_download(
+    session: &mut connection::ImapSession,
+    args: &DownloadArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<ActionReport> {
+    let criteria = args.filter.to_criteria(args.limit, default_folder);
+    let sp = ui.spinner("Searching...");
+    let messages = search::search(session, &criteria)?;
+    sp.finish_and_clear();
+
+    if messages.is_empty() {
+        ui.info("No messages found.");
+        return Ok(ActionReport::new("download-attachments", &BTreeMap::new()));
+    }
+
+    display::display_messages(&messages, ui);
+
+    let out_dir = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if !args.yes {
+        let confirm = inquire::Confirm::new(&format!(
+            "Save the attachments of {} message(s) to {}?",
+            messages.len(),
+            out_dir.display()
+        ))
+        .with_default(false)
+        .prompt()
+        .context("Prompt failed")?;
+
+        if !confirm {
+            println!("Aborted.");
+            let by_folder = search::group_by_folder(&messages, &criteria.folder);
+            let mut report = ActionReport::new("download-attachments", &by_folder);
+            report.aborted = true;
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner("Downloading attachments...");
+    let result = attachments::download(
+        session,
+        &messages,
+        &criteria.folder,
+        &out_dir,
+        args.name_filter.as_ref(),
+        args.force,
+    );
+    sp.finish_and_clear();
+    let (report, summary) = result?;
+
+    let mut line = format!(
+        "Saved {} attachment(s) from {} message(s) to {}",
+        summary.saved,
+        report.acted,
+        out_dir.display()
+    );
+    if summary.existing > 0 {
+        line.push_str(&format!(" ({} skipped, already exist)", summary.existing));
+    }
+    if summary.without > 0 {
+        let kind = if args.name_filter.is_some() {
+            "no matching attachments"
+        } else {
+            "no attachments"
+        };
+        line.push_str(&format!("; {} message(s) had {kind}", summary.without));
+    }
+    ui.info(line);
+    Ok(report)
+}
+
+/// Read a `--uids-from` file, or stdin for `-`, into UIDs per folder.
+fn read_uid_list(
+    path: Option<&Path>,
+    default_folder: &str,
+) -> Result<Option<BTreeMap<String, Vec<u32>>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let text = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read UIDs from stdin")?
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read UIDs from {}", path.display()))?
+    };
+    Ok(Some(search::parse_uid_list(&text, default_folder)?))
+}
+
+/// Print `report` if requested, and fail the command when any message could not be acted on.
+/// Sets `no_match` when the search behind the action found nothing.
+fn finish_action(
+    report: &ActionReport,
+    format: Option<ReportFormat>,
+    no_match: &mut bool,
+) -> Result<()> {
+    if format == Some(ReportFormat::Json) {
+        display::print_json(&report.command, report, false);
+    }
+    *no_match = report.matched == 0;
+    if report.failed > 0 {
+        bail!(
+            "{} of {} message(s) failed; see warnings above",
+            report.failed,
+            report.matched
+        );
+    }
+    Ok(())
+}
+
+/// Accept an IMAP keyword (RFC 3501 `flag-keyword`, an atom). Keywords are sent
+/// unquoted in the FLAGS list, so anything an atom can't hold is refused, and system
+/// flags like `\Seen` are left to their own options.
+fn parse_keyword(keyword: &str) -> Result<String> {
+    if keyword.is_empty() {
+        bail!("Keyword must not be empty");
+    }
+    if keyword.starts_with('\\') {
+        bail!("'{keyword}' is a system flag; use --read, --flagged etc. instead");
+    }
+    if let Some(c) = keyword
+        .chars()
+        .find(|&c| !c.is_ascii_graphic() || "(){%*\"\\]".contains(c))
+    {
+        bail!("Invalid keyword '{keyword}': {c:?} is not allowed in an IMAP keyword");
+    }
+    Ok(keyword.to_string())
+}
+
+fn validate_mark_flags(flags: &MarkFlags) -> Result<()> {
+    let system = flags.system();
+    if system.iter().all(|&(set, unset)| !set && !unset)
+        && flags.add_keyword.is_empty()
+        && flags.remove_keyword.is_empty()
+    {
+        let options: Vec<String> = SYSTEM_FLAGS
+            .iter()
+            .flat_map(|f| [f.set_option, f.unset_option])
+            .chain(["--add-keyword", "--remove-keyword"])
+            .map(str::to_string)
+            .collect();
+        bail!("Specify at least one flag: {}", options.join(", "));
+    }
+    for (f, &(set, unset)) in SYSTEM_FLAGS.iter().zip(&system) {
+        if set && unset {
+            bail!(
+                "Cannot use {} and {} together",
+                f.set_option,
+                f.unset_option
+            );
+        }
+    }
+    // Keywords are case-insensitive (RFC 3501)
+    if let Some(kw) = flags.add_keyword.iter().find(|kw| {
+        flags
+            .remove_keyword
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(kw))
+    }) {
+        bail!("Cannot both add and remove keyword '{kw}'");
+    }
+    Ok(())
+}
+
+/// Build the STORE operations for a mark: at most one `+FLAGS` and one `-FLAGS`,
+/// so each UID chunk needs no more than two round trips.
+fn mark_store_ops(flags: &MarkFlags) -> Vec<String> {
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for (f, (set, unset)) in SYSTEM_FLAGS.iter().zip(flags.system()) {
+        if set {
+            add.push(f.flag);
+        }
+        if unset {
+            remove.push(f.flag);
+        }
+    }
+    add.extend(flags.add_keyword.iter().map(String::as_str));
+    remove.extend(flags.remove_keyword.iter().map(String::as_str));
+
+    let mut ops = Vec::new();
+    if !add.is_empty() {
+        ops.push(format!("+FLAGS ({})", add.join(" ")));
+    }
+    if !remove.is_empty() {
+        ops.push(format!("-FLAGS ({})", remove.join(" ")));
+    }
+    ops
+}
+
+/// The exact IMAP commands `cmd_mark` issues for `by_folder`, for `--show-commands`.
+fn planned_mark_commands(
+    by_folder: &BTreeMap<String, Vec<u32>>,
+    store_ops: &[String],
+) -> Vec<String> {
+    let mut commands = Vec::new();
+    for (folder, uids) in by_folder {
+        commands.push(format!("SELECT {}", search::imap_quote(folder)));
+        for chunk in search::build_uid_set(uids) {
+            for op in store_ops {
+                commands.push(format!("UID STORE {chunk} {op}"));
+            }
+        }
+    }
+    commands
+}
+
+fn mark_action_desc(flags: &MarkFlags) -> String {
+    let mut actions = Vec::new();
+    for (f, (set, unset)) in SYSTEM_FLAGS.iter().zip(flags.system()) {
+        if set {
+            actions.push(f.set_desc.to_string());
+        }
+        if unset {
+            actions.push(f.unset_desc.to_string());
+        }
+    }
+    if !flags.add_keyword.is_empty() {
+        actions.push(format!("add keyword {}", flags.add_keyword.join(" ")));
+    }
+    if !flags.remove_keyword.is_empty() {
+        actions.push(format!("remove keyword {}", flags.remove_keyword.join(" ")));
+    }
+    actions.join(" + ")
+}
+
+fn cmd_mark(
+    session: &mut connection::ImapSession,
+    args: &MarkArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<ActionReport> {
+    validate_mark_flags(&args.flags)?;
+
+    let mut criteria = args.filter.to_criteria(args.limit, default_folder);
+    criteria.uids = read_uid_list(args.uids_from.as_deref(), &criteria.folder)?;
+    let sp = ui.spinner("Searching...");
+    let mut messages = search::search(session, &criteria)?;
+    sp.finish_and_clear();
+
+    let mut by_folder = search::group_by_folder(&messages, &criteria.folder);
+    let mut report = ActionReport::new("mark", &by_folder);
+
+    if messages.is_empty() {
+        ui.info("No messages match the criteria.");
+        return Ok(report);
+    }
+
+    if args.interactive {
+        delete::pick_messages(&mut messages, &mut by_folder, &criteria.folder, &mut report)?;
+        if messages.is_empty() {
+            report.aborted = true;
+            ui.info("No messages selected.");
+            return Ok(report);
+        }
+    } else {
+        display::display_messages(&messages, ui);
+    }
+
+    let action_desc = mark_action_desc(&args.flags);
+    let store_ops = mark_store_ops(&args.flags);
+
+    if args.dry_run {
+        report.dry_run = true;
+        println!(
+            "Dry run: would {action_desc} {} message(s).",
+            messages.len()
+        );
+        if args.show_commands {
+            println!("Commands:");
+            for cmd in planned_mark_commands(&by_folder, &store_ops) {
+                println!("  {cmd}");
+            }
+        }
+        return Ok(report);
+    }
+
+    if !args.yes && !args.interactive {
+        let confirm =
+            inquire::Confirm::new(&format!("{action_desc} {} message(s)?", messages.len()))
+                .with_default(false)
+                .prompt()
+                .context("Prompt failed")?;
+
+        if !confirm {
+            report.aborted = true;
+            println!("Aborted.");
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner("Updating flags...");
+
+    for (folder, uids) in &by_folder {
+        let mut updated = 0usize;
+        let result = mark_folder(session, folder, uids, &store_ops, &mut updated);
+        report.add_acted(folder, updated);
+        if let Err(e) = result {
+            sp.suspend(|| eprintln!("Warning: {e:#}"));
+            report.add_failed(folder, uids.len() - updated);
+        }
+    }
+
+    sp.finish_and_clear();
+    ui.info(format_args!("Updated {} message(s).", report.acted));
+    Ok(report)
+}
+
+fn mark_folder(
+    session: &mut connection::ImapSession,
+    folder: &str,
+    uids: &[u32],
+    store_ops: &[String],
+    updated: &mut usize,
+) -> Result<()> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    for chunk in &search::build_uid_set(uids) {
+        for op in store_ops {
+            session
+                .uid_store(chunk, op)
+                .with_context(|| format!("Failed to store flags in '{folder}'"))?;
+        }
+        *updated += search::uid_set_len(chunk);
+    }
+    Ok(())
+}
+
+fn cmd_cat(
+    session: &mut connection::ImapSession,
+    args: &CatArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    use std::io::Write;
+
+    // Fetch two so an ambiguous filter is reported instead of picking one silently
+    let criteria = args.filter.to_criteria(Some(2), default_folder);
+    let (folder, uid) = match args.uid {
+        Some(uid) => {
+            if criteria.has_filters() {
+                bail!("--uid cannot be combined with search filters (only --folder)");
+            }
+            (criteria.folder.clone(), uid)
+        }
+        None => {
+            let sp = ui.spinner("Searching...");
+            let messages = search::search(session, &criteria);
+            sp.finish_and_clear();
+            match messages?.as_slice() {
+                [] => bail!("No messages found."),
+                [msg] => (
+                    msg.folder
+                        .clone()
+                        .unwrap_or_else(|| criteria.folder.clone()),
+                    msg.uid,
+                ),
+                _ => bail!("More than one message matches; narrow the filters or pass --uid"),
+            }
+        }
+    };
+
+    let sp = ui.spinner("Fetching...");
+    let raw = read::fetch_raw(session, &folder, uid);
+    sp.finish_and_clear();
+    let raw = raw?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&raw)
+        .context("Failed to write to stdout")?;
+    stdout.flush().context("Failed to write to stdout")?;
+    Ok(())
+}
+
+fn cmd_append(
+    session: &mut connection::ImapSession,
+    args: &AppendArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<ActionReport> {
+    let folder = args.folder.as_deref().unwrap_or(default_folder);
+    let flags = append::append_flags(args.read, args.flagged);
+
+    let sp = ui.spinner("Appending...");
+    let report = append::append_files(session, folder, &args.files, &flags, args.date);
+    sp.finish_and_clear();
+    let report = report?;
+
+    ui.info(format_args!(
+        "Appended {} message(s) to {folder}",
+        report.acted
+    ));
+    Ok(report)
+}
+
+fn cmd_show(
+    session: &mut connection::ImapSession,
+    args: &ShowArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    use std::io::Write;
+
+    let folder = args.folder.as_deref().unwrap_or(default_folder);
+
+    let sp = ui.spinner("Fetching...");
+    let raw = read::fetch_raw(session, folder, args.uid);
+    sp.finish_and_clear();
+    let raw = raw?;
+
+    if args.raw {
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&raw)
+            .context("Failed to write to stdout")?;
+        stdout.flush().context("Failed to write to stdout")?;
+    } else {
+        read::print_message(&raw);
+    }
+    Ok(())
+}
+
+/// Write one BODY section of a message to `--output` or stdout, without marking it read.
+fn cmd_fetch(
+    session: &mut connection::ImapSession,
+    args: &FetchArgs,
+    default_folder: &str,
+    ui: display::Ui,
+) -> Result<()> {
+    use std::io::Write;
+
+    let folder = args.folder.as_deref().unwrap_or(default_folder);
+
+    let sp = ui.spinner("Fetching...");
+    let data = read::fetch_section(session, folder, args.uid, &args.section);
+    sp.finish_and_clear();
+    let data = data?;
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, &data)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            if !ui.quiet {
+                eprintln!("Wrote {} bytes to {}", data.len(), path.display());
+            }
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout
+                .write_all(&data)
+                .context("Failed to write to stdout")?;
+            stdout.flush().context("Failed to write to stdout")?;
+        }
+    }
+    Ok(())
+}
+
+/// Print `search` results as a table, honoring `--columns`.
+fn display_search_table(
+    messages: &mut [display::MessageRow],
+    args: &SearchArgs,
+    criteria: &search::SearchCriteria,
+    ui: display::Ui,
+) {
+    if args.columns.is_empty() {
+        display::display_messages(messages, ui);
+        return;
+    }
+    let columns: Vec<display::Column> = args.columns.iter().map(|&c| c.into()).collect();
+    // A single-folder search leaves `folder` unset; fill it in when asked for
+    if columns.contains(&display::Column::Folder) {
+        for msg in messages.iter_mut().filter(|m| m.folder.is_none()) {
+            msg.folder = Some(criteria.folder.clone());
+        }
+    }
+    display::display_messages_columns(messages, &columns, ui);
+}
+
+/// Count matching messages. Returns the total across all searched folders.
+fn cmd_count(
+    session: &mut connection::ImapSession,
+    args: &CountArgs,
+    default_folder: &str,
+    format: OutputFormat,
+    ui: display::Ui,
+) -> Result<usize> {
+    let criteria = args.filter.to_criteria(None, default_folder);
+    let query = search::build_query_for(session, &criteria)?;
+    let json = format == OutputFormat::Json;
+
+    let sp = if args.silent || json {
+        ProgressBar::hidden()
+    } else {
+        ui.spinner("Counting...")
+    };
+
+    if criteria.all_folders {
+        let folder_names = search::list_searchable_folders(session, &criteria)?;
+
+        let mut grand_total = 0usize;
+        let mut results: Vec<(String, usize)> = Vec::new();
+
+        let mut skipped = search::SkippedFolders::new(criteria.quiet_warnings);
+        for (i, folder) in folder_names.iter().enumerate() {
+            sp.set_message(format!(
+                "Counting {folder} ({}/{})...",
+                i + 1,
+                folder_names.len()
+            ));
+            if let Err(e) = session.select(folder) {
+                skipped.skip(folder, e);
+                continue;
+            }
+            match search::uid_search(session, &query, criteria.charset()) {
+                Ok(uids) => {
+                    let count = uids.len();
+                    if count > 0 {
+                        results.push((folder.clone(), count));
+                        grand_total += count;
+                    }
+                }
+                Err(e) => skipped.skip(folder, format!("search failed: {e:#}")),
+            }
+        }
+
+        sp.finish_and_clear();
+        skipped.finish();
+
+        if args.silent {
+            return Ok(grand_total);
+        }
+
+        if json {
+            let folders: Vec<serde_json::Value> = results
+                .iter()
+                .map(|(f, c)| serde_json::json!({"folder": f, "count": c}))
+                .collect();
+            display::print_json(
+                "count",
+                &serde_json::json!({"folders": folders, "total": grand_total}),
+                args.json_envelope,
+            );
+        } else if results.is_empty() {
+            println!("0 message(s) match.");
+        } else {
+            for (folder, count) in &results {
+                println!("{count} message(s) in {folder}");
+            }
+            if results.len() > 1 {
+                println!("{grand_total} message(s) total");
+            }
+        }
+        Ok(grand_total)
+    } else {
+        session
+            .select(&criteria.folder)
+            .with_context(|| format!("Failed to select '{}'", criteria.folder))?;
+
+        let uids = search::uid_search(session, &query, criteria.charset())?;
+        sp.finish_and_clear();
+        if args.silent {
+            return Ok(uids.len());
+        }
+        if json {
+            display::print_json(
+                "count",
+                &serde_json::json!({"folder": criteria.folder, "count": uids.len()}),
+                args.json_envelope,
+            );
+        } else {
+            println!("{} message(s) in {}", uids.len(), criteria.folder);
+        }
+        Ok(uids.len())
+    }
+}
+
+/// Exit status when `search`, `count`, `read` or an action matched no messages.
+const EXIT_NO_MATCH: u8 = 1;
+/// Exit status for errors, the same as clap uses for bad arguments.
+const EXIT_ERROR: u8 = 2;
+
+/// How a run that didn't fail ended.
+enum Outcome {
+    Done,
+    NoMatch,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(Outcome::Done) => ExitCode::SUCCESS,
+        Ok(Outcome::NoMatch) => ExitCode::from(EXIT_NO_MATCH),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn run() -> Result<Outcome> {
+    let mut cli = Cli::parse();
+    display::set_color(display::use_color(
+        cli.no_color,
+        std::env::var_os("NO_COLOR").as_deref(),
+    ));
+    display::set_date_zone(cli.tz);
+    display::set_relative_dates(cli.relative_dates);
+
+    // Handle commands that don't need an IMAP connection
+    match &cli.command {
+        Commands::Completions { shell } => {
+            clap_complete::generate(
+                *shell,
+                &mut Cli::command(),
+                "slashmail",
+                &mut std::io::stdout(),
+            );
+            return Ok(Outcome::Done);
+        }
+        Commands::Manpage => {
+            clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+            return Ok(Outcome::Done);
+        }
+        _ => {}
+    }
+
+    let format = command_format(&cli.command)?;
+
+    // Load config: explicit --config path > default location > empty
+    let cfg = config::Config::load_all(&cli.config, !cli.no_env_expand)?
+        .select_account(cli.account.as_deref())?;
+
+    // Resolve values: CLI/env > host URL > config > built-in default
+    let target = parse_host(
+        cli.host
+            .as_deref()
+            .or(cfg.host.as_deref())
+            .unwrap_or("127.0.0.1"),
+    )?;
+    let security = resolve_security(cli.tls, cli.starttls, target.tls, &cfg)?;
+    let default_port = cfg.default_port(security);
+    let host = target.host;
+    let port = cli
+        .port
+        .or(target.port)
+        .or(cfg.port)
+        .unwrap_or(default_port);
+    let user = cli.user.or(cfg.user).ok_or_else(|| {
+        anyhow::anyhow!("IMAP username required (use -u/--user or SLASHMAIL_USER env)")
+    })?;
+    let default_folder = cfg.default_folder.unwrap_or_else(|| "INBOX".to_string());
+    let default_trash = cfg.trash_folder.unwrap_or_else(|| "Trash".to_string());
+    let mark_read_on_delete = cfg.mark_read_on_delete.unwrap_or(false);
+
+    if matches!(cli.command, Commands::Forget) {
+        if keychain::forget(&host, &user)? {
+            println!("Removed saved password for {user} on {host}");
+        } else {
+            println!("No saved password for {user} on {host}");
+        }
+        return Ok(Outcome::Done);
+    }
+
+    let mut pass = if matches!(cli.command, Commands::Login) {
+        prompt_password()?
+    } else {
+        get_password(cfg.pass_command.as_deref(), &host, &user)?
+    };
+
+    // `count --silent` reports through the exit code only; JSON keeps stdout clean
+    let silent = matches!(&cli.command, Commands::Count(args) if args.silent)
+        || format != OutputFormat::Table;
+
+    let ui = display::Ui {
+        quiet: cli.quiet,
+        verbose: cli.verbose,
+    };
+    let sp = if silent {
+        ProgressBar::hidden()
+    } else {
+        ui.spinner("Connecting...")
+    };
+    let timeout_secs = cli.timeout.or(cfg.timeout).unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let timeout = (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs));
+    let connect_timeout = cli.connect_timeout.map(Duration::from_secs).or(timeout);
+    // Timed from here so the password prompt doesn't count
+    let started = Instant::now();
+    if cli.timings {
+        timings::enable();
+    }
+    connection::set_verbose(cli.verbose);
+    if let Some(filter) = cli.command.filter_mut() {
+        filter.skip_folders = cfg.skip_folders.unwrap_or_default();
+    }
+    // Off unless the config sets `undo_log = true`
+    let undo_log = cfg
+        .undo_log
+        .unwrap_or(false)
+        .then(undo::default_path)
+        .flatten();
+    let opts = connection::ConnectOptions {
+        security,
+        connect_timeout,
+        timeout,
+        ca_cert: cli.ca_cert.clone().or(cfg.ca_cert.map(PathBuf::from)),
+        insecure: cli.insecure,
+        quiet: false,
+    };
+    let session_result = {
+        let _t = timings::start(Phase::Connect);
+        connection::connect(&host, port, &user, &pass, &opts)
+    }
+    .map_err(|e| timeout_error(e.into(), timeout_secs));
+    sp.finish_and_clear();
+
+    // `search --jobs` workers log in later, so they need a copy; zeroized when dropped
+    let jobs = match &cli.command {
+        Commands::Search(args) => usize::from(args.jobs),
+        _ => 1,
+    };
+    let worker_pass = Zeroizing::new(if jobs > 1 {
+        pass.clone()
+    } else {
+        String::new()
+    });
+    // The first connection has already printed any TLS warnings
+    let worker_opts = connection::ConnectOptions {
+        quiet: true,
+        ..opts.clone()
+    };
+    let connect_worker = || {
+        let _t = timings::start(Phase::Connect);
+        let pass = Zeroizing::new(worker_pass.to_string());
+        connection::connect(&host, port, &user, &pass, &worker_opts)
+    };
+
+    // `login` only saves a password the server has accepted
+    let login_saved = (matches!(cli.command, Commands::Login) && session_result.is_ok())
+        .then(|| keychain::store(&host, &user, &pass));
+
+    // Clear password from memory on both success and error paths.
+    pass.zeroize();
+
+    let mut session = session_result?;
+
+    let mut no_match = false;
+    let result = match &cli.command {
+        Commands::Search(args) => {
+            let (limit, invert_order) = args.limit_and_order();
+            let mut criteria = args.filter.to_criteria(limit, &default_folder);
+            criteria.invert_order = invert_order;
+            criteria.sort = args.sort.into();
+            criteria.offset = args.offset;
+            let sp = if silent {
+                ProgressBar::hidden()
+            } else {
+                ui.spinner("Searching...")
+            };
+            if args.thread {
+                let (threads, messages) = thread::search_threads(&mut session, &criteria, ui)?;
+                sp.finish_and_clear();
+                no_match = threads.is_empty();
+                display::display_threads(&threads, &messages, ui);
+            } else {
+                let mut messages =
+                    search::search_parallel(&mut session, &criteria, jobs, connect_worker, |p| {
+                        show_search_progress(&sp, p)
+                    })?;
+                sp.finish_and_clear();
+                no_match = messages.is_empty();
+                match format {
+                    OutputFormat::Json => {
+                        display::display_messages_json(&messages, args.json_envelope)
+                    }
+                    OutputFormat::Csv => display::display_messages_csv(&messages),
+                    OutputFormat::Tsv => display::display_messages_tsv(&messages),
+                    OutputFormat::Table => display_search_table(&mut messages, args, &criteria, ui),
+                }
+            }
+            Ok(())
+        }
+        Commands::Thread(args) => {
+            let criteria = args.filter.to_criteria(args.limit, &default_folder);
+            let sp = ui.spinner("Searching...");
+            let (threads, messages) = thread::search_threads(&mut session, &criteria, ui)?;
+            sp.finish_and_clear();
+            no_match = threads.is_empty();
+            display::display_threads(&threads, &messages, ui);
+            Ok(())
+        }
+        Commands::Read(args) => {
+            let limit = args.limit.or(Some(1));
+            let criteria = args.filter.to_criteria(limit, &default_folder);
+            let sp = ui.spinner("Searching...");
+            let messages = search::search(&mut session, &criteria)?;
+            sp.finish_and_clear();
+            if messages.is_empty() {
+                ui.info("No messages found.");
+                no_match = true;
+                Ok(())
+            } else {
+                let sp = ui.spinner("Fetching...");
+                let r = read::read_messages(&mut session, &messages, &criteria.folder);
+                sp.finish_and_clear();
+                r
+            }
+        }
+        Commands::Delete(args) => {
+            let mut criteria = args.filter.to_criteria(args.limit, &default_folder);
+            criteria.uids = read_uid_list(args.uids_from.as_deref(), &criteria.folder)?;
+            let trash = args.trash_folder.as_deref().unwrap_or(&default_trash);
+            let opts = delete::ActOptions {
+                yes: args.yes,
+                dry_run: args.dry_run,
+                show_commands: args.show_commands,
+                mark_read: args.mark_read_on_delete || mark_read_on_delete,
+                expunge: args.expunge,
+                interactive: args.interactive,
+                create: args.create,
+                undo_log: undo_log.clone(),
+            };
+            delete::delete(&mut session, &criteria, trash, &opts, ui)
+                .map_err(anyhow::Error::from)
+                .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Move(args) => {
+            let mut criteria = args.filter.to_criteria(args.limit, &default_folder);
+            criteria.uids = read_uid_list(args.uids_from.as_deref(), &criteria.folder)?;
+            let opts = delete::ActOptions {
+                yes: args.yes,
+                dry_run: args.dry_run,
+                show_commands: args.show_commands,
+                interactive: args.interactive,
+                create: args.create,
+                undo_log: undo_log.clone(),
+                ..Default::default()
+            };
+            delete::search_and_move(&mut session, &criteria, &args.to, &opts, ui)
+                .map_err(anyhow::Error::from)
+                .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Export(args) => cmd_export(&mut session, args, &default_folder, ui)
+            .and_then(|report| finish_action(&report, args.report, &mut no_match)),
+        Commands::DownloadAttachments(args) => {
+            cmd_download(&mut session, args, &default_folder, ui)
+                .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Mark(args) => cmd_mark(&mut session, args, &default_folder, ui)
+            .and_then(|report| finish_action(&report, args.report, &mut no_match)),
+        Commands::Append(args) => cmd_append(&mut session, args, &default_folder, ui)
+            .and_then(|report| finish_action(&report, args.report, &mut no_match)),
+        Commands::Purge(args) => {
+            let trash = args.trash_folder.as_deref().unwrap_or(&default_trash);
+            let opts = delete::ActOptions {
+                yes: args.yes,
+                dry_run: args.dry_run,
+                expunge: true,
+                ..Default::default()
+            };
+            delete::purge(
+                &mut session,
+                trash,
+                args.older_than.as_deref(),
+                args.newer_than.as_deref(),
+                &opts,
+                ui,
+            )
+            .map_err(anyhow::Error::from)
+            .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Dedupe(args) => {
+            let criteria = args.filter.to_criteria(None, &default_folder);
+            let trash = args.trash_folder.as_deref().unwrap_or(&default_trash);
+            let opts = delete::ActOptions {
+                yes: args.yes,
+                dry_run: args.dry_run,
+                ..Default::default()
+            };
+            dedupe::dedupe(&mut session, &criteria, trash, &opts, ui)
+                .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Undo(args) => {
+            let opts = delete::ActOptions {
+                yes: args.yes,
+                dry_run: args.dry_run,
+                ..Default::default()
+            };
+            let log = undo_log.as_deref().context(
+                "The undo log is off; set undo_log = true in the config to record moves",
+            )?;
+            undo::undo(&mut session, log, &opts, ui)
+                .and_then(|report| finish_action(&report, args.report, &mut no_match))
+        }
+        Commands::Count(args) => {
+            cmd_count(&mut session, args, &default_folder, format, ui).map(|total| {
+                no_match = total == 0;
+            })
+        }
+        Commands::Show(args) => cmd_show(&mut session, args, &default_folder, ui),
+        Commands::Watch(args) => cmd_watch(&mut session, args, &default_folder, ui),
+        Commands::Cat(args) => cmd_cat(&mut session, args, &default_folder, ui),
+        Commands::Fetch(args) => cmd_fetch(&mut session, args, &default_folder, ui),
+        Commands::Folder(cmd) => cmd_folder(&mut session, cmd),
+        Commands::Quota(args) => cmd_quota(&mut session, args, ui),
+        Commands::Status => cmd_status(&mut session, ui),
+        Commands::Capabilities => cmd_capabilities(&session),
+        Commands::Ping => cmd_ping(&mut session, &host),
+        Commands::Stats(args) => cmd_stats(&mut session, args, &default_folder, ui),
+        Commands::Unsubscribe(args) => cmd_unsubscribe(&mut session, args, &default_folder, ui),
+        Commands::Login => login_saved
+            .expect("set once the login succeeded")
+            .map(|()| println!("Saved password for {user} on {host} in the system keychain")),
+        Commands::Completions { .. } | Commands::Manpage | Commands::Forget => unreachable!(),
+    };
+
+    let result = result.map_err(|e| timeout_error(e, timeout_secs));
+
+    let _ = session.logout();
+    timings::print_report(started.elapsed());
+    result.map(|()| {
+        if no_match {
+            Outcome::NoMatch
+        } else {
+            Outcome::Done
+        }
+    })
+}
 
-    let mut total_messages: u32 = 0;
-    let mut total_unseen: u32 = 0;
-    let mut total_recent: u32 = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for name in &folder_names {
-        // Folder names are server-controlled, so always quote via imap_quote()
-        // which strips control chars and escapes IMAP-special characters.
-        let quoted = search::imap_quote(name);
-        let cmd = format!("STATUS {quoted} (MESSAGES UNSEEN RECENT)");
-        let response = match session.run_command_and_read_response(&cmd) {
-            Ok(r) => r,
-            Err(_) => {
-                table.add_row(vec![name.as_str(), "?", "?", "?"]);
-                continue;
-            }
+    #[test]
+    fn search_args_first_sorts_oldest() {
+        let cli = Cli::parse_from(["slashmail", "search", "--first", "3"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
         };
+        assert_eq!(args.limit_and_order(), (Some(3), true));
+    }
 
-        let text = String::from_utf8_lossy(&response);
-        let mut messages: u32 = 0;
-        let mut unseen: u32 = 0;
-        let mut recent: u32 = 0;
-
-        if let Some(cap) = status_regex().captures(&text) {
-            let attrs = &cap[1];
-            // Parse key-value pairs: MESSAGES 142 UNSEEN 12 RECENT 3
-            let tokens: Vec<&str> = attrs.split_whitespace().collect();
-            for pair in tokens.chunks(2) {
-                if pair.len() == 2 {
-                    let val: u32 = pair[1].parse().unwrap_or(0);
-                    match pair[0].to_uppercase().as_str() {
-                        "MESSAGES" => messages = val,
-                        "UNSEEN" => unseen = val,
-                        "RECENT" => recent = val,
-                        _ => {}
-                    }
-                }
-            }
-        }
+    #[test]
+    fn search_args_last_and_limit_are_newest() {
+        let cli = Cli::parse_from(["slashmail", "search", "--last", "3"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(args.limit_and_order(), (Some(3), false));
 
-        total_messages += messages;
-        total_unseen += unseen;
-        total_recent += recent;
+        let cli = Cli::parse_from(["slashmail", "search", "-n", "5"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(args.limit_and_order(), (Some(5), false));
+    }
 
-        table.add_row(vec![
-            name.as_str(),
-            &messages.to_string(),
-            &unseen.to_string(),
-            &recent.to_string(),
+    #[test]
+    fn search_args_sort_and_reverse() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "search",
+            "--sort",
+            "size",
+            "--reverse",
+            "-n",
+            "4",
         ]);
-    }
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(args.sort, SortBy::Size);
+        assert_eq!(args.limit_and_order(), (Some(4), true));
+        assert_eq!(search::SortKey::from(args.sort), search::SortKey::Size);
 
-    sp.finish_and_clear();
+        let cli = Cli::parse_from(["slashmail", "search"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(args.sort, SortBy::Date);
+        assert_eq!(args.limit_and_order(), (None, false));
 
-    // Total row
-    table.add_row(vec![
-        Cell::new("Total").fg(Color::Cyan),
-        Cell::new(total_messages).fg(Color::Cyan),
-        Cell::new(total_unseen).fg(Color::Cyan),
-        Cell::new(total_recent).fg(Color::Cyan),
-    ]);
+        assert!(Cli::try_parse_from(["slashmail", "search", "--sort", "flags"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "search", "--first", "3", "--reverse"]).is_err());
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--last", "3", "--sort", "size"]).is_err()
+        );
+    }
 
-    println!("{table}");
-    Ok(())
-}
+    #[test]
+    fn search_args_columns_keep_order() {
+        let cli = Cli::parse_from(["slashmail", "search", "--columns", "subject,date,uid"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(
+            args.columns,
+            vec![ColumnArg::Subject, ColumnArg::Date, ColumnArg::Uid]
+        );
+        assert!(command_format(&Commands::Search(args)).is_ok());
 
-fn cmd_export(
-    session: &mut connection::ImapSession,
-    args: &ExportArgs,
-    default_folder: &str,
-) -> Result<()> {
-    let criteria = args.filter.to_criteria(args.limit, default_folder);
-    let sp = spinner("Searching...");
-    let messages = search::search(session, &criteria)?;
-    sp.finish_and_clear();
+        let err = Cli::try_parse_from(["slashmail", "search", "--columns", "uid,flags"])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("flags") && err.contains("subject"), "{err}");
 
-    if messages.is_empty() {
-        println!("No messages found.");
-        return Ok(());
+        let cli = Cli::parse_from(["slashmail", "search", "--columns", "uid", "--json"]);
+        assert!(command_format(&cli.command).is_err());
     }
 
-    display::display_messages(&messages);
+    #[test]
+    fn search_args_first_conflicts_with_limit() {
+        assert!(Cli::try_parse_from(["slashmail", "search", "--first", "3", "-n", "2"]).is_err());
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--first", "3", "--last", "2"]).is_err()
+        );
+    }
 
-    let out_dir = args
-        .output_dir
-        .clone()
-        .unwrap_or_else(|| PathBuf::from("."));
+    #[test]
+    fn search_args_offset_pages_with_limit() {
+        let cli = Cli::parse_from(["slashmail", "search", "--offset", "20", "-n", "10"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!((args.offset, args.limit), (20, Some(10)));
+        let cli = Cli::parse_from(["slashmail", "search"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        assert_eq!(args.offset, 0);
+        assert!(Cli::try_parse_from(["slashmail", "search", "--offset", "5", "--thread"]).is_err());
+    }
 
-    if !args.yes {
-        let confirm = inquire::Confirm::new(&format!(
-            "Export {} message(s) to {}?",
-            messages.len(),
-            out_dir.display()
-        ))
-        .with_default(false)
-        .prompt()
-        .context("Prompt failed")?;
+    #[test]
+    fn status_command_neutralizes_crlf_folder() {
+        let cmd = status_command("INBOX\r\nA1 DELETE \"Archive\"");
+        assert!(!cmd.contains('\r') && !cmd.contains('\n'));
+        assert_eq!(
+            cmd,
+            "STATUS \"INBOXA1 DELETE \\\"Archive\\\"\" (MESSAGES UNSEEN RECENT)"
+        );
+    }
 
-        if !confirm {
-            println!("Aborted.");
-            return Ok(());
-        }
+    #[test]
+    fn parse_quota_response_all_resources() {
+        let data = b"* QUOTAROOT INBOX \"\"\r\n* QUOTA \"\" (STORAGE 512 1024 MESSAGE 10 100)\r\nA1 OK done\r\n";
+        assert_eq!(
+            parse_quota_response(data).unwrap(),
+            vec![(
+                String::new(),
+                vec![
+                    ("STORAGE".to_string(), 512, 1024),
+                    ("MESSAGE".to_string(), 10, 100)
+                ]
+            )]
+        );
     }
 
-    let sp = spinner("Exporting...");
-    let (exported, skipped) =
-        export::export_messages(session, &messages, &criteria.folder, &out_dir, args.force)?;
-    sp.finish_and_clear();
+    #[test]
+    fn parse_quota_response_literal_root() {
+        let data = b"* QUOTA {9}\r\nuser)\r\nme (STORAGE 1 2)\r\nA1 OK done\r\n";
+        assert_eq!(
+            parse_quota_response(data).unwrap(),
+            vec![(
+                "user)\r\nme".to_string(),
+                vec![("STORAGE".to_string(), 1, 2)]
+            )]
+        );
+    }
 
-    print!("Exported {exported} message(s) to {}", out_dir.display());
-    if skipped > 0 {
-        print!(" ({skipped} skipped, already exist)");
+    #[test]
+    fn parse_quota_response_literal_resource_names() {
+        let data = b"* QUOTA \"\" ({7}\r\nSTORAGE 3 8 \"MESSAGE\" 4 9)\r\nA1 OK done\r\n";
+        assert_eq!(
+            parse_quota_response(data).unwrap(),
+            vec![(
+                String::new(),
+                vec![("STORAGE".to_string(), 3, 8), ("MESSAGE".to_string(), 4, 9)]
+            )]
+        );
     }
-    println!();
-    Ok(())
-}
 
-fn validate_mark_flags(read: bool, unread: bool, flagged: bool, unflagged: bool) -> Result<()> {
-    if !read && !unread && !flagged && !unflagged {
-        bail!("Specify at least one flag: --read, --unread, --flagged, --unflagged");
+    #[test]
+    fn parse_quota_response_reordered_and_spaced_roots() {
+        let data = b"* QUOTAROOT INBOX \"User quota\" \"Shared quota\"\r\n\
+* QUOTA \"User quota\" (MESSAGE 0 5000 STORAGE 1234 10000)\r\n\
+* QUOTA \"Shared quota\" (STORAGE 7 20)\r\nA1 OK done\r\n";
+        assert_eq!(
+            parse_quota_response(data).unwrap(),
+            vec![
+                (
+                    "User quota".to_string(),
+                    vec![
+                        ("MESSAGE".to_string(), 0, 5000),
+                        ("STORAGE".to_string(), 1234, 10000)
+                    ]
+                ),
+                (
+                    "Shared quota".to_string(),
+                    vec![("STORAGE".to_string(), 7, 20)]
+                )
+            ]
+        );
     }
-    if read && unread {
-        bail!("Cannot use --read and --unread together");
+
+    #[test]
+    fn quota_command_quotes_the_folder() {
+        assert_eq!(quota_command("INBOX"), "GETQUOTAROOT \"INBOX\"");
+        assert_eq!(
+            quota_command("Shared \"Team\"\r\n"),
+            "GETQUOTAROOT \"Shared \\\"Team\\\"\""
+        );
     }
-    if flagged && unflagged {
-        bail!("Cannot use --flagged and --unflagged together");
+
+    #[test]
+    fn parse_quota_response_none() {
+        assert!(parse_quota_response(b"A1 OK done\r\n").unwrap().is_empty());
     }
-    Ok(())
-}
 
-fn mark_store_ops(read: bool, unread: bool, flagged: bool, unflagged: bool) -> Vec<String> {
-    let mut ops = Vec::new();
-    if read {
-        ops.push("+FLAGS (\\Seen)".to_string());
+    #[test]
+    fn parse_status_response_quoted_and_literal_mailbox() {
+        let data = b"* STATUS \"Sent (old)\" (MESSAGES 142 UNSEEN 12 RECENT 3)\r\nA1 OK done\r\n";
+        assert_eq!(parse_status_response(data).unwrap(), (142, 12, 3));
+
+        let data = b"* STATUS {8}\r\nA\r\n(B) C (unseen 2 MESSAGES 5)\r\nA1 OK done\r\n";
+        assert_eq!(parse_status_response(data).unwrap(), (5, 2, 0));
+
+        let data = b"* STATUS {5}\r\nINBOX ({8}\r\nMESSAGES 7 \"RECENT\" 1)\r\nA1 OK done\r\n";
+        assert_eq!(parse_status_response(data).unwrap(), (7, 0, 1));
     }
-    if unread {
-        ops.push("-FLAGS (\\Seen)".to_string());
+
+    #[test]
+    fn parse_status_response_parenthesized_folder_name() {
+        let data =
+            b"* STATUS \"Projects (2024)\" (MESSAGES 40 UNSEEN 4 RECENT 1)\r\nA1 OK done\r\n";
+        assert_eq!(parse_status_response(data).unwrap(), (40, 4, 1));
+
+        // Unbalanced parens in the name must not shift the attribute list either
+        let data = b"* STATUS \"Drafts (old\" (MESSAGES 2 UNSEEN 1)\r\nA1 OK done\r\n";
+        assert_eq!(parse_status_response(data).unwrap(), (2, 1, 0));
     }
-    if flagged {
-        ops.push("+FLAGS (\\Flagged)".to_string());
+
+    #[test]
+    fn parse_list_status_response_pairs_lists_with_status() {
+        let data = b"* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+* STATUS INBOX (MESSAGES 17 UNSEEN 16 RECENT 2)\r\n\
+* LIST (\\Noselect \\HasChildren) \"/\" \"[Gmail]\"\r\n\
+* LIST (\\HasNoChildren) \"/\" {15}\r\nProjects (2024)\r\n\
+* STATUS \"Projects (2024)\" (MESSAGES 4 UNSEEN 0)\r\n\
+A1 OK LIST completed\r\n";
+        assert_eq!(
+            parse_list_status_response(data).unwrap(),
+            vec![
+                ("INBOX".to_string(), Some((17, 16, 2))),
+                ("[Gmail]".to_string(), None),
+                ("Projects (2024)".to_string(), Some((4, 0, 0))),
+            ]
+        );
+        assert!(parse_list_status_response(b"A1 OK done\r\n")
+            .unwrap()
+            .is_empty());
     }
-    if unflagged {
-        ops.push("-FLAGS (\\Flagged)".to_string());
+
+    #[test]
+    fn parse_status_response_malformed() {
+        assert!(parse_status_response(b"* STATUS INBOX (MESSAGES 1\r\n").is_err());
+        assert_eq!(parse_status_response(b"A1 OK done\r\n").unwrap(), (0, 0, 0));
     }
-    ops
-}
 
-fn mark_action_desc(read: bool, unread: bool, flagged: bool, unflagged: bool) -> String {
-    let mut actions = Vec::new();
-    if read {
-        actions.push("mark read");
+    #[test]
+    fn repeated_from_and_subject_are_collected() {
+        let cli = Cli::try_parse_from([
+            "slashmail",
+            "search",
+            "--from",
+            "alice",
+            "--from",
+            "bob",
+            "--subject",
+            "invoice",
+        ])
+        .unwrap();
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        let criteria = args.filter.to_criteria(None, "INBOX");
+        assert_eq!(criteria.from, vec!["alice", "bob"]);
+        assert_eq!(criteria.subject, vec!["invoice"]);
     }
-    if unread {
-        actions.push("mark unread");
+
+    #[test]
+    fn on_conflicts_with_since_and_before() {
+        let cli = Cli::try_parse_from(["slashmail", "search", "--on", "2025-03-09"]).unwrap();
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        assert_eq!(
+            args.filter.to_criteria(None, "INBOX").on.as_deref(),
+            Some("2025-03-09")
+        );
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--on", "1d", "--since", "2d"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--on", "1d", "--before", "2d"]).is_err()
+        );
     }
-    if flagged {
-        actions.push("flag");
+
+    #[test]
+    fn flag_filters_reach_criteria() {
+        let cli = Cli::try_parse_from([
+            "slashmail",
+            "count",
+            "--all-folders",
+            "--unseen",
+            "--unanswered",
+        ])
+        .unwrap();
+        let Commands::Count(args) = cli.command else {
+            panic!("expected count");
+        };
+        let criteria = args.filter.to_criteria(None, "INBOX");
+        assert!(criteria.all_folders && criteria.unseen && criteria.unanswered);
+        assert!(Cli::try_parse_from(["slashmail", "count", "--seen", "--unseen"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "count", "--answered", "--unanswered"]).is_err());
     }
-    if unflagged {
-        actions.push("unflag");
+
+    #[test]
+    fn parse_header_filter_splits_at_first_colon() {
+        assert_eq!(
+            parse_header_filter("List-Id:rust-users").unwrap(),
+            ("List-Id".to_string(), "rust-users".to_string())
+        );
+        assert_eq!(
+            parse_header_filter("X-Spam-Status: Yes, score=5:2").unwrap(),
+            ("X-Spam-Status".to_string(), "Yes, score=5:2".to_string())
+        );
+        assert_eq!(
+            parse_header_filter("X-Mailer:").unwrap(),
+            ("X-Mailer".to_string(), String::new())
+        );
     }
-    actions.join(" + ")
-}
 
-fn cmd_mark(
-    session: &mut connection::ImapSession,
-    args: &MarkArgs,
-    default_folder: &str,
-) -> Result<()> {
-    validate_mark_flags(args.read, args.unread, args.flagged, args.unflagged)?;
+    #[test]
+    fn parse_header_filter_rejects_bad_values() {
+        let err = parse_header_filter("List-Id").unwrap_err().to_string();
+        assert!(err.contains("NAME:VALUE"), "{err}");
+        assert!(parse_header_filter(":value").is_err());
+        assert!(parse_header_filter("List Id:value").is_err());
+    }
 
-    let criteria = args.filter.to_criteria(args.limit, default_folder);
-    let sp = spinner("Searching...");
-    let messages = search::search(session, &criteria)?;
-    sp.finish_and_clear();
+    #[test]
+    fn header_filter_is_repeatable() {
+        let cli = Cli::try_parse_from([
+            "slashmail",
+            "search",
+            "--header",
+            "List-Id:rust",
+            "--header",
+            "X-Mailer:",
+        ])
+        .unwrap();
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        assert_eq!(
+            args.filter.to_criteria(None, "INBOX").headers,
+            vec![
+                ("List-Id".to_string(), "rust".to_string()),
+                ("X-Mailer".to_string(), String::new()),
+            ]
+        );
+        assert!(Cli::try_parse_from(["slashmail", "search", "--header", "nocolon"]).is_err());
+    }
 
-    if messages.is_empty() {
-        println!("No messages match the criteria.");
-        return Ok(());
+    #[test]
+    fn parse_host_imaps_url_with_port() {
+        assert_eq!(
+            parse_host("imaps://imap.example.com:993").unwrap(),
+            HostTarget {
+                host: "imap.example.com".into(),
+                port: Some(993),
+                tls: Some(true),
+            }
+        );
     }
 
-    display::display_messages(&messages);
+    #[test]
+    fn parse_host_imap_url_without_port() {
+        assert_eq!(
+            parse_host("imap://h/").unwrap(),
+            HostTarget {
+                host: "h".into(),
+                port: None,
+                tls: Some(false),
+            }
+        );
+        assert_eq!(
+            parse_host("IMAPS://[::1]:1993").unwrap(),
+            HostTarget {
+                host: "::1".into(),
+                port: Some(1993),
+                tls: Some(true),
+            }
+        );
+    }
 
-    let action_desc = mark_action_desc(args.read, args.unread, args.flagged, args.unflagged);
+    #[cfg(unix)]
+    #[test]
+    fn run_pass_command_trims_trailing_newline() {
+        assert_eq!(run_pass_command("printf 's3cret\\n'").unwrap(), "s3cret");
+        assert_eq!(run_pass_command("printf 'a b\\r\\n\\n'").unwrap(), "a b");
+    }
 
-    if args.dry_run {
-        println!(
-            "Dry run: would {action_desc} {} message(s).",
-            messages.len()
+    #[cfg(unix)]
+    #[test]
+    fn run_pass_command_failure_is_an_error() {
+        let err = run_pass_command("exit 3").unwrap_err().to_string();
+        assert!(err.contains("failed"), "{err}");
+        assert!(
+            run_pass_command("true").is_err(),
+            "empty output must be rejected"
         );
-        return Ok(());
     }
 
-    if !args.yes {
-        let confirm =
-            inquire::Confirm::new(&format!("{action_desc} {} message(s)?", messages.len()))
-                .with_default(false)
-                .prompt()
-                .context("Prompt failed")?;
+    #[test]
+    fn resolve_security_precedence() {
+        let none = config::Config::default();
+        let starttls_cfg: config::Config = toml::from_str("starttls = true").unwrap();
+        let tls_cfg: config::Config = toml::from_str("tls = true").unwrap();
 
-        if !confirm {
-            println!("Aborted.");
-            return Ok(());
+        assert_eq!(
+            resolve_security(false, false, None, &none).unwrap(),
+            Security::Plain
+        );
+        assert_eq!(
+            resolve_security(true, false, None, &starttls_cfg).unwrap(),
+            Security::Tls
+        );
+        assert_eq!(
+            resolve_security(false, true, None, &tls_cfg).unwrap(),
+            Security::StartTls
+        );
+        assert_eq!(
+            resolve_security(false, false, None, &starttls_cfg).unwrap(),
+            Security::StartTls
+        );
+        // imap:// means "not implicit TLS", which STARTTLS from the config still upgrades
+        assert_eq!(
+            resolve_security(false, false, Some(false), &starttls_cfg).unwrap(),
+            Security::StartTls
+        );
+        assert_eq!(
+            resolve_security(false, false, Some(true), &starttls_cfg).unwrap(),
+            Security::Tls
+        );
+        assert!(resolve_security(false, true, Some(true), &none).is_err());
+    }
+
+    #[test]
+    fn no_skip_aliases_include_skipped() {
+        for flag in ["--include-skipped", "--no-skip", "--include-trash"] {
+            let cli = Cli::try_parse_from(["slashmail", "delete", "--all-folders", flag]).unwrap();
+            let Commands::Delete(args) = cli.command else {
+                panic!("expected delete");
+            };
+            assert!(args.filter.include_skipped);
         }
+        assert!(Cli::try_parse_from(["slashmail", "count", "--no-skip"]).is_err());
+    }
+
+    #[test]
+    fn search_jobs_needs_all_folders_and_is_bounded() {
+        let cli =
+            Cli::try_parse_from(["slashmail", "search", "--all-folders", "--jobs", "4"]).unwrap();
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        assert_eq!(args.jobs, 4);
+        let cli = Cli::try_parse_from(["slashmail", "search"]).unwrap();
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        assert_eq!(args.jobs, 1);
+        assert!(Cli::try_parse_from(["slashmail", "search", "--jobs", "4"]).is_err());
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--all-folders", "--jobs", "0"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["slashmail", "search", "--all-folders", "--jobs", "9"]).is_err()
+        );
     }
 
-    let store_ops = mark_store_ops(args.read, args.unread, args.flagged, args.unflagged);
+    #[test]
+    fn timeout_accepts_zero() {
+        let cli = Cli::try_parse_from(["slashmail", "--timeout", "0", "status"]).unwrap();
+        assert_eq!(cli.timeout, Some(0));
+        assert!(Cli::try_parse_from(["slashmail", "--timeout", "-1", "status"]).is_err());
+    }
 
-    let sp = spinner("Updating flags...");
+    #[test]
+    fn timeout_error_prefixes_socket_timeouts() {
+        let io = std::io::Error::new(std::io::ErrorKind::WouldBlock, "read");
+        let err = timeout_error(anyhow::Error::new(io), 30);
+        assert!(err.to_string().contains("timed out after 30s"), "{err}");
+        let err = timeout_error(anyhow::anyhow!("IMAP login failed"), 30);
+        assert_eq!(err.to_string(), "IMAP login failed");
+    }
 
-    // Group by folder
-    let mut by_folder: std::collections::HashMap<String, Vec<u32>> =
-        std::collections::HashMap::new();
-    for msg in &messages {
-        let folder = msg
-            .folder
-            .clone()
-            .unwrap_or_else(|| criteria.folder.clone());
-        by_folder.entry(folder).or_default().push(msg.uid);
+    #[test]
+    fn starttls_conflicts_with_tls() {
+        assert!(Cli::try_parse_from(["slashmail", "--starttls", "status"]).is_ok());
+        assert!(Cli::try_parse_from(["slashmail", "--tls", "--starttls", "status"]).is_err());
     }
 
-    let mut total = 0usize;
-    for (folder, uids) in &by_folder {
-        session
-            .select(folder)
-            .with_context(|| format!("Failed to select '{folder}'"))?;
-
-        for chunk in &search::build_uid_set(uids) {
-            for op in &store_ops {
-                session
-                    .uid_store(chunk, op)
-                    .with_context(|| format!("Failed to store flags in '{folder}'"))?;
+    #[test]
+    fn parse_host_bare_hostname_unchanged() {
+        assert_eq!(
+            parse_host("imap.example.com").unwrap(),
+            HostTarget {
+                host: "imap.example.com".into(),
+                port: None,
+                tls: None,
             }
-        }
+        );
+    }
+
+    #[test]
+    fn parse_host_rejects_bad_urls() {
+        assert!(parse_host("pop3://h").is_err());
+        assert!(parse_host("imaps://h:99999").is_err());
+        assert!(parse_host("imaps://h:abc").is_err());
+        assert!(parse_host("imaps://user@h").is_err());
+        assert!(parse_host("imaps://h/INBOX").is_err());
+        assert!(parse_host("imaps://").is_err());
+    }
 
-        total += uids.len();
+    #[test]
+    fn json_envelope_requires_json() {
+        let format_of = |argv: &[&str]| command_format(&Cli::try_parse_from(argv).unwrap().command);
+        assert_eq!(
+            format_of(&["slashmail", "search", "--json", "--json-envelope"]).unwrap(),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format_of(&["slashmail", "count", "--format", "json", "--json-envelope"]).unwrap(),
+            OutputFormat::Json
+        );
+        let err = format_of(&["slashmail", "count", "--json-envelope"]).unwrap_err();
+        assert!(err.to_string().contains("--format json"), "{err}");
     }
 
-    sp.finish_and_clear();
-    println!("Updated {total} message(s).");
-    Ok(())
-}
+    #[test]
+    fn export_mbox_needs_output_file() {
+        let format_of = |argv: &[&str]| command_format(&Cli::try_parse_from(argv).unwrap().command);
+        assert!(format_of(&[
+            "slashmail",
+            "export",
+            "--format",
+            "mbox",
+            "--output-file",
+            "a.mbox"
+        ])
+        .is_ok());
+        assert!(format_of(&["slashmail", "export", "-o", "out"]).is_ok());
+        assert!(format_of(&["slashmail", "export", "--format", "mbox"]).is_err());
+        assert!(format_of(&["slashmail", "export", "--output-file", "a.mbox"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "export", "--format", "json"]).is_err());
+        assert!(format_of(&[
+            "slashmail",
+            "export",
+            "--format",
+            "maildir",
+            "-o",
+            "Archive"
+        ])
+        .is_ok());
+        assert!(format_of(&[
+            "slashmail",
+            "export",
+            "--format",
+            "maildir",
+            "--output-file",
+            "x"
+        ])
+        .is_err());
+        assert!(format_of(&["slashmail", "export", "--filename-template", "{uid}.eml"]).is_ok());
+        assert!(format_of(&[
+            "slashmail",
+            "export",
+            "--format",
+            "maildir",
+            "--filename-template",
+            "{uid}.eml"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from([
+            "slashmail",
+            "export",
+            "--format",
+            "mbox",
+            "--output-file",
+            "a.mbox",
+            "-o",
+            "dir",
+        ])
+        .is_err());
+    }
 
-fn cmd_count(
-    session: &mut connection::ImapSession,
-    args: &CountArgs,
-    default_folder: &str,
-) -> Result<()> {
-    let criteria = args.filter.to_criteria(None, default_folder);
-    let query = search::build_query(&criteria)?;
+    #[test]
+    fn format_json_matches_json_flag() {
+        let format_of = |argv: &[&str]| command_format(&Cli::try_parse_from(argv).unwrap().command);
+        assert_eq!(
+            format_of(&["slashmail", "search"]).unwrap(),
+            OutputFormat::Table
+        );
+        assert_eq!(
+            format_of(&["slashmail", "search", "--format", "json"]).unwrap(),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format_of(&["slashmail", "count", "--format", "json"]).unwrap(),
+            OutputFormat::Json
+        );
+        assert!(format_of(&["slashmail", "search", "--format", "json", "--thread"]).is_err());
+        assert!(format_of(&["slashmail", "count", "--format", "json", "--silent"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "status", "--format", "json"]).is_err());
+        assert_eq!(
+            format_of(&["slashmail", "search", "--format", "csv"]).unwrap(),
+            OutputFormat::Csv
+        );
+        assert!(format_of(&["slashmail", "search", "--format", "tsv", "--json-envelope"]).is_err());
+        assert!(format_of(&["slashmail", "count", "--format", "csv"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "search", "--format", "mbox"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "search", "--format", "xml"]).is_err());
+    }
 
-    let sp = spinner("Counting...");
+    #[test]
+    fn report_accepts_only_json() {
+        for cmd in ["delete", "export"] {
+            assert!(Cli::try_parse_from(["slashmail", cmd, "--report", "json"]).is_ok());
+        }
+        assert!(Cli::try_parse_from(["slashmail", "purge", "--report", "json"]).is_ok());
+        assert!(
+            Cli::try_parse_from(["slashmail", "move", "--to", "Archive", "--report", "json"])
+                .is_ok()
+        );
+        assert!(Cli::try_parse_from(["slashmail", "delete", "--report", "yaml"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "search", "--report", "json"]).is_err());
+    }
 
-    if criteria.all_folders {
-        let folders = session
-            .list(Some(""), Some("*"))
-            .context("Failed to list folders")?;
-        let folder_names: Vec<String> = folders
-            .iter()
-            .map(|f| f.name().to_string())
-            .filter(|n| !search::folders_to_skip(n))
-            .collect();
+    #[test]
+    fn count_silent_conflicts_with_json() {
+        assert!(Cli::try_parse_from(["slashmail", "count", "--silent"]).is_ok());
+        assert!(Cli::try_parse_from(["slashmail", "count", "--silent", "--json"]).is_err());
+    }
 
-        let mut grand_total = 0usize;
-        let mut results: Vec<(String, usize)> = Vec::new();
+    #[test]
+    fn thread_command_takes_filters_and_limit() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "thread",
+            "--folder",
+            "Lists",
+            "--subject",
+            "release",
+            "-n",
+            "50",
+        ]);
+        let Commands::Thread(args) = cli.command else {
+            panic!("expected thread command");
+        };
+        let criteria = args.filter.to_criteria(args.limit, "INBOX");
+        assert_eq!(criteria.folder, "Lists");
+        assert_eq!(criteria.limit, Some(50));
+        assert!(Cli::try_parse_from(["slashmail", "thread", "--format", "json"]).is_err());
+    }
 
-        for folder in &folder_names {
-            match session.select(folder) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Warning: skipping folder '{folder}': {e}");
-                    continue;
-                }
-            }
-            match session.uid_search(&query) {
-                Ok(uids) => {
-                    let count = uids.len();
-                    if count > 0 {
-                        results.push((folder.clone(), count));
-                        grand_total += count;
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Warning: search failed in '{folder}': {e}");
-                }
-            }
+    #[test]
+    fn interactive_replaces_yes() {
+        for cmd in [
+            vec!["delete"],
+            vec!["move", "--to", "Archive"],
+            vec!["mark", "--read"],
+        ] {
+            let argv = |extra: &[&'static str]| {
+                let mut argv = vec!["slashmail"];
+                argv.extend(&cmd);
+                argv.extend(extra);
+                argv
+            };
+            assert!(Cli::try_parse_from(argv(&["--interactive"])).is_ok());
+            assert!(Cli::try_parse_from(argv(&["--interactive", "--yes"])).is_err());
         }
+    }
 
-        sp.finish_and_clear();
+    #[test]
+    fn create_applies_to_move_and_trash() {
+        let cli = Cli::parse_from(["slashmail", "move", "--to", "Archive/2026", "--create"]);
+        let Commands::Move(args) = cli.command else {
+            panic!("expected move");
+        };
+        assert!(args.create);
+        let cli = Cli::parse_from(["slashmail", "delete", "--create"]);
+        let Commands::Delete(args) = cli.command else {
+            panic!("expected delete");
+        };
+        assert!(args.create);
+        assert!(Cli::try_parse_from(["slashmail", "delete", "--create", "--expunge"]).is_err());
+    }
 
-        if args.json {
-            let folders: Vec<serde_json::Value> = results
-                .iter()
-                .map(|(f, c)| serde_json::json!({"folder": f, "count": c}))
-                .collect();
-            println!(
-                "{}",
-                serde_json::json!({"folders": folders, "total": grand_total})
-            );
-        } else if results.is_empty() {
-            println!("0 message(s) match.");
-        } else {
-            for (folder, count) in &results {
-                println!("{count} message(s) in {folder}");
-            }
-            if results.len() > 1 {
-                println!("{grand_total} message(s) total");
-            }
+    #[test]
+    fn uids_from_on_action_commands() {
+        for cmd in [
+            vec!["delete"],
+            vec!["move", "--to", "Archive"],
+            vec!["mark", "--read"],
+        ] {
+            let argv = |extra: &[&'static str]| {
+                let mut argv = vec!["slashmail"];
+                argv.extend(&cmd);
+                argv.extend(extra);
+                argv
+            };
+            assert!(Cli::try_parse_from(argv(&["--uids-from", "-"])).is_ok());
+            assert!(Cli::try_parse_from(argv(&["--uids-from", "-", "--all-folders"])).is_err());
         }
-    } else {
-        session
-            .select(&criteria.folder)
-            .with_context(|| format!("Failed to select '{}'", criteria.folder))?;
+        assert!(Cli::try_parse_from(["slashmail", "search", "--uids-from", "-"]).is_err());
+    }
 
-        let uids = session.uid_search(&query).context("IMAP SEARCH failed")?;
-        sp.finish_and_clear();
-        if args.json {
-            println!(
-                "{}",
-                serde_json::json!({"folder": criteria.folder, "count": uids.len()})
-            );
-        } else {
-            println!("{} message(s) in {}", uids.len(), criteria.folder);
-        }
+    #[test]
+    fn undo_takes_yes_and_dry_run() {
+        let cli = Cli::parse_from(["slashmail", "undo", "--dry-run"]);
+        let Commands::Undo(args) = cli.command else {
+            panic!("expected undo");
+        };
+        assert!(args.dry_run && !args.yes);
+        assert!(Cli::try_parse_from(["slashmail", "undo", "--from", "x"]).is_err());
     }
 
-    Ok(())
-}
+    #[test]
+    fn download_attachments_parses_name_filter() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "download",
+            "--from",
+            "billing",
+            "--name-filter",
+            "*.PDF",
+            "-o",
+            "invoices",
+        ]);
+        let Commands::DownloadAttachments(args) = cli.command else {
+            panic!("expected download-attachments");
+        };
+        assert!(args.name_filter.unwrap().is_match("march.pdf"));
+        assert_eq!(args.output_dir, Some(PathBuf::from("invoices")));
+        assert!(
+            Cli::try_parse_from(["slashmail", "download-attachments", "--name-filter", ""])
+                .is_err()
+        );
+    }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    #[test]
+    fn unsubscribe_yes_requires_open() {
+        let cli = Cli::parse_from(["slashmail", "unsubscribe", "--from", "news", "--open"]);
+        let Commands::Unsubscribe(args) = cli.command else {
+            panic!("expected unsubscribe");
+        };
+        assert!(args.open && !args.yes);
+        assert!(Cli::try_parse_from(["slashmail", "unsubscribe", "--yes"]).is_err());
+    }
 
-    // Handle commands that don't need an IMAP connection
-    match &cli.command {
-        Commands::Completions { shell } => {
-            clap_complete::generate(
-                *shell,
-                &mut Cli::command(),
-                "slashmail",
-                &mut std::io::stdout(),
-            );
-            return Ok(());
-        }
-        Commands::Manpage => {
-            clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
-            return Ok(());
-        }
-        _ => {}
+    #[test]
+    fn folder_subcommands_parse() {
+        let cli = Cli::parse_from(["slashmail", "folder", "rename", "Old", "Work/New"]);
+        let Commands::Folder(FolderCommand::Rename { old, new }) = cli.command else {
+            panic!("expected folder rename");
+        };
+        assert_eq!((old.as_str(), new.as_str()), ("Old", "Work/New"));
+
+        let cli = Cli::parse_from(["slashmail", "folder", "delete", "Old", "--yes"]);
+        assert!(matches!(
+            cli.command,
+            Commands::Folder(FolderCommand::Delete { yes: true, .. })
+        ));
+        assert!(Cli::try_parse_from(["slashmail", "folder", "create"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "folder", "create", "A", "--yes"]).is_err());
     }
 
-    // Load config: explicit --config path > default location > empty
-    let cfg = config::Config::load(cli.config.as_deref())?;
-
-    // Resolve values: CLI/env > config > built-in default
-    let tls = cli.tls || cfg.tls.unwrap_or(false);
-    let host = cli
-        .host
-        .or(cfg.host)
-        .unwrap_or_else(|| "127.0.0.1".to_string());
-    let port = cli
-        .port
-        .or(cfg.port)
-        .unwrap_or(if tls { 993 } else { 1143 });
-    let user = cli.user.or(cfg.user).ok_or_else(|| {
-        anyhow::anyhow!("IMAP username required (use -u/--user or SLASHMAIL_USER env)")
-    })?;
-    let default_folder = cfg.default_folder.unwrap_or_else(|| "INBOX".to_string());
-    let default_trash = cfg.trash_folder.unwrap_or_else(|| "Trash".to_string());
+    #[test]
+    fn gmail_filters_reach_criteria() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "count",
+            "--gmail-raw",
+            "in:sent",
+            "--label",
+            "Receipts",
+        ]);
+        let Commands::Count(args) = cli.command else {
+            panic!("expected count");
+        };
+        let criteria = args.filter.to_criteria(None, "INBOX");
+        assert_eq!(criteria.gmail_raw.as_deref(), Some("in:sent"));
+        assert_eq!(criteria.label.as_deref(), Some("Receipts"));
+    }
 
-    let mut pass = get_password()?;
+    #[test]
+    fn quiet_is_global() {
+        assert!(Cli::parse_from(["slashmail", "-q", "search"]).quiet);
+        assert!(Cli::parse_from(["slashmail", "delete", "--quiet", "--dry-run"]).quiet);
+        assert!(!Cli::parse_from(["slashmail", "search"]).quiet);
+    }
 
-    let sp = spinner("Connecting...");
-    let session_result = connection::connect(&host, port, tls, &user, &pass);
-    sp.finish_and_clear();
+    #[test]
+    fn verbose_is_global() {
+        assert!(Cli::parse_from(["slashmail", "-v", "count"]).verbose);
+        assert!(Cli::parse_from(["slashmail", "search", "--verbose", "--from", "a"]).verbose);
+    }
 
-    // Clear password from memory on both success and error paths.
-    pass.zeroize();
+    #[test]
+    fn move_to_is_destination_and_recipient_filters() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "move",
+            "--to",
+            "Archive",
+            "--recipient",
+            "bob@example.com",
+            "--cc",
+            "carol@example.com",
+        ]);
+        let Commands::Move(args) = cli.command else {
+            panic!("expected move command");
+        };
+        assert_eq!(args.to, "Archive");
+        let criteria = args.filter.to_criteria(None, "INBOX");
+        assert_eq!(criteria.to.as_deref(), Some("bob@example.com"));
+        assert_eq!(criteria.cc.as_deref(), Some("carol@example.com"));
+
+        let cli = Cli::parse_from(["slashmail", "move", "--to", "Archive"]);
+        let Commands::Move(args) = cli.command else {
+            panic!("expected move command");
+        };
+        assert_eq!(args.filter.to_criteria(None, "INBOX").to, None);
+    }
 
-    let mut session = session_result?;
+    #[test]
+    fn search_to_and_cc_reach_criteria() {
+        let cli = Cli::parse_from(["slashmail", "search", "--to", "alice@x", "--cc", "bob@x"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search command");
+        };
+        let criteria = args.filter.to_criteria(None, "INBOX");
+        assert_eq!(
+            search::build_query(&criteria).unwrap(),
+            "TO \"alice@x\" CC \"bob@x\""
+        );
+    }
 
-    let result = match &cli.command {
-        Commands::Search(args) => {
-            let criteria = args.filter.to_criteria(args.limit, &default_folder);
-            let sp = spinner("Searching...");
-            let messages = search::search(&mut session, &criteria)?;
-            sp.finish_and_clear();
-            if args.json {
-                display::display_messages_json(&messages);
-            } else {
-                display::display_messages(&messages);
-            }
-            Ok(())
-        }
-        Commands::Read(args) => {
-            let limit = args.limit.or(Some(1));
-            let criteria = args.filter.to_criteria(limit, &default_folder);
-            let sp = spinner("Searching...");
-            let messages = search::search(&mut session, &criteria)?;
-            sp.finish_and_clear();
-            if messages.is_empty() {
-                println!("No messages found.");
-                Ok(())
-            } else {
-                let sp = spinner("Fetching...");
-                let r = read::read_messages(&mut session, &messages, &criteria.folder);
-                sp.finish_and_clear();
-                r
-            }
-        }
-        Commands::Delete(args) => {
-            let criteria = args.filter.to_criteria(args.limit, &default_folder);
-            let trash = args.trash_folder.as_deref().unwrap_or(&default_trash);
-            delete::delete(&mut session, &criteria, trash, args.yes, args.dry_run)
-        }
-        Commands::Move(args) => {
-            let criteria = args.filter.to_criteria(args.limit, &default_folder);
-            delete::search_and_move(&mut session, &criteria, &args.to, args.yes, args.dry_run)
-        }
-        Commands::Export(args) => cmd_export(&mut session, args, &default_folder),
-        Commands::Mark(args) => cmd_mark(&mut session, args, &default_folder),
-        Commands::Count(args) => cmd_count(&mut session, args, &default_folder),
-        Commands::Quota => cmd_quota(&mut session),
-        Commands::Status => cmd_status(&mut session),
-        Commands::Completions { .. } | Commands::Manpage => unreachable!(),
-    };
+    #[test]
+    fn timings_is_global() {
+        assert!(Cli::try_parse_from(["slashmail", "--timings", "status"]).is_ok());
+        assert!(Cli::try_parse_from(["slashmail", "count", "--timings"]).is_ok());
+    }
 
-    let _ = session.logout();
-    result
-}
+    #[test]
+    fn connect_timeout_rejects_zero() {
+        assert!(Cli::try_parse_from(["slashmail", "--connect-timeout", "5", "status"]).is_ok());
+        assert!(Cli::try_parse_from(["slashmail", "--connect-timeout", "0", "status"]).is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn cat_uid_conflicts_with_all_folders() {
+        assert!(Cli::try_parse_from(["slashmail", "cat", "--uid", "7"]).is_ok());
+        assert!(Cli::try_parse_from(["slashmail", "cat", "--uid", "7", "--all-folders"]).is_err());
+    }
+
+    #[test]
+    fn append_requires_files_and_checks_date() {
+        let cli = Cli::try_parse_from([
+            "slashmail",
+            "append",
+            "a.eml",
+            "b.eml",
+            "-f",
+            "Archive",
+            "--read",
+            "--date",
+            "2025-03-09",
+        ])
+        .unwrap();
+        let Commands::Append(args) = cli.command else {
+            panic!("expected append");
+        };
+        assert_eq!(args.files.len(), 2);
+        assert!(args.read && !args.flagged);
+        assert!(args.date.is_some());
+        assert!(Cli::try_parse_from(["slashmail", "append", "-f", "Archive"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "append", "a.eml", "--date", "soon"]).is_err());
+    }
+
+    #[test]
+    fn show_takes_uid_folder_and_raw() {
+        let cli =
+            Cli::try_parse_from(["slashmail", "show", "42", "-f", "Archive", "--raw"]).unwrap();
+        let Commands::Show(args) = cli.command else {
+            panic!("expected show");
+        };
+        assert_eq!(args.uid, 42);
+        assert_eq!(args.folder.as_deref(), Some("Archive"));
+        assert!(args.raw);
+        assert!(Cli::try_parse_from(["slashmail", "show"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "show", "abc"]).is_err());
+    }
+
+    #[test]
+    fn fetch_requires_uid_and_section() {
+        assert!(
+            Cli::try_parse_from(["slashmail", "fetch", "--uid", "5", "--section", "1.2"]).is_ok()
+        );
+        assert!(Cli::try_parse_from(["slashmail", "fetch", "--uid", "5"]).is_err());
+        assert!(Cli::try_parse_from(["slashmail", "fetch", "--section", "1"]).is_err());
+    }
 
     #[test]
     fn validate_mark_flags_no_flags() {
-        assert!(validate_mark_flags(false, false, false, false).is_err());
+        assert!(validate_mark_flags(&MarkFlags::default()).is_err());
     }
 
     #[test]
     fn validate_mark_flags_read_and_unread() {
-        assert!(validate_mark_flags(true, true, false, false).is_err());
+        assert!(validate_mark_flags(&MarkFlags {
+            read: true,
+            unread: true,
+            ..Default::default()
+        })
+        .is_err());
     }
 
     #[test]
     fn validate_mark_flags_flagged_and_unflagged() {
-        assert!(validate_mark_flags(false, false, true, true).is_err());
+        assert!(validate_mark_flags(&MarkFlags {
+            flagged: true,
+            unflagged: true,
+            ..Default::default()
+        })
+        .is_err());
     }
 
     #[test]
     fn validate_mark_flags_single_flag() {
-        assert!(validate_mark_flags(true, false, false, false).is_ok());
-        assert!(validate_mark_flags(false, true, false, false).is_ok());
-        assert!(validate_mark_flags(false, false, true, false).is_ok());
-        assert!(validate_mark_flags(false, false, false, true).is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            read: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            unread: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            flagged: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            unflagged: true,
+            ..Default::default()
+        })
+        .is_ok());
     }
 
     #[test]
     fn validate_mark_flags_valid_combo() {
-        assert!(validate_mark_flags(true, false, true, false).is_ok());
-        assert!(validate_mark_flags(false, true, false, true).is_ok());
-        assert!(validate_mark_flags(true, false, false, true).is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            read: true,
+            flagged: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            unread: true,
+            unflagged: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            read: true,
+            unflagged: true,
+            ..Default::default()
+        })
+        .is_ok());
     }
 
     #[test]
     fn mark_store_ops_read() {
         assert_eq!(
-            mark_store_ops(true, false, false, false),
+            mark_store_ops(&MarkFlags {
+                read: true,
+                ..Default::default()
+            }),
             vec!["+FLAGS (\\Seen)"]
         );
     }
@@ -905,7 +3799,10 @@ mod tests {
     #[test]
     fn mark_store_ops_unread() {
         assert_eq!(
-            mark_store_ops(false, true, false, false),
+            mark_store_ops(&MarkFlags {
+                unread: true,
+                ..Default::default()
+            }),
             vec!["-FLAGS (\\Seen)"]
         );
     }
@@ -913,7 +3810,10 @@ mod tests {
     #[test]
     fn mark_store_ops_flagged() {
         assert_eq!(
-            mark_store_ops(false, false, true, false),
+            mark_store_ops(&MarkFlags {
+                flagged: true,
+                ..Default::default()
+            }),
             vec!["+FLAGS (\\Flagged)"]
         );
     }
@@ -921,30 +3821,336 @@ mod tests {
     #[test]
     fn mark_store_ops_unflagged() {
         assert_eq!(
-            mark_store_ops(false, false, false, true),
+            mark_store_ops(&MarkFlags {
+                unflagged: true,
+                ..Default::default()
+            }),
             vec!["-FLAGS (\\Flagged)"]
         );
     }
 
     #[test]
     fn mark_store_ops_combo() {
-        let ops = mark_store_ops(true, false, true, false);
-        assert_eq!(ops, vec!["+FLAGS (\\Seen)", "+FLAGS (\\Flagged)"]);
+        let ops = mark_store_ops(&MarkFlags {
+            read: true,
+            flagged: true,
+            ..Default::default()
+        });
+        assert_eq!(ops, vec!["+FLAGS (\\Seen \\Flagged)"]);
+    }
+
+    #[test]
+    fn mark_store_ops_combo_remove() {
+        let ops = mark_store_ops(&MarkFlags {
+            unread: true,
+            unflagged: true,
+            ..Default::default()
+        });
+        assert_eq!(ops, vec!["-FLAGS (\\Seen \\Flagged)"]);
+    }
+
+    #[test]
+    fn mark_store_ops_mixed_signs() {
+        let ops = mark_store_ops(&MarkFlags {
+            read: true,
+            unflagged: true,
+            ..Default::default()
+        });
+        assert_eq!(ops, vec!["+FLAGS (\\Seen)", "-FLAGS (\\Flagged)"]);
+    }
+
+    #[test]
+    fn mark_store_ops_answered_draft_deleted() {
+        let flags = MarkFlags {
+            answered: true,
+            undraft: true,
+            deleted: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            mark_store_ops(&flags),
+            vec!["+FLAGS (\\Answered \\Deleted)", "-FLAGS (\\Draft)"]
+        );
+        let flags = MarkFlags {
+            unanswered: true,
+            draft: true,
+            undeleted: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            mark_store_ops(&flags),
+            vec!["+FLAGS (\\Draft)", "-FLAGS (\\Answered \\Deleted)"]
+        );
+    }
+
+    #[test]
+    fn validate_mark_flags_set_and_unset_same_flag() {
+        for (set, unset) in [
+            ("--answered", "--unanswered"),
+            ("--draft", "--undraft"),
+            ("--deleted", "--undeleted"),
+        ] {
+            let cli = Cli::parse_from(["slashmail", "mark", set, unset]);
+            let Commands::Mark(args) = cli.command else {
+                panic!("expected mark");
+            };
+            let err = validate_mark_flags(&args.flags).unwrap_err().to_string();
+            assert_eq!(err, format!("Cannot use {set} and {unset} together"));
+        }
+        let single = MarkFlags {
+            undeleted: true,
+            ..Default::default()
+        };
+        assert!(validate_mark_flags(&single).is_ok());
+    }
+
+    #[test]
+    fn mark_action_desc_answered_draft_deleted() {
+        let flags = MarkFlags {
+            answered: true,
+            undraft: true,
+            undeleted: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            mark_action_desc(&flags),
+            "mark answered + mark not draft + undelete"
+        );
+    }
+
+    #[test]
+    fn mark_store_ops_keywords() {
+        let add = ["$Important".to_string(), "Work".to_string()];
+        let remove = ["$Junk".to_string()];
+        assert_eq!(
+            mark_store_ops(&MarkFlags {
+                read: true,
+                add_keyword: add.to_vec(),
+                remove_keyword: remove.to_vec(),
+                ..Default::default()
+            }),
+            vec!["+FLAGS (\\Seen $Important Work)", "-FLAGS ($Junk)"]
+        );
+        assert_eq!(
+            mark_store_ops(&MarkFlags {
+                remove_keyword: remove.to_vec(),
+                ..Default::default()
+            }),
+            vec!["-FLAGS ($Junk)"]
+        );
+    }
+
+    #[test]
+    fn validate_mark_flags_keywords() {
+        let work = ["Work".to_string()];
+        assert!(validate_mark_flags(&MarkFlags {
+            add_keyword: work.to_vec(),
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(validate_mark_flags(&MarkFlags {
+            remove_keyword: work.to_vec(),
+            ..Default::default()
+        })
+        .is_ok());
+        let lower = ["work".to_string()];
+        assert!(validate_mark_flags(&MarkFlags {
+            add_keyword: work.to_vec(),
+            remove_keyword: lower.to_vec(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn parse_keyword_accepts_atoms_only() {
+        assert_eq!(parse_keyword("$Important").unwrap(), "$Important");
+        assert_eq!(parse_keyword("Work").unwrap(), "Work");
+        for bad in [
+            "",
+            "two words",
+            "\\Seen",
+            "a(b",
+            "a)b",
+            "a{b",
+            "a%b",
+            "a*b",
+            "a\"b",
+            "a]b",
+            "a\\b",
+            "caf\u{e9}",
+            "tab\there",
+        ] {
+            assert!(parse_keyword(bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn mark_parses_repeated_keywords() {
+        let cli = Cli::parse_from([
+            "slashmail",
+            "mark",
+            "--add-keyword",
+            "Work",
+            "--add-keyword",
+            "$Important",
+            "--remove-keyword",
+            "$Junk",
+        ]);
+        let Commands::Mark(args) = cli.command else {
+            panic!("expected mark");
+        };
+        assert_eq!(args.flags.add_keyword, ["Work", "$Important"]);
+        assert_eq!(args.flags.remove_keyword, ["$Junk"]);
+        assert!(Cli::try_parse_from(["slashmail", "mark", "--add-keyword", "has space"]).is_err());
+    }
+
+    #[test]
+    fn mark_flag_filters_are_renamed() {
+        let cli = Cli::parse_from(["slashmail", "mark", "--is-flagged", "--unflagged"]);
+        let Commands::Mark(args) = cli.command else {
+            panic!("expected mark");
+        };
+        assert!(args.filter.flagged && !args.flags.flagged && args.flags.unflagged);
+
+        let cli = Cli::parse_from([
+            "slashmail",
+            "mark",
+            "--is-answered",
+            "--is-draft",
+            "--deleted",
+        ]);
+        let Commands::Mark(args) = cli.command else {
+            panic!("expected mark");
+        };
+        assert!(args.filter.answered && args.filter.draft);
+        assert!(!args.flags.answered && !args.flags.draft && args.flags.deleted);
+
+        let cli = Cli::parse_from(["slashmail", "search", "--flagged"]);
+        let Commands::Search(args) = cli.command else {
+            panic!("expected search");
+        };
+        assert!(args.filter.flagged);
+    }
+
+    #[test]
+    fn mark_action_desc_keywords() {
+        let add = ["Work".to_string()];
+        let remove = ["$Junk".to_string(), "Old".to_string()];
+        assert_eq!(
+            mark_action_desc(&MarkFlags {
+                read: true,
+                add_keyword: add.to_vec(),
+                remove_keyword: remove.to_vec(),
+                ..Default::default()
+            }),
+            "mark read + add keyword Work + remove keyword $Junk Old"
+        );
+    }
+
+    #[test]
+    fn planned_mark_commands_one_store_per_op() {
+        let by_folder = BTreeMap::from([("INBOX".to_string(), vec![4, 5, 6])]);
+        let ops = mark_store_ops(&MarkFlags {
+            read: true,
+            unflagged: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            planned_mark_commands(&by_folder, &ops),
+            vec![
+                "SELECT \"INBOX\"",
+                "UID STORE 4:6 +FLAGS (\\Seen)",
+                "UID STORE 4:6 -FLAGS (\\Flagged)",
+            ]
+        );
+    }
+
+    #[test]
+    fn show_commands_requires_dry_run() {
+        assert!(
+            Cli::try_parse_from(["slashmail", "delete", "--dry-run", "--show-commands"]).is_ok()
+        );
+        assert!(Cli::try_parse_from(["slashmail", "delete", "--show-commands"]).is_err());
     }
 
     #[test]
     fn mark_action_desc_single() {
-        assert_eq!(mark_action_desc(true, false, false, false), "mark read");
-        assert_eq!(mark_action_desc(false, true, false, false), "mark unread");
-        assert_eq!(mark_action_desc(false, false, true, false), "flag");
-        assert_eq!(mark_action_desc(false, false, false, true), "unflag");
+        assert_eq!(
+            mark_action_desc(&MarkFlags {
+                read: true,
+                ..Default::default()
+            }),
+            "mark read"
+        );
+        assert_eq!(
+            mark_action_desc(&MarkFlags {
+                unread: true,
+                ..Default::default()
+            }),
+            "mark unread"
+        );
+        assert_eq!(
+            mark_action_desc(&MarkFlags {
+                flagged: true,
+                ..Default::default()
+            }),
+            "flag"
+        );
+        assert_eq!(
+            mark_action_desc(&MarkFlags {
+                unflagged: true,
+                ..Default::default()
+            }),
+            "unflag"
+        );
     }
 
     #[test]
     fn mark_action_desc_combo() {
         assert_eq!(
-            mark_action_desc(true, false, true, false),
+            mark_action_desc(&MarkFlags {
+                read: true,
+                flagged: true,
+                ..Default::default()
+            }),
             "mark read + flag"
         );
     }
+
+    #[test]
+    fn empty_trash_is_purge() {
+        let cli = Cli::parse_from(["slashmail", "empty-trash", "--dry-run"]);
+        let Commands::Purge(args) = cli.command else {
+            panic!("expected purge");
+        };
+        assert!(args.dry_run);
+        assert!(args.older_than.is_none() && args.newer_than.is_none());
+    }
+
+    #[test]
+    fn delete_purge_is_expunge() {
+        let cli = Cli::parse_from(["slashmail", "delete", "--from", "spam", "--purge"]);
+        let Commands::Delete(args) = cli.command else {
+            panic!("expected delete");
+        };
+        assert!(args.expunge);
+
+        let err = Cli::try_parse_from(["slashmail", "delete", "--purge", "--trash-folder", "Bin"]);
+        assert!(
+            err.is_err(),
+            "--purge cannot be combined with --trash-folder"
+        );
+    }
+
+    #[test]
+    fn capability_note_matches_notable_capabilities() {
+        assert_eq!(capability_note("IDLE"), Some("watch"));
+        assert!(capability_note("THREAD=REFERENCES").is_some());
+        assert!(capability_note("COMPRESS=DEFLATE").is_some());
+        assert!(capability_note("CONDSTORE").is_some());
+        assert!(capability_note("X-GM-EXT-1").is_some());
+        assert_eq!(capability_note("IMAP4REV1"), None);
+        assert_eq!(capability_note("AUTH=PLAIN"), None);
+    }
 }
@@ -1,6 +1,7 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use imap_proto::types::{MessageSection, SectionPath};
 
-use crate::connection::ImapSession;
+use crate::connection::{ImapOps, ImapSession};
 use crate::display::MessageRow;
 use crate::search;
 
@@ -69,7 +70,8 @@ pub fn read_messages(
     Ok(())
 }
 
-fn print_message(raw: &[u8]) {
+/// Print headers (From/To/Cc/Date/Subject) and the decoded body of one message.
+pub fn print_message(raw: &[u8]) {
     let parsed = match mailparse::parse_mail(raw) {
         Ok(m) => m,
         Err(e) => {
@@ -213,6 +215,88 @@ fn extract_disposition_filename(disposition: &str) -> Option<String> {
     })
 }
 
+/// Parse an IMAP section spec (`1.2`, `2.MIME`, `HEADER`, `1.TEXT`) into a SectionPath.
+/// Part numbers must be non-zero without leading zeros; MIME is only valid after a part
+/// number.
+pub fn parse_section_spec(spec: &str) -> Result<SectionPath> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid section '{spec}' (expected part numbers like 1.2, optionally ending in .HEADER, .TEXT or .MIME; or HEADER/TEXT)"
+        )
+    };
+
+    let text_section = |s: &str| match s.to_uppercase().as_str() {
+        "HEADER" => Some(MessageSection::Header),
+        "TEXT" => Some(MessageSection::Text),
+        "MIME" => Some(MessageSection::Mime),
+        _ => None,
+    };
+
+    let tokens: Vec<&str> = spec.split('.').collect();
+    let mut parts = Vec::new();
+    let mut suffix = None;
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.parse::<u32>() {
+            Ok(n) if suffix.is_none() && !tok.starts_with(['+', '0']) => parts.push(n),
+            _ if i + 1 == tokens.len() => suffix = Some(text_section(tok).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        }
+    }
+
+    match (parts.is_empty(), suffix) {
+        (true, Some(MessageSection::Mime)) | (true, None) => Err(invalid()),
+        (true, Some(section)) => Ok(SectionPath::Full(section)),
+        (false, suffix) => Ok(SectionPath::Part(parts, suffix)),
+    }
+}
+
+/// Fetch the complete raw message (`BODY.PEEK[]`) without marking it read.
+pub fn fetch_raw(session: &mut ImapSession, folder: &str, uid: u32) -> Result<Vec<u8>> {
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    let fetches = session
+        .uid_fetch(&uid.to_string(), "BODY.PEEK[]")
+        .with_context(|| format!("Failed to fetch UID {uid} from '{folder}'"))?;
+
+    match fetches
+        .iter()
+        .find(|f| f.uid == Some(uid))
+        .and_then(|f| f.body())
+    {
+        Some(body) => Ok(body.to_vec()),
+        None => bail!("Message UID {uid} not found in '{folder}'"),
+    }
+}
+
+/// Fetch the raw bytes of one section of a message without marking it read.
+pub fn fetch_section(
+    session: &mut ImapSession,
+    folder: &str,
+    uid: u32,
+    spec: &str,
+) -> Result<Vec<u8>> {
+    let path = parse_section_spec(spec)?;
+    session
+        .select(folder)
+        .with_context(|| format!("Failed to select '{folder}'"))?;
+
+    let query = format!("BODY.PEEK[{}]", spec.to_uppercase());
+    let fetches = session
+        .uid_fetch(&uid.to_string(), &query)
+        .with_context(|| format!("Failed to fetch UID {uid} from '{folder}'"))?;
+
+    let fetch = match fetches.iter().find(|f| f.uid == Some(uid)) {
+        Some(f) => f,
+        None => bail!("Message UID {uid} not found in '{folder}'"),
+    };
+    match fetch.section(&path) {
+        Some(data) => Ok(data.to_vec()),
+        None => bail!("Section '{spec}' not returned for UID {uid}"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -275,4 +359,46 @@ mod tests {
     fn extract_disposition_filename_missing() {
         assert_eq!(extract_disposition_filename("attachment"), None);
     }
+
+    #[test]
+    fn parse_section_spec_parts() {
+        assert_eq!(
+            parse_section_spec("1").unwrap(),
+            SectionPath::Part(vec![1], None)
+        );
+        assert_eq!(
+            parse_section_spec("1.2").unwrap(),
+            SectionPath::Part(vec![1, 2], None)
+        );
+    }
+
+    #[test]
+    fn parse_section_spec_text_suffixes() {
+        assert_eq!(
+            parse_section_spec("2.mime").unwrap(),
+            SectionPath::Part(vec![2], Some(MessageSection::Mime))
+        );
+        assert_eq!(
+            parse_section_spec("1.2.HEADER").unwrap(),
+            SectionPath::Part(vec![1, 2], Some(MessageSection::Header))
+        );
+        assert_eq!(
+            parse_section_spec("TEXT").unwrap(),
+            SectionPath::Full(MessageSection::Text)
+        );
+        assert_eq!(
+            parse_section_spec("header").unwrap(),
+            SectionPath::Full(MessageSection::Header)
+        );
+    }
+
+    #[test]
+    fn parse_section_spec_rejects_invalid() {
+        for spec in [
+            "", "0", "1.0", "01", "1.02", "1..2", "MIME", "1.MIME.2", "1.BODY", "+1", "1 2", "1]",
+            "HEADER.1",
+        ] {
+            assert!(parse_section_spec(spec).is_err(), "accepted '{spec}'");
+        }
+    }
 }
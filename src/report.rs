@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Outcome for one folder of an action.
+#[derive(Debug, Default, Serialize)]
+pub struct FolderReport {
+    pub folder: String,
+    pub matched: usize,
+    pub acted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Outcome of a `mark`/`move`/`delete`/`export` run, printed by `--report json`.
+/// `matched` counts search hits; `acted + skipped + failed` may fall short of it when
+/// a message disappears between search and action.
+#[derive(Debug, Default, Serialize)]
+pub struct ActionReport {
+    pub command: String,
+    pub dry_run: bool,
+    pub aborted: bool,
+    pub matched: usize,
+    pub acted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub folders: Vec<FolderReport>,
+}
+
+impl ActionReport {
+    /// Start a report for `command` with one entry per folder of matched UIDs.
+    pub fn new(command: &str, by_folder: &BTreeMap<String, Vec<u32>>) -> Self {
+        let folders: Vec<FolderReport> = by_folder
+            .iter()
+            .map(|(folder, uids)| FolderReport {
+                folder: folder.clone(),
+                matched: uids.len(),
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            command: command.to_string(),
+            matched: folders.iter().map(|f| f.matched).sum(),
+            folders,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_acted(&mut self, folder: &str, n: usize) {
+        self.acted += n;
+        self.folder_mut(folder).acted += n;
+    }
+
+    pub fn add_skipped(&mut self, folder: &str, n: usize) {
+        self.skipped += n;
+        self.folder_mut(folder).skipped += n;
+    }
+
+    pub fn add_failed(&mut self, folder: &str, n: usize) {
+        self.failed += n;
+        self.folder_mut(folder).failed += n;
+    }
+
+    fn folder_mut(&mut self, folder: &str) -> &mut FolderReport {
+        let idx = match self.folders.iter().position(|f| f.folder == folder) {
+            Some(i) => i,
+            None => {
+                self.folders.push(FolderReport {
+                    folder: folder.to_string(),
+                    ..Default::default()
+                });
+                self.folders.len() - 1
+            }
+        };
+        &mut self.folders[idx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn by_folder() -> BTreeMap<String, Vec<u32>> {
+        BTreeMap::from([
+            ("Archive".to_string(), vec![1, 2]),
+            ("INBOX".to_string(), vec![5, 6, 7]),
+        ])
+    }
+
+    #[test]
+    fn new_counts_matches_per_folder() {
+        let report = ActionReport::new("move", &by_folder());
+        assert_eq!(report.command, "move");
+        assert_eq!(report.matched, 5);
+        assert_eq!(report.folders.len(), 2);
+        assert_eq!(report.folders[0].folder, "Archive");
+        assert_eq!(report.folders[0].matched, 2);
+        assert_eq!(report.folders[1].matched, 3);
+    }
+
+    #[test]
+    fn add_updates_folder_and_totals() {
+        let mut report = ActionReport::new("delete", &by_folder());
+        report.add_acted("INBOX", 2);
+        report.add_failed("INBOX", 1);
+        report.add_skipped("Archive", 2);
+        assert_eq!((report.acted, report.skipped, report.failed), (2, 2, 1));
+        assert_eq!(report.folders[1].acted, 2);
+        assert_eq!(report.folders[1].failed, 1);
+        assert_eq!(report.folders[0].skipped, 2);
+    }
+
+    #[test]
+    fn serializes_to_flat_json() {
+        let mut report = ActionReport::new("export", &by_folder());
+        report.add_acted("Archive", 2);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["command"], "export");
+        assert_eq!(json["acted"], 2);
+        assert_eq!(json["folders"][0]["folder"], "Archive");
+        assert_eq!(json["dry_run"], false);
+    }
+}
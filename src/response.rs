@@ -0,0 +1,297 @@
+use anyhow::{bail, Result};
+
+/// One token of a raw IMAP server response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// An atom or number, including `NIL` and the leading `*` / tag.
+    Atom(String),
+    /// A quoted string or literal, unescaped.
+    Str(Vec<u8>),
+    /// A parenthesized list.
+    List(Vec<Token>),
+    /// Free-form text after an `OK`/`NO`/`BAD`/`BYE`/`PREAUTH` status, up to end of line.
+    Text(String),
+}
+
+impl Token {
+    pub fn as_atom(&self) -> Option<&str> {
+        match self {
+            Token::Atom(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Atom, quoted string or literal as text (e.g. a mailbox name).
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Token::Atom(a) => Some(a.clone()),
+            Token::Str(s) => Some(String::from_utf8_lossy(s).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn as_number<T: std::str::FromStr>(&self) -> Option<T> {
+        self.as_atom().and_then(|a| a.parse().ok())
+    }
+
+    /// True for an atom equal to `word`, ignoring ASCII case.
+    pub fn is_atom(&self, word: &str) -> bool {
+        self.as_atom().is_some_and(|a| a.eq_ignore_ascii_case(word))
+    }
+}
+
+const STATUS_WORDS: [&str; 5] = ["OK", "NO", "BAD", "BYE", "PREAUTH"];
+
+/// Split a raw response into lines of tokens. Line breaks inside literals (`{N}\r\n`
+/// followed by N raw bytes) and quoted strings do not end a line.
+pub fn tokenize(data: &[u8]) -> Result<Vec<Vec<Token>>> {
+    let mut lexer = Lexer { data, pos: 0 };
+    let mut lines = Vec::new();
+    while lexer.pos < data.len() {
+        let line = lexer.line()?;
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume a CRLF (or bare LF) if one is next; returns whether it did.
+    fn eat_line_end(&mut self) -> bool {
+        match self.peek() {
+            Some(b'\r') if self.data.get(self.pos + 1) == Some(&b'\n') => {
+                self.pos += 2;
+                true
+            }
+            Some(b'\n') => {
+                self.pos += 1;
+                true
+            }
+            None => true,
+            _ => false,
+        }
+    }
+
+    fn line(&mut self) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_spaces();
+            if self.eat_line_end() {
+                return Ok(tokens);
+            }
+            if tokens.len() == 2 && STATUS_WORDS.iter().any(|w| tokens[1].is_atom(w)) {
+                tokens.push(Token::Text(self.rest_of_line()));
+                continue;
+            }
+            tokens.push(self.token()?);
+        }
+    }
+
+    fn rest_of_line(&mut self) -> String {
+        let start = self.pos;
+        while !matches!(self.peek(), None | Some(b'\r') | Some(b'\n')) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.data[start..self.pos]).into_owned()
+    }
+
+    fn token(&mut self) -> Result<Token> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_spaces();
+                    match self.peek() {
+                        Some(b')') => {
+                            self.pos += 1;
+                            return Ok(Token::List(items));
+                        }
+                        None | Some(b'\r') | Some(b'\n') => bail!("Unterminated list in response"),
+                        _ => items.push(self.token()?),
+                    }
+                }
+            }
+            Some(b')') => bail!("Unbalanced ')' in response"),
+            Some(b'"') => self.quoted(),
+            Some(b'{') => self.literal(),
+            _ => Ok(self.atom()),
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Token> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(Token::Str(out));
+                }
+                Some(b'\\') => {
+                    match self.data.get(self.pos + 1) {
+                        Some(&c) => out.push(c),
+                        None => bail!("Unterminated quoted string in response"),
+                    }
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    out.push(c);
+                    self.pos += 1;
+                }
+                None => bail!("Unterminated quoted string in response"),
+            }
+        }
+    }
+
+    /// `{N}` or `{N+}` followed by CRLF and N raw bytes.
+    fn literal(&mut self) -> Result<Token> {
+        let close = match self.data[self.pos..].iter().position(|&c| c == b'}') {
+            Some(i) => self.pos + i,
+            None => bail!("Unterminated literal length in response"),
+        };
+        let spec = String::from_utf8_lossy(&self.data[self.pos + 1..close]);
+        let len: usize = match spec.trim_end_matches('+').parse() {
+            Ok(n) => n,
+            Err(_) => bail!("Invalid literal length '{{{spec}}}' in response"),
+        };
+        self.pos = close + 1;
+        if !self.eat_line_end() {
+            bail!("Literal length not followed by CRLF in response");
+        }
+        let end = self.pos + len;
+        if end > self.data.len() {
+            bail!("Literal of {len} bytes truncated in response");
+        }
+        let bytes = self.data[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(Token::Str(bytes))
+    }
+
+    fn atom(&mut self) -> Token {
+        let start = self.pos;
+        while !matches!(
+            self.peek(),
+            None | Some(b' ' | b'\r' | b'\n' | b'(' | b')' | b'"' | b'{')
+        ) {
+            self.pos += 1;
+        }
+        // A stray CR not followed by LF: take it as a one-byte atom so the lexer advances
+        if self.pos == start {
+            self.pos += 1;
+        }
+        Token::Atom(String::from_utf8_lossy(&self.data[start..self.pos]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str) -> Token {
+        Token::Atom(s.to_string())
+    }
+
+    fn string(s: &str) -> Token {
+        Token::Str(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn tokenize_simple_lines() {
+        let lines = tokenize(b"* SORT 5 3 1\r\nA1 OK SORT completed\r\n").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            vec![atom("*"), atom("SORT"), atom("5"), atom("3"), atom("1")]
+        );
+        assert_eq!(
+            lines[1],
+            vec![atom("A1"), atom("OK"), Token::Text("SORT completed".into())]
+        );
+    }
+
+    #[test]
+    fn tokenize_status_text_keeps_brackets_and_parens() {
+        let lines = tokenize(b"A1 NO [TRYCREATE] no such (folder\r\n").unwrap();
+        assert_eq!(
+            lines[0][2],
+            Token::Text("[TRYCREATE] no such (folder".into())
+        );
+    }
+
+    #[test]
+    fn tokenize_nested_lists_and_quoted() {
+        let lines = tokenize(b"* STATUS \"My \\\"Box\\\"\" (MESSAGES 2 (X Y))\r\n").unwrap();
+        assert_eq!(lines[0][2], string("My \"Box\""));
+        assert_eq!(
+            lines[0][3],
+            Token::List(vec![
+                atom("MESSAGES"),
+                atom("2"),
+                Token::List(vec![atom("X"), atom("Y")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn tokenize_literal_with_line_break() {
+        let data = b"* STATUS {10}\r\nWeird\r\nBox (MESSAGES 4)\r\nA1 OK done\r\n";
+        let lines = tokenize(data).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][2], string("Weird\r\nBox"));
+        assert_eq!(lines[0][3], Token::List(vec![atom("MESSAGES"), atom("4")]));
+    }
+
+    #[test]
+    fn tokenize_non_sync_literal() {
+        let lines = tokenize(b"* QUOTA {2+}\r\n\"( (STORAGE 1 2)\r\n").unwrap();
+        assert_eq!(lines[0][2], string("\"("));
+        assert_eq!(
+            lines[0][3],
+            Token::List(vec![atom("STORAGE"), atom("1"), atom("2")])
+        );
+    }
+
+    #[test]
+    fn tokenize_rejects_truncated_literal() {
+        assert!(tokenize(b"* STATUS {10}\r\nshort").is_err());
+        assert!(tokenize(b"* STATUS {x}\r\nab").is_err());
+    }
+
+    #[test]
+    fn tokenize_rejects_unbalanced() {
+        assert!(tokenize(b"* STATUS INBOX (MESSAGES 1\r\n").is_err());
+        assert!(tokenize(b"* STATUS \"INBOX\r\n").is_err());
+    }
+
+    #[test]
+    fn tokenize_stray_cr_terminates() {
+        let lines = tokenize(b"* SORT 1\r2\r\n").unwrap();
+        assert_eq!(lines[0][2], atom("1"));
+        assert_eq!(lines[0].len(), 5);
+    }
+
+    #[test]
+    fn token_helpers() {
+        assert!(atom("sort").is_atom("SORT"));
+        assert_eq!(atom("42").as_number::<u32>(), Some(42));
+        assert_eq!(string("INBOX").as_string().as_deref(), Some("INBOX"));
+        assert_eq!(Token::List(vec![]).as_string(), None);
+    }
+}
@@ -1,36 +1,158 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use regex::Regex;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 
-use crate::connection::ImapSession;
+use crate::connection::{ImapOps, ImapSession};
 use crate::display::MessageRow;
+use crate::error::{self, SlashmailError};
+use crate::response::{self, Token};
+use crate::timings::{self, Phase};
+use crate::unsubscribe;
 
+#[derive(Default)]
 pub struct SearchCriteria {
     pub folder: String,
     pub all_folders: bool,
-    pub subject: Option<String>,
-    pub from: Option<String>,
+    /// With `all_folders`, also search folders normally skipped (Trash, Spam, All Mail).
+    pub include_skipped: bool,
+    /// Config `skip_folders`: more full folder names `all_folders` leaves out.
+    pub skip_folders: Vec<String>,
+    /// Subject terms; several are combined with `OR`, so any may match.
+    pub subject: Vec<String>,
+    /// From terms; several are combined with `OR`, so any may match.
+    pub from: Vec<String>,
+    /// Exclude messages whose subject contains this (`NOT SUBJECT`).
+    pub not_subject: Option<String>,
+    /// Exclude messages whose From contains this (`NOT FROM`).
+    pub not_from: Option<String>,
     pub to: Option<String>,
     pub cc: Option<String>,
     pub body: Option<String>,
     pub text: Option<String>,
+    /// `HEADER <name> <value>` terms; an empty value matches any message with the field.
+    pub headers: Vec<(String, String)>,
     pub seen: bool,
     pub unseen: bool,
     pub since: Option<String>,
     pub before: Option<String>,
+    /// A single day (`ON`); exclusive with `since` / `before`.
+    pub on: Option<String>,
+    /// Apply `since` / `before` / `on` to the Date: header (`SENTSINCE` etc.)
+    /// instead of the server's received date.
+    pub sent: bool,
     pub larger: Option<String>,
     pub smaller: Option<String>,
     pub flagged: bool,
     pub unflagged: bool,
     pub answered: bool,
+    pub unanswered: bool,
     pub draft: bool,
+    /// Hand-written search keys appended verbatim (CR/LF removed) and ANDed with the rest.
+    pub raw: Option<String>,
+    /// Gmail search syntax (`X-GM-RAW`); needs the X-GM-EXT-1 capability.
+    pub gmail_raw: Option<String>,
+    /// Gmail label (`X-GM-LABELS`); needs the X-GM-EXT-1 capability.
+    pub label: Option<String>,
+    /// What to order results by before `limit` is applied.
+    pub sort: SortKey,
+    /// Flip the order: oldest, smallest or Z-A first instead of newest, largest or A-Z.
+    pub invert_order: bool,
+    /// Charset sent with SEARCH, SORT and THREAD; `None` means UTF-8.
+    pub charset: Option<String>,
+    /// Replace per-folder "skipping folder" warnings with a single summary line.
+    pub quiet_warnings: bool,
+    /// Results to skip after sorting, before `limit` is applied.
+    pub offset: usize,
+    /// UIDs per folder to act on instead of searching the whole folder (`--uids-from`).
+    pub uids: Option<BTreeMap<String, Vec<u32>>>,
     pub limit: Option<usize>,
 }
 
+/// Result order for `search`. Date and size run descending (newest, largest first);
+/// from and subject run ascending, ignoring case. `SearchCriteria::invert_order` flips each.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Date,
+    Size,
+    From,
+    Subject,
+}
+
+impl SortKey {
+    /// RFC 5256 SORT key. The server sorts FROM by mailbox and SUBJECT by base subject
+    /// (without "Re:"), which can differ slightly from the client-side fallback.
+    fn imap_key(self) -> &'static str {
+        match self {
+            SortKey::Date => "DATE",
+            SortKey::Size => "SIZE",
+            SortKey::From => "FROM",
+            SortKey::Subject => "SUBJECT",
+        }
+    }
+
+    fn descending(self) -> bool {
+        matches!(self, SortKey::Date | SortKey::Size)
+    }
+}
+
 /// Strip CRLF and control chars to prevent IMAP command injection.
 fn sanitize(s: &str) -> String {
     s.chars().filter(|c| !c.is_control()).collect()
 }
 
+impl SearchCriteria {
+    /// The charset to announce to the server for this search.
+    pub fn charset(&self) -> &str {
+        self.charset.as_deref().unwrap_or(DEFAULT_CHARSET)
+    }
+
+    /// Whether any search term is set, i.e. the query is more than `ALL`. The folder,
+    /// ordering, paging and `uids` don't count.
+    pub fn has_filters(&self) -> bool {
+        !self.subject.is_empty()
+            || !self.from.is_empty()
+            || self.not_subject.is_some()
+            || self.not_from.is_some()
+            || self.to.is_some()
+            || self.cc.is_some()
+            || self.body.is_some()
+            || self.text.is_some()
+            || !self.headers.is_empty()
+            || self.seen
+            || self.unseen
+            || self.since.is_some()
+            || self.before.is_some()
+            || self.on.is_some()
+            || self.larger.is_some()
+            || self.smaller.is_some()
+            || self.flagged
+            || self.unflagged
+            || self.answered
+            || self.unanswered
+            || self.draft
+            || self.raw.is_some()
+            || self.gmail_raw.is_some()
+            || self.label.is_some()
+    }
+}
+
+const DEFAULT_CHARSET: &str = "UTF-8";
+
+/// Charset names are sent unquoted, so restrict them to IANA-style atoms.
+fn validate_charset(charset: &str) -> Result<()> {
+    let valid = !charset.is_empty()
+        && charset
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if !valid {
+        bail!("Invalid charset '{charset}' (expected a name like UTF-8 or ISO-8859-1)");
+    }
+    Ok(())
+}
+
 /// Escape a string for use inside IMAP quoted strings (RFC 9051 §4.3).
 pub fn imap_quote(s: &str) -> String {
     let clean = sanitize(s);
@@ -59,7 +181,7 @@ fn format_imap_date(day: u32, month: u32, year: i64) -> Result<String> {
 }
 
 /// Convert seconds since epoch to (year, month, day) using civil calendar math.
-fn epoch_to_date(secs: i64) -> (i64, u32, u32) {
+pub(crate) fn epoch_to_date(secs: i64) -> (i64, u32, u32) {
     // Algorithm from Howard Hinnant's chrono-Compatible Low-Level Date Algorithms
     let z = secs / 86400 + 719468;
     let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
@@ -128,17 +250,37 @@ fn days_in_month(year: i64, month: u32) -> u32 {
     }
 }
 
+/// Shorthand equivalent of a named date such as "yesterday" (ASCII case-insensitive).
+fn named_date(s: &str) -> Option<&'static str> {
+    Some(match s.to_ascii_lowercase().as_str() {
+        "today" => "0d",
+        "yesterday" => "1d",
+        "last-week" => "1w",
+        "last-month" => "1m",
+        "last-year" => "1y",
+        _ => return None,
+    })
+}
+
 /// Parse date into IMAP format (D-Mon-YYYY).
-/// Accepts ISO 8601 (YYYY-MM-DD) or relative shorthand (7d, 2w, 3m, 1y).
+/// Accepts ISO 8601 (YYYY-MM-DD), relative shorthand (7d, 2w, 3m, 1y) or a named
+/// date (today, yesterday, last-week, last-month, last-year).
 fn parse_date(s: &str) -> Result<String> {
-    if let Some(result) = resolve_relative_date(s) {
+    if let Some(result) = resolve_relative_date(named_date(s).unwrap_or(s)) {
         return result;
     }
+    // SEARCH SINCE/BEFORE/ON only take a date, so "12h" can't be honored
+    let sub_day = Regex::new(r"(?i)^\d+\s*(h|hr|hrs|hours?|min|mins|minutes?)$").unwrap();
+    if sub_day.is_match(s) {
+        bail!(
+            "IMAP search supports day granularity; hour/minute filters are not available (got '{s}', try 1d)"
+        );
+    }
 
     let re = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})$").unwrap();
     let caps = re.captures(s).ok_or_else(|| {
         anyhow::anyhow!(
-            "Invalid date '{}' (expected YYYY-MM-DD or relative like 7d, 2w, 3m, 1y)",
+            "Invalid date '{}' (expected YYYY-MM-DD, relative like 7d, 2w, 3m, 1y, or today, yesterday, last-week, last-month, last-year)",
             s
         )
     })?;
@@ -155,14 +297,24 @@ fn parse_date(s: &str) -> Result<String> {
     })
 }
 
-pub fn build_query(criteria: &SearchCriteria) -> Result<String> {
+pub fn build_query(criteria: &SearchCriteria) -> error::Result<String> {
+    validate_charset(criteria.charset())?;
+    validate_flag_filters(criteria)?;
     let mut parts = Vec::new();
 
-    if let Some(ref subj) = criteria.subject {
-        parts.push(format!("SUBJECT {}", imap_quote(subj)));
+    if let Some(subj) = any_of("SUBJECT", &criteria.subject) {
+        parts.push(subj);
+    }
+    if let Some(from) = any_of("FROM", &criteria.from) {
+        parts.push(from);
+    }
+    if let Some(ref subj) = criteria.not_subject {
+        let subj = non_empty("--not-subject", subj)?;
+        parts.push(format!("NOT SUBJECT {}", imap_quote(subj)));
     }
-    if let Some(ref from) = criteria.from {
-        parts.push(format!("FROM {}", imap_quote(from)));
+    if let Some(ref from) = criteria.not_from {
+        let from = non_empty("--not-from", from)?;
+        parts.push(format!("NOT FROM {}", imap_quote(from)));
     }
     if let Some(ref to) = criteria.to {
         parts.push(format!("TO {}", imap_quote(to)));
@@ -171,10 +323,13 @@ pub fn build_query(criteria: &SearchCriteria) -> Result<String> {
         parts.push(format!("CC {}", imap_quote(cc)));
     }
     if let Some(ref body) = criteria.body {
-        parts.push(format!("BODY {}", imap_quote(body)));
+        parts.push(format!("BODY {}", imap_quote(non_empty("--body", body)?)));
     }
     if let Some(ref text) = criteria.text {
-        parts.push(format!("TEXT {}", imap_quote(text)));
+        parts.push(format!("TEXT {}", imap_quote(non_empty("--text", text)?)));
+    }
+    for (name, value) in &criteria.headers {
+        parts.push(format!("HEADER {} {}", imap_quote(name), imap_quote(value)));
     }
     if criteria.seen {
         parts.push("SEEN".to_string());
@@ -182,20 +337,43 @@ pub fn build_query(criteria: &SearchCriteria) -> Result<String> {
     if criteria.unseen {
         parts.push("UNSEEN".to_string());
     }
+    let sent = if criteria.sent { "SENT" } else { "" };
+    if criteria.sent
+        && criteria.since.is_none()
+        && criteria.before.is_none()
+        && criteria.on.is_none()
+    {
+        return Err(anyhow!("--sent needs --since, --before or --on").into());
+    }
     if let Some(ref since) = criteria.since {
         let date = parse_date(since)?;
-        parts.push(format!("SINCE {date}"));
+        parts.push(format!("{sent}SINCE {date}"));
     }
     if let Some(ref before) = criteria.before {
         let date = parse_date(before)?;
-        parts.push(format!("BEFORE {date}"));
+        parts.push(format!("{sent}BEFORE {date}"));
+    }
+    if let Some(ref on) = criteria.on {
+        if criteria.since.is_some() || criteria.before.is_some() {
+            return Err(anyhow!("--on cannot be combined with --since or --before").into());
+        }
+        let date = parse_date(on)?;
+        parts.push(format!("{sent}ON {date}"));
+    }
+    let larger = criteria.larger.as_deref().map(parse_size).transpose()?;
+    let smaller = criteria.smaller.as_deref().map(parse_size).transpose()?;
+    if let (Some(lo), Some(hi)) = (larger, smaller) {
+        // LARGER and SMALLER are both strict, so the range holds nothing unless hi > lo + 1
+        if hi <= lo.saturating_add(1) {
+            return Err(
+                anyhow!("--larger {lo} and --smaller {hi} leave no possible message size").into(),
+            );
+        }
     }
-    if let Some(ref larger) = criteria.larger {
-        let bytes = parse_size(larger)?;
+    if let Some(bytes) = larger {
         parts.push(format!("LARGER {bytes}"));
     }
-    if let Some(ref smaller) = criteria.smaller {
-        let bytes = parse_size(smaller)?;
+    if let Some(bytes) = smaller {
         parts.push(format!("SMALLER {bytes}"));
     }
     if criteria.flagged {
@@ -207,9 +385,23 @@ pub fn build_query(criteria: &SearchCriteria) -> Result<String> {
     if criteria.answered {
         parts.push("ANSWERED".to_string());
     }
+    if criteria.unanswered {
+        parts.push("UNANSWERED".to_string());
+    }
     if criteria.draft {
         parts.push("DRAFT".to_string());
     }
+    if let Some(ref raw) = criteria.raw {
+        parts.push(raw_query(raw)?);
+    }
+    if let Some(ref query) = criteria.gmail_raw {
+        let query = non_empty("--gmail-raw", query)?;
+        parts.push(format!("X-GM-RAW {}", imap_quote(query)));
+    }
+    if let Some(ref label) = criteria.label {
+        let label = non_empty("--label", label)?;
+        parts.push(format!("X-GM-LABELS {}", imap_quote(label)));
+    }
 
     if parts.is_empty() {
         Ok("ALL".to_string())
@@ -218,29 +410,228 @@ pub fn build_query(criteria: &SearchCriteria) -> Result<String> {
     }
 }
 
+/// `build_query`, after checking that the server supports the extensions the
+/// criteria use: `--gmail-raw` and `--label` only work on Gmail (X-GM-EXT-1).
+pub fn build_query_for(session: &impl ImapOps, criteria: &SearchCriteria) -> error::Result<String> {
+    let query = build_query(criteria)?;
+    if (criteria.gmail_raw.is_some() || criteria.label.is_some())
+        && !session.has_capability("X-GM-EXT-1")
+    {
+        return Err(anyhow!("--gmail-raw and --label are not supported by this server (they need Gmail's X-GM-EXT-1 extension)").into());
+    }
+    Ok(query)
+}
+
+/// `KEY "value"` for one value; for more, nest the binary `OR` from the right:
+/// `OR (KEY "a") (OR (KEY "b") (KEY "c"))`.
+fn any_of(key: &str, values: &[String]) -> Option<String> {
+    let (last, rest) = values.split_last()?;
+    let mut query = format!("{key} {}", imap_quote(last));
+    for value in rest.iter().rev() {
+        query = format!("OR ({key} {}) ({query})", imap_quote(value));
+    }
+    Some(query)
+}
+
+/// Make a `--raw-query` safe to put on the command line without otherwise touching
+/// its syntax: line breaks become spaces so they cannot end the command. Literals
+/// need a continuation round trip, so they are refused rather than left to hang.
+fn raw_query(raw: &str) -> Result<String> {
+    let query = raw.replace(['\r', '\n'], " ");
+    let query = query.trim();
+    if query.is_empty() {
+        bail!("--raw-query needs a non-empty query");
+    }
+    static LITERAL: OnceLock<Regex> = OnceLock::new();
+    let literal = LITERAL.get_or_init(|| Regex::new(r"\{\d+\+?\}").unwrap());
+    if literal.is_match(query) {
+        bail!("--raw-query cannot contain literals ({{N}}); use a quoted string instead");
+    }
+    Ok(query.to_string())
+}
+
+/// Reject flag filters that contradict each other and could never match.
+fn validate_flag_filters(criteria: &SearchCriteria) -> Result<()> {
+    if criteria.seen && criteria.unseen {
+        bail!("Cannot use --seen and --unseen together");
+    }
+    if criteria.flagged && criteria.unflagged {
+        bail!("Cannot use --flagged and --unflagged together");
+    }
+    if criteria.answered && criteria.unanswered {
+        bail!("Cannot use --answered and --unanswered together");
+    }
+    Ok(())
+}
+
+/// Reject a content filter that would be empty once sent: `BODY ""` matches every
+/// message, which is never what an explicit `--body` asks for.
+fn non_empty<'a>(flag: &str, value: &'a str) -> Result<&'a str> {
+    if sanitize(value).trim().is_empty() {
+        bail!("{flag} needs a non-empty search term");
+    }
+    Ok(value)
+}
+
+/// Size suffixes, matched ignoring case; the longer binary forms come first so "5MiB"
+/// isn't read as "5Mi" + "B". K/M/G/T are binary too (1K = 1024).
+const SIZE_SUFFIXES: [(&str, u64); 8] = [
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("TiB", 1 << 40),
+    ("K", 1 << 10),
+    ("M", 1 << 20),
+    ("G", 1 << 30),
+    ("T", 1 << 40),
+];
+
 fn parse_size(s: &str) -> Result<u64> {
     let s = s.trim();
     if s.is_empty() {
-        bail!("Invalid size '' (expected bytes, or value with K/M suffix such as 10K or 5M)");
-    }
-
-    let (raw, multiplier) = if let Some(n) = s.strip_suffix('M').or_else(|| s.strip_suffix('m')) {
-        (n.trim(), 1_048_576_u64)
-    } else if let Some(n) = s.strip_suffix('K').or_else(|| s.strip_suffix('k')) {
-        (n.trim(), 1024_u64)
-    } else {
-        (s, 1_u64)
+        bail!("Invalid size '' (expected bytes, or value with K/M/G/T suffix such as 10K or 5M)");
+    }
+
+    let (raw, multiplier) = SIZE_SUFFIXES
+        .iter()
+        .find_map(|&(suffix, multiplier)| {
+            let split = s.len().checked_sub(suffix.len())?;
+            let (n, tail) = (s.get(..split)?, s.get(split..)?);
+            tail.eq_ignore_ascii_case(suffix)
+                .then(|| (n.trim(), multiplier))
+        })
+        .unwrap_or((s, 1));
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid size '{s}' (expected bytes, or value with K/M/G/T suffix such as 10K or 1.5M)"
+        )
     };
 
-    let value = raw.parse::<u64>().with_context(|| {
-        format!("Invalid size '{s}' (expected bytes, or value with K/M suffix such as 10K or 5M)")
-    })?;
+    // A decimal mantissa like "1.5": digits on both sides of a single dot, rounded to bytes
+    if let Some((whole, frac)) = raw.split_once('.') {
+        let digits = |p: &str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit());
+        if !digits(whole) || !digits(frac) {
+            return Err(invalid());
+        }
+        let value: f64 = raw.parse().map_err(|_| invalid())?;
+        let bytes = (value * multiplier as f64).round();
+        if bytes >= u64::MAX as f64 {
+            bail!("Size '{s}' is too large");
+        }
+        return Ok(bytes as u64);
+    }
+
+    let value = raw.parse::<u64>().map_err(|_| invalid())?;
 
     value
         .checked_mul(multiplier)
         .ok_or_else(|| anyhow::anyhow!("Size '{s}' is too large"))
 }
 
+/// The header values a message listing shows, decoded; missing ones are empty.
+#[derive(Debug, Default, PartialEq)]
+struct Headers {
+    subject: String,
+    /// Display form, e.g. `Alice <alice@example.com>`.
+    from: String,
+    /// The first From address alone, lowercased, for grouping by sender.
+    from_address: String,
+    date: String,
+    message_id: String,
+    /// Raw List-Unsubscribe value; never in ENVELOPE, so always read from header text.
+    list_unsubscribe: String,
+    /// Raw List-Unsubscribe-Post value.
+    list_unsubscribe_post: String,
+}
+
+/// Headers of a fetched header block, with RFC 2047 encoded-words (`=?UTF-8?B?...?=`)
+/// decoded. If the block as a whole doesn't parse, each line is parsed on its own so
+/// one bad header doesn't hide the rest. Used when the server sends no ENVELOPE.
+fn header_fields(header_bytes: &[u8]) -> Headers {
+    let headers = match mailparse::parse_headers(header_bytes) {
+        Ok((headers, _)) => headers,
+        Err(_) => header_bytes
+            .split(|&b| b == b'\n')
+            .filter_map(|line| mailparse::parse_header(line).ok().map(|(h, _)| h))
+            .collect(),
+    };
+
+    let mut fields = Headers::default();
+    for h in &headers {
+        match h.get_key().to_lowercase().as_str() {
+            "subject" => fields.subject = h.get_value(),
+            "from" => fields.from = h.get_value(),
+            "date" => fields.date = h.get_value(),
+            "message-id" => fields.message_id = h.get_value(),
+            "list-unsubscribe" => fields.list_unsubscribe = h.get_value(),
+            "list-unsubscribe-post" => fields.list_unsubscribe_post = h.get_value(),
+            _ => {}
+        }
+    }
+    fields.from_address = sender_address(&fields.from);
+    fields
+}
+
+/// Headers from a FETCH ENVELOPE. The server has already split From into display
+/// name, mailbox and host, so only encoded-words are left to decode.
+fn envelope_fields(env: &imap_proto::types::Envelope<'_>) -> Headers {
+    let text = |value: Option<&[u8]>| value.map(decode_words).unwrap_or_default();
+    // Group syntax shows up as entries without a host; skip those markers
+    let sender = env
+        .from
+        .iter()
+        .flatten()
+        .find(|addr| addr.mailbox.is_some() && addr.host.is_some());
+    let (from, from_address) = match sender {
+        Some(addr) => {
+            let address = format!("{}@{}", text(addr.mailbox), text(addr.host));
+            let name = text(addr.name);
+            let from = if name.is_empty() {
+                address.clone()
+            } else {
+                format!("{name} <{address}>")
+            };
+            (from, address.to_lowercase())
+        }
+        None => (String::new(), String::new()),
+    };
+    Headers {
+        subject: text(env.subject),
+        from,
+        from_address,
+        date: text(env.date),
+        message_id: text(env.message_id),
+        ..Default::default()
+    }
+}
+
+/// Decode RFC 2047 encoded-words and unfold line breaks in a raw header value.
+fn decode_words(raw: &[u8]) -> String {
+    let mut line = b"X: ".to_vec();
+    line.extend_from_slice(raw);
+    match mailparse::parse_header(&line) {
+        Ok((header, _)) => header.get_value(),
+        Err(_) => String::from_utf8_lossy(raw).into_owned(),
+    }
+}
+
+/// The first mailbox address in a From value, lowercased, so "Alice <A@x>" and "a@x"
+/// compare equal. Falls back to the trimmed, lowercased value when it doesn't parse.
+fn sender_address(from: &str) -> String {
+    let first = mailparse::addrparse(from)
+        .ok()
+        .and_then(|list| match list.iter().next() {
+            Some(mailparse::MailAddr::Single(info)) => Some(info.addr.clone()),
+            Some(mailparse::MailAddr::Group(group)) => {
+                group.addrs.first().map(|info| info.addr.clone())
+            }
+            None => None,
+        });
+    first
+        .unwrap_or_else(|| from.trim().to_string())
+        .to_lowercase()
+}
+
 /// Truncate a string to at most `max` characters, appending "..." if truncated.
 /// Safe for multi-byte UTF-8.
 fn truncate_str(s: &str, max: usize) -> String {
@@ -254,47 +645,84 @@ fn truncate_str(s: &str, max: usize) -> String {
 
 /// Parse SORT response bytes into a Vec of UIDs (preserving server order).
 fn parse_sort_response(data: &[u8]) -> Result<Vec<u32>> {
-    let text = String::from_utf8_lossy(data);
+    parse_uid_list_response(data, "SORT")
+}
+
+/// Parse the UIDs from untagged `* <keyword> ...` lines of a raw SORT or SEARCH response.
+fn parse_uid_list_response(data: &[u8], keyword: &str) -> Result<Vec<u32>> {
+    let lines =
+        response::tokenize(data).with_context(|| format!("Malformed {keyword} response"))?;
     let mut uids = Vec::new();
-    let mut saw_sort = false;
-
-    for line in text.lines() {
-        if let Some(rest) = line.strip_prefix("* SORT ") {
-            saw_sort = true;
-            for tok in rest.split_whitespace() {
-                if let Ok(uid) = tok.parse::<u32>() {
-                    uids.push(uid);
-                }
+
+    for line in &lines {
+        match line.as_slice() {
+            [star, kw, rest @ ..] if star.is_atom("*") && kw.is_atom(keyword) => {
+                uids.extend(rest.iter().filter_map(Token::as_number::<u32>));
             }
-        }
-        // Check for error in tagged response (format: "tag NO ..." or "tag BAD ...")
-        if !line.starts_with('*') {
-            let mut tokens = line.splitn(3, ' ');
-            if let (Some(_tag), Some(status)) = (tokens.next(), tokens.next()) {
-                if status == "NO" || status == "BAD" {
-                    bail!("SORT command rejected by server: {line}");
-                }
+            // Tagged completion "tag NO ..." or "tag BAD ..."
+            [tag, status, rest @ ..]
+                if !tag.is_atom("*") && (status.is_atom("NO") || status.is_atom("BAD")) =>
+            {
+                let text = match rest.first() {
+                    Some(Token::Text(t)) => t.as_str(),
+                    _ => "",
+                };
+                bail!(
+                    "{keyword} command rejected by server: {} {text}",
+                    status.as_atom().unwrap_or_default()
+                );
             }
+            _ => {}
         }
     }
 
-    // Empty SORT response (no matches) is valid — server sends "* SORT" with no UIDs
+    // Empty response (no matches) is valid — server sends "* SORT" with no UIDs
     // or may omit the line entirely
-    if !saw_sort && !uids.is_empty() {
-        bail!("Unexpected SORT response format");
-    }
+    Ok(uids)
+}
 
+/// `UID SEARCH CHARSET <charset> <query>` on the selected folder, UIDs ascending.
+/// Issued raw because the imap crate's `uid_search` cannot announce a charset.
+pub fn uid_search(
+    session: &mut impl ImapOps,
+    query: &str,
+    charset: &str,
+) -> error::Result<Vec<u32>> {
+    validate_charset(charset)?;
+    let cmd = format!("UID SEARCH CHARSET {charset} {query}");
+    let _t = timings::start(Phase::Search);
+    let data = session
+        .run_command_and_read_response(&cmd)
+        .context("IMAP SEARCH failed")?;
+    let mut uids = parse_uid_list_response(&data, "SEARCH")?;
+    uids.sort_unstable();
     Ok(uids)
 }
 
-/// Try UID SORT (REVERSE DATE), returns Ok(Some(ordered_uids)) if server supports SORT,
+/// SORT criteria for `key` in its default direction, flipped when `invert` is set.
+fn sort_criteria(key: SortKey, invert: bool) -> String {
+    if key.descending() != invert {
+        format!("(REVERSE {})", key.imap_key())
+    } else {
+        format!("({})", key.imap_key())
+    }
+}
+
+/// Try UID SORT on `key`, returns Ok(Some(ordered_uids)) if server supports SORT,
 /// Ok(None) if not, or Err on failure.
-fn try_uid_sort(session: &mut ImapSession, query: &str) -> Result<Option<Vec<u32>>> {
+fn try_uid_sort(
+    session: &mut impl ImapOps,
+    query: &str,
+    charset: &str,
+    key: SortKey,
+    invert: bool,
+) -> Result<Option<Vec<u32>>> {
     if !session.has_capability("SORT") {
         return Ok(None);
     }
 
-    let cmd = format!("UID SORT (REVERSE DATE) UTF-8 {query}");
+    let cmd = format!("UID SORT {} {charset} {query}", sort_criteria(key, invert));
+    let _t = timings::start(Phase::Search);
     match session.run_command_and_read_response(&cmd) {
         Ok(data) => {
             let uids = parse_sort_response(&data)?;
@@ -307,6 +735,23 @@ fn try_uid_sort(session: &mut ImapSession, query: &str) -> Result<Option<Vec<u32
     }
 }
 
+/// Group message UIDs by folder, using `default_folder` for rows without one.
+/// Folders are ordered by name so the resulting command sequence is stable.
+pub fn group_by_folder(
+    messages: &[MessageRow],
+    default_folder: &str,
+) -> BTreeMap<String, Vec<u32>> {
+    let mut by_folder: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for msg in messages {
+        let folder = msg
+            .folder
+            .clone()
+            .unwrap_or_else(|| default_folder.to_string());
+        by_folder.entry(folder).or_default().push(msg.uid);
+    }
+    by_folder
+}
+
 /// Build UID set strings with range compression, chunked to stay under IMAP command length limits.
 /// Consecutive UIDs are compressed into `start:end` ranges.
 /// Each returned string stays under MAX_UID_SET_LENGTH chars.
@@ -359,12 +804,63 @@ pub fn build_uid_set(uids: &[u32]) -> Vec<String> {
     chunks
 }
 
+/// Number of UIDs covered by a set produced by [`build_uid_set`].
+pub fn uid_set_len(set: &str) -> usize {
+    set.split(',')
+        .map(|part| match part.split_once(':') {
+            Some((s, e)) => match (s.parse::<u32>(), e.parse::<u32>()) {
+                (Ok(s), Ok(e)) => (e.max(s) - e.min(s)) as usize + 1,
+                _ => 1,
+            },
+            None => 1,
+        })
+        .sum()
+}
+
+/// Drop the first `offset` items, then keep at most `limit`.
+fn window<T>(items: &mut Vec<T>, offset: usize, limit: Option<usize>) {
+    items.drain(..offset.min(items.len()));
+    if let Some(n) = limit {
+        items.truncate(n);
+    }
+}
+
+/// Sort rows by `key` (see `SortKey` for the default directions, flipped by `invert`),
+/// then apply `offset` and `limit`. Dates compare by timestamp, never the display string. Ties are
+/// broken by UID like server SORT breaks them by sequence number, so `--limit` keeps
+/// the same messages from one run to the next (e.g. a `--dry-run` and the real run
+/// after it).
+fn sort_and_truncate(
+    messages: &mut Vec<MessageRow>,
+    key: SortKey,
+    invert: bool,
+    offset: usize,
+    limit: Option<usize>,
+) {
+    match key {
+        SortKey::Date => messages.sort_by_key(|m| (m.timestamp, m.uid)),
+        SortKey::Size => messages.sort_by_key(|m| (m.size, m.uid)),
+        SortKey::From => messages.sort_by_cached_key(|m| (m.from.to_lowercase(), m.uid)),
+        SortKey::Subject => messages.sort_by_cached_key(|m| (m.subject.to_lowercase(), m.uid)),
+    }
+    if key.descending() != invert {
+        messages.reverse();
+    }
+    window(messages, offset, limit);
+}
+
+/// Search `folder` and fetch the matching rows, sorted and paged. `fetched(done, total)`
+/// is called as the rows come in, once the number of messages to fetch is known.
+#[allow(clippy::too_many_arguments)]
 fn fetch_messages(
-    session: &mut ImapSession,
+    session: &mut impl ImapOps,
     folder: &str,
     query: &str,
+    criteria: &SearchCriteria,
     include_folder: bool,
+    offset: usize,
     limit: Option<usize>,
+    fetched: &mut dyn FnMut(usize, usize),
 ) -> Result<Vec<MessageRow>> {
     // Sanitize folder name for the raw SORT path
     let clean_folder = sanitize(folder);
@@ -372,107 +868,25 @@ fn fetch_messages(
         .select(&clean_folder)
         .with_context(|| format!("Failed to select folder '{clean_folder}'"))?;
 
-    // Try server-side SORT first, fall back to SEARCH + client sort
-    let (ordered_uids, pre_sorted) = match try_uid_sort(session, query)? {
-        Some(mut uids) => {
-            // With server SORT, we can truncate before FETCH
-            if let Some(n) = limit {
-                uids.truncate(n);
-            }
-            (uids, true)
-        }
-        None => {
-            let uid_set = session.uid_search(query).context("IMAP SEARCH failed")?;
-            let mut uids: Vec<u32> = uid_set.into_iter().collect();
-            uids.sort();
-            (uids, false)
-        }
-    };
+    let (mut ordered_uids, pre_sorted) = matching_uids(session, query, criteria)?;
+    if pre_sorted {
+        // With server SORT, we can page before FETCH
+        window(&mut ordered_uids, offset, limit);
+    }
 
     if ordered_uids.is_empty() {
         return Ok(Vec::new());
     }
 
-    let uid_chunks = build_uid_set(&ordered_uids);
-
-    // FETCH results may come back in arbitrary order; index by UID
-    let mut by_uid = std::collections::HashMap::new();
-    for chunk in &uid_chunks {
-        let mut warned_invalid_uid = false;
-        let fetches = session
-            .uid_fetch(
-                chunk,
-                "(UID FLAGS RFC822.SIZE BODY.PEEK[HEADER.FIELDS (Subject From Date)])",
-            )
-            .context("IMAP FETCH failed")?;
-
-        for fetch in fetches.iter() {
-            let uid = match fetch.uid {
-                Some(u) if u > 0 => u,
-                _ => {
-                    if !warned_invalid_uid {
-                        eprintln!(
-                            "Warning: skipping fetched message(s) with missing/invalid UID in '{clean_folder}'"
-                        );
-                        warned_invalid_uid = true;
-                    }
-                    continue;
-                }
-            };
-            let size = fetch.size.unwrap_or(0);
-            let header_bytes = fetch.header().unwrap_or(b"");
-            let header_str = String::from_utf8_lossy(header_bytes);
-
-            let (mut subject, mut from, mut date) = (String::new(), String::new(), String::new());
-
-            let parsed = mailparse::parse_headers(header_bytes);
-            if let Ok((headers, _)) = parsed {
-                for h in &headers {
-                    match h.get_key().to_lowercase().as_str() {
-                        "subject" => subject = h.get_value(),
-                        "from" => from = h.get_value(),
-                        "date" => date = h.get_value(),
-                        _ => {}
-                    }
-                }
-            } else {
-                for line in header_str.lines() {
-                    if let Some(v) = line.strip_prefix("Subject: ") {
-                        subject = v.to_string();
-                    } else if let Some(v) = line.strip_prefix("From: ") {
-                        from = v.to_string();
-                    } else if let Some(v) = line.strip_prefix("Date: ") {
-                        date = v.to_string();
-                    }
-                }
-            }
-
-            from = truncate_str(&from, 40);
-            subject = truncate_str(&subject, 60);
-            let timestamp = mailparse::dateparse(&date).unwrap_or(0);
-
-            if let Some(pos) = date.find(" +").or_else(|| date.find(" -")) {
-                date.truncate(pos);
-            }
-
-            by_uid.insert(
-                uid,
-                MessageRow {
-                    uid,
-                    folder: if include_folder {
-                        Some(clean_folder.clone())
-                    } else {
-                        None
-                    },
-                    from,
-                    subject,
-                    date,
-                    timestamp,
-                    size,
-                },
-            );
-        }
-    }
+    let total = ordered_uids.len();
+    fetched(0, total);
+    let mut by_uid = fetch_rows(
+        session,
+        &clean_folder,
+        &ordered_uids,
+        include_folder,
+        &mut |done| fetched(done, total),
+    )?;
 
     if pre_sorted {
         // Preserve server SORT order
@@ -482,145 +896,881 @@ fn fetch_messages(
             .collect())
     } else {
         let mut messages: Vec<MessageRow> = by_uid.into_values().collect();
-        messages.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        if let Some(n) = limit {
-            messages.truncate(n);
-        }
+        sort_and_truncate(
+            &mut messages,
+            criteria.sort,
+            criteria.invert_order,
+            offset,
+            limit,
+        );
         Ok(messages)
     }
 }
 
-pub fn folders_to_skip(name: &str) -> bool {
-    let lower = name.to_lowercase();
-    lower == "trash"
-        || lower == "spam"
-        || lower == "junk"
-        || lower.contains("all mail")
-        || lower == "[gmail]/all mail"
-        || lower == "[gmail]/spam"
-        || lower == "[gmail]/trash"
+/// UIDs in the selected folder matching `query`: from server SORT when available
+/// (`true` with them, already in `criteria.sort` order), else from SEARCH, unordered.
+fn matching_uids(
+    session: &mut impl ImapOps,
+    query: &str,
+    criteria: &SearchCriteria,
+) -> Result<(Vec<u32>, bool)> {
+    let charset = criteria.charset();
+    let sorted = try_uid_sort(
+        session,
+        query,
+        charset,
+        criteria.sort,
+        criteria.invert_order,
+    )?;
+    match sorted {
+        Some(uids) => Ok((uids, true)),
+        None => Ok((uid_search(session, query, charset)?, false)),
+    }
 }
 
-pub fn search(session: &mut ImapSession, criteria: &SearchCriteria) -> Result<Vec<MessageRow>> {
-    let query = build_query(criteria)?;
-
-    if criteria.all_folders {
-        let folders = session
-            .list(Some(""), Some("*"))
-            .context("Failed to list folders")?;
-        let folder_names: Vec<String> = folders
-            .iter()
-            .map(|f| f.name().to_string())
-            .filter(|n| !folders_to_skip(n))
-            .collect();
+/// The `--uids-from` UIDs of the selected folder that still match: all of them when
+/// there are no filters, else what a `UID set ...` SEARCH keeps.
+fn listed_uids(
+    session: &mut impl ImapOps,
+    listed: &[u32],
+    query: &str,
+    criteria: &SearchCriteria,
+) -> Result<Vec<u32>> {
+    if !criteria.has_filters() {
+        return Ok(listed.to_vec());
+    }
+    let mut matched = Vec::new();
+    for chunk in &build_uid_set(listed) {
+        let narrowed = format!("UID {chunk} {query}");
+        matched.extend(uid_search(session, &narrowed, criteria.charset())?);
+    }
+    Ok(matched)
+}
 
-        let mut all_messages = Vec::new();
-        for folder in &folder_names {
-            match fetch_messages(session, folder, &query, true, None) {
-                Ok(msgs) => all_messages.extend(msgs),
-                Err(e) => {
-                    eprintln!("Warning: skipping folder '{folder}': {e}");
-                }
+/// Rows for `uids` in the selected `folder`, indexed by UID since FETCH results may
+/// come back in any order. UIDs that no longer exist are simply missing. `progress` gets
+/// the number of UIDs done after each chunk.
+fn fetch_rows(
+    session: &mut impl ImapOps,
+    folder: &str,
+    uids: &[u32],
+    include_folder: bool,
+    progress: &mut dyn FnMut(usize),
+) -> Result<HashMap<u32, MessageRow>> {
+    let folder_column = include_folder.then(|| folder.to_string());
+    let mut by_uid = HashMap::new();
+    let mut without_envelope = Vec::new();
+    let mut done = 0;
+    for chunk in &build_uid_set(uids) {
+        for fetched in fetch_headers(session, chunk, true, folder)? {
+            if fetched.headers.is_some() {
+                by_uid.insert(fetched.uid, message_row(fetched, &folder_column));
+            } else {
+                without_envelope.push(fetched.uid);
             }
         }
-        all_messages.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        if let Some(n) = criteria.limit {
-            all_messages.truncate(n);
+        done += uid_set_len(chunk);
+        progress(done);
+    }
+    // ENVELOPE is mandatory in IMAP4rev1, but read the header text if a server skips it
+    for chunk in &build_uid_set(&without_envelope) {
+        for fetched in fetch_headers(session, chunk, false, folder)? {
+            by_uid.insert(fetched.uid, message_row(fetched, &folder_column));
         }
-        Ok(all_messages)
-    } else {
-        ensure_folder_exists(session, &criteria.folder)?;
-        fetch_messages(session, &criteria.folder, &query, false, criteria.limit)
     }
+    Ok(by_uid)
 }
 
-pub fn ensure_folder_exists(session: &mut ImapSession, folder: &str) -> Result<()> {
-    let folders = session
-        .list(Some(""), Some(folder))
-        .context("Failed to list folders")?;
-    let exists = folders.iter().any(|f| f.name() == folder);
-    if !exists {
-        bail!(
-            "Folder '{folder}' does not exist. Use `slashmail status` to list available folders."
+/// Rows for the UIDs given with `--uids-from`, per folder, sorted and limited like a
+/// search. With no other filters the UIDs are fetched directly; otherwise a
+/// `UID set ...` SEARCH narrows them first. UIDs that no longer exist are left out.
+fn fetch_listed(
+    session: &mut impl ImapOps,
+    listed: &BTreeMap<String, Vec<u32>>,
+    criteria: &SearchCriteria,
+) -> Result<Vec<MessageRow>> {
+    let query = build_query_for(session, criteria)?;
+    // Rows outside the default folder need their folder for grouping later
+    let include_folder = listed.keys().any(|f| *f != criteria.folder);
+    let mut messages = Vec::new();
+    for (folder, uids) in listed {
+        ensure_folder_exists(session, folder)?;
+        let clean_folder = sanitize(folder);
+        session
+            .select(&clean_folder)
+            .with_context(|| format!("Failed to select folder '{clean_folder}'"))?;
+        let uids = listed_uids(session, uids, &query, criteria)?;
+        messages.extend(
+            fetch_rows(session, &clean_folder, &uids, include_folder, &mut |_| {})?.into_values(),
         );
     }
-    Ok(())
+    sort_and_truncate(
+        &mut messages,
+        criteria.sort,
+        criteria.invert_order,
+        criteria.offset,
+        criteria.limit,
+    );
+    Ok(messages)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn sanitize_removes_control_chars() {
-        assert_eq!(sanitize("hello"), "hello");
-        assert_eq!(sanitize("he\nllo"), "hello");
-        assert_eq!(sanitize("he\rllo"), "hello");
-        assert_eq!(sanitize("he\r\nllo"), "hello");
-        assert_eq!(sanitize("he\x00llo"), "hello");
-        assert_eq!(sanitize(""), "");
+/// Parse a `--uids-from` list: whitespace- or comma-separated UIDs, all in
+/// `default_folder`, or the JSON array printed by `search --format json` (optionally
+/// wrapped by `--json-envelope`), where each row's `folder` is used when set. An
+/// empty JSON array (a search that found nothing) gives an empty map.
+pub fn parse_uid_list(
+    text: &str,
+    default_folder: &str,
+) -> error::Result<BTreeMap<String, Vec<u32>>> {
+    let mut listed: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    let trimmed = text.trim_start();
+    let json = trimmed.starts_with('[') || trimmed.starts_with('{');
+    if json {
+        let value: serde_json::Value =
+            serde_json::from_str(trimmed).context("Invalid JSON in UID list")?;
+        let rows = value
+            .get("results")
+            .unwrap_or(&value)
+            .as_array()
+            .context("Expected a JSON array of messages in UID list")?;
+        for row in rows {
+            let uid = row
+                .get("uid")
+                .and_then(serde_json::Value::as_u64)
+                .and_then(|u| u32::try_from(u).ok())
+                .with_context(|| format!("Message without a valid \"uid\" in UID list: {row}"))?;
+            let folder = row
+                .get("folder")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or(default_folder);
+            listed.entry(folder.to_string()).or_default().push(uid);
+        }
+    } else {
+        for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() {
+                continue;
+            }
+            let uid = word
+                .parse::<u32>()
+                .ok()
+                .filter(|&u| u > 0)
+                .with_context(|| format!("Invalid UID '{word}' in UID list"))?;
+            listed
+                .entry(default_folder.to_string())
+                .or_default()
+                .push(uid);
+        }
     }
-
-    #[test]
-    fn sanitize_preserves_unicode() {
-        assert_eq!(sanitize("héllo wörld"), "héllo wörld");
+    for uids in listed.values_mut() {
+        uids.sort_unstable();
+        uids.dedup();
     }
-
-    #[test]
-    fn imap_quote_wraps_in_quotes() {
-        assert_eq!(imap_quote("hello"), "\"hello\"");
+    if listed.is_empty() && !json {
+        return Err(anyhow!("The UID list is empty").into());
     }
+    Ok(listed)
+}
 
-    #[test]
-    fn imap_quote_escapes_backslash() {
-        assert_eq!(imap_quote("he\\llo"), "\"he\\\\llo\"");
-    }
+/// One message's FETCH response, before it becomes a `MessageRow`.
+struct Fetched {
+    uid: u32,
+    size: u32,
+    flags: Vec<String>,
+    headers: Option<Headers>,
+}
 
-    #[test]
-    fn imap_quote_escapes_double_quote() {
-        assert_eq!(imap_quote("he\"llo"), "\"he\\\"llo\"");
-    }
+/// UID, RFC822.SIZE, flags and listing headers of each message in `uid_set`. With `envelope`
+/// the headers come from ENVELOPE and are `None` for a message the server sent none
+/// for; otherwise they are parsed from the header text. The List-Unsubscribe headers
+/// are read from header text either way.
+fn fetch_headers(
+    session: &mut impl ImapOps,
+    uid_set: &str,
+    envelope: bool,
+    folder: &str,
+) -> Result<Vec<Fetched>> {
+    let query = if envelope {
+        "(UID FLAGS RFC822.SIZE ENVELOPE BODY.PEEK[HEADER.FIELDS (List-Unsubscribe List-Unsubscribe-Post)])"
+    } else {
+        "(UID FLAGS RFC822.SIZE BODY.PEEK[HEADER.FIELDS (Subject From Date Message-ID List-Unsubscribe List-Unsubscribe-Post)])"
+    };
+    let fetches = session
+        .uid_fetch(uid_set, query)
+        .context("IMAP FETCH failed")?;
+
+    let mut warned_invalid_uid = false;
+    let mut rows = Vec::with_capacity(fetches.len());
+    for fetch in fetches.iter() {
+        let uid = match fetch.uid {
+            Some(u) if u > 0 => u,
+            _ => {
+                if !warned_invalid_uid {
+                    eprintln!(
+                        "Warning: skipping fetched message(s) with missing/invalid UID in '{folder}'"
+                    );
+                    warned_invalid_uid = true;
+                }
+                continue;
+            }
+        };
+        let text = header_fields(fetch.header().unwrap_or(b""));
+        let headers = if envelope {
+            fetch.envelope().map(|env| Headers {
+                list_unsubscribe: text.list_unsubscribe,
+                list_unsubscribe_post: text.list_unsubscribe_post,
+                ..envelope_fields(env)
+            })
+        } else {
+            Some(text)
+        };
+        let flags = fetch
+            .flags()
+            .iter()
+            .filter(|f| **f != imap::types::Flag::Recent)
+            .map(|f| f.to_string())
+            .collect();
+        rows.push(Fetched {
+            uid,
+            size: fetch.size.unwrap_or(0),
+            flags,
+            headers,
+        });
+    }
+    Ok(rows)
+}
 
-    #[test]
-    fn imap_quote_strips_control_chars() {
-        assert_eq!(imap_quote("he\nllo"), "\"hello\"");
+/// A listing row, with From and Subject truncated for the table and the Date's zone
+/// offset dropped.
+fn message_row(fetched: Fetched, folder: &Option<String>) -> MessageRow {
+    let Fetched {
+        uid,
+        size,
+        flags,
+        headers,
+    } = fetched;
+    let Headers {
+        subject,
+        from,
+        from_address,
+        mut date,
+        message_id,
+        list_unsubscribe,
+        list_unsubscribe_post,
+    } = headers.unwrap_or_default();
+    let timestamp = mailparse::dateparse(&date).unwrap_or(0);
+    if let Some(pos) = date.find(" +").or_else(|| date.find(" -")) {
+        date.truncate(pos);
+    }
+    MessageRow {
+        uid,
+        folder: folder.clone(),
+        from: truncate_str(&from, 40),
+        subject: truncate_str(&subject, 60),
+        date,
+        timestamp,
+        size,
+        message_id: (!message_id.is_empty()).then_some(message_id),
+        from_address,
+        flags,
+        unsubscribe: unsubscribe::targets(&list_unsubscribe),
+        unsubscribe_one_click: unsubscribe::is_one_click(&list_unsubscribe_post),
     }
+}
 
-    #[test]
-    fn parse_date_converts_iso_to_imap() {
-        assert_eq!(parse_date("2025-01-01").unwrap(), "1-Jan-2025");
-        assert_eq!(parse_date("2025-01-31").unwrap(), "31-Jan-2025");
-        assert_eq!(parse_date("2024-12-31").unwrap(), "31-Dec-2024");
-        assert_eq!(parse_date("2025-06-15").unwrap(), "15-Jun-2025");
+/// Full folder names (lowercased) that `--all-folders` leaves out by default.
+const SKIPPED_FOLDERS: &[&str] = &[
+    "trash",
+    "spam",
+    "junk",
+    "all mail",
+    "[gmail]/all mail",
+    "[gmail]/spam",
+    "[gmail]/trash",
+    "[google mail]/all mail",
+    "[google mail]/spam",
+    "[google mail]/trash",
+];
+
+pub fn folders_to_skip(name: &str) -> bool {
+    is_skipped(name, &[])
+}
+
+/// Whole-name, case-insensitive match against the default list and `extra`, so
+/// "Recall Mail" or "Trashcan" stay searchable.
+fn is_skipped(name: &str, extra: &[String]) -> bool {
+    let lower = name.to_lowercase();
+    SKIPPED_FOLDERS.iter().any(|s| *s == lower) || extra.iter().any(|e| e.to_lowercase() == lower)
+}
+
+/// Apply the all-folders skip list, failing with an explanation when nothing is left
+/// rather than letting the caller report a bare "No messages found."
+pub fn searchable_folders(
+    names: Vec<String>,
+    include_skipped: bool,
+    extra: &[String],
+) -> error::Result<Vec<String>> {
+    if names.is_empty() {
+        return Err(anyhow!("The server returned no folders to search.").into());
+    }
+    if include_skipped {
+        return Ok(names);
+    }
+    let kept: Vec<String> = names
+        .into_iter()
+        .filter(|n| !is_skipped(n, extra))
+        .collect();
+    if kept.is_empty() {
+        return Err(anyhow!(
+            "No searchable folders after applying skip list; use --include-skipped"
+        )
+        .into());
     }
+    Ok(kept)
+}
 
-    #[test]
-    fn parse_date_rejects_invalid_formats() {
-        assert!(parse_date("1-Jan-2025").is_err());
-        assert!(parse_date("Jan-1-2025").is_err());
-        assert!(parse_date("").is_err());
-        assert!(parse_date("2025-13-01").is_err());
-        assert!(parse_date("2025-00-01").is_err());
-        assert!(parse_date("2025-01-00").is_err());
-        assert!(parse_date("2025-01-32").is_err());
+/// Reports folders skipped during an all-folders pass: one warning per folder, or
+/// with `quiet` a single summary line from `finish`.
+pub struct SkippedFolders {
+    quiet: bool,
+    count: usize,
+}
+
+impl SkippedFolders {
+    pub fn new(quiet: bool) -> Self {
+        Self { quiet, count: 0 }
     }
 
-    #[test]
-    fn parse_date_relative_returns_valid_imap_date() {
-        // We can't assert exact dates since they depend on "now",
-        // but we can verify the format is valid IMAP date (D-Mon-YYYY)
-        let re = Regex::new(r"^\d{1,2}-(Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)-\d{4}$")
-            .unwrap();
-        for input in ["7d", "14d", "2w", "1m", "3m", "6m", "1y", "2y"] {
-            let result = parse_date(input).unwrap();
-            assert!(
-                re.is_match(&result),
-                "'{input}' produced invalid IMAP date: '{result}'"
-            );
+    pub fn skip(&mut self, folder: &str, reason: impl std::fmt::Display) {
+        self.count += 1;
+        if !self.quiet {
+            warn(format!("Warning: skipping folder '{folder}': {reason:#}"));
         }
     }
 
-    #[test]
+    pub fn finish(&self) {
+        if self.quiet && self.count > 0 {
+            warn(format!("Skipped {} folder(s).", self.count));
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// What `warn` printed on this thread, for tests.
+    static WARNINGS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+fn warn(line: String) {
+    #[cfg(test)]
+    WARNINGS.with(|w| w.borrow_mut().push(line.clone()));
+    eprintln!("{line}");
+}
+
+/// List every folder on the server and keep the ones `--all-folders` should search.
+pub fn list_searchable_folders(
+    session: &mut impl ImapOps,
+    criteria: &SearchCriteria,
+) -> error::Result<Vec<String>> {
+    let folders = session
+        .list(Some(""), Some("*"))
+        .context("Failed to list folders")?;
+    let names = folders.iter().map(|f| f.name().to_string()).collect();
+    searchable_folders(names, criteria.include_skipped, &criteria.skip_folders)
+}
+
+/// Search `criteria`, newest first (oldest first with `invert_order`), skipping `offset`
+/// results and truncated to `limit`.
+///
+/// The rows returned are exactly the set `delete`, `move`, `mark` and `export` display
+/// and then act on: those commands group these rows by folder and never search again,
+/// so `--limit N` acts on the N messages shown and no others.
+pub fn search(
+    session: &mut impl ImapOps,
+    criteria: &SearchCriteria,
+) -> error::Result<Vec<MessageRow>> {
+    search_with_progress(session, criteria, |_| {})
+}
+
+/// Most rows an all-folders search needs from any one folder: the merged page is the
+/// first `offset + limit` rows overall, and no folder can contribute more than that.
+/// With server SORT, `fetch_messages` then fetches only that many headers per folder.
+/// Only for date and size: the server sorts FROM and SUBJECT by other rules than the
+/// merge does, so its first rows in a folder may not be the ones the merge keeps.
+fn per_folder_limit(criteria: &SearchCriteria) -> Option<usize> {
+    match criteria.sort {
+        SortKey::Date | SortKey::Size => criteria
+            .limit
+            .map(|limit| limit.saturating_add(criteria.offset)),
+        SortKey::From | SortKey::Subject => None,
+    }
+}
+
+/// Where a search is, for callers that show progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchProgress<'a> {
+    /// Starting `folder`, number `index` (1-based) of `total` in an all-folders search.
+    Folder {
+        folder: &'a str,
+        index: usize,
+        total: usize,
+    },
+    /// `done` of the `total` messages to fetch from the current folder are in. Sent
+    /// once SEARCH or SORT has said how many there are.
+    Fetched { done: usize, total: usize },
+}
+
+/// Like `search`, calling `progress` before each folder of an all-folders search and as
+/// each folder's rows are fetched.
+pub fn search_with_progress(
+    session: &mut impl ImapOps,
+    criteria: &SearchCriteria,
+    mut progress: impl FnMut(SearchProgress<'_>),
+) -> error::Result<Vec<MessageRow>> {
+    if let Some(listed) = &criteria.uids {
+        return Ok(fetch_listed(session, listed, criteria)?);
+    }
+    let query = build_query_for(session, criteria)?;
+
+    if criteria.all_folders {
+        let folder_names = list_searchable_folders(session, criteria)?;
+        let per_folder = per_folder_limit(criteria);
+
+        let mut skipped = SkippedFolders::new(criteria.quiet_warnings);
+        let mut all_messages = Vec::new();
+        for (i, folder) in folder_names.iter().enumerate() {
+            progress(SearchProgress::Folder {
+                folder,
+                index: i + 1,
+                total: folder_names.len(),
+            });
+            let mut fetched = |done, total| progress(SearchProgress::Fetched { done, total });
+            match fetch_messages(
+                session,
+                folder,
+                &query,
+                criteria,
+                true,
+                0,
+                per_folder,
+                &mut fetched,
+            ) {
+                Ok(msgs) => all_messages.extend(msgs),
+                Err(e) => skipped.skip(folder, e),
+            }
+        }
+        skipped.finish();
+        sort_and_truncate(
+            &mut all_messages,
+            criteria.sort,
+            criteria.invert_order,
+            criteria.offset,
+            criteria.limit,
+        );
+        Ok(all_messages)
+    } else {
+        ensure_folder_exists(session, &criteria.folder)?;
+        Ok(fetch_messages(
+            session,
+            &criteria.folder,
+            &query,
+            criteria,
+            false,
+            criteria.offset,
+            criteria.limit,
+            &mut |done, total| progress(SearchProgress::Fetched { done, total }),
+        )?)
+    }
+}
+
+/// UIDs fetched per step of a `SearchIter`.
+const ITER_CHUNK: usize = 500;
+
+/// Streaming form of `search`: rows are fetched `ITER_CHUNK` UIDs at a time as the
+/// iterator is advanced, so callers can process and drop them instead of holding
+/// every match in memory. It matches the same messages as `search`, but not always
+/// in the same order, and so `offset`/`limit` may pick a different page:
+///
+/// - Folders come out one after another, in listing order; `search` merges them.
+/// - Within a folder, rows follow `criteria.sort` only when the server has SORT.
+///   Without it, `criteria.sort` is ignored (sorting would need every row first) and
+///   rows come in UID order, newest first, or oldest first with `invert_order`.
+///
+/// `offset` and `limit` count across folders. A folder that fails yields one `Err` and
+/// the iterator moves on to the next folder; an invalid query or missing folder yields
+/// a single `Err`.
+pub fn search_iter<'a, S: ImapOps>(
+    session: &'a mut S,
+    criteria: &'a SearchCriteria,
+) -> SearchIter<'a, S> {
+    let mut iter = SearchIter {
+        session,
+        criteria,
+        query: String::new(),
+        folders: VecDeque::new(),
+        include_folder: false,
+        folder: String::new(),
+        uids: VecDeque::new(),
+        rows: VecDeque::new(),
+        skip: criteria.offset,
+        left: criteria.limit,
+        error: None,
+    };
+    if let Err(e) = iter.start() {
+        iter.error = Some(e);
+    }
+    iter
+}
+
+/// Iterator returned by `search_iter`.
+pub struct SearchIter<'a, S = ImapSession> {
+    session: &'a mut S,
+    criteria: &'a SearchCriteria,
+    query: String,
+    /// Folders not started yet.
+    folders: VecDeque<String>,
+    include_folder: bool,
+    /// The folder being fetched, and its UIDs not fetched yet, in result order.
+    folder: String,
+    uids: VecDeque<u32>,
+    /// Fetched rows not handed out yet.
+    rows: VecDeque<MessageRow>,
+    skip: usize,
+    left: Option<usize>,
+    /// A failure from `start`, yielded once before the iterator ends.
+    error: Option<anyhow::Error>,
+}
+
+impl<S: ImapOps> SearchIter<'_, S> {
+    fn start(&mut self) -> Result<()> {
+        let criteria = self.criteria;
+        self.query = build_query_for(self.session, criteria)?;
+        let folders = if let Some(listed) = &criteria.uids {
+            self.include_folder = listed.keys().any(|f| *f != criteria.folder);
+            for folder in listed.keys() {
+                ensure_folder_exists(self.session, folder)?;
+            }
+            listed.keys().cloned().collect()
+        } else if criteria.all_folders {
+            self.include_folder = true;
+            list_searchable_folders(self.session, criteria)?
+        } else {
+            ensure_folder_exists(self.session, &criteria.folder)?;
+            vec![criteria.folder.clone()]
+        };
+        self.folders = folders.into();
+        Ok(())
+    }
+
+    /// Select `folder` and return its matching UIDs in result order.
+    fn folder_uids(&mut self, folder: &str) -> Result<Vec<u32>> {
+        let criteria = self.criteria;
+        self.session
+            .select(folder)
+            .with_context(|| format!("Failed to select folder '{folder}'"))?;
+
+        let mut uids = match criteria.uids.as_ref().and_then(|l| l.get(folder)) {
+            Some(listed) => listed_uids(self.session, listed, &self.query, criteria)?,
+            None => match matching_uids(self.session, &self.query, criteria)? {
+                (sorted, true) => return Ok(sorted),
+                (uids, false) => uids,
+            },
+        };
+        uids.sort_unstable();
+        if !criteria.invert_order {
+            uids.reverse();
+        }
+        Ok(uids)
+    }
+}
+
+impl<S: ImapOps> Iterator for SearchIter<'_, S> {
+    type Item = error::Result<MessageRow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            self.folders.clear();
+            return Some(Err(e.into()));
+        }
+        loop {
+            if self.left == Some(0) {
+                return None;
+            }
+            if let Some(row) = self.rows.pop_front() {
+                if let Some(left) = &mut self.left {
+                    *left -= 1;
+                }
+                return Some(Ok(row));
+            }
+            if !self.uids.is_empty() {
+                let n = self.uids.len().min(ITER_CHUNK);
+                let chunk: Vec<u32> = self.uids.drain(..n).collect();
+                match fetch_rows(
+                    self.session,
+                    &self.folder,
+                    &chunk,
+                    self.include_folder,
+                    &mut |_| {},
+                ) {
+                    Ok(mut by_uid) => self
+                        .rows
+                        .extend(chunk.iter().filter_map(|uid| by_uid.remove(uid))),
+                    Err(e) => {
+                        self.uids.clear();
+                        return Some(Err(e
+                            .context(format!("Failed to fetch messages from '{}'", self.folder))
+                            .into()));
+                    }
+                }
+                continue;
+            }
+            let folder = sanitize(&self.folders.pop_front()?);
+            match self.folder_uids(&folder) {
+                Ok(uids) => {
+                    let mut uids = VecDeque::from(uids);
+                    let skipped = self.skip.min(uids.len());
+                    uids.drain(..skipped);
+                    self.skip -= skipped;
+                    if let Some(left) = self.left {
+                        uids.truncate(left);
+                    }
+                    self.folder = folder;
+                    self.uids = uids;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// All-folders search spread over `jobs` sessions: `session` plus up to `jobs - 1`
+/// more opened by `connect`, each on its own thread, taking the next unsearched folder
+/// until none are left. Results are merged and sorted as in `search_with_progress`.
+/// A worker that fails to connect is reported and its share goes to the others.
+/// With several jobs the folders are fetched side by side, so only
+/// `SearchProgress::Folder` is reported.
+/// Without `all_folders`, or with `jobs <= 1`, this is `search_with_progress`.
+pub fn search_parallel<S: ImapOps + Send>(
+    session: &mut S,
+    criteria: &SearchCriteria,
+    jobs: usize,
+    connect: impl Fn() -> error::Result<S> + Sync,
+    progress: impl FnMut(SearchProgress<'_>) + Send,
+) -> error::Result<Vec<MessageRow>> {
+    if !criteria.all_folders || jobs <= 1 {
+        return search_with_progress(session, criteria, progress);
+    }
+    let query = build_query_for(session, criteria)?;
+    let folder_names = list_searchable_folders(session, criteria)?;
+    let total = folder_names.len();
+
+    let per_folder = per_folder_limit(criteria);
+    let next = AtomicUsize::new(0);
+    let progress = Mutex::new(progress);
+    let all_messages = Mutex::new(Vec::new());
+    let skipped = Mutex::new(SkippedFolders::new(criteria.quiet_warnings));
+    let work = |session: &mut S| loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let Some(folder) = folder_names.get(i) else {
+            break;
+        };
+        (progress.lock().unwrap())(SearchProgress::Folder {
+            folder,
+            index: i + 1,
+            total,
+        });
+        match fetch_messages(
+            session,
+            folder,
+            &query,
+            criteria,
+            true,
+            0,
+            per_folder,
+            &mut |_, _| {},
+        ) {
+            Ok(msgs) => all_messages.lock().unwrap().extend(msgs),
+            Err(e) => skipped.lock().unwrap().skip(folder, e),
+        }
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 1..jobs.min(total) {
+            scope.spawn(|| match connect() {
+                Ok(mut worker) => {
+                    work(&mut worker);
+                    let _ = worker.logout();
+                }
+                Err(e) => eprintln!("Warning: extra connection failed: {e:#}"),
+            });
+        }
+        work(session);
+    });
+    skipped.into_inner().unwrap().finish();
+
+    let mut all_messages = all_messages.into_inner().unwrap();
+    sort_and_truncate(
+        &mut all_messages,
+        criteria.sort,
+        criteria.invert_order,
+        criteria.offset,
+        criteria.limit,
+    );
+    Ok(all_messages)
+}
+
+pub(crate) fn folder_exists(session: &mut impl ImapOps, folder: &str) -> Result<bool> {
+    let folders = session
+        .list(Some(""), Some(folder))
+        .context("Failed to list folders")?;
+    Ok(folders.iter().any(|f| f.name() == folder))
+}
+
+pub fn ensure_folder_exists(session: &mut impl ImapOps, folder: &str) -> error::Result<()> {
+    if !folder_exists(session, folder)? {
+        // Only pay for a full LIST on the error path
+        let suggestion = session.list(Some(""), Some("*")).ok().and_then(|all| {
+            let names: Vec<String> = all.iter().map(|f| f.name().to_string()).collect();
+            suggest_folder(folder, &names)
+        });
+        return Err(SlashmailError::FolderNotFound {
+            folder: folder.to_string(),
+            suggestion,
+        });
+    }
+    Ok(())
+}
+
+/// Like `ensure_folder_exists`, but CREATE `folder` when it is missing; returns whether
+/// it was created. A CREATE that fails because the folder appeared in the meantime
+/// (another client made it) is not an error.
+pub fn ensure_or_create_folder(session: &mut impl ImapOps, folder: &str) -> error::Result<bool> {
+    if folder_exists(session, folder)? {
+        return Ok(false);
+    }
+    if let Err(e) = session.create(folder) {
+        if folder_exists(session, folder)? {
+            return Ok(false);
+        }
+        return Err(anyhow::Error::new(e)
+            .context(format!("Failed to create folder '{folder}'"))
+            .into());
+    }
+    Ok(true)
+}
+
+/// The closest folder name to a mistyped `name`, if any is within a few edits.
+/// Comparison is case-insensitive; the allowed distance grows with the name length.
+fn suggest_folder(name: &str, candidates: &[String]) -> Option<String> {
+    let wanted = name.to_lowercase();
+    let max_distance = (wanted.chars().count() / 3).max(2);
+    candidates
+        .iter()
+        .map(|c| (strsim::levenshtein(&wanted, &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeImap};
+    use imap_proto::types::{Address, Envelope};
+
+    #[test]
+    fn sanitize_removes_control_chars() {
+        assert_eq!(sanitize("hello"), "hello");
+        assert_eq!(sanitize("he\nllo"), "hello");
+        assert_eq!(sanitize("he\rllo"), "hello");
+        assert_eq!(sanitize("he\r\nllo"), "hello");
+        assert_eq!(sanitize("he\x00llo"), "hello");
+        assert_eq!(sanitize(""), "");
+    }
+
+    #[test]
+    fn sanitize_preserves_unicode() {
+        assert_eq!(sanitize("héllo wörld"), "héllo wörld");
+    }
+
+    #[test]
+    fn imap_quote_wraps_in_quotes() {
+        assert_eq!(imap_quote("hello"), "\"hello\"");
+    }
+
+    #[test]
+    fn imap_quote_escapes_backslash() {
+        assert_eq!(imap_quote("he\\llo"), "\"he\\\\llo\"");
+    }
+
+    #[test]
+    fn imap_quote_escapes_double_quote() {
+        assert_eq!(imap_quote("he\"llo"), "\"he\\\"llo\"");
+    }
+
+    #[test]
+    fn imap_quote_strips_control_chars() {
+        assert_eq!(imap_quote("he\nllo"), "\"hello\"");
+    }
+
+    #[test]
+    fn parse_date_converts_iso_to_imap() {
+        assert_eq!(parse_date("2025-01-01").unwrap(), "1-Jan-2025");
+        assert_eq!(parse_date("2025-01-31").unwrap(), "31-Jan-2025");
+        assert_eq!(parse_date("2024-12-31").unwrap(), "31-Dec-2024");
+        assert_eq!(parse_date("2025-06-15").unwrap(), "15-Jun-2025");
+    }
+
+    #[test]
+    fn parse_date_named_days_match_shorthand() {
+        assert_eq!(parse_date("today").unwrap(), parse_date("0d").unwrap());
+        assert_eq!(parse_date("yesterday").unwrap(), parse_date("1d").unwrap());
+        assert_eq!(parse_date("Yesterday").unwrap(), parse_date("1d").unwrap());
+        assert_eq!(parse_date("last-week").unwrap(), parse_date("1w").unwrap());
+        assert_eq!(parse_date("last-month").unwrap(), parse_date("1m").unwrap());
+        assert_eq!(parse_date("last-year").unwrap(), parse_date("1y").unwrap());
+        assert!(parse_date("last-decade").is_err());
+    }
+
+    #[test]
+    fn parse_date_rejects_hours_and_minutes() {
+        for s in ["12h", "30min", "2 hours"] {
+            let err = parse_date(s).unwrap_err().to_string();
+            assert!(
+                err.contains(
+                    "IMAP search supports day granularity; hour/minute filters are not available"
+                ),
+                "{s}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_date_rejects_invalid_formats() {
+        assert!(parse_date("1-Jan-2025").is_err());
+        assert!(parse_date("Jan-1-2025").is_err());
+        assert!(parse_date("").is_err());
+        assert!(parse_date("2025-13-01").is_err());
+        assert!(parse_date("2025-00-01").is_err());
+        assert!(parse_date("2025-01-00").is_err());
+        assert!(parse_date("2025-01-32").is_err());
+    }
+
+    #[test]
+    fn parse_date_relative_returns_valid_imap_date() {
+        // We can't assert exact dates since they depend on "now",
+        // but we can verify the format is valid IMAP date (D-Mon-YYYY)
+        let re = Regex::new(r"^\d{1,2}-(Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)-\d{4}$")
+            .unwrap();
+        for input in ["7d", "14d", "2w", "1m", "3m", "6m", "1y", "2y"] {
+            let result = parse_date(input).unwrap();
+            assert!(
+                re.is_match(&result),
+                "'{input}' produced invalid IMAP date: '{result}'"
+            );
+        }
+    }
+
+    #[test]
     fn parse_date_relative_zero_days_is_today() {
         // 0d should produce today's date
         let result = parse_date("0d").unwrap();
@@ -677,6 +1827,56 @@ mod tests {
         assert!(parse_date("7").is_err());
     }
 
+    #[test]
+    fn parse_uid_list_plain_numbers() {
+        let listed = parse_uid_list("12 7\n7,30\n\n", "INBOX").unwrap();
+        assert_eq!(
+            listed,
+            BTreeMap::from([("INBOX".to_string(), vec![7, 12, 30])])
+        );
+        assert!(parse_uid_list("12 x", "INBOX").is_err());
+        assert!(parse_uid_list("0", "INBOX").is_err());
+        assert!(parse_uid_list(" \n", "INBOX").is_err());
+    }
+
+    #[test]
+    fn parse_uid_list_search_json() {
+        let json =
+            r#"[{"uid":4,"folder":"Archive","subject":"a"},{"uid":9,"folder":null},{"uid":2}]"#;
+        let listed = parse_uid_list(json, "INBOX").unwrap();
+        assert_eq!(
+            listed,
+            BTreeMap::from([
+                ("Archive".to_string(), vec![4]),
+                ("INBOX".to_string(), vec![2, 9]),
+            ])
+        );
+
+        let envelope = r#"{"slashmail_version":"0.5.0","command":"search","results":[{"uid":5}]}"#;
+        assert_eq!(
+            parse_uid_list(envelope, "INBOX").unwrap(),
+            BTreeMap::from([("INBOX".to_string(), vec![5])])
+        );
+        assert!(parse_uid_list(r#"[{"subject":"no uid"}]"#, "INBOX").is_err());
+        assert!(parse_uid_list("[1, 2", "INBOX").is_err());
+    }
+
+    #[test]
+    fn empty_json_uid_list_matches_nothing() {
+        let listed = parse_uid_list("[]\n", "INBOX").unwrap();
+        assert!(listed.is_empty());
+        let empty_envelope = r#"{"slashmail_version":"0.5.0","command":"search","results":[]}"#;
+        assert!(parse_uid_list(empty_envelope, "INBOX").unwrap().is_empty());
+
+        let mut fake = inbox(&[]);
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            uids: Some(listed),
+            ..Default::default()
+        };
+        assert!(search(&mut fake, &criteria).unwrap().is_empty());
+    }
+
     #[test]
     fn parse_size_plain_bytes() {
         assert_eq!(parse_size("1024").unwrap(), 1024);
@@ -697,6 +1897,33 @@ mod tests {
         assert_eq!(parse_size("5M").unwrap(), 5_242_880);
     }
 
+    #[test]
+    fn parse_size_gigabytes_and_binary_suffixes() {
+        assert_eq!(parse_size("1G").unwrap(), 1_073_741_824);
+        assert_eq!(parse_size("1g").unwrap(), 1_073_741_824);
+        assert_eq!(parse_size("2GiB").unwrap(), 2_147_483_648);
+        assert_eq!(parse_size("3KiB").unwrap(), 3072);
+        assert_eq!(parse_size("5mib").unwrap(), 5_242_880);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        assert!(parse_size("1B").is_err());
+        assert!(parse_size("GiB").is_err());
+    }
+
+    #[test]
+    fn parse_size_decimal_values() {
+        assert_eq!(parse_size("1.5M").unwrap(), 1_572_864);
+        assert_eq!(parse_size("0.5K").unwrap(), 512);
+        assert_eq!(parse_size("2.25GiB").unwrap(), 2_415_919_104);
+        assert_eq!(parse_size("1.5").unwrap(), 2);
+        assert!(parse_size("abc.M").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+        assert!(parse_size("1,5M").is_err());
+        assert!(parse_size(".5M").is_err());
+        assert!(parse_size("1.M").is_err());
+        assert!(parse_size("1.5e3K").is_err());
+        assert!(parse_size("99999999999.5T").is_err());
+    }
+
     #[test]
     fn parse_size_invalid_errors() {
         assert!(parse_size("abc").is_err());
@@ -706,6 +1933,184 @@ mod tests {
     #[test]
     fn parse_size_overflow_errors() {
         assert!(parse_size("18446744073709551615M").is_err());
+        assert!(parse_size("17179869184GiB").is_err());
+    }
+
+    #[test]
+    fn header_fields_decodes_base64_utf8_subject() {
+        // "Réunion équipe – ordre du jour" as a Base64 UTF-8 encoded-word
+        let raw = b"Subject: =?UTF-8?B?UsOpdW5pb24gw6lxdWlwZSDigJMgb3JkcmUgZHUgam91cg==?=\r\n\
+                    From: =?ISO-8859-1?Q?Andr=E9_M=FCller?= <andre@example.com>\r\n\
+                    Date: Sun, 9 Mar 2025 12:00:00 +0000\r\n\r\n";
+        let headers = header_fields(raw);
+        assert_eq!(headers.subject, "Réunion équipe – ordre du jour");
+        assert_eq!(headers.from, "André Müller <andre@example.com>");
+        assert_eq!(headers.from_address, "andre@example.com");
+        assert_eq!(headers.date, "Sun, 9 Mar 2025 12:00:00 +0000");
+    }
+
+    #[test]
+    fn header_fields_decoded_subject_truncates_on_char_boundary() {
+        // 72 x "é" (2 bytes each) across two adjacent encoded-words
+        let word = "=?UTF-8?B?".to_string() + &"w6nDqcOp".repeat(12) + "?=";
+        let raw = format!("Subject: {word} {word}\r\n\r\n");
+        let subject = header_fields(raw.as_bytes()).subject;
+        assert_eq!(subject.chars().count(), 72);
+        let shown = truncate_str(&subject, 60);
+        assert_eq!(shown.chars().count(), 60);
+        assert!(shown.ends_with("é..."));
+    }
+
+    #[test]
+    fn sender_address_ignores_display_name_and_case() {
+        assert_eq!(
+            sender_address("Alice <Alice@Example.com>"),
+            "alice@example.com"
+        );
+        assert_eq!(sender_address("alice@example.com"), "alice@example.com");
+        assert_eq!(
+            sender_address("\"Doe, Jane\" <jane@x.org>, bob@y.org"),
+            "jane@x.org"
+        );
+        assert_eq!(sender_address("  Not An Address "), "not an address");
+    }
+
+    #[test]
+    fn header_fields_plain_headers_unchanged() {
+        let raw = b"subject: Hello\r\nFROM: a@b.c\r\n\r\n";
+        assert_eq!(
+            header_fields(raw),
+            Headers {
+                subject: "Hello".to_string(),
+                from: "a@b.c".to_string(),
+                from_address: "a@b.c".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    fn address<'a>(name: Option<&'a str>, mailbox: &'a str, host: &'a str) -> Address<'a> {
+        Address {
+            name: name.map(str::as_bytes),
+            adl: None,
+            mailbox: Some(mailbox.as_bytes()),
+            host: Some(host.as_bytes()),
+        }
+    }
+
+    fn envelope<'a>(subject: &'a str, from: Vec<Address<'a>>) -> Envelope<'a> {
+        Envelope {
+            date: Some(b"Sun, 9 Mar 2025 12:00:00 +0000"),
+            subject: Some(subject.as_bytes()),
+            from: Some(from),
+            sender: None,
+            reply_to: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            in_reply_to: None,
+            message_id: Some(b"<abc@example.com>"),
+        }
+    }
+
+    #[test]
+    fn envelope_fields_decode_words_and_split_address() {
+        let env = envelope(
+            "=?UTF-8?B?UsOpdW5pb24=?= du jour",
+            vec![address(
+                Some("=?ISO-8859-1?Q?Andr=E9_M=FCller?="),
+                "Andre",
+                "Example.com",
+            )],
+        );
+        let headers = envelope_fields(&env);
+        assert_eq!(headers.subject, "Réunion du jour");
+        assert_eq!(headers.from, "André Müller <Andre@Example.com>");
+        assert_eq!(headers.from_address, "andre@example.com");
+        assert_eq!(headers.date, "Sun, 9 Mar 2025 12:00:00 +0000");
+        assert_eq!(headers.message_id, "<abc@example.com>");
+    }
+
+    #[test]
+    fn envelope_fields_keeps_raw_utf8_and_commas_in_names() {
+        let env = envelope("Café", vec![address(Some("Doe, Jane"), "jane", "x.org")]);
+        let headers = envelope_fields(&env);
+        assert_eq!(headers.subject, "Café");
+        assert_eq!(headers.from, "Doe, Jane <jane@x.org>");
+        assert_eq!(headers.from_address, "jane@x.org");
+    }
+
+    #[test]
+    fn envelope_fields_skips_group_markers() {
+        let group_start = Address {
+            name: None,
+            adl: None,
+            mailbox: Some(b"undisclosed-recipients"),
+            host: None,
+        };
+        let env = envelope("Hi", vec![group_start, address(None, "bob", "y.org")]);
+        let headers = envelope_fields(&env);
+        assert_eq!(headers.from, "bob@y.org");
+        assert_eq!(headers.from_address, "bob@y.org");
+    }
+
+    #[test]
+    fn envelope_fields_without_from_is_empty() {
+        let mut env = envelope("Hi", vec![]);
+        env.from = None;
+        env.subject = None;
+        let headers = envelope_fields(&env);
+        assert_eq!(headers.from, "");
+        assert_eq!(headers.subject, "");
+    }
+
+    #[test]
+    fn message_row_parses_list_unsubscribe() {
+        let headers = header_fields(
+            b"List-Unsubscribe: <mailto:leave@list.example>,\r\n <https://list.example/u/1>\r\n\
+List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n\r\n",
+        );
+        let fetched = Fetched {
+            uid: 1,
+            size: 0,
+            flags: Vec::new(),
+            headers: Some(headers),
+        };
+        let row = message_row(fetched, &None);
+        assert_eq!(
+            row.unsubscribe,
+            ["mailto:leave@list.example", "https://list.example/u/1"]
+        );
+        assert!(row.unsubscribe_one_click);
+
+        let plain = Fetched {
+            uid: 2,
+            size: 0,
+            flags: Vec::new(),
+            headers: Some(header_fields(b"Subject: Hi\r\n\r\n")),
+        };
+        let row = message_row(plain, &None);
+        assert!(row.unsubscribe.is_empty() && !row.unsubscribe_one_click);
+    }
+
+    #[test]
+    fn message_row_drops_zone_and_keeps_timestamp() {
+        let headers = Headers {
+            date: "Sun, 9 Mar 2025 12:00:00 +0100".to_string(),
+            message_id: "<a@b>".to_string(),
+            ..Default::default()
+        };
+        let fetched = Fetched {
+            uid: 7,
+            size: 10,
+            flags: vec!["\\Seen".to_string()],
+            headers: Some(headers),
+        };
+        let row = message_row(fetched, &None);
+        assert_eq!(row.date, "Sun, 9 Mar 2025 12:00:00");
+        assert_eq!(row.timestamp, 1_741_518_000);
+        assert_eq!(row.message_id.as_deref(), Some("<a@b>"));
+        assert_eq!(row.flags, ["\\Seen"]);
     }
 
     #[test]
@@ -733,8 +2138,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -749,9 +2156,44 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "ALL");
+        assert!(!c.has_filters());
+    }
+
+    #[test]
+    fn has_filters_ignores_folder_order_and_paging() {
+        let mut c = SearchCriteria {
+            folder: "Archive".into(),
+            all_folders: true,
+            sort: SortKey::Size,
+            invert_order: true,
+            offset: 5,
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert!(!c.has_filters());
+        c.label = Some("Work".into());
+        assert!(c.has_filters());
+        c.label = None;
+        c.unanswered = true;
+        assert!(c.has_filters());
     }
 
     #[test]
@@ -759,8 +2201,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: Some("test".into()),
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: vec!["test".into()],
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -775,6 +2219,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "SUBJECT \"test\"");
@@ -785,8 +2244,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: Some("invoice".into()),
-            from: Some("user@example.com".into()),
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: vec!["invoice".into()],
+            from: vec!["user@example.com".into()],
             to: None,
             cc: None,
             body: None,
@@ -801,6 +2262,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(
@@ -814,8 +2290,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: Some("alice@example.com".into()),
             cc: Some("bob@example.com".into()),
             body: None,
@@ -830,6 +2308,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(
@@ -843,8 +2336,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -859,6 +2354,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "SEEN");
@@ -869,8 +2379,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: Some("alice@example.com".into()),
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: vec!["alice@example.com".into()],
             to: None,
             cc: None,
             body: None,
@@ -885,6 +2397,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(
@@ -898,8 +2425,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -914,6 +2443,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(
@@ -927,8 +2471,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -943,6 +2489,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "LARGER 1048576");
@@ -953,8 +2514,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -969,6 +2532,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert!(build_query(&c).is_err());
@@ -979,8 +2557,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -995,6 +2575,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert!(build_query(&c).is_err());
@@ -1031,7 +2626,98 @@ mod tests {
     }
 
     #[test]
-    fn folders_to_skip_filters_correctly() {
+    fn parse_uid_list_response_search() {
+        let data = b"* SEARCH 2 9 4\r\nA001 OK SEARCH completed\r\n";
+        assert_eq!(
+            parse_uid_list_response(data, "SEARCH").unwrap(),
+            vec![2, 9, 4]
+        );
+        let empty = b"* SEARCH\r\nA001 OK SEARCH completed\r\n";
+        assert!(parse_uid_list_response(empty, "SEARCH").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_sort_response_ignores_literal_in_unsolicited_fetch() {
+        // A literal whose bytes look like a tagged NO must not be read as one
+        let data = b"* SORT 4 2\r\n* 3 FETCH (BODY[] {14}\r\nA001 NO oops\r\n)\r\nA001 OK SORT completed\r\n";
+        assert_eq!(parse_sort_response(data).unwrap(), vec![4, 2]);
+    }
+
+    #[test]
+    fn parse_sort_response_malformed_literal() {
+        let data = b"* SORT 4 2\r\n* 3 FETCH (BODY[] {99}\r\nshort\r\n";
+        assert!(parse_sort_response(data).is_err());
+    }
+
+    #[test]
+    fn parse_uid_list_response_badcharset() {
+        let data = b"A001 NO [BADCHARSET (UTF-8)] Unsupported charset\r\n";
+        let err = parse_uid_list_response(data, "SEARCH").unwrap_err();
+        assert!(err.to_string().contains("SEARCH command rejected"));
+    }
+
+    #[test]
+    fn charset_defaults_to_utf8() {
+        let mut c = default_test_criteria();
+        assert_eq!(c.charset(), "UTF-8");
+        c.charset = Some("ISO-8859-1".into());
+        assert_eq!(c.charset(), "ISO-8859-1");
+    }
+
+    #[test]
+    fn validate_charset_rejects_injection() {
+        assert!(validate_charset("UTF-8").is_ok());
+        assert!(validate_charset("iso-8859-15").is_ok());
+        assert!(validate_charset("").is_err());
+        assert!(validate_charset("UTF-8 ALL").is_err());
+        assert!(validate_charset("\"UTF-8\"").is_err());
+
+        let mut c = default_test_criteria();
+        c.charset = Some("UTF-8\r\nA1 LOGOUT".into());
+        assert!(build_query(&c).is_err());
+    }
+
+    #[test]
+    fn raw_sort_and_search_commands_carry_no_crlf() {
+        let mut c = default_test_criteria();
+        c.folder = "INBOX\r\nA1 DELETE INBOX".into();
+        c.subject = vec!["x\r\nA2 LOGOUT".into()];
+        c.from = vec!["\nA3 EXPUNGE".into()];
+        let query = build_query(&c).unwrap();
+        for cmd in [
+            format!(
+                "UID SORT {} {} {query}",
+                sort_criteria(SortKey::Date, false),
+                c.charset()
+            ),
+            format!("UID SEARCH CHARSET {} {query}", c.charset()),
+            format!("SELECT {}", imap_quote(&c.folder)),
+        ] {
+            assert!(!cmd.contains('\r') && !cmd.contains('\n'), "{cmd:?}");
+        }
+        assert_eq!(sanitize(&c.folder), "INBOXA1 DELETE INBOX");
+    }
+
+    #[test]
+    fn suggest_folder_nearest_match() {
+        let folders = names(&["INBOX", "Archive", "Sent", "Drafts", "Archive/2024"]);
+        assert_eq!(
+            suggest_folder("Arhive", &folders).as_deref(),
+            Some("Archive")
+        );
+        assert_eq!(suggest_folder("inbx", &folders).as_deref(), Some("INBOX"));
+        assert_eq!(suggest_folder("Draft", &folders).as_deref(), Some("Drafts"));
+    }
+
+    #[test]
+    fn suggest_folder_none_when_too_far() {
+        let folders = names(&["INBOX", "Archive", "Sent"]);
+        assert_eq!(suggest_folder("Receipts", &folders), None);
+        assert_eq!(suggest_folder("Arhive", &[]), None);
+    }
+
+    #[test]
+    fn folders_to_skip_filters_correctly() {
         assert!(folders_to_skip("Trash"));
         assert!(folders_to_skip("Spam"));
         assert!(folders_to_skip("Junk"));
@@ -1043,6 +2729,36 @@ mod tests {
         assert!(!folders_to_skip("Sent"));
     }
 
+    #[test]
+    fn folders_to_skip_matches_whole_names_only() {
+        assert!(folders_to_skip("All Mail"));
+        assert!(folders_to_skip("[Google Mail]/All Mail"));
+        assert!(!folders_to_skip("Recall Mail"));
+        assert!(!folders_to_skip("Archive/All Mail Backups"));
+        assert!(!folders_to_skip("Trashcan"));
+        assert!(!folders_to_skip("Junk Drawer"));
+    }
+
+    #[test]
+    fn configured_skip_folders_are_skipped() {
+        let extra = vec!["Newsletters".to_string()];
+        assert!(is_skipped("newsletters", &extra));
+        assert!(is_skipped("Trash", &extra));
+        assert!(!is_skipped("Newsletters/Old", &extra));
+        assert!(!is_skipped("Newsletters", &[]));
+        let accented = vec!["Éléments supprimés".to_string()];
+        assert!(is_skipped("éléments SUPPRIMÉS", &accented));
+    }
+
+    #[test]
+    fn searchable_folders_applies_configured_skip_list() {
+        let extra = vec!["newsletters".to_string()];
+        let kept = searchable_folders(names(&["INBOX", "Newsletters", "Trash"]), false, &extra);
+        assert_eq!(kept.unwrap(), ["INBOX"]);
+        let all = searchable_folders(names(&["INBOX", "Newsletters"]), true, &extra);
+        assert_eq!(all.unwrap(), ["INBOX", "Newsletters"]);
+    }
+
     #[test]
     fn build_uid_set_empty() {
         assert!(build_uid_set(&[]).is_empty());
@@ -1079,13 +2795,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn uid_set_len_counts_ranges() {
+        assert_eq!(uid_set_len("7"), 1);
+        assert_eq!(uid_set_len("1:3,7,10:12"), 7);
+        let uids: Vec<u32> = (0..2000).map(|i| i * 3).collect();
+        let total: usize = build_uid_set(&uids).iter().map(|c| uid_set_len(c)).sum();
+        assert_eq!(total, 2000);
+    }
+
     #[test]
     fn build_query_body_only() {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: Some("invoice".into()),
@@ -1100,6 +2827,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "BODY \"invoice\"");
@@ -1110,8 +2852,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -1126,6 +2870,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(build_query(&c).unwrap(), "TEXT \"meeting\"");
@@ -1136,8 +2895,10 @@ mod tests {
         let c = SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: Some("report".into()),
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: vec!["report".into()],
+            from: Vec::new(),
             to: None,
             cc: None,
             body: Some("quarterly".into()),
@@ -1152,6 +2913,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         };
         assert_eq!(
@@ -1160,13 +2936,159 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_query_body_with_from_multi_word() {
+        let mut c = default_test_criteria();
+        c.from = vec!["boss".into()];
+        c.body = Some("quarterly results".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "FROM \"boss\" BODY \"quarterly results\""
+        );
+    }
+
+    #[test]
+    fn build_query_text_quotes_specials() {
+        let mut c = default_test_criteria();
+        c.text = Some("say \"hi\" to C:\\".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "TEXT \"say \\\"hi\\\" to C:\\\\\""
+        );
+    }
+
+    #[test]
+    fn build_query_two_froms_use_or() {
+        let mut c = default_test_criteria();
+        c.from = vec!["alice".into(), "bob".into()];
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "OR (FROM \"alice\") (FROM \"bob\")"
+        );
+    }
+
+    #[test]
+    fn build_query_three_froms_nest_or() {
+        let mut c = default_test_criteria();
+        c.from = vec!["a".into(), "b".into(), "c".into()];
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "OR (FROM \"a\") (OR (FROM \"b\") (FROM \"c\"))"
+        );
+    }
+
+    #[test]
+    fn build_query_four_subjects_nest_or() {
+        let mut c = default_test_criteria();
+        c.subject = vec!["a".into(), "b".into(), "c".into(), "d".into()];
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "OR (SUBJECT \"a\") (OR (SUBJECT \"b\") (OR (SUBJECT \"c\") (SUBJECT \"d\")))"
+        );
+    }
+
+    #[test]
+    fn build_query_or_groups_are_anded() {
+        let mut c = default_test_criteria();
+        c.subject = vec!["invoice".into(), "receipt".into()];
+        c.from = vec!["shop".into()];
+        c.unseen = true;
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "OR (SUBJECT \"invoice\") (SUBJECT \"receipt\") FROM \"shop\" UNSEEN"
+        );
+    }
+
+    #[test]
+    fn build_query_not_from_with_subject() {
+        let mut c = default_test_criteria();
+        c.subject = vec!["invoice".into()];
+        c.not_from = Some("billing@x".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "SUBJECT \"invoice\" NOT FROM \"billing@x\""
+        );
+    }
+
+    #[test]
+    fn build_query_not_subject_alone() {
+        let mut c = default_test_criteria();
+        c.not_subject = Some("[spam]".into());
+        assert_eq!(build_query(&c).unwrap(), "NOT SUBJECT \"[spam]\"");
+    }
+
+    #[test]
+    fn build_query_positive_and_negated_same_field() {
+        let mut c = default_test_criteria();
+        c.from = vec!["example.com".into()];
+        c.not_from = Some("noreply@".into());
+        c.not_subject = Some("digest".into());
+        c.unseen = true;
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "FROM \"example.com\" NOT SUBJECT \"digest\" NOT FROM \"noreply@\" UNSEEN"
+        );
+    }
+
+    #[test]
+    fn build_query_rejects_empty_negations() {
+        let mut c = default_test_criteria();
+        c.not_from = Some(String::new());
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("--not-from"), "{err}");
+
+        let mut c = default_test_criteria();
+        c.not_subject = Some(" ".into());
+        assert!(build_query(&c).is_err());
+    }
+
+    #[test]
+    fn build_query_headers() {
+        let mut c = default_test_criteria();
+        c.from = vec!["lists".into()];
+        c.headers = vec![
+            ("List-Id".into(), "rust-users".into()),
+            ("X-Mailer".into(), String::new()),
+        ];
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "FROM \"lists\" HEADER \"List-Id\" \"rust-users\" HEADER \"X-Mailer\" \"\""
+        );
+    }
+
+    #[test]
+    fn build_query_header_value_is_quoted() {
+        let mut c = default_test_criteria();
+        c.headers = vec![("Message-ID".into(), "<a\"b@x>\r\nA1 LOGOUT".into())];
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "HEADER \"Message-ID\" \"<a\\\"b@x>A1 LOGOUT\""
+        );
+    }
+
+    #[test]
+    fn build_query_rejects_empty_body_and_text() {
+        for value in ["", "   ", "\r\n"] {
+            let mut c = default_test_criteria();
+            c.body = Some(value.into());
+            let err = build_query(&c).unwrap_err().to_string();
+            assert!(err.contains("--body"), "{err}");
+
+            let mut c = default_test_criteria();
+            c.text = Some(value.into());
+            assert!(build_query(&c).unwrap_err().to_string().contains("--text"));
+        }
+    }
+
     /// Helper to build a default SearchCriteria with all fields set to None/false.
     fn default_test_criteria() -> SearchCriteria {
         SearchCriteria {
             folder: "INBOX".into(),
             all_folders: false,
-            subject: None,
-            from: None,
+            include_skipped: false,
+            skip_folders: Vec::new(),
+            subject: Vec::new(),
+            from: Vec::new(),
             to: None,
             cc: None,
             body: None,
@@ -1181,6 +3103,21 @@ mod tests {
             unflagged: false,
             answered: false,
             draft: false,
+            invert_order: false,
+            charset: None,
+            quiet_warnings: false,
+            headers: Vec::new(),
+            unanswered: false,
+            not_subject: None,
+            not_from: None,
+            on: None,
+            sent: false,
+            raw: None,
+            gmail_raw: None,
+            label: None,
+            sort: SortKey::Date,
+            offset: 0,
+            uids: None,
             limit: None,
         }
     }
@@ -1192,6 +3129,62 @@ mod tests {
         assert_eq!(build_query(&c).unwrap(), "SMALLER 1048576");
     }
 
+    #[test]
+    fn build_query_on() {
+        let mut c = default_test_criteria();
+        c.on = Some("2025-03-09".into());
+        assert_eq!(build_query(&c).unwrap(), "ON 9-Mar-2025");
+    }
+
+    #[test]
+    fn build_query_on_relative() {
+        let mut c = default_test_criteria();
+        c.on = Some("7d".into());
+        let expected = format!("ON {}", parse_date("7d").unwrap());
+        assert_eq!(build_query(&c).unwrap(), expected);
+    }
+
+    #[test]
+    fn build_query_on_rejects_since_and_before() {
+        let mut c = default_test_criteria();
+        c.on = Some("2025-03-09".into());
+        c.since = Some("2025-03-01".into());
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("--on"), "{err}");
+
+        c.since = None;
+        c.before = Some("2025-04-01".into());
+        assert!(build_query(&c).is_err());
+    }
+
+    #[test]
+    fn build_query_sent_range() {
+        let mut c = default_test_criteria();
+        c.since = Some("2025-01-01".into());
+        c.before = Some("2025-02-01".into());
+        c.sent = true;
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "SENTSINCE 1-Jan-2025 SENTBEFORE 1-Feb-2025"
+        );
+    }
+
+    #[test]
+    fn build_query_sent_on() {
+        let mut c = default_test_criteria();
+        c.on = Some("2025-03-09".into());
+        c.sent = true;
+        assert_eq!(build_query(&c).unwrap(), "SENTON 9-Mar-2025");
+    }
+
+    #[test]
+    fn build_query_sent_without_date_errors() {
+        let mut c = default_test_criteria();
+        c.sent = true;
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("--sent"), "{err}");
+    }
+
     #[test]
     fn build_query_size_range() {
         let mut c = default_test_criteria();
@@ -1200,6 +3193,29 @@ mod tests {
         assert_eq!(build_query(&c).unwrap(), "LARGER 1024 SMALLER 1048576");
     }
 
+    #[test]
+    fn build_query_rejects_empty_size_range() {
+        let mut c = default_test_criteria();
+        c.larger = Some("1M".into());
+        c.smaller = Some("100K".into());
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("no possible message size"), "{err}");
+
+        // Strict bounds: only a 101-byte message fits between 100 and 102
+        c.larger = Some("100".into());
+        c.smaller = Some("101".into());
+        assert!(build_query(&c).is_err());
+        c.smaller = Some("102".into());
+        assert_eq!(build_query(&c).unwrap(), "LARGER 100 SMALLER 102");
+    }
+
+    #[test]
+    fn build_query_invalid_smaller_errors() {
+        let mut c = default_test_criteria();
+        c.smaller = Some("5X".into());
+        assert!(build_query(&c).is_err());
+    }
+
     #[test]
     fn build_query_flagged() {
         let mut c = default_test_criteria();
@@ -1221,10 +3237,560 @@ mod tests {
         assert_eq!(build_query(&c).unwrap(), "ANSWERED");
     }
 
+    #[test]
+    fn build_query_unanswered() {
+        let mut c = default_test_criteria();
+        c.unanswered = true;
+        assert_eq!(build_query(&c).unwrap(), "UNANSWERED");
+    }
+
+    #[test]
+    fn build_query_flag_filters_combine() {
+        let mut c = default_test_criteria();
+        c.unseen = true;
+        c.flagged = true;
+        c.unanswered = true;
+        assert_eq!(build_query(&c).unwrap(), "UNSEEN FLAGGED UNANSWERED");
+    }
+
+    #[test]
+    fn build_query_rejects_contradictory_flags() {
+        let mut c = default_test_criteria();
+        c.seen = true;
+        c.unseen = true;
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("--seen and --unseen"), "{err}");
+
+        let mut c = default_test_criteria();
+        c.flagged = true;
+        c.unflagged = true;
+        assert!(build_query(&c).is_err());
+
+        let mut c = default_test_criteria();
+        c.answered = true;
+        c.unanswered = true;
+        assert!(build_query(&c).is_err());
+    }
+
+    #[test]
+    fn build_query_raw_alone() {
+        let mut c = default_test_criteria();
+        c.raw = Some("OR KEYWORD $Important UNKEYWORD $Junk".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "OR KEYWORD $Important UNKEYWORD $Junk"
+        );
+    }
+
+    #[test]
+    fn build_query_raw_anded_with_filters() {
+        let mut c = default_test_criteria();
+        c.from = vec!["boss".into()];
+        c.unseen = true;
+        c.raw = Some("NOT KEYWORD \"$Done\"".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "FROM \"boss\" UNSEEN NOT KEYWORD \"$Done\""
+        );
+    }
+
+    #[test]
+    fn build_query_raw_strips_line_breaks() {
+        let mut c = default_test_criteria();
+        c.raw = Some("UNSEEN\r\nA2 LOGOUT\n".into());
+        assert_eq!(build_query(&c).unwrap(), "UNSEEN  A2 LOGOUT");
+    }
+
+    #[test]
+    fn build_query_raw_rejects_empty_and_literals() {
+        let mut c = default_test_criteria();
+        c.raw = Some(" \r\n".into());
+        assert!(build_query(&c).is_err());
+
+        c.raw = Some("SUBJECT {5}".into());
+        let err = build_query(&c).unwrap_err().to_string();
+        assert!(err.contains("literals"), "{err}");
+    }
+
+    #[test]
+    fn build_query_gmail_raw_and_label() {
+        let mut c = default_test_criteria();
+        c.unseen = true;
+        c.gmail_raw = Some("has:attachment \"older_than:1y\"".into());
+        c.label = Some("Work/Clients".into());
+        assert_eq!(
+            build_query(&c).unwrap(),
+            "UNSEEN X-GM-RAW \"has:attachment \\\"older_than:1y\\\"\" X-GM-LABELS \"Work/Clients\""
+        );
+
+        c.label = Some(String::new());
+        assert!(build_query(&c).is_err());
+    }
+
     #[test]
     fn build_query_draft() {
         let mut c = default_test_criteria();
         c.draft = true;
         assert_eq!(build_query(&c).unwrap(), "DRAFT");
     }
+
+    fn test_row(uid: u32, timestamp: i64) -> MessageRow {
+        MessageRow {
+            uid,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn searchable_folders_filters_skip_list() {
+        let kept =
+            searchable_folders(names(&["INBOX", "Trash", "Archive", "Spam"]), false, &[]).unwrap();
+        assert_eq!(kept, ["INBOX", "Archive"]);
+    }
+
+    #[test]
+    fn searchable_folders_only_skipped_names_errors() {
+        let err = searchable_folders(
+            names(&["[Gmail]/All Mail", "[Gmail]/Spam", "[Gmail]/Trash"]),
+            false,
+            &[],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--include-skipped"));
+    }
+
+    #[test]
+    fn searchable_folders_include_skipped_keeps_all() {
+        let kept = searchable_folders(names(&["Trash", "Junk"]), true, &[]).unwrap();
+        assert_eq!(kept, ["Trash", "Junk"]);
+    }
+
+    #[test]
+    fn searchable_folders_empty_list_errors() {
+        assert!(searchable_folders(Vec::new(), false, &[]).is_err());
+        assert!(searchable_folders(Vec::new(), true, &[]).is_err());
+    }
+
+    #[test]
+    fn skipped_folders_counts_in_both_modes() {
+        for quiet in [false, true] {
+            let mut skipped = SkippedFolders::new(quiet);
+            skipped.skip("Shared/a", "permission denied");
+            skipped.skip("Shared/b", "permission denied");
+            assert_eq!(skipped.count, 2);
+            skipped.finish();
+        }
+    }
+
+    #[test]
+    fn group_by_folder_uses_default_and_sorts() {
+        let mut archived = test_row(7, 0);
+        archived.folder = Some("Archive".to_string());
+        let rows = vec![test_row(3, 0), archived, test_row(1, 0)];
+
+        let grouped = group_by_folder(&rows, "INBOX");
+        let folders: Vec<&String> = grouped.keys().collect();
+        assert_eq!(folders, ["Archive", "INBOX"]);
+        assert_eq!(grouped["INBOX"], vec![3, 1]);
+        assert_eq!(grouped["Archive"], vec![7]);
+    }
+
+    #[test]
+    fn sort_criteria_default_is_newest_first() {
+        assert_eq!(sort_criteria(SortKey::Date, false), "(REVERSE DATE)");
+        assert_eq!(sort_criteria(SortKey::Date, true), "(DATE)");
+    }
+
+    #[test]
+    fn sort_criteria_per_key() {
+        assert_eq!(sort_criteria(SortKey::Size, false), "(REVERSE SIZE)");
+        assert_eq!(sort_criteria(SortKey::Size, true), "(SIZE)");
+        assert_eq!(sort_criteria(SortKey::From, false), "(FROM)");
+        assert_eq!(sort_criteria(SortKey::From, true), "(REVERSE FROM)");
+        assert_eq!(sort_criteria(SortKey::Subject, false), "(SUBJECT)");
+        assert_eq!(sort_criteria(SortKey::Subject, true), "(REVERSE SUBJECT)");
+    }
+
+    #[test]
+    fn sort_and_truncate_newest_first() {
+        let mut rows = vec![test_row(1, 100), test_row(2, 300), test_row(3, 200)];
+        sort_and_truncate(&mut rows, SortKey::Date, false, 0, Some(2));
+        let uids: Vec<u32> = rows.iter().map(|m| m.uid).collect();
+        assert_eq!(uids, vec![2, 3]);
+    }
+
+    #[test]
+    fn sort_and_truncate_oldest_first() {
+        let mut rows = vec![test_row(1, 100), test_row(2, 300), test_row(3, 200)];
+        sort_and_truncate(&mut rows, SortKey::Date, true, 0, Some(2));
+        let uids: Vec<u32> = rows.iter().map(|m| m.uid).collect();
+        assert_eq!(uids, vec![1, 3]);
+    }
+
+    #[test]
+    fn sort_and_truncate_offset_pages() {
+        let page = |offset, limit| {
+            let mut rows: Vec<MessageRow> = (1..=5)
+                .map(|uid| test_row(uid, i64::from(uid) * 100))
+                .collect();
+            sort_and_truncate(&mut rows, SortKey::Date, false, offset, limit);
+            rows.iter().map(|m| m.uid).collect::<Vec<u32>>()
+        };
+        assert_eq!(page(0, Some(2)), vec![5, 4]);
+        assert_eq!(page(2, Some(2)), vec![3, 2]);
+        assert_eq!(page(4, Some(2)), vec![1]);
+        assert_eq!(page(3, None), vec![2, 1]);
+        assert!(page(9, Some(2)).is_empty());
+    }
+
+    #[test]
+    fn window_slices_server_sorted_uids() {
+        let mut uids = vec![10, 20, 30, 40];
+        window(&mut uids, 1, Some(2));
+        assert_eq!(uids, vec![20, 30]);
+        let mut uids = vec![10, 20];
+        window(&mut uids, 5, None);
+        assert!(uids.is_empty());
+    }
+
+    fn sized_row(uid: u32, size: u32, from: &str, subject: &str) -> MessageRow {
+        MessageRow {
+            size,
+            from: from.to_string(),
+            subject: subject.to_string(),
+            ..test_row(uid, 0)
+        }
+    }
+
+    fn sorted_uids(key: SortKey, invert: bool) -> Vec<u32> {
+        let mut rows = vec![
+            sized_row(1, 500, "bob", "beta"),
+            sized_row(2, 9000, "Alice", "gamma"),
+            sized_row(3, 20, "carol", "Alpha"),
+        ];
+        sort_and_truncate(&mut rows, key, invert, 0, None);
+        rows.iter().map(|m| m.uid).collect()
+    }
+
+    #[test]
+    fn sort_and_truncate_by_size() {
+        assert_eq!(sorted_uids(SortKey::Size, false), vec![2, 1, 3]);
+        assert_eq!(sorted_uids(SortKey::Size, true), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn sort_and_truncate_by_from_and_subject_ignore_case() {
+        assert_eq!(sorted_uids(SortKey::From, false), vec![2, 1, 3]);
+        assert_eq!(sorted_uids(SortKey::From, true), vec![3, 1, 2]);
+        assert_eq!(sorted_uids(SortKey::Subject, false), vec![3, 1, 2]);
+        assert_eq!(sorted_uids(SortKey::Subject, true), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn sort_and_truncate_breaks_ties_by_uid() {
+        let mut rows = vec![test_row(4, 100), test_row(9, 100), test_row(2, 100)];
+        sort_and_truncate(&mut rows, SortKey::Date, false, 0, Some(2));
+        let uids: Vec<u32> = rows.iter().map(|m| m.uid).collect();
+        assert_eq!(uids, vec![9, 4]);
+
+        let mut rows = vec![test_row(4, 100), test_row(9, 100), test_row(2, 100)];
+        sort_and_truncate(&mut rows, SortKey::Date, true, 0, Some(2));
+        let uids: Vec<u32> = rows.iter().map(|m| m.uid).collect();
+        assert_eq!(uids, vec![2, 4]);
+    }
+
+    fn inbox(capabilities: &[&str]) -> FakeImap {
+        FakeImap::new(capabilities).folder(
+            "INBOX",
+            vec![
+                fake::message(
+                    1,
+                    "Tue, 02 Jan 2024 10:00:00 +0000",
+                    "b@x.example",
+                    "Second",
+                ),
+                fake::message(2, "Mon, 01 Jan 2024 10:00:00 +0000", "a@x.example", "First"),
+                fake::message(
+                    3,
+                    "Thu, 04 Jan 2024 10:00:00 +0000",
+                    "d@x.example",
+                    "Fourth",
+                ),
+                fake::message(4, "Wed, 03 Jan 2024 10:00:00 +0000", "c@x.example", "Third"),
+            ],
+        )
+    }
+
+    fn uids_of(rows: &[MessageRow]) -> Vec<u32> {
+        rows.iter().map(|m| m.uid).collect()
+    }
+
+    #[test]
+    fn search_fetches_in_uid_set_chunks() {
+        // Every other UID, so no ranges compress and the set must be split
+        let uids: Vec<u32> = (1..4000).step_by(2).collect();
+        let messages = uids
+            .iter()
+            .map(|&uid| fake::message(uid, "Mon, 01 Jan 2024 10:00:00 +0000", "a@x", "Hi"))
+            .collect();
+        let mut fake = FakeImap::new(&[]).folder("INBOX", messages);
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            ..Default::default()
+        };
+        let mut fetched = Vec::new();
+        let rows = search_with_progress(&mut fake, &criteria, |p| {
+            if let SearchProgress::Fetched { done, total } = p {
+                fetched.push((done, total));
+            }
+        })
+        .unwrap();
+        assert_eq!(rows.len(), uids.len());
+        let fetches = fake.sent("UID FETCH");
+        assert!(fetches.len() > 1);
+        assert_eq!(fetches.len(), build_uid_set(&uids).len());
+        // Zero up front, then once per chunk up to the total
+        assert_eq!(fetched.len(), fetches.len() + 1);
+        assert_eq!(fetched[0], (0, uids.len()));
+        assert_eq!(fetched.last(), Some(&(uids.len(), uids.len())));
+    }
+
+    #[test]
+    fn search_sorts_and_pages_without_server_sort() {
+        let mut fake = inbox(&[]);
+        let mut criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [3, 4]);
+        criteria.offset = 1;
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [4, 1]);
+        criteria.offset = 0;
+        criteria.invert_order = true;
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [2, 1]);
+        criteria.sort = SortKey::Subject;
+        criteria.invert_order = false;
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [2, 3]);
+        // All four were fetched each time and sorted here
+        assert!(fake
+            .sent("UID FETCH")
+            .iter()
+            .all(|c| c.starts_with("UID FETCH 1:4 ")));
+    }
+
+    #[test]
+    fn search_iter_orders_by_uid_without_server_sort() {
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            ..Default::default()
+        };
+        let iter_uids = |fake: &mut FakeImap| -> Vec<u32> {
+            search_iter(fake, &criteria)
+                .map(|r| r.unwrap().uid)
+                .collect()
+        };
+        // Same messages as `search`; only the server's SORT gives the same order
+        let mut fake = inbox(&[]);
+        assert_eq!(
+            uids_of(&search(&mut fake, &criteria).unwrap()),
+            [3, 4, 1, 2]
+        );
+        assert_eq!(iter_uids(&mut fake), [4, 3, 2, 1]);
+        let mut fake = inbox(&["SORT"]);
+        assert_eq!(iter_uids(&mut fake), [3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn search_iter_narrows_listed_uids_like_search() {
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            from: vec!["a@x".into()],
+            uids: Some(BTreeMap::from([("INBOX".to_string(), vec![1, 2])])),
+            ..Default::default()
+        };
+        let mut fake = inbox(&[]);
+        let rows: Vec<u32> = search_iter(&mut fake, &criteria)
+            .map(|r| r.unwrap().uid)
+            .collect();
+        assert_eq!(rows, [2]);
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [2]);
+    }
+
+    #[test]
+    fn search_pages_before_fetch_with_server_sort() {
+        let mut fake = inbox(&["SORT"]);
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(uids_of(&search(&mut fake, &criteria).unwrap()), [4, 1]);
+        assert_eq!(fake.sent("UID SORT"), ["UID SORT (REVERSE DATE) UTF-8 ALL"]);
+        let fetches = fake.sent("UID FETCH");
+        assert_eq!(fetches.len(), 1);
+        assert!(fetches[0].starts_with("UID FETCH 1,4 "), "{}", fetches[0]);
+    }
+
+    #[test]
+    fn all_folders_search_merges_and_skips_trash() {
+        let mut fake = inbox(&[])
+            .folder(
+                "Work",
+                vec![fake::message(
+                    7,
+                    "Fri, 05 Jan 2024 10:00:00 +0000",
+                    "w@x",
+                    "Newest",
+                )],
+            )
+            .folder(
+                "Trash",
+                vec![fake::message(
+                    9,
+                    "Sat, 06 Jan 2024 10:00:00 +0000",
+                    "t@x",
+                    "Gone",
+                )],
+            );
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            all_folders: true,
+            limit: Some(3),
+            ..Default::default()
+        };
+        let rows = search(&mut fake, &criteria).unwrap();
+        let got: Vec<(Option<&str>, u32)> =
+            rows.iter().map(|m| (m.folder.as_deref(), m.uid)).collect();
+        assert_eq!(
+            got,
+            [(Some("Work"), 7), (Some("INBOX"), 3), (Some("INBOX"), 4)]
+        );
+        assert!(fake.sent("SELECT \"Trash\"").is_empty());
+    }
+
+    #[test]
+    fn all_folders_search_skips_failing_folders_quietly() {
+        let warnings = || WARNINGS.with(|w| std::mem::take(&mut *w.borrow_mut()));
+        for quiet in [false, true] {
+            let mut fake = inbox(&[]).unselectable("Shared/a").unselectable("Shared/b");
+            let criteria = SearchCriteria {
+                folder: "INBOX".into(),
+                all_folders: true,
+                quiet_warnings: quiet,
+                ..Default::default()
+            };
+            warnings();
+            assert_eq!(search(&mut fake, &criteria).unwrap().len(), 4);
+            let printed = warnings();
+            if quiet {
+                assert_eq!(printed, ["Skipped 2 folder(s)."]);
+            } else {
+                assert_eq!(printed.len(), 2);
+                assert!(printed[0].starts_with("Warning: skipping folder 'Shared/a'"));
+                // The server's reply, not just the outer context
+                assert!(printed[0].contains("permission denied"), "{}", printed[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_search_skips_failing_folders_quietly() {
+        let warnings = || WARNINGS.with(|w| std::mem::take(&mut *w.borrow_mut()));
+        let mut fake = inbox(&[]).unselectable("Shared/a").unselectable("Shared/b");
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            all_folders: true,
+            quiet_warnings: true,
+            ..Default::default()
+        };
+        // No extra connections, so every folder is searched (and warned about) here
+        let connect = || Err(anyhow::anyhow!("no more connections").into());
+        warnings();
+        let found = search_parallel(&mut fake, &criteria, 2, connect, |_| {}).unwrap();
+        assert_eq!(found.len(), 4);
+        assert_eq!(warnings(), ["Skipped 2 folder(s)."]);
+    }
+
+    #[test]
+    fn search_reports_a_missing_folder() {
+        let mut fake = inbox(&[]);
+        let criteria = SearchCriteria {
+            folder: "INBX".into(),
+            ..Default::default()
+        };
+        let err = search(&mut fake, &criteria).err().unwrap();
+        assert!(matches!(
+            &err,
+            SlashmailError::FolderNotFound { suggestion: Some(s), .. } if s == "INBOX"
+        ));
+    }
+
+    #[test]
+    fn all_folders_limit_caps_fetches_per_folder_with_server_sort() {
+        let mut fake = inbox(&["SORT"]).folder(
+            "Work",
+            vec![
+                fake::message(7, "Fri, 05 Jan 2024 10:00:00 +0000", "w@x", "Newest"),
+                fake::message(8, "Sun, 31 Dec 2023 10:00:00 +0000", "w@x", "Oldest"),
+                fake::message(9, "Wed, 03 Jan 2024 09:00:00 +0000", "w@x", "Middle"),
+            ],
+        );
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            all_folders: true,
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let rows = search(&mut fake, &criteria).unwrap();
+        let got: Vec<(Option<&str>, u32)> =
+            rows.iter().map(|m| (m.folder.as_deref(), m.uid)).collect();
+        assert_eq!(got, [(Some("INBOX"), 3), (Some("INBOX"), 4)]);
+        // offset + limit = 3 UIDs per folder at most, one FETCH each
+        let fetched: Vec<usize> = fake
+            .sent("UID FETCH")
+            .iter()
+            .map(|c| uid_set_len(c.split(' ').nth(2).unwrap()))
+            .collect();
+        assert_eq!(fetched, [3, 3]);
+    }
+
+    #[test]
+    fn all_folders_subject_sort_is_not_capped_by_server_order() {
+        // The server sorts "Re: Alpha" first by base subject; the merge puts "Beta" first.
+        let mut fake = FakeImap::new(&["SORT"])
+            .folder(
+                "INBOX",
+                vec![
+                    fake::message(1, "Mon, 01 Jan 2024 10:00:00 +0000", "a@x", "Re: Alpha"),
+                    fake::message(2, "Tue, 02 Jan 2024 10:00:00 +0000", "b@x", "Beta"),
+                ],
+            )
+            .folder(
+                "Work",
+                vec![fake::message(
+                    3,
+                    "Wed, 03 Jan 2024 10:00:00 +0000",
+                    "c@x",
+                    "Zulu",
+                )],
+            );
+        let criteria = SearchCriteria {
+            folder: "INBOX".into(),
+            all_folders: true,
+            sort: SortKey::Subject,
+            limit: Some(1),
+            ..Default::default()
+        };
+        let rows = search(&mut fake, &criteria).unwrap();
+        let got: Vec<&str> = rows.iter().map(|m| m.subject.as_str()).collect();
+        assert_eq!(got, ["Beta"]);
+    }
 }
@@ -0,0 +1,131 @@
+use comfy_table::{presets::UTF8_FULL_CONDENSED, Cell, CellAlignment, Table};
+use std::collections::HashMap;
+
+use crate::display::{format_size, MessageRow};
+
+/// Message count and total size for one sender address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderStats {
+    pub address: String,
+    pub count: usize,
+    pub size: u64,
+}
+
+/// Aggregate `messages` by sender address (`MessageRow::from_address`), so display
+/// names don't split one sender into several rows.
+pub fn sender_stats(messages: &[MessageRow]) -> Vec<SenderStats> {
+    let mut by_sender: HashMap<&str, SenderStats> = HashMap::new();
+    for m in messages {
+        let entry = by_sender
+            .entry(m.from_address.as_str())
+            .or_insert_with(|| SenderStats {
+                address: m.from_address.clone(),
+                count: 0,
+                size: 0,
+            });
+        entry.count += 1;
+        entry.size += u64::from(m.size);
+    }
+    by_sender.into_values().collect()
+}
+
+/// The `n` senders with the most messages, ties broken by size, then address.
+pub fn top_by_count(stats: &[SenderStats], n: usize) -> Vec<SenderStats> {
+    let mut top = stats.to_vec();
+    top.sort_by(|a, b| {
+        (b.count, b.size)
+            .cmp(&(a.count, a.size))
+            .then_with(|| a.address.cmp(&b.address))
+    });
+    top.truncate(n);
+    top
+}
+
+/// The `n` senders with the largest total size, ties broken by count, then address.
+pub fn top_by_size(stats: &[SenderStats], n: usize) -> Vec<SenderStats> {
+    let mut top = stats.to_vec();
+    top.sort_by(|a, b| {
+        (b.size, b.count)
+            .cmp(&(a.size, a.count))
+            .then_with(|| a.address.cmp(&b.address))
+    });
+    top.truncate(n);
+    top
+}
+
+/// A table of `rows` with rank, sender, message count and total size.
+pub fn sender_table(rows: &[SenderStats]) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(vec!["#", "Sender", "Messages", "Size"]);
+    for (i, s) in rows.iter().enumerate() {
+        let sender = if s.address.is_empty() {
+            "(no sender)"
+        } else {
+            s.address.as_str()
+        };
+        table.add_row(vec![
+            Cell::new(i + 1),
+            Cell::new(sender),
+            Cell::new(s.count).set_alignment(CellAlignment::Right),
+            Cell::new(format_size(s.size)).set_alignment(CellAlignment::Right),
+        ]);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(from_address: &str, size: u32) -> MessageRow {
+        MessageRow {
+            uid: 1,
+            size,
+            from_address: from_address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn stats(address: &str, count: usize, size: u64) -> SenderStats {
+        SenderStats {
+            address: address.to_string(),
+            count,
+            size,
+        }
+    }
+
+    #[test]
+    fn sender_stats_aggregates_by_address() {
+        let messages = vec![row("a@x", 100), row("b@x", 5000), row("a@x", 300)];
+        let mut got = sender_stats(&messages);
+        got.sort_by(|a, b| a.address.cmp(&b.address));
+        assert_eq!(got, [stats("a@x", 2, 400), stats("b@x", 1, 5000)]);
+    }
+
+    #[test]
+    fn top_by_count_and_size_order_descending() {
+        let all = [
+            stats("a@x", 2, 400),
+            stats("b@x", 1, 5000),
+            stats("c@x", 2, 900),
+            stats("d@x", 1, 10),
+        ];
+        let by_count: Vec<String> = top_by_count(&all, 3)
+            .into_iter()
+            .map(|s| s.address)
+            .collect();
+        assert_eq!(by_count, ["c@x", "a@x", "b@x"]);
+        let by_size: Vec<String> = top_by_size(&all, 2)
+            .into_iter()
+            .map(|s| s.address)
+            .collect();
+        assert_eq!(by_size, ["b@x", "c@x"]);
+    }
+
+    #[test]
+    fn sender_table_labels_missing_sender() {
+        let table = sender_table(&[stats("", 1, 10)]).to_string();
+        assert!(table.contains("(no sender)"), "{table}");
+    }
+}
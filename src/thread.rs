@@ -0,0 +1,413 @@
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+
+use crate::connection::{ImapOps, ImapSession};
+use crate::display::{MessageRow, Ui};
+use crate::search::{self, SearchCriteria};
+use crate::timings::{self, Phase};
+
+/// Server-side threading algorithm (RFC 5256).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadAlgorithm {
+    References,
+    OrderedSubject,
+}
+
+impl ThreadAlgorithm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThreadAlgorithm::References => "REFERENCES",
+            ThreadAlgorithm::OrderedSubject => "ORDEREDSUBJECT",
+        }
+    }
+}
+
+/// A message in a conversation tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadNode {
+    /// UID of the message, or None for a parent the server knows of but did not match.
+    pub uid: Option<u32>,
+    pub children: Vec<ThreadNode>,
+}
+
+/// Pick the best THREAD algorithm the server advertises, preferring REFERENCES.
+pub fn detect_algorithm(session: &ImapSession) -> Option<ThreadAlgorithm> {
+    if session.has_capability("THREAD=REFERENCES") {
+        Some(ThreadAlgorithm::References)
+    } else if session.has_capability("THREAD=ORDEREDSUBJECT") {
+        Some(ThreadAlgorithm::OrderedSubject)
+    } else {
+        None
+    }
+}
+
+/// Parse a `* THREAD` response into conversation trees.
+///
+/// Handles both REFERENCES responses, which may nest and start with a missing parent
+/// (`((1)(2))`), and ORDEREDSUBJECT responses, where each thread is a root followed by
+/// its children (`(1 (2)(3))`).
+pub fn parse_thread_response(data: &[u8]) -> Result<Vec<ThreadNode>> {
+    let text = String::from_utf8_lossy(data);
+    let mut threads = Vec::new();
+
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("* THREAD") {
+            let mut parser = ThreadParser {
+                bytes: rest.as_bytes(),
+                pos: 0,
+            };
+            threads.extend(parser.parse_threads()?);
+        }
+        // Check for error in tagged response (format: "tag NO ..." or "tag BAD ...")
+        if !line.starts_with('*') {
+            let mut tokens = line.splitn(3, ' ');
+            if let (Some(_tag), Some(status)) = (tokens.next(), tokens.next()) {
+                if status == "NO" || status == "BAD" {
+                    bail!("THREAD command rejected by server: {line}");
+                }
+            }
+        }
+    }
+
+    Ok(threads)
+}
+
+struct ThreadParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ThreadParser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos] == b' ' {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn parse_threads(&mut self) -> Result<Vec<ThreadNode>> {
+        let mut threads = Vec::new();
+        while let Some(c) = self.peek() {
+            if c != b'(' {
+                bail!("Unexpected THREAD response format");
+            }
+            threads.push(self.parse_list()?);
+        }
+        Ok(threads)
+    }
+
+    /// thread-list = "(" (thread-members / thread-nested) ")"
+    fn parse_list(&mut self) -> Result<ThreadNode> {
+        self.pos += 1; // opening paren
+        let mut members = Vec::new();
+        let mut nested = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(b')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'(') => nested.push(self.parse_list()?),
+                Some(c) if c.is_ascii_digit() && nested.is_empty() => {
+                    let start = self.pos;
+                    while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+                        self.pos += 1;
+                    }
+                    let num = std::str::from_utf8(&self.bytes[start..self.pos])?;
+                    members.push(num.parse::<u32>()?);
+                }
+                _ => bail!("Unexpected THREAD response format"),
+            }
+        }
+
+        // Members form a parent→child chain; nested lists hang off the last member.
+        let mut node = ThreadNode {
+            uid: None,
+            children: nested,
+        };
+        for uid in members.into_iter().rev() {
+            node = if node.uid.is_none() {
+                ThreadNode {
+                    uid: Some(uid),
+                    children: node.children,
+                }
+            } else {
+                ThreadNode {
+                    uid: Some(uid),
+                    children: vec![node],
+                }
+            };
+        }
+        Ok(node)
+    }
+}
+
+/// Drop nodes whose UID is not in `keep`, promoting their children.
+fn prune(node: ThreadNode, keep: &HashSet<u32>) -> Vec<ThreadNode> {
+    let children: Vec<ThreadNode> = node
+        .children
+        .into_iter()
+        .flat_map(|c| prune(c, keep))
+        .collect();
+    match node.uid {
+        Some(uid) if keep.contains(&uid) => vec![ThreadNode {
+            uid: Some(uid),
+            children,
+        }],
+        None if children.len() > 1 => vec![ThreadNode {
+            uid: None,
+            children,
+        }],
+        _ => children,
+    }
+}
+
+/// Strip reply/forward prefixes so "Re: Fwd: Hello" and "hello" group together.
+fn normalize_subject(subject: &str) -> String {
+    let mut s = subject.trim();
+    loop {
+        let lower = s.to_lowercase();
+        let stripped = ["re:", "fwd:", "fw:"]
+            .iter()
+            .find(|p| lower.starts_with(**p))
+            .map(|p| s[p.len()..].trim_start());
+        match stripped {
+            Some(rest) => s = rest,
+            None => break,
+        }
+    }
+    s.to_lowercase()
+}
+
+/// Client-side fallback: group rows by normalized subject. The oldest message of
+/// each group is the root and the rest are its replies.
+pub fn group_by_subject(messages: &[MessageRow]) -> Vec<ThreadNode> {
+    let mut order: Vec<String> = Vec::new();
+    let mut groups: HashMap<String, Vec<&MessageRow>> = HashMap::new();
+    for msg in messages {
+        let key = normalize_subject(&msg.subject);
+        if !groups.contains_key(&key) {
+            order.push(key.clone());
+        }
+        groups.entry(key).or_default().push(msg);
+    }
+
+    order
+        .into_iter()
+        .filter_map(|key| groups.remove(&key))
+        .map(|mut rows| {
+            rows.sort_by_key(|m| m.timestamp);
+            let root = rows[0];
+            ThreadNode {
+                uid: Some(root.uid),
+                children: rows[1..]
+                    .iter()
+                    .map(|m| ThreadNode {
+                        uid: Some(m.uid),
+                        children: Vec::new(),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Latest timestamp of any message in the tree.
+fn latest_timestamp(node: &ThreadNode, by_uid: &HashMap<u32, &MessageRow>) -> i64 {
+    let own = node
+        .uid
+        .and_then(|u| by_uid.get(&u))
+        .map(|m| m.timestamp)
+        .unwrap_or(i64::MIN);
+    node.children
+        .iter()
+        .map(|c| latest_timestamp(c, by_uid))
+        .fold(own, i64::max)
+}
+
+/// Search a single folder and group the results into conversations, most recently
+/// active first. Uses server THREAD when available, else groups by subject (and says
+/// so on stderr unless `--quiet`).
+pub fn search_threads(
+    session: &mut ImapSession,
+    criteria: &SearchCriteria,
+    ui: Ui,
+) -> Result<(Vec<ThreadNode>, Vec<MessageRow>)> {
+    if criteria.all_folders {
+        bail!("Threading works on a single folder; use --folder instead of --all-folders");
+    }
+
+    let messages = search::search(session, criteria)?;
+    if messages.is_empty() {
+        return Ok((Vec::new(), messages));
+    }
+
+    let mut threads = match detect_algorithm(session) {
+        Some(algo) => {
+            // search() has already selected the folder
+            let query = search::build_query(criteria)?;
+            let cmd = format!(
+                "UID THREAD {} {} {query}",
+                algo.as_str(),
+                criteria.charset()
+            );
+            let response = {
+                let _t = timings::start(Phase::Search);
+                session.run_command_and_read_response(&cmd)
+            };
+            match response {
+                Ok(data) => {
+                    let keep: HashSet<u32> = messages.iter().map(|m| m.uid).collect();
+                    parse_thread_response(&data)?
+                        .into_iter()
+                        .flat_map(|t| prune(t, &keep))
+                        .collect()
+                }
+                Err(e) => {
+                    if !ui.quiet {
+                        eprintln!("THREAD failed, grouping by subject: {e:#}");
+                    }
+                    group_by_subject(&messages)
+                }
+            }
+        }
+        None => {
+            if !ui.quiet {
+                eprintln!("Server lacks THREAD; grouping by subject instead");
+            }
+            group_by_subject(&messages)
+        }
+    };
+
+    let by_uid: HashMap<u32, &MessageRow> = messages.iter().map(|m| (m.uid, m)).collect();
+    threads.sort_by_cached_key(|t| std::cmp::Reverse(latest_timestamp(t, &by_uid)));
+
+    Ok((threads, messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(uid: u32) -> ThreadNode {
+        ThreadNode {
+            uid: Some(uid),
+            children: Vec::new(),
+        }
+    }
+
+    fn node(uid: Option<u32>, children: Vec<ThreadNode>) -> ThreadNode {
+        ThreadNode { uid, children }
+    }
+
+    fn row(uid: u32, subject: &str, timestamp: i64) -> MessageRow {
+        MessageRow {
+            uid,
+            subject: subject.into(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_thread_response_references() {
+        // Example from RFC 5256 §4
+        let data = b"* THREAD (2)(3 6 (4 23)(44 7 96))\r\nA001 OK Thread completed\r\n";
+        let threads = parse_thread_response(data).unwrap();
+        assert_eq!(
+            threads,
+            vec![
+                leaf(2),
+                node(
+                    Some(3),
+                    vec![node(
+                        Some(6),
+                        vec![
+                            node(Some(4), vec![leaf(23)]),
+                            node(Some(44), vec![node(Some(7), vec![leaf(96)])]),
+                        ]
+                    )]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_thread_response_references_missing_parent() {
+        let data = b"* THREAD ((170)(179))(171)\r\nA001 OK Thread completed\r\n";
+        let threads = parse_thread_response(data).unwrap();
+        assert_eq!(
+            threads,
+            vec![node(None, vec![leaf(170), leaf(179)]), leaf(171)]
+        );
+    }
+
+    #[test]
+    fn parse_thread_response_ordered_subject() {
+        let data = b"* THREAD (166)(174 (175)(176))(179)\r\nA001 OK Thread completed\r\n";
+        let threads = parse_thread_response(data).unwrap();
+        assert_eq!(
+            threads,
+            vec![
+                leaf(166),
+                node(Some(174), vec![leaf(175), leaf(176)]),
+                leaf(179)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_thread_response_empty() {
+        let data = b"* THREAD\r\nA001 OK Thread completed\r\n";
+        assert!(parse_thread_response(data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_thread_response_server_error() {
+        let data = b"A001 BAD Unknown command\r\n";
+        assert!(parse_thread_response(data).is_err());
+    }
+
+    #[test]
+    fn parse_thread_response_malformed() {
+        assert!(parse_thread_response(b"* THREAD (1 2\r\n").is_err());
+        assert!(parse_thread_response(b"* THREAD x\r\n").is_err());
+    }
+
+    #[test]
+    fn prune_promotes_children_of_dropped_nodes() {
+        let tree = node(Some(1), vec![node(Some(2), vec![leaf(3)]), leaf(4)]);
+        let keep: HashSet<u32> = [1, 3].into_iter().collect();
+        assert_eq!(prune(tree, &keep), vec![node(Some(1), vec![leaf(3)])]);
+    }
+
+    #[test]
+    fn prune_collapses_single_child_placeholder() {
+        let tree = node(None, vec![leaf(1), leaf(2)]);
+        let keep: HashSet<u32> = [2].into_iter().collect();
+        assert_eq!(prune(tree, &keep), vec![leaf(2)]);
+    }
+
+    #[test]
+    fn normalize_subject_strips_prefixes() {
+        assert_eq!(normalize_subject("Re: Fwd: Hello"), "hello");
+        assert_eq!(normalize_subject("RE:hello"), "hello");
+        assert_eq!(normalize_subject("Hello"), "hello");
+    }
+
+    #[test]
+    fn group_by_subject_oldest_is_root() {
+        let rows = vec![
+            row(3, "Re: Launch", 300),
+            row(2, "Other", 200),
+            row(1, "Launch", 100),
+        ];
+        let threads = group_by_subject(&rows);
+        assert_eq!(threads, vec![node(Some(1), vec![leaf(3)]), leaf(2)]);
+    }
+}
@@ -0,0 +1,152 @@
+use comfy_table::{presets::UTF8_FULL_CONDENSED, Table};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A stage of a run reported by `--timings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Connect,
+    List,
+    Select,
+    Search,
+    Fetch,
+    Act,
+    Logout,
+}
+
+impl Phase {
+    const ALL: [Phase; 7] = [
+        Phase::Connect,
+        Phase::List,
+        Phase::Select,
+        Phase::Search,
+        Phase::Fetch,
+        Phase::Act,
+        Phase::Logout,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Connect => "connect",
+            Phase::List => "list",
+            Phase::Select => "select",
+            Phase::Search => "search",
+            Phase::Fetch => "fetch",
+            Phase::Act => "act",
+            Phase::Logout => "logout",
+        }
+    }
+}
+
+/// (calls, total time) per phase, indexed like `Phase::ALL`; `None` until `enable()`.
+static TOTALS: Mutex<Option<[(u32, Duration); 7]>> = Mutex::new(None);
+
+/// Start recording. Without this, `start` and `record` are no-ops.
+pub fn enable() {
+    *TOTALS.lock().unwrap() = Some(Default::default());
+}
+
+pub fn record(phase: Phase, elapsed: Duration) {
+    if let Some(totals) = TOTALS.lock().unwrap().as_mut() {
+        let slot = &mut totals[phase as usize];
+        slot.0 += 1;
+        slot.1 += elapsed;
+    }
+}
+
+/// Time until the returned guard is dropped and add it to `phase`.
+pub fn start(phase: Phase) -> Timer {
+    Timer {
+        phase,
+        start: Instant::now(),
+    }
+}
+
+pub struct Timer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record(self.phase, self.start.elapsed());
+    }
+}
+
+/// Print the recorded phases to stderr, with time not spent in any phase (parsing,
+/// prompts, local I/O) as "other". Does nothing unless `enable()` was called.
+pub fn print_report(total: Duration) {
+    if let Some(table) = report_table(total) {
+        eprintln!("{table}");
+    }
+}
+
+fn report_table(total: Duration) -> Option<Table> {
+    let totals = (*TOTALS.lock().unwrap())?;
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(vec!["Phase", "Calls", "Time"]);
+    let mut accounted = Duration::ZERO;
+    for phase in Phase::ALL {
+        let (calls, time) = totals[phase as usize];
+        if calls == 0 {
+            continue;
+        }
+        accounted += time;
+        table.add_row(vec![
+            phase.label().to_string(),
+            calls.to_string(),
+            format_duration(time),
+        ]);
+    }
+    table.add_row(vec![
+        "other".to_string(),
+        String::new(),
+        format_duration(total.saturating_sub(accounted)),
+    ]);
+    table.add_row(vec![
+        "total".to_string(),
+        String::new(),
+        format_duration(total),
+    ]);
+    Some(table)
+}
+
+fn format_duration(d: Duration) -> String {
+    if d >= Duration::from_secs(1) {
+        format!("{:.2}s", d.as_secs_f64())
+    } else {
+        format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_units() {
+        assert_eq!(format_duration(Duration::from_micros(1500)), "1.5ms");
+        assert_eq!(format_duration(Duration::from_millis(2340)), "2.34s");
+    }
+
+    #[test]
+    fn report_lists_used_phases_and_remainder() {
+        enable();
+        record(Phase::Search, Duration::from_millis(30));
+        record(Phase::Search, Duration::from_millis(20));
+        {
+            let _t = start(Phase::Fetch);
+        }
+        let table = report_table(Duration::from_millis(100))
+            .unwrap()
+            .to_string();
+        assert!(table.contains("search"));
+        assert!(table.contains("50.0ms"));
+        assert!(table.contains("fetch"));
+        assert!(!table.contains("connect"));
+        assert!(table.contains("other"));
+        assert!(table.contains("100.0ms"));
+    }
+}
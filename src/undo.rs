@@ -0,0 +1,464 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::connection::ImapSession;
+use crate::dedupe::normalize_message_id;
+use crate::delete::{self, ActOptions};
+use crate::display::{MessageRow, Ui};
+use crate::report::ActionReport;
+use crate::search::{self, SearchCriteria};
+
+/// Entries kept in the log; older ones are dropped when a new one is written.
+const MAX_ENTRIES: usize = 100;
+
+/// Message-IDs per SEARCH when looking the moved messages up again.
+const IDS_PER_SEARCH: usize = 20;
+
+/// One `move` or trash `delete`: where the messages went and where they came from.
+/// Messages are identified by Message-ID, since MOVE gives them new UIDs, and by
+/// their new UIDs as well when the server reported them.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub dest: String,
+    pub messages: Vec<LoggedMessage>,
+}
+
+/// The messages with one Message-ID moved out of one folder.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoggedMessage {
+    /// The folder the messages were moved out of.
+    pub folder: String,
+    /// Without angle brackets, as `normalize_message_id` returns it.
+    pub message_id: String,
+    /// How many were moved; `undo` moves back no more than this.
+    #[serde(default = "one")]
+    pub count: usize,
+    /// Their UIDs in the destination, from COPYUID; empty if the server sent none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dest_uids: Vec<u32>,
+}
+
+fn one() -> usize {
+    1
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("slashmail").join("undo.jsonl"))
+}
+
+/// Record in the log at `path` that `messages` were moved to `dest`; `new_uids` maps
+/// (source folder, UID) to the destination UID where the server reported it.
+/// Failing to write the log only warns: the move itself has already happened.
+pub(crate) fn record(
+    path: &Path,
+    dest: &str,
+    messages: &[MessageRow],
+    default_folder: &str,
+    new_uids: &HashMap<(&str, u32), u32>,
+) {
+    let logged = log_messages(messages, default_folder, new_uids);
+    let missing = messages.len() - logged.iter().map(|m| m.count).sum::<usize>();
+    if missing > 0 {
+        eprintln!("Note: {missing} message(s) without a Message-ID can't be undone.");
+    }
+    if logged.is_empty() {
+        return;
+    }
+    let entry = Entry {
+        time: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        dest: dest.to_string(),
+        messages: logged,
+    };
+    if let Err(e) = append(path, &entry) {
+        eprintln!("Warning: could not write undo log: {e:#}");
+    }
+}
+
+/// One `LoggedMessage` per (folder, Message-ID); messages without one are left out.
+fn log_messages(
+    messages: &[MessageRow],
+    default_folder: &str,
+    new_uids: &HashMap<(&str, u32), u32>,
+) -> Vec<LoggedMessage> {
+    let mut grouped: BTreeMap<(&str, String), LoggedMessage> = BTreeMap::new();
+    for m in messages {
+        let folder = m.folder.as_deref().unwrap_or(default_folder);
+        let Some(id) = m.message_id.as_deref().and_then(normalize_message_id) else {
+            continue;
+        };
+        let logged = grouped
+            .entry((folder, id.clone()))
+            .or_insert_with(|| LoggedMessage {
+                folder: folder.to_string(),
+                message_id: id,
+                count: 0,
+                dest_uids: Vec::new(),
+            });
+        logged.count += 1;
+        logged.dest_uids.extend(new_uids.get(&(folder, m.uid)));
+    }
+    grouped.into_values().collect()
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(text
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Replace the log with `lines`: written to a temporary file beside it and renamed
+/// over it, so an interrupted write leaves the old log intact.
+fn write_lines(path: &Path, lines: &[String]) -> Result<()> {
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, text).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Add `entry` at the end of the log, keeping at most `MAX_ENTRIES`.
+pub fn append(path: &Path, entry: &Entry) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut lines = read_lines(path)?;
+    lines.push(serde_json::to_string(entry)?);
+    let excess = lines.len().saturating_sub(MAX_ENTRIES);
+    write_lines(path, &lines[excess..])
+}
+
+/// The most recent entry, if any.
+pub fn last_entry(path: &Path) -> Result<Option<Entry>> {
+    let Some(line) = read_lines(path)?.pop() else {
+        return Ok(None);
+    };
+    let entry = serde_json::from_str(&line)
+        .with_context(|| format!("Malformed last entry in {}", path.display()))?;
+    Ok(Some(entry))
+}
+
+/// Remove the most recent entry once it has been undone.
+pub fn drop_last(path: &Path) -> Result<()> {
+    let mut lines = read_lines(path)?;
+    lines.pop();
+    write_lines(path, &lines)
+}
+
+/// `OR` chain matching any of `ids` in the Message-ID header.
+fn message_id_query(ids: &[&str]) -> String {
+    let mut ids = ids.iter().rev();
+    let last = ids.next().copied().unwrap_or_default();
+    let mut query = format!("HEADER Message-ID {}", search::imap_quote(last));
+    for id in ids {
+        query = format!(
+            "OR (HEADER Message-ID {}) ({query})",
+            search::imap_quote(id)
+        );
+    }
+    query
+}
+
+/// UIDs in `entry.dest` of the logged messages, grouped by the folder each came from.
+/// HEADER search matches substrings, so hits are kept only on an exact Message-ID.
+fn find_moved(session: &mut ImapSession, entry: &Entry) -> Result<BTreeMap<String, Vec<u32>>> {
+    let ids: HashSet<&str> = entry
+        .messages
+        .iter()
+        .map(|m| m.message_id.as_str())
+        .collect();
+    let ids: Vec<&str> = ids.into_iter().collect();
+
+    let mut hits: HashMap<String, Vec<u32>> = HashMap::new();
+    for chunk in ids.chunks(IDS_PER_SEARCH) {
+        let criteria = SearchCriteria {
+            folder: entry.dest.clone(),
+            raw: Some(message_id_query(chunk)),
+            ..Default::default()
+        };
+        for row in search::search(session, &criteria)? {
+            if let Some(id) = row.message_id.as_deref().and_then(normalize_message_id) {
+                hits.entry(id).or_default().push(row.uid);
+            }
+        }
+    }
+    Ok(pick_moved(entry, hits))
+}
+
+/// Assign the destination UIDs found per Message-ID to the logged messages: the
+/// UIDs COPYUID reported first, then the newest (highest) ones, and never more than
+/// the logged count, so copies that were already there stay put. When COPYUID
+/// covered every message, nothing else is taken.
+fn pick_moved(entry: &Entry, mut hits: HashMap<String, Vec<u32>>) -> BTreeMap<String, Vec<u32>> {
+    let mut by_folder: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for m in &entry.messages {
+        let Some(found) = hits.get_mut(&m.message_id) else {
+            continue;
+        };
+        let exact = m.dest_uids.len() >= m.count;
+        found.sort_unstable_by_key(|uid| (!m.dest_uids.contains(uid), std::cmp::Reverse(*uid)));
+        let picked: Vec<u32> = found
+            .iter()
+            .copied()
+            .filter(|uid| !exact || m.dest_uids.contains(uid))
+            .take(m.count)
+            .collect();
+        found.retain(|uid| !picked.contains(uid));
+        if !picked.is_empty() {
+            let uids = by_folder.entry(m.folder.clone()).or_default();
+            uids.extend(picked);
+            uids.sort_unstable();
+        }
+    }
+    by_folder
+}
+
+/// Move the messages of the last `move`/`delete` in the log at `path` back to the
+/// folders they came from, then drop that entry. Messages no longer in the
+/// destination are skipped.
+pub fn undo(
+    session: &mut ImapSession,
+    path: &Path,
+    opts: &ActOptions,
+    ui: Ui,
+) -> Result<ActionReport> {
+    let Some(entry) = last_entry(path)? else {
+        ui.info("Nothing to undo.");
+        return Ok(ActionReport::new("undo", &BTreeMap::new()));
+    };
+
+    let sp = ui.spinner(&format!("Looking for moved messages in {}...", entry.dest));
+    let by_folder = find_moved(session, &entry)?;
+    sp.finish_and_clear();
+
+    let mut report = ActionReport::new("undo", &by_folder);
+    let mut logged: BTreeMap<&str, usize> = BTreeMap::new();
+    for m in &entry.messages {
+        *logged.entry(m.folder.as_str()).or_default() += m.count;
+    }
+    for (folder, n) in logged {
+        let gone = n.saturating_sub(by_folder.get(folder).map_or(0, Vec::len));
+        if gone > 0 {
+            report.add_skipped(folder, gone);
+        }
+    }
+    if report.skipped > 0 {
+        eprintln!(
+            "{} message(s) are no longer in {}; skipping them.",
+            report.skipped, entry.dest
+        );
+    }
+    let found = report.matched;
+    if by_folder.is_empty() {
+        ui.info("None of the moved messages are left to move back.");
+        if !opts.dry_run {
+            drop_last(path)?;
+        }
+        return Ok(report);
+    }
+
+    for (folder, uids) in &by_folder {
+        println!("{} message(s): {} -> {folder}", uids.len(), entry.dest);
+    }
+
+    if opts.dry_run {
+        report.dry_run = true;
+        println!("Dry run: {found} message(s) would be moved back.");
+        return Ok(report);
+    }
+
+    if !opts.yes {
+        let confirm = inquire::Confirm::new(&format!(
+            "Move {found} message(s) back out of {}?",
+            entry.dest
+        ))
+        .with_default(false)
+        .prompt()
+        .context("Prompt failed")?;
+
+        if !confirm {
+            report.aborted = true;
+            println!("Aborted.");
+            return Ok(report);
+        }
+    }
+
+    let sp = ui.spinner(&format!("Moving back from {}...", entry.dest));
+    for (folder, uids) in &by_folder {
+        let mut moved = 0usize;
+        let result = delete::move_folder(
+            session,
+            &entry.dest,
+            uids,
+            folder,
+            false,
+            &mut moved,
+            &mut Vec::new(),
+        );
+        report.add_acted(folder, moved);
+        if let Err(e) = result {
+            sp.suspend(|| eprintln!("Warning: {e:#}"));
+            report.add_failed(folder, uids.len() - moved);
+        }
+    }
+    sp.finish_and_clear();
+
+    // Keep the entry after a failure: running undo again picks up what is still there
+    if report.failed == 0 {
+        drop_last(path)?;
+    }
+    ui.info(format_args!("Moved {} message(s) back.", report.acted));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(dest: &str, ids: &[(&str, &str)]) -> Entry {
+        Entry {
+            time: 1_700_000_000,
+            dest: dest.to_string(),
+            messages: ids
+                .iter()
+                .map(|(folder, id)| LoggedMessage {
+                    folder: folder.to_string(),
+                    message_id: id.to_string(),
+                    count: 1,
+                    dest_uids: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("slashmail-undo-{}-{name}", std::process::id()))
+            .join("undo.jsonl")
+    }
+
+    #[test]
+    fn append_then_last_and_drop() {
+        let path = temp_log("roundtrip");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(last_entry(&path).unwrap(), None);
+
+        let first = entry("Archive", &[("INBOX", "a@x")]);
+        let second = entry("Trash", &[("INBOX", "b@x"), ("Work", "c@x")]);
+        append(&path, &first).unwrap();
+        append(&path, &second).unwrap();
+        assert_eq!(last_entry(&path).unwrap(), Some(second));
+
+        drop_last(&path).unwrap();
+        assert_eq!(last_entry(&path).unwrap(), Some(first));
+        drop_last(&path).unwrap();
+        assert_eq!(last_entry(&path).unwrap(), None);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn append_keeps_the_newest_entries() {
+        let path = temp_log("cap");
+        let _ = std::fs::remove_file(&path);
+        for i in 0..MAX_ENTRIES + 5 {
+            append(&path, &entry(&format!("F{i}"), &[("INBOX", "a@x")])).unwrap();
+        }
+        assert_eq!(read_lines(&path).unwrap().len(), MAX_ENTRIES);
+        let last = last_entry(&path).unwrap().unwrap();
+        assert_eq!(last.dest, format!("F{}", MAX_ENTRIES + 4));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rewriting_the_log_leaves_no_temp_file() {
+        let path = temp_log("tmp");
+        let _ = std::fs::remove_file(&path);
+        append(&path, &entry("Archive", &[("INBOX", "a@x")])).unwrap();
+        drop_last(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        let dir = path.parent().unwrap();
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn entries_without_count_read_as_one() {
+        let line =
+            r#"{"time":1,"dest":"Trash","messages":[{"folder":"INBOX","message_id":"a@x"}]}"#;
+        let entry: Entry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.messages[0].count, 1);
+        assert!(entry.messages[0].dest_uids.is_empty());
+    }
+
+    fn row(uid: u32, folder: &str, message_id: Option<&str>) -> MessageRow {
+        MessageRow {
+            uid,
+            folder: Some(folder.to_string()),
+            message_id: message_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn log_messages_counts_per_folder_and_id() {
+        let rows = [
+            row(1, "INBOX", Some("<a@x>")),
+            row(2, "INBOX", Some("<a@x>")),
+            row(3, "Work", Some("<a@x>")),
+            row(4, "Work", None),
+        ];
+        let new_uids = HashMap::from([(("INBOX", 1), 10), (("INBOX", 2), 11)]);
+        let logged = log_messages(&rows, "INBOX", &new_uids);
+        assert_eq!(logged.len(), 2);
+        assert_eq!((logged[0].folder.as_str(), logged[0].count), ("INBOX", 2));
+        assert_eq!(logged[0].dest_uids, [10, 11]);
+        assert_eq!((logged[1].folder.as_str(), logged[1].count), ("Work", 1));
+        assert!(logged[1].dest_uids.is_empty());
+    }
+
+    #[test]
+    fn pick_moved_takes_only_the_logged_count() {
+        let mut logged = entry("Trash", &[("INBOX", "a@x"), ("Work", "b@x")]);
+        logged.messages[1].dest_uids = vec![7];
+        let hits = HashMap::from([
+            // An older copy (3) was in the trash before the move
+            ("a@x".to_string(), vec![3, 9]),
+            ("b@x".to_string(), vec![7, 8]),
+        ]);
+        let picked = pick_moved(&logged, hits);
+        assert_eq!(picked["INBOX"], [9]);
+        assert_eq!(picked["Work"], [7]);
+
+        // COPYUID named the moved copy and it is gone: leave the others alone
+        let hits = HashMap::from([("b@x".to_string(), vec![8])]);
+        assert!(!pick_moved(&logged, hits).contains_key("Work"));
+    }
+
+    #[test]
+    fn message_id_query_ors_every_id() {
+        assert_eq!(message_id_query(&["a@x"]), "HEADER Message-ID \"a@x\"");
+        assert_eq!(
+            message_id_query(&["a@x", "b@x", "c\"d@x"]),
+            "OR (HEADER Message-ID \"a@x\") (OR (HEADER Message-ID \"b@x\") (HEADER Message-ID \"c\\\"d@x\"))"
+        );
+    }
+}
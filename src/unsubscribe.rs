@@ -0,0 +1,181 @@
+use anyhow::{bail, Context, Result};
+
+use crate::display::MessageRow;
+
+/// The `mailto:` and `http(s):` URIs of a List-Unsubscribe header value
+/// (RFC 2369: `<mailto:leave@list.example>, <https://list.example/u/123>`), in the
+/// sender's order. Other schemes and text outside angle brackets are ignored.
+pub fn targets(value: &str) -> Vec<String> {
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('>').map(|(uri, _)| uri))
+        .map(|uri| uri.split_whitespace().collect::<String>())
+        .filter(|uri| {
+            let lower = uri.to_lowercase();
+            ["mailto:", "https://", "http://"]
+                .iter()
+                .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+        })
+        .collect()
+}
+
+/// Whether a List-Unsubscribe-Post value announces RFC 8058 one-click unsubscribe.
+pub fn is_one_click(post: &str) -> bool {
+    post.split_whitespace()
+        .collect::<String>()
+        .eq_ignore_ascii_case("List-Unsubscribe=One-Click")
+}
+
+/// The messages sharing one set of unsubscribe targets.
+#[derive(Debug, PartialEq)]
+pub struct MailingList {
+    /// Sender of the first (newest, in search order) message.
+    pub from: String,
+    pub targets: Vec<String>,
+    /// Set when any of the messages announced one-click unsubscribe.
+    pub one_click: bool,
+    pub messages: usize,
+}
+
+impl MailingList {
+    /// The target to open: a web link if there is one, since it usually unsubscribes
+    /// directly, otherwise the first `mailto:`.
+    pub fn preferred_target(&self) -> Option<&str> {
+        self.targets
+            .iter()
+            .find(|t| !t.to_lowercase().starts_with("mailto:"))
+            .or_else(|| self.targets.first())
+            .map(String::as_str)
+    }
+}
+
+/// Group `messages` with a List-Unsubscribe header by their targets, in order of
+/// first appearance. Messages without one are left out.
+pub fn mailing_lists(messages: &[MessageRow]) -> Vec<MailingList> {
+    let mut lists: Vec<MailingList> = Vec::new();
+    for msg in messages.iter().filter(|m| !m.unsubscribe.is_empty()) {
+        match lists.iter_mut().find(|l| l.targets == msg.unsubscribe) {
+            Some(list) => {
+                list.messages += 1;
+                list.one_click |= msg.unsubscribe_one_click;
+            }
+            None => lists.push(MailingList {
+                from: msg.from.clone(),
+                targets: msg.unsubscribe.clone(),
+                one_click: msg.unsubscribe_one_click,
+                messages: 1,
+            }),
+        }
+    }
+    lists
+}
+
+/// Open `target` with the desktop's handler for its scheme (browser or mail client).
+/// The URI comes from the sender, so it is passed as a plain argument, never through
+/// a shell.
+pub fn open(target: &str) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(windows)]
+    let mut command = {
+        let mut c = std::process::Command::new("rundll32");
+        c.arg("url.dll,FileProtocolHandler");
+        c
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = std::process::Command::new("xdg-open");
+
+    let status = command
+        .arg(target)
+        .status()
+        .with_context(|| format!("Failed to open '{target}'"))?;
+    if !status.success() {
+        bail!("Failed to open '{target}' ({status})");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(uid: u32, from: &str, unsubscribe: &[&str], one_click: bool) -> MessageRow {
+        MessageRow {
+            uid,
+            from: from.to_string(),
+            unsubscribe: unsubscribe.iter().map(|t| t.to_string()).collect(),
+            unsubscribe_one_click: one_click,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn targets_keeps_mailto_and_web_links() {
+        assert_eq!(
+            targets("<mailto:leave@list.example?subject=unsubscribe>, <https://list.example/u/1>"),
+            [
+                "mailto:leave@list.example?subject=unsubscribe",
+                "https://list.example/u/1"
+            ]
+        );
+        // Folded inside the brackets, other schemes and bare text dropped
+        assert_eq!(
+            targets("<https://list.example/\r\n u/2> (web), <ftp://x.example/>, mailto:bare@x"),
+            ["https://list.example/u/2"]
+        );
+        assert!(targets("").is_empty());
+        assert!(targets("<mailto:>").is_empty());
+    }
+
+    #[test]
+    fn one_click_needs_the_rfc_8058_value() {
+        assert!(is_one_click("List-Unsubscribe=One-Click"));
+        assert!(is_one_click(" list-unsubscribe=one-click "));
+        assert!(!is_one_click(""));
+        assert!(!is_one_click("List-Unsubscribe=Yes"));
+    }
+
+    #[test]
+    fn mailing_lists_group_by_targets() {
+        let messages = vec![
+            row(3, "News <news@a.example>", &["https://a.example/u"], false),
+            row(2, "Plain <p@b.example>", &[], false),
+            row(
+                1,
+                "News team <news@a.example>",
+                &["https://a.example/u"],
+                true,
+            ),
+            row(
+                4,
+                "Digest <d@c.example>",
+                &["mailto:d-leave@c.example"],
+                false,
+            ),
+        ];
+        let lists = mailing_lists(&messages);
+        assert_eq!(lists.len(), 2);
+        assert_eq!(lists[0].from, "News <news@a.example>");
+        assert_eq!(lists[0].messages, 2);
+        assert!(lists[0].one_click);
+        assert_eq!(lists[1].targets, ["mailto:d-leave@c.example"]);
+        assert!(!lists[1].one_click);
+    }
+
+    #[test]
+    fn preferred_target_favors_web_links() {
+        let list = |targets: &[&str]| MailingList {
+            from: String::new(),
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            one_click: false,
+            messages: 1,
+        };
+        assert_eq!(
+            list(&["mailto:x@y", "https://y/u"]).preferred_target(),
+            Some("https://y/u")
+        );
+        assert_eq!(list(&["mailto:x@y"]).preferred_target(), Some("mailto:x@y"));
+        assert_eq!(list(&[]).preferred_target(), None);
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{bail, Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::connection::{ImapOps, ImapSession};
+use crate::display::MessageRow;
+use crate::search::{self, SearchCriteria};
+
+/// Servers may drop a client that has been idle for 30 minutes (RFC 2177), so IDLE is
+/// re-issued before that.
+pub const DEFAULT_IDLE_SECS: u64 = 29 * 60;
+
+/// Watch `folder` with IDLE, calling `on_new` with the rows of messages that arrive
+/// (oldest first), until `stop` is set. IDLE is re-issued every `idle_timeout`.
+/// Only messages with a UID above the folder's UIDNEXT at the start are reported.
+pub fn watch(
+    session: &mut ImapSession,
+    folder: &str,
+    idle_timeout: Duration,
+    stop: &AtomicBool,
+    mut on_new: impl FnMut(&[MessageRow]),
+) -> Result<()> {
+    if !session.has_capability("IDLE") {
+        bail!("Server does not support IDLE (RFC 2177), which watch needs");
+    }
+    search::ensure_folder_exists(session, folder)?;
+    let mailbox = session
+        .select(folder)
+        .with_context(|| format!("Failed to select folder '{folder}'"))?;
+    let mut next_uid = match mailbox.uid_next {
+        Some(n) => n,
+        None => next_uid_after(&search::uid_search(session, "ALL", "UTF-8")?),
+    };
+
+    while !stop.load(Ordering::SeqCst) {
+        // A Ctrl-C shuts the socket down, so errors after `stop` are expected
+        match session.idle(idle_timeout) {
+            Ok(_) => {}
+            Err(_) if stop.load(Ordering::SeqCst) => break,
+            Err(e) => return Err(e).context("IDLE failed"),
+        }
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let rows = match search::search(session, &arrivals_since(folder, next_uid)) {
+            Err(_) if stop.load(Ordering::SeqCst) => break,
+            rows => rows?,
+        };
+        // `UID n:*` also matches the highest UID when it is below n
+        let rows: Vec<MessageRow> = rows.into_iter().filter(|m| m.uid >= next_uid).collect();
+        if !rows.is_empty() {
+            next_uid = next_uid_after(&rows.iter().map(|m| m.uid).collect::<Vec<_>>());
+            on_new(&rows);
+        }
+    }
+    Ok(())
+}
+
+/// Messages in `folder` with UID `next_uid` or above, oldest first.
+fn arrivals_since(folder: &str, next_uid: u32) -> SearchCriteria {
+    SearchCriteria {
+        folder: folder.to_string(),
+        raw: Some(format!("UID {next_uid}:*")),
+        invert_order: true,
+        ..Default::default()
+    }
+}
+
+fn next_uid_after(uids: &[u32]) -> u32 {
+    uids.iter().max().map_or(1, |&max| max.saturating_add(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_uid_after_highest() {
+        assert_eq!(next_uid_after(&[]), 1);
+        assert_eq!(next_uid_after(&[7, 3, 12]), 13);
+    }
+
+    #[test]
+    fn arrivals_since_queries_uid_range_oldest_first() {
+        let criteria = arrivals_since("INBOX", 42);
+        assert_eq!(criteria.raw.as_deref(), Some("UID 42:*"));
+        assert!(criteria.invert_order);
+        assert_eq!(search::build_query(&criteria).unwrap(), "UID 42:*");
+    }
+}
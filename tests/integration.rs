@@ -1,6 +1,6 @@
 #![cfg(feature = "integration-tests")]
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::thread;
 use std::time::Duration;
 
@@ -8,10 +8,19 @@ use lettre::message::header::ContentType;
 use lettre::transport::smtp::client::Tls;
 use lettre::{Message, SmtpTransport, Transport};
 
-use slashmail::connection::{self, ImapSession};
-use slashmail::delete;
+use slashmail::append;
+use slashmail::attachments;
+use slashmail::connection::{self, ImapOps, ImapSession};
+use slashmail::dedupe;
+use slashmail::delete::{self, ActOptions};
+use slashmail::display::Ui;
 use slashmail::export;
+use slashmail::folder;
+use slashmail::read;
 use slashmail::search::{self, SearchCriteria};
+use slashmail::stats;
+use slashmail::undo;
+use slashmail::watch;
 
 static COUNTER: AtomicU32 = AtomicU32::new(0);
 
@@ -83,15 +92,42 @@ fn send_email_with_cc(from: &str, to: &str, cc: &str, subject: &str, body: &str)
 fn imap_connect(user: &str) -> ImapSession {
     // GreenMail auto-creates accounts; login with full email, password = email
     let email = user_email(user);
-    connection::connect("127.0.0.1", imap_port(), false, &email, &email).unwrap()
+    connection::connect(
+        "127.0.0.1",
+        imap_port(),
+        &email,
+        &email,
+        &connection::ConnectOptions::default(),
+    )
+    .unwrap()
+}
+
+/// APPEND a message straight into `folder` with an explicit INTERNALDATE (RFC 2822 `date`),
+/// since mail delivered over SMTP is always dated now.
+fn append_dated(user: &str, folder: &str, subject: &str, date: &str) {
+    let email = user_email(user);
+    let stream = std::net::TcpStream::connect(("127.0.0.1", imap_port())).unwrap();
+    let mut client = imap::Client::new(stream);
+    client.read_greeting().unwrap();
+    let mut session = client.login(&email, &email).map_err(|e| e.0).unwrap();
+    let message = format!(
+        "From: sender@localhost\r\nTo: {email}\r\nSubject: {subject}\r\nDate: {date}\r\n\r\nbody\r\n"
+    );
+    let internal_date = chrono::DateTime::parse_from_rfc2822(date).unwrap();
+    session
+        .append_with_flags_and_date(folder, message, &[], internal_date)
+        .unwrap();
+    session.logout().unwrap();
 }
 
 fn default_criteria(folder: &str) -> SearchCriteria {
     SearchCriteria {
         folder: folder.to_string(),
         all_folders: false,
-        subject: None,
-        from: None,
+        include_skipped: false,
+        skip_folders: Vec::new(),
+        subject: Vec::new(),
+        from: Vec::new(),
         to: None,
         cc: None,
         body: None,
@@ -106,6 +142,21 @@ fn default_criteria(folder: &str) -> SearchCriteria {
         unflagged: false,
         answered: false,
         draft: false,
+        invert_order: false,
+        charset: None,
+        quiet_warnings: false,
+        headers: Vec::new(),
+        unanswered: false,
+        not_subject: None,
+        not_from: None,
+        on: None,
+        sent: false,
+        raw: None,
+        gmail_raw: None,
+        label: None,
+        sort: search::SortKey::Date,
+        offset: 0,
+        uids: None,
         limit: None,
     }
 }
@@ -137,6 +188,31 @@ fn connect_and_logout() {
     session.logout().unwrap();
 }
 
+#[test]
+fn capabilities_cached_on_connect() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    assert!(session.has_capability("IMAP4rev1"));
+    let cached: Vec<String> = session
+        .capabilities()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    session.refresh_capabilities().unwrap();
+    assert_eq!(session.capabilities(), cached);
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn noop_on_live_session() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    session.noop().unwrap();
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_empty_mailbox() {
     let user = unique_user();
@@ -176,7 +252,7 @@ fn search_by_subject() {
 
     let mut session = imap_connect(&user);
     let mut criteria = default_criteria("INBOX");
-    criteria.subject = Some("Report".to_string());
+    criteria.subject = vec!["Report".to_string()];
     let results = search::search(&mut session, &criteria).unwrap();
 
     assert_eq!(results.len(), 2);
@@ -184,6 +260,21 @@ fn search_by_subject() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_decodes_non_ascii_subject() {
+    let user = unique_user();
+    // lettre sends non-ASCII subjects as RFC 2047 encoded-words
+    send_email(&user, "Réunion équipe – ordre du jour", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let results = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].subject, "Réunion équipe – ordre du jour");
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_by_from() {
     let user = unique_user();
@@ -193,7 +284,7 @@ fn search_by_from() {
 
     let mut session = imap_connect(&user);
     let mut criteria = default_criteria("INBOX");
-    criteria.from = Some("alice@localhost".to_string());
+    criteria.from = vec!["alice@localhost".to_string()];
     let results = search::search(&mut session, &criteria).unwrap();
 
     assert_eq!(results.len(), 1);
@@ -202,6 +293,54 @@ fn search_by_from() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_by_several_senders() {
+    let user = unique_user();
+    send_email_from("alice@localhost", &user, "From Alice", "body");
+    send_email_from("bob@localhost", &user, "From Bob", "body");
+    send_email_from("carol@localhost", &user, "From Carol", "body");
+    send_email_from("dave@localhost", &user, "From Dave", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.from = vec![
+        "alice@".to_string(),
+        "bob@".to_string(),
+        "carol@".to_string(),
+    ];
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|r| !r.from.contains("dave")));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_excludes_not_from_and_not_subject() {
+    let user = unique_user();
+    send_email_from("alice@localhost", &user, "Invoice March", "body");
+    send_email_from("billing@localhost", &user, "Invoice April", "body");
+    send_email_from("alice@localhost", &user, "Lunch", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.subject = vec!["Invoice".to_string()];
+    criteria.not_from = Some("billing@".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("March"));
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.not_subject = Some("Invoice".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("Lunch"));
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_by_to() {
     let user = unique_user();
@@ -313,6 +452,38 @@ fn search_by_seen() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_by_answered_and_unanswered() {
+    let user = unique_user();
+    send_email(&user, "Replied msg", "body");
+    send_email(&user, "Pending msg", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.select("INBOX").unwrap();
+
+    let mut all_uids: Vec<u32> = session.uid_search("ALL").unwrap().into_iter().collect();
+    all_uids.sort();
+    assert_eq!(all_uids.len(), 2);
+    session
+        .uid_store(&all_uids[0].to_string(), "+FLAGS.SILENT (\\Answered)")
+        .unwrap();
+
+    let mut answered = default_criteria("INBOX");
+    answered.answered = true;
+    let results = search::search(&mut session, &answered).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].uid, all_uids[0]);
+
+    let mut unanswered = default_criteria("INBOX");
+    unanswered.unanswered = true;
+    let results = search::search(&mut session, &unanswered).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].uid, all_uids[1]);
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_with_limit() {
     let user = unique_user();
@@ -331,6 +502,101 @@ fn search_with_limit() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_iter_streams_rows_with_offset_and_limit() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    for i in 0..5 {
+        let body = format!("From: a@localhost\r\nSubject: Stream {i}\r\n\r\nbody\r\n");
+        session.append("INBOX", &[], body.as_bytes(), None).unwrap();
+    }
+
+    let criteria = default_criteria("INBOX");
+    let all: Vec<_> = search::search_iter(&mut session, &criteria)
+        .map(|row| row.unwrap().subject)
+        .collect();
+    let mut sorted = all.clone();
+    sorted.sort();
+    assert_eq!(
+        sorted,
+        ["Stream 0", "Stream 1", "Stream 2", "Stream 3", "Stream 4"]
+    );
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.offset = 1;
+    criteria.limit = Some(2);
+    let page: Vec<_> = search::search_iter(&mut session, &criteria)
+        .map(|row| row.unwrap().subject)
+        .collect();
+    assert_eq!(page, all[1..3]);
+
+    let missing = default_criteria("NoSuchFolder");
+    let results: Vec<_> = search::search_iter(&mut session, &missing).collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_reverse_returns_oldest_first() {
+    let user = unique_user();
+    for i in 0..3 {
+        send_email(&user, &format!("Message {i}"), "body");
+        // Date headers have one-second resolution; keep them distinct.
+        thread::sleep(Duration::from_millis(1100));
+    }
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.invert_order = true;
+    criteria.limit = Some(2);
+    let results = search::search(&mut session, &criteria).unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert!(results[0].subject.contains("Message 0"));
+    assert!(results[1].subject.contains("Message 1"));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_sort_by_size_and_subject() {
+    let user = unique_user();
+    send_email(&user, "banana", &"x".repeat(5_000));
+    send_email(&user, "Apple", &"x".repeat(20_000));
+    send_email(&user, "cherry", "tiny");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let subjects = |session: &mut ImapSession, sort, invert| {
+        let mut criteria = default_criteria("INBOX");
+        criteria.sort = sort;
+        criteria.invert_order = invert;
+        search::search(session, &criteria)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.subject)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        subjects(&mut session, search::SortKey::Size, false),
+        ["Apple", "banana", "cherry"]
+    );
+    assert_eq!(
+        subjects(&mut session, search::SortKey::Size, true),
+        ["cherry", "banana", "Apple"]
+    );
+    assert_eq!(
+        subjects(&mut session, search::SortKey::Subject, false),
+        ["Apple", "banana", "cherry"]
+    );
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_by_size() {
     let user = unique_user();
@@ -351,6 +617,95 @@ fn search_by_size() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_on_exact_day() {
+    let user = unique_user();
+    append_dated(
+        &user,
+        "INBOX",
+        "Day before",
+        "Sat, 8 Mar 2025 23:00:00 +0000",
+    );
+    append_dated(
+        &user,
+        "INBOX",
+        "On the day",
+        "Sun, 9 Mar 2025 12:00:00 +0000",
+    );
+    append_dated(
+        &user,
+        "INBOX",
+        "Day after",
+        "Mon, 10 Mar 2025 01:00:00 +0000",
+    );
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.on = Some("2025-03-09".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("On the day"));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_by_sent_date_uses_date_header() {
+    let user = unique_user();
+    // Delivered now, but the Date: header claims January 2020
+    let email = Message::builder()
+        .from("sender@localhost".parse().unwrap())
+        .to(user_email(&user).parse().unwrap())
+        .subject("Delayed")
+        .date(std::time::UNIX_EPOCH + Duration::from_secs(1_578_304_800))
+        .header(ContentType::TEXT_PLAIN)
+        .body("body".to_string())
+        .unwrap();
+    SmtpTransport::builder_dangerous("127.0.0.1")
+        .port(smtp_port())
+        .tls(Tls::None)
+        .build()
+        .send(&email)
+        .unwrap();
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.before = Some("2021-01-01".to_string());
+    assert!(search::search(&mut session, &criteria).unwrap().is_empty());
+
+    criteria.sent = true;
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("Delayed"));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_by_size_range() {
+    let user = unique_user();
+    send_email(&user, "Small msg", "tiny");
+    send_email(&user, "Medium msg", &"x".repeat(10_000));
+    send_email(&user, "Huge msg", &"x".repeat(200_000));
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.smaller = Some("5K".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("Small msg"));
+
+    criteria.larger = Some("5K".to_string());
+    criteria.smaller = Some("100K".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("Medium msg"));
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_by_date_range() {
     let user = unique_user();
@@ -417,7 +772,17 @@ fn delete_moves_to_trash() {
     session.create("Trash").unwrap();
 
     let criteria = default_criteria("INBOX");
-    delete::delete(&mut session, &criteria, "Trash", true, false).unwrap();
+    delete::delete(
+        &mut session,
+        &criteria,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
 
     // Verify INBOX is empty
     let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
@@ -440,7 +805,18 @@ fn delete_dry_run() {
     let mut session = imap_connect(&user);
 
     let criteria = default_criteria("INBOX");
-    delete::delete(&mut session, &criteria, "Trash", true, true).unwrap();
+    delete::delete(
+        &mut session,
+        &criteria,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            dry_run: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
 
     // Messages should still be in INBOX
     let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
@@ -463,7 +839,17 @@ fn move_to_folder() {
     session.create("Archive").unwrap();
 
     let criteria = default_criteria("INBOX");
-    delete::search_and_move(&mut session, &criteria, "Archive", true, false).unwrap();
+    delete::search_and_move(
+        &mut session,
+        &criteria,
+        "Archive",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
 
     // Verify INBOX is empty
     let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
@@ -476,6 +862,112 @@ fn move_to_folder() {
     session.logout().unwrap();
 }
 
+#[test]
+fn undo_moves_logged_messages_back() {
+    let user = unique_user();
+    send_email(&user, "Undo me", "body");
+    send_email(&user, "Stay archived", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Archive").unwrap();
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    let undo_me = inbox.iter().find(|m| m.subject == "Undo me").unwrap();
+    let message_id = dedupe::normalize_message_id(undo_me.message_id.as_deref().unwrap()).unwrap();
+
+    let subject = |s: &str| SearchCriteria {
+        subject: vec![s.to_string()],
+        ..default_criteria("INBOX")
+    };
+    // Archived before, without the log: undo must leave it where it is
+    let unlogged = ActOptions {
+        yes: true,
+        ..Default::default()
+    };
+    delete::search_and_move(
+        &mut session,
+        &subject("Stay archived"),
+        "Archive",
+        &unlogged,
+        Ui::default(),
+    )
+    .unwrap();
+
+    let log = std::env::temp_dir().join(format!("slashmail-undo-it-{user}.jsonl"));
+    let _ = std::fs::remove_file(&log);
+    let opts = ActOptions {
+        yes: true,
+        undo_log: Some(log.clone()),
+        ..Default::default()
+    };
+    delete::search_and_move(
+        &mut session,
+        &subject("Undo me"),
+        "Archive",
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+    let entry = undo::last_entry(&log).unwrap().unwrap();
+    assert_eq!(entry.messages.len(), 1);
+    assert_eq!(entry.messages[0].message_id, message_id);
+    assert_eq!(entry.messages[0].count, 1);
+
+    let report = undo::undo(&mut session, &log, &opts, Ui::default()).unwrap();
+    assert_eq!(report.acted, 1);
+    assert!(undo::last_entry(&log).unwrap().is_none());
+
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].subject, "Undo me");
+    let archive = search::search(&mut session, &default_criteria("Archive")).unwrap();
+    assert_eq!(archive.len(), 1);
+    assert_eq!(archive[0].subject, "Stay archived");
+
+    std::fs::remove_file(&log).unwrap();
+    session.logout().unwrap();
+}
+
+#[test]
+fn move_listed_uids_only() {
+    let user = unique_user();
+    for subject in ["Listed one", "Not listed", "Listed two"] {
+        send_email(&user, subject, "body");
+    }
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Archive").unwrap();
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    let listed: Vec<String> = inbox
+        .iter()
+        .filter(|m| m.subject.starts_with("Listed"))
+        .map(|m| m.uid.to_string())
+        .collect();
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.uids = Some(search::parse_uid_list(&listed.join("\n"), "INBOX").unwrap());
+    delete::search_and_move(
+        &mut session,
+        &criteria,
+        "Archive",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
+
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].subject, "Not listed");
+    let archive = search::search(&mut session, &default_criteria("Archive")).unwrap();
+    assert_eq!(archive.len(), 2);
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn count_via_uid_search() {
     let user = unique_user();
@@ -538,19 +1030,26 @@ fn export_creates_eml_files() {
     assert_eq!(messages.len(), 1);
 
     let temp_dir = std::env::temp_dir().join(format!("slashmail_export_{user}"));
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", &temp_dir, false).unwrap();
-
-    assert_eq!(exported, 1);
-    assert_eq!(skipped, 0);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(report.acted, 1);
+    assert_eq!(report.skipped, 0);
 
     // Verify .eml file exists and contains expected content
     let entries: Vec<_> = std::fs::read_dir(&temp_dir)
         .unwrap()
         .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "eml"))
         .collect();
     assert_eq!(entries.len(), 1);
-    assert!(entries[0].path().extension().unwrap() == "eml");
 
     let content = std::fs::read_to_string(entries[0].path()).unwrap();
     assert!(content.contains("Export Test"));
@@ -560,6 +1059,73 @@ fn export_creates_eml_files() {
     session.logout().unwrap();
 }
 
+#[test]
+fn download_attachments_saves_matching_parts() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    let with_parts = "From: a@localhost\r\nSubject: Invoice\r\nMIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+--b\r\nContent-Type: text/plain\r\n\r\nSee attached.\r\n\
+--b\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"invoice.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQ=\r\n\
+--b\r\nContent-Type: text/csv\r\nContent-Disposition: attachment; filename=\"items.csv\"\r\n\r\na,b\r\n\
+--b--\r\n";
+    session
+        .append("INBOX", &[], with_parts.as_bytes(), None)
+        .unwrap();
+    session
+        .append(
+            "INBOX",
+            &[],
+            b"From: a@localhost\r\nSubject: Plain\r\n\r\nno files\r\n",
+            None,
+        )
+        .unwrap();
+
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(messages.len(), 2);
+    let uid = messages
+        .iter()
+        .find(|m| m.subject == "Invoice")
+        .unwrap()
+        .uid;
+
+    let temp_dir = std::env::temp_dir().join(format!("slashmail_attachments_{user}"));
+    let filter = attachments::parse_name_filter("*.pdf").unwrap();
+    let (report, summary) = attachments::download(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        Some(&filter),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(report.acted, 1);
+    assert_eq!(report.skipped, 1);
+    assert_eq!(summary.saved, 1);
+    assert_eq!(summary.without, 1);
+    let saved = std::fs::read(temp_dir.join(format!("{uid}-invoice.pdf"))).unwrap();
+    assert_eq!(saved, b"%PDF-1.4");
+    assert!(!temp_dir.join(format!("{uid}-items.csv")).exists());
+
+    // A second run leaves the existing file alone
+    let (_, summary) = attachments::download(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        Some(&filter),
+        false,
+    )
+    .unwrap();
+    assert_eq!((summary.saved, summary.existing), (0, 1));
+
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    session.logout().unwrap();
+}
+
 #[test]
 fn export_skips_existing_without_force() {
     let user = unique_user();
@@ -573,15 +1139,29 @@ fn export_skips_existing_without_force() {
     let temp_dir = std::env::temp_dir().join(format!("slashmail_skip_{user}"));
 
     // First export
-    let (exported, _) =
-        export::export_messages(&mut session, &messages, "INBOX", &temp_dir, false).unwrap();
-    assert_eq!(exported, 1);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 1);
 
     // Second export without force — should skip
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", &temp_dir, false).unwrap();
-    assert_eq!(exported, 0);
-    assert_eq!(skipped, 1);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 0);
+    assert_eq!(report.skipped, 1);
 
     let _ = std::fs::remove_dir_all(&temp_dir);
     session.logout().unwrap();
@@ -600,13 +1180,28 @@ fn export_force_overwrites() {
     let temp_dir = std::env::temp_dir().join(format!("slashmail_force_{user}"));
 
     // First export
-    export::export_messages(&mut session, &messages, "INBOX", &temp_dir, false).unwrap();
+    export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
 
     // Second export with force — should overwrite
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", &temp_dir, true).unwrap();
-    assert_eq!(exported, 1);
-    assert_eq!(skipped, 0);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        true,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 1);
+    assert_eq!(report.skipped, 0);
 
     let _ = std::fs::remove_dir_all(&temp_dir);
     session.logout().unwrap();
@@ -641,27 +1236,35 @@ fn export_multiple_folders_uid_collision() {
 
     let temp_dir = std::env::temp_dir().join(format!("slashmail_multi_{user}"));
 
-    // Export all — both messages should be exported even if UIDs collide
-    let (exported, skipped) =
-        export::export_messages(&mut session, &all_messages, "INBOX", &temp_dir, false).unwrap();
-
-    assert_eq!(
-        exported + skipped,
-        2,
-        "All messages should be accounted for"
-    );
-
-    // Count actual .eml files on disk — folder-prefixed filenames prevent UID collisions
-    let entries: Vec<_> = std::fs::read_dir(&temp_dir)
+    // Export all — both messages are written even though their UIDs collide
+    let report = export::export_messages(
+        &mut session,
+        &all_messages,
+        "INBOX",
+        &temp_dir,
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(report.acted, 2, "Both messages should be exported");
+    assert_eq!(report.skipped, 0);
+
+    // Folder-prefixed filenames keep colliding UIDs apart on disk
+    let mut names: Vec<String> = std::fs::read_dir(&temp_dir)
         .unwrap()
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "eml"))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".eml"))
         .collect();
+    names.sort();
     assert_eq!(
-        entries.len(),
+        names.len(),
         2,
         "Both messages should exist as separate files"
     );
+    assert!(names[0].starts_with("Archive_"), "{names:?}");
+    assert!(names[1].starts_with("INBOX_"), "{names:?}");
     let _ = std::fs::remove_dir_all(&temp_dir);
     session.logout().unwrap();
 }
@@ -677,7 +1280,16 @@ fn move_to_nonexistent_folder_fails() {
     let mut session = imap_connect(&user);
     let criteria = default_criteria("INBOX");
 
-    let result = delete::search_and_move(&mut session, &criteria, "NonExistentFolder", true, false);
+    let result = delete::search_and_move(
+        &mut session,
+        &criteria,
+        "NonExistentFolder",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    );
     assert!(result.is_err());
     let err_msg = format!("{}", result.unwrap_err());
     assert!(
@@ -693,30 +1305,100 @@ fn move_to_nonexistent_folder_fails() {
 }
 
 #[test]
-fn delete_to_nonexistent_trash_fails() {
+fn move_with_create_makes_missing_folder() {
     let user = unique_user();
-    send_email(&user, "Delete fail test", "body");
+    send_email(&user, "Move create test", "body");
     sleep_for_delivery();
 
     let mut session = imap_connect(&user);
     let criteria = default_criteria("INBOX");
-
-    // Don't create Trash folder — should fail
-    let result = delete::delete(&mut session, &criteria, "Trash", true, false);
-    assert!(result.is_err());
-
-    // Messages should still be in INBOX
-    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
-    assert_eq!(inbox.len(), 1, "Message should still be in INBOX");
+    let opts = ActOptions {
+        yes: true,
+        create: true,
+        ..Default::default()
+    };
+
+    delete::search_and_move(&mut session, &criteria, "Archive", &opts, Ui::default()).unwrap();
+    let archived = search::search(&mut session, &default_criteria("Archive")).unwrap();
+    assert_eq!(archived.len(), 1);
+
+    // The folder exists now; --create must not fail on a second run
+    send_email(&user, "Move create test 2", "body");
+    sleep_for_delivery();
+    delete::search_and_move(&mut session, &criteria, "Archive", &opts, Ui::default()).unwrap();
+    let archived = search::search(&mut session, &default_criteria("Archive")).unwrap();
+    assert_eq!(archived.len(), 2);
 
     session.logout().unwrap();
 }
 
 #[test]
-fn ensure_folder_exists_returns_error_for_missing_folder() {
+fn ensure_or_create_folder_tolerates_existing() {
     let user = unique_user();
-    send_email(&user, "Folder check test", "body");
-    sleep_for_delivery();
+    let mut session = imap_connect(&user);
+
+    assert!(search::ensure_or_create_folder(&mut session, "Trash").unwrap());
+    assert!(!search::ensure_or_create_folder(&mut session, "Trash").unwrap());
+    search::ensure_folder_exists(&mut session, "Trash").unwrap();
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn folder_create_rename_delete() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+
+    folder::create_folder(&mut session, "Projects").unwrap();
+    assert!(folder::create_folder(&mut session, "Projects").is_err());
+
+    folder::rename_folder(&mut session, "Projects", "Archive").unwrap();
+    assert!(search::ensure_folder_exists(&mut session, "Projects").is_err());
+    search::ensure_folder_exists(&mut session, "Archive").unwrap();
+    assert!(folder::rename_folder(&mut session, "Missing", "Other").is_err());
+
+    folder::delete_folder(&mut session, "Archive").unwrap();
+    assert!(search::ensure_folder_exists(&mut session, "Archive").is_err());
+    assert!(folder::delete_folder(&mut session, "Archive").is_err());
+    assert!(folder::delete_folder(&mut session, "inbox").is_err());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn delete_to_nonexistent_trash_fails() {
+    let user = unique_user();
+    send_email(&user, "Delete fail test", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let criteria = default_criteria("INBOX");
+
+    // Don't create Trash folder — should fail
+    let result = delete::delete(
+        &mut session,
+        &criteria,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    );
+    assert!(result.is_err());
+
+    // Messages should still be in INBOX
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(inbox.len(), 1, "Message should still be in INBOX");
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn ensure_folder_exists_returns_error_for_missing_folder() {
+    let user = unique_user();
+    send_email(&user, "Folder check test", "body");
+    sleep_for_delivery();
 
     let mut session = imap_connect(&user);
     let result = search::ensure_folder_exists(&mut session, "NoSuchFolder");
@@ -882,7 +1564,7 @@ fn search_all_folders_with_subject_filter() {
     // Search all folders with subject filter — should find Report Q1 (INBOX) + Report Q2 (Archive)
     let mut all_criteria = default_criteria("INBOX");
     all_criteria.all_folders = true;
-    all_criteria.subject = Some("Report".to_string());
+    all_criteria.subject = vec!["Report".to_string()];
     let results = search::search(&mut session, &all_criteria).unwrap();
 
     assert_eq!(
@@ -924,7 +1606,11 @@ fn delete_all_folders() {
     // Delete with all_folders — should move messages from both INBOX and Archive to Trash
     let mut all_criteria = default_criteria("INBOX");
     all_criteria.all_folders = true;
-    delete::delete(&mut session, &all_criteria, "Trash", true, false).unwrap();
+    let opts = ActOptions {
+        yes: true,
+        ..Default::default()
+    };
+    delete::delete(&mut session, &all_criteria, "Trash", &opts, Ui::default()).unwrap();
 
     // Both INBOX and Archive should be empty
     let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
@@ -1080,6 +1766,52 @@ fn search_by_body() {
     session.logout().unwrap();
 }
 
+#[test]
+fn search_by_header() {
+    let user = unique_user();
+    send_email(&user, "Header probe alpha", "body");
+    send_email(&user, "Header probe beta", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.headers = vec![("Subject".into(), "probe beta".into())];
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("beta"));
+
+    // An empty value matches any message that has the field at all
+    criteria.headers = vec![("X-Slashmail-Missing".into(), String::new())];
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert!(results.is_empty());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_with_raw_query() {
+    let user = unique_user();
+    send_email_from("alice@localhost", &user, "Raw alpha", "body");
+    send_email_from("alice@localhost", &user, "Raw beta", "body");
+    send_email_from("bob@localhost", &user, "Raw gamma", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let mut criteria = default_criteria("INBOX");
+    criteria.from = vec!["alice@".to_string()];
+    criteria.raw = Some("NOT SUBJECT \"alpha\"".to_string());
+    let results = search::search(&mut session, &criteria).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].subject.contains("beta"));
+
+    // Malformed keys come back as the server's error, not a silent empty result
+    criteria.raw = Some("NOSUCHKEY".to_string());
+    assert!(search::search(&mut session, &criteria).is_err());
+
+    session.logout().unwrap();
+}
+
 #[test]
 fn search_by_text() {
     let user = unique_user();
@@ -1120,16 +1852,30 @@ fn export_skip_existing() {
     let tmp = tempfile::tempdir().unwrap();
 
     // First export succeeds
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", tmp.path(), false).unwrap();
-    assert_eq!(exported, 1);
-    assert_eq!(skipped, 0);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 1);
+    assert_eq!(report.skipped, 0);
 
     // Second export without force skips the existing file
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", tmp.path(), false).unwrap();
-    assert_eq!(exported, 0);
-    assert_eq!(skipped, 1);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 0);
+    assert_eq!(report.skipped, 1);
 
     session.logout().unwrap();
 }
@@ -1148,15 +1894,785 @@ fn export_force_overwrite() {
     let tmp = tempfile::tempdir().unwrap();
 
     // First export
-    let (exported, _) =
-        export::export_messages(&mut session, &messages, "INBOX", tmp.path(), false).unwrap();
-    assert_eq!(exported, 1);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 1);
 
     // Second export with force overwrites
-    let (exported, skipped) =
-        export::export_messages(&mut session, &messages, "INBOX", tmp.path(), true).unwrap();
-    assert_eq!(exported, 1);
-    assert_eq!(skipped, 0);
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        export::DEFAULT_FILENAME_TEMPLATE,
+        true,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 1);
+    assert_eq!(report.skipped, 0);
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn fetch_raw_returns_source_without_marking_seen() {
+    let user = unique_user();
+    send_email(&user, "Raw source test", "Pipe me somewhere");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let results = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(results.len(), 1);
+
+    let raw = read::fetch_raw(&mut session, "INBOX", results[0].uid).unwrap();
+    let text = String::from_utf8_lossy(&raw);
+    assert!(text.contains("Subject: Raw source test"));
+    assert!(text.contains("Pipe me somewhere"));
+
+    let mut unseen = default_criteria("INBOX");
+    unseen.unseen = true;
+    assert_eq!(search::search(&mut session, &unseen).unwrap().len(), 1);
+
+    assert!(read::fetch_raw(&mut session, "INBOX", results[0].uid + 100).is_err());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn fetch_section_returns_one_part() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    let message = "From: a@localhost\r\nSubject: Parts\r\nMIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+--b\r\nContent-Type: text/plain\r\n\r\nFirst part\r\n\
+--b\r\nContent-Type: text/csv\r\n\r\na,b\r\n\
+--b--\r\n";
+    session
+        .append("INBOX", &[], message.as_bytes(), None)
+        .unwrap();
+    let results = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(results.len(), 1);
+    let uid = results[0].uid;
+
+    let part = read::fetch_section(&mut session, "INBOX", uid, "2").unwrap();
+    assert_eq!(String::from_utf8_lossy(&part).trim_end(), "a,b");
+    let header = read::fetch_section(&mut session, "INBOX", uid, "header").unwrap();
+    assert!(String::from_utf8_lossy(&header).contains("Subject: Parts"));
+
+    // BODY.PEEK leaves the message unread
+    let mut unseen = default_criteria("INBOX");
+    unseen.unseen = true;
+    assert_eq!(search::search(&mut session, &unseen).unwrap().len(), 1);
+
+    assert!(read::fetch_section(&mut session, "INBOX", uid, "1..2").is_err());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_all_folders_reports_progress_per_folder() {
+    let user = unique_user();
+    send_email(&user, "Progress msg", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Archive").unwrap();
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.all_folders = true;
+    let mut calls: Vec<(String, usize, usize)> = Vec::new();
+    search::search_with_progress(&mut session, &criteria, |p| {
+        if let search::SearchProgress::Folder {
+            folder,
+            index,
+            total,
+        } = p
+        {
+            calls.push((folder.to_string(), index, total))
+        }
+    })
+    .unwrap();
+
+    let total = calls.len();
+    assert!(calls.iter().any(|(f, _, _)| f == "INBOX"));
+    assert!(calls.iter().any(|(f, _, _)| f == "Archive"));
+    for (idx, (_, i, n)) in calls.iter().enumerate() {
+        assert_eq!(*i, idx + 1);
+        assert_eq!(*n, total);
+    }
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn search_parallel_matches_serial_search() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    for folder in ["Archive", "Work", "Lists"] {
+        session.create(folder).unwrap();
+    }
+    for (i, folder) in ["INBOX", "Archive", "Work", "Lists"].iter().enumerate() {
+        append_dated(
+            &user,
+            folder,
+            &format!("Parallel {folder}"),
+            &format!("Mon, {} Jan 2020 10:00:00 +0000", i + 6),
+        );
+    }
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.all_folders = true;
+    let serial = search::search(&mut session, &criteria).unwrap();
+    let mut calls = Vec::new();
+    let parallel = search::search_parallel(
+        &mut session,
+        &criteria,
+        3,
+        || Ok(imap_connect(&user)),
+        |p| {
+            if let search::SearchProgress::Folder { folder, .. } = p {
+                calls.push(folder.to_string())
+            }
+        },
+    )
+    .unwrap();
+
+    let key = |rows: &[slashmail::display::MessageRow]| -> Vec<(Option<String>, String)> {
+        rows.iter()
+            .map(|m| (m.folder.clone(), m.subject.clone()))
+            .collect()
+    };
+    assert_eq!(parallel.len(), 4);
+    // Same rows in the same (newest first) order as the serial search
+    assert_eq!(key(&parallel), key(&serial));
+    assert_eq!(parallel[0].subject, "Parallel Lists");
+    for folder in ["Archive", "INBOX", "Lists", "Work"] {
+        assert_eq!(calls.iter().filter(|f| *f == folder).count(), 1, "{folder}");
+    }
+
+    // A worker that cannot connect leaves its folders to the others
+    let parallel = search::search_parallel(
+        &mut session,
+        &criteria,
+        3,
+        || Err(anyhow::anyhow!("no more connections").into()),
+        |_| {},
+    )
+    .unwrap();
+    assert_eq!(key(&parallel), key(&serial));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn delete_expunge_from_trash_directly() {
+    let user = unique_user();
+    send_email(&user, "Old trash 1", "body");
+    send_email(&user, "Old trash 2", "body");
+    send_email(&user, "Keep in inbox", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+
+    let mut old = default_criteria("INBOX");
+    old.subject = vec!["Old trash".into()];
+    delete::delete(
+        &mut session,
+        &old,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
+
+    // Targeting Trash by name without --expunge would be a self-move
+    let mut in_trash = default_criteria("Trash");
+    in_trash.before = Some("2099-01-01".into());
+    let result = delete::delete(
+        &mut session,
+        &in_trash,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    );
+    assert!(result.unwrap_err().to_string().contains("--expunge"));
+
+    delete::delete(
+        &mut session,
+        &in_trash,
+        "Trash",
+        &ActOptions {
+            yes: true,
+            expunge: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    )
+    .unwrap();
+
+    let trash = search::search(&mut session, &default_criteria("Trash")).unwrap();
+    assert!(trash.is_empty(), "Trash should be empty after expunge");
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(inbox.len(), 1, "INBOX should be untouched");
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn move_to_same_folder_errors() {
+    let user = unique_user();
+    send_email(&user, "Self move", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let criteria = default_criteria("INBOX");
+    let result = delete::search_and_move(
+        &mut session,
+        &criteria,
+        "INBOX",
+        &ActOptions {
+            yes: true,
+            ..Default::default()
+        },
+        Ui::default(),
+    );
+    assert!(result.is_err());
+
+    let inbox = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(inbox.len(), 1);
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn delete_mark_read_on_delete_sets_seen() {
+    let user = unique_user();
+    send_email(&user, "Trash me unread", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+
+    let opts = ActOptions {
+        yes: true,
+        mark_read: true,
+        ..Default::default()
+    };
+    delete::delete(
+        &mut session,
+        &default_criteria("INBOX"),
+        "Trash",
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+
+    let mut unseen = default_criteria("Trash");
+    unseen.unseen = true;
+    assert!(search::search(&mut session, &unseen).unwrap().is_empty());
+
+    let mut seen = default_criteria("Trash");
+    seen.seen = true;
+    assert_eq!(search::search(&mut session, &seen).unwrap().len(), 1);
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn delete_report_counts_per_folder() {
+    let user = unique_user();
+    send_email(&user, "Report me one", "body");
+    send_email(&user, "Report me two", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+
+    let opts = ActOptions {
+        yes: true,
+        ..Default::default()
+    };
+    let report = delete::delete(
+        &mut session,
+        &default_criteria("INBOX"),
+        "Trash",
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+
+    assert_eq!(report.command, "delete");
+    assert_eq!((report.matched, report.acted, report.failed), (2, 2, 0));
+    assert_eq!(report.folders.len(), 1);
+    assert_eq!(report.folders[0].folder, "INBOX");
+    assert_eq!(report.folders[0].acted, 2);
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn purge_older_than_keeps_recent_trash() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+
+    append_dated(&user, "Trash", "Ancient", "Mon, 6 Jan 2020 10:00:00 +0000");
+    append_dated(
+        &user,
+        "Trash",
+        "Also ancient",
+        "Tue, 7 Jan 2020 10:00:00 +0000",
+    );
+    let now = chrono::Utc::now().to_rfc2822();
+    append_dated(&user, "Trash", "Fresh", &now);
+
+    let opts = ActOptions {
+        yes: true,
+        expunge: true,
+        ..Default::default()
+    };
+    let report = delete::purge(
+        &mut session,
+        "Trash",
+        Some("30d"),
+        None,
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+    assert_eq!(report.command, "purge");
+    assert_eq!((report.matched, report.acted), (2, 2));
+
+    let remaining = search::search(&mut session, &default_criteria("Trash")).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].subject, "Fresh");
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn export_manifest_records_flags_and_internal_date() {
+    let user = unique_user();
+    send_email(&user, "Manifest me", "body");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.select("INBOX").unwrap();
+    session
+        .uid_store("1:*", "+FLAGS (\\Seen \\Flagged)")
+        .unwrap();
+
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    let tmp = tempfile::tempdir().unwrap();
+    export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        export::DEFAULT_FILENAME_TEMPLATE,
+        false,
+    )
+    .unwrap();
+
+    let manifest = std::fs::read_to_string(tmp.path().join(export::MANIFEST_FILE)).unwrap();
+    let entries: Vec<export::ManifestEntry> = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].folder, "INBOX");
+    assert_eq!(entries[0].uid, messages[0].uid);
+    assert!(tmp.path().join(&entries[0].file).exists());
+    assert!(entries[0].flags.contains(&"\\Seen".to_string()));
+    assert!(entries[0].flags.contains(&"\\Flagged".to_string()));
+    assert!(!entries[0].flags.contains(&"\\Recent".to_string()));
+    assert!(entries[0].internal_date.is_some());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn export_mbox_writes_one_file_newest_first() {
+    let user = unique_user();
+    append_dated(
+        &user,
+        "INBOX",
+        "Older mbox",
+        "Mon, 6 Jan 2020 10:00:00 +0000",
+    );
+    append_dated(
+        &user,
+        "INBOX",
+        "Newer mbox",
+        "Tue, 7 Jan 2020 10:00:00 +0000",
+    );
+
+    let mut session = imap_connect(&user);
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(messages.len(), 2);
+
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("archive.mbox");
+    let report = export::export_mbox(&mut session, &messages, "INBOX", &path, false).unwrap();
+    assert_eq!(report.acted, 2);
+
+    let mbox = std::fs::read_to_string(&path).unwrap();
+    let separators: Vec<&str> = mbox.lines().filter(|l| l.starts_with("From ")).collect();
+    assert_eq!(
+        separators,
+        [
+            "From sender@localhost Tue Jan  7 10:00:00 2020",
+            "From sender@localhost Mon Jan  6 10:00:00 2020",
+        ]
+    );
+    assert!(mbox.find("Newer mbox").unwrap() < mbox.find("Older mbox").unwrap());
+    assert!(!mbox.contains('\r'));
+
+    // An existing file is only replaced with force
+    assert!(export::export_mbox(&mut session, &messages, "INBOX", &path, false).is_err());
+    assert!(export::export_mbox(&mut session, &messages, "INBOX", &path, true).is_ok());
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn export_maildir_encodes_flags_in_filenames() {
+    let user = unique_user();
+    append_dated(
+        &user,
+        "INBOX",
+        "Maildir new",
+        "Mon, 6 Jan 2020 10:00:00 +0000",
+    );
+    append_dated(
+        &user,
+        "INBOX",
+        "Maildir seen",
+        "Tue, 7 Jan 2020 10:00:00 +0000",
+    );
+
+    let mut session = imap_connect(&user);
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(messages.len(), 2);
+    let seen = messages
+        .iter()
+        .find(|m| m.subject == "Maildir seen")
+        .unwrap()
+        .uid;
+    session.select("INBOX").unwrap();
+    session
+        .uid_store(&seen.to_string(), "+FLAGS.SILENT (\\Seen \\Flagged)")
+        .unwrap();
+
+    let tmp = tempfile::tempdir().unwrap();
+    let report = export::export_maildir(&mut session, &messages, "INBOX", tmp.path()).unwrap();
+    assert_eq!(report.acted, 2);
+
+    let names = |sub: &str| -> Vec<String> {
+        std::fs::read_dir(tmp.path().join(sub))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert!(names("tmp").is_empty());
+    let new = names("new");
+    let cur = names("cur");
+    assert_eq!(new.len(), 1);
+    assert_eq!(cur.len(), 1);
+    assert!(!new[0].contains(":2,"));
+    assert!(cur[0].ends_with(":2,FS"), "{}", cur[0]);
+
+    let body = std::fs::read_to_string(tmp.path().join("cur").join(&cur[0])).unwrap();
+    assert!(body.contains("Maildir seen"));
+    assert!(!body.contains('\r'));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn export_filename_template_suffixes_collisions() {
+    let user = unique_user();
+    append_dated(
+        &user,
+        "INBOX",
+        "Same: subject",
+        "Mon, 6 Jan 2020 10:00:00 +0000",
+    );
+    append_dated(
+        &user,
+        "INBOX",
+        "Same: subject",
+        "Tue, 7 Jan 2020 10:00:00 +0000",
+    );
+
+    let mut session = imap_connect(&user);
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(messages.len(), 2);
+
+    let tmp = tempfile::tempdir().unwrap();
+    let template = "{subject}.eml";
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        template,
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.acted, 2);
+
+    // The lower UID (appended first) keeps the plain name
+    let first = std::fs::read_to_string(tmp.path().join("Same: subject.eml")).unwrap();
+    assert!(first.contains("6 Jan 2020"));
+    let second = std::fs::read_to_string(tmp.path().join("Same: subject-1.eml")).unwrap();
+    assert!(second.contains("7 Jan 2020"));
+
+    // Names are stable, so a re-run skips both files instead of adding -2
+    let report = export::export_messages(
+        &mut session,
+        &messages,
+        "INBOX",
+        tmp.path(),
+        template,
+        false,
+    )
+    .unwrap();
+    assert_eq!((report.acted, report.skipped), (0, 2));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn append_uploads_files_with_flags_and_date() {
+    let user = unique_user();
+    let tmp = tempfile::tempdir().unwrap();
+    let files: Vec<std::path::PathBuf> = ["One", "Two"]
+        .iter()
+        .map(|subject| {
+            let path = tmp.path().join(format!("{subject}.eml"));
+            let message = format!(
+                "From: sender@localhost\r\nSubject: Appended {subject}\r\n\
+                 Date: Mon, 6 Jan 2020 10:00:00 +0000\r\n\r\nbody\r\n"
+            );
+            std::fs::write(&path, message).unwrap();
+            path
+        })
+        .collect();
+
+    let mut session = imap_connect(&user);
+    let flags = append::append_flags(true, true);
+    let date = append::parse_append_date("2020-01-06").unwrap();
+    let report = append::append_files(&mut session, "INBOX", &files, &flags, Some(date)).unwrap();
+    assert_eq!((report.matched, report.acted, report.failed), (2, 2, 0));
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.flagged = true;
+    criteria.seen = true;
+    criteria.on = Some("2020-01-06".to_string());
+    let messages = search::search(&mut session, &criteria).unwrap();
+    let mut subjects: Vec<&str> = messages.iter().map(|m| m.subject.as_str()).collect();
+    subjects.sort_unstable();
+    assert_eq!(subjects, ["Appended One", "Appended Two"]);
+
+    // A missing folder is reported before anything is uploaded
+    let err = append::append_files(&mut session, "NoSuchFolder", &files, &[], None)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("does not exist"), "{err}");
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn delete_with_limit_acts_on_displayed_messages() {
+    let user = unique_user();
+    for i in 1..=4 {
+        send_email(&user, &format!("Limit {i}"), "body");
+    }
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+
+    let mut criteria = default_criteria("INBOX");
+    criteria.limit = Some(2);
+    let shown: Vec<String> = search::search(&mut session, &criteria)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.subject)
+        .collect();
+    assert_eq!(shown.len(), 2);
+
+    let opts = ActOptions {
+        yes: true,
+        ..Default::default()
+    };
+    let report = delete::delete(&mut session, &criteria, "Trash", &opts, Ui::default()).unwrap();
+    assert_eq!(report.acted, 2);
+
+    let mut trashed: Vec<String> = search::search(&mut session, &default_criteria("Trash"))
+        .unwrap()
+        .into_iter()
+        .map(|m| m.subject)
+        .collect();
+    let mut expected = shown.clone();
+    trashed.sort();
+    expected.sort();
+    assert_eq!(trashed, expected);
+
+    let remaining = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    assert_eq!(remaining.len(), 2);
+    assert!(remaining.iter().all(|m| !shown.contains(&m.subject)));
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn watch_reports_new_messages_once() {
+    let user = unique_user();
+    send_email(&user_email(&user), "Already here", "old");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let stop = AtomicBool::new(false);
+    let mut seen = Vec::new();
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_secs(1));
+            append_dated(&user, "INBOX", "Fresh 1", "Mon, 6 Jan 2020 10:00:00 +0000");
+            append_dated(&user, "INBOX", "Fresh 2", "Tue, 7 Jan 2020 10:00:00 +0000");
+        });
+        watch::watch(
+            &mut session,
+            "INBOX",
+            Duration::from_secs(2),
+            &stop,
+            |rows| {
+                seen.extend(rows.iter().map(|m| m.subject.clone()));
+                if seen.len() >= 2 {
+                    stop.store(true, Ordering::SeqCst);
+                }
+            },
+        )
+        .unwrap();
+    });
+
+    // Oldest first, and the message that was there before the watch started is skipped
+    assert_eq!(seen, ["Fresh 1", "Fresh 2"]);
+    session.logout().unwrap();
+}
+
+#[test]
+fn dedupe_moves_extra_copies_to_trash() {
+    let user = unique_user();
+    let mut session = imap_connect(&user);
+    session.create("Trash").unwrap();
+    let message = |id: &str, subject: &str| {
+        format!("From: a@localhost\r\nSubject: {subject}\r\nMessage-ID: {id}\r\n\r\nbody\r\n")
+    };
+    for body in [
+        message("<dup@localhost>", "Copy 1"),
+        message("<dup@localhost>", "Copy 2"),
+        message(" <dup@localhost> ", "Copy 3"),
+        message("<unique@localhost>", "Unique"),
+    ] {
+        session.append("INBOX", &[], body.as_bytes(), None).unwrap();
+    }
+    session
+        .append(
+            "INBOX",
+            &[],
+            b"From: a@localhost\r\nSubject: No id\r\n\r\nx\r\n",
+            None,
+        )
+        .unwrap();
+    session
+        .append(
+            "INBOX",
+            &[],
+            b"From: a@localhost\r\nSubject: No id\r\n\r\nx\r\n",
+            None,
+        )
+        .unwrap();
+
+    let opts = ActOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    let report = dedupe::dedupe(
+        &mut session,
+        &default_criteria("INBOX"),
+        "Trash",
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.matched, 2);
+    assert_eq!(
+        search::search(&mut session, &default_criteria("INBOX"))
+            .unwrap()
+            .len(),
+        6
+    );
+
+    let opts = ActOptions {
+        yes: true,
+        ..Default::default()
+    };
+    let report = dedupe::dedupe(
+        &mut session,
+        &default_criteria("INBOX"),
+        "Trash",
+        &opts,
+        Ui::default(),
+    )
+    .unwrap();
+    assert_eq!(report.acted, 2);
+
+    let mut subjects: Vec<String> = search::search(&mut session, &default_criteria("INBOX"))
+        .unwrap()
+        .into_iter()
+        .map(|m| m.subject)
+        .collect();
+    subjects.sort();
+    // The first copy stays, and messages without a Message-ID are never touched
+    assert_eq!(subjects, ["Copy 1", "No id", "No id", "Unique"]);
+    assert_eq!(
+        search::search(&mut session, &default_criteria("Trash"))
+            .unwrap()
+            .len(),
+        2
+    );
+
+    session.logout().unwrap();
+}
+
+#[test]
+fn stats_groups_senders_by_address() {
+    let user = unique_user();
+    send_email_from("Alice <alice@localhost>", &user, "One", "a");
+    send_email_from("alice@localhost", &user, "Two", "b");
+    send_email_from("Bob <bob@localhost>", &user, "Three", "c");
+    sleep_for_delivery();
+
+    let mut session = imap_connect(&user);
+    let messages = search::search(&mut session, &default_criteria("INBOX")).unwrap();
+    let top = stats::top_by_count(&stats::sender_stats(&messages), 10);
+    let counts: Vec<(&str, usize)> = top.iter().map(|s| (s.address.as_str(), s.count)).collect();
+    assert_eq!(counts, [("alice@localhost", 2), ("bob@localhost", 1)]);
 
     session.logout().unwrap();
 }